@@ -0,0 +1,245 @@
+// Copyright (c) Silence Laboratories Pte. Ltd. All Rights Reserved.
+// This software is licensed under the Silence Laboratories License Agreement.
+
+//! Key ceremony driven by a declarative config.
+//!
+//! Runs keygen or key refresh for all participants of a local (offline)
+//! ceremony, records the public transcript (all broadcast messages),
+//! writes a backup of every resulting key share and a key certificate.
+//! Parties running on separate hosts should use the `tcp_ceremony`
+//! example instead.
+//!
+//! ```sh
+//! cargo run --example ceremony -- ceremony.json
+//! ```
+//!
+//! Config:
+//!
+//! ```json
+//! {
+//!   "operation": "keygen",
+//!   "threshold": 2,
+//!   "participants": [
+//!     { "name": "alice", "backup": "alice.share" },
+//!     { "name": "bob", "backup": "bob.share" },
+//!     { "name": "carol", "backup": "carol.share" }
+//!   ],
+//!   "transcript": "transcript.jsonl",
+//!   "certificate": "certificate.json"
+//! }
+//! ```
+//!
+//! Party ids are assigned in order of `participants`. For `"refresh"`
+//! the existing backups are read and replaced by refreshed key shares.
+
+use std::{fs, io::Write};
+
+use k256::elliptic_curve::group::GroupEncoding;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use dkls23_ll::dkg::{self, Keyshare, KeysharePublic};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Operation {
+    Keygen,
+    Refresh,
+}
+
+#[derive(Deserialize)]
+struct Participant {
+    name: String,
+    backup: String,
+}
+
+#[derive(Deserialize)]
+struct Config {
+    operation: Operation,
+    threshold: u8,
+    participants: Vec<Participant>,
+    transcript: String,
+    certificate: String,
+}
+
+#[derive(Serialize)]
+struct CertificateParty {
+    name: String,
+    #[serde(flatten)]
+    public: KeysharePublic,
+}
+
+#[derive(Serialize)]
+struct Certificate {
+    public_key: String,
+    threshold: u8,
+    participants: Vec<CertificateParty>,
+    transcript_sha256: String,
+}
+
+/// Public transcript of a ceremony: every broadcast message as one
+/// JSON line.
+struct Transcript {
+    out: fs::File,
+    hash: Sha256,
+}
+
+impl Transcript {
+    fn create(path: &str) -> Result<Self> {
+        Ok(Self {
+            out: fs::File::create(path)?,
+            hash: Sha256::new(),
+        })
+    }
+
+    fn record<T: Serialize>(
+        &mut self,
+        round: &str,
+        from_id: u8,
+        msg: &T,
+    ) -> Result<()> {
+        let mut payload = vec![];
+        ciborium::into_writer(msg, &mut payload)?;
+
+        let line = serde_json::json!({
+            "round": round,
+            "from_id": from_id,
+            "payload": hex::encode(&payload),
+        });
+        let line = format!("{line}\n");
+
+        self.hash.update(line.as_bytes());
+        self.out.write_all(line.as_bytes())?;
+
+        Ok(())
+    }
+}
+
+fn run(
+    mut parties: Vec<dkg::State>,
+    transcript: &mut Transcript,
+) -> Result<Vec<Keyshare>> {
+    let mut rng = rand::thread_rng();
+
+    let msg1: Vec<dkg::KeygenMsg1> =
+        parties.iter().map(|p| p.generate_msg1()).collect();
+    for msg in &msg1 {
+        transcript.record("msg1", msg.from_id, msg)?;
+    }
+
+    let mut msg2 = vec![];
+    for (id, party) in parties.iter_mut().enumerate() {
+        let batch = msg1.iter().filter(|m| m.from_id as usize != id);
+        msg2.extend(party.handle_msg1(&mut rng, batch)?);
+    }
+
+    let commitments: Vec<[u8; 32]> =
+        parties.iter().map(|p| p.calculate_commitment_2()).collect();
+    for (id, commitment) in commitments.iter().enumerate() {
+        transcript.record("commitment", id as u8, commitment)?;
+    }
+
+    let mut msg3 = vec![];
+    for (id, party) in parties.iter_mut().enumerate() {
+        let batch = msg2
+            .iter()
+            .filter(|m: &&dkg::KeygenMsg2| m.to_id as usize == id);
+        msg3.extend(party.handle_msg2(&mut rng, batch)?);
+    }
+
+    let mut msg4 = vec![];
+    for (id, party) in parties.iter_mut().enumerate() {
+        let batch = msg3
+            .iter()
+            .filter(|m: &&dkg::KeygenMsg3| m.to_id as usize == id);
+        msg4.push(party.handle_msg3(&mut rng, batch, &commitments)?);
+    }
+    for msg in &msg4 {
+        transcript.record("msg4", msg.from_id, msg)?;
+    }
+
+    parties
+        .iter_mut()
+        .enumerate()
+        .map(|(id, party)| {
+            let batch = msg4.iter().filter(|m| m.from_id as usize != id);
+            Ok(party.handle_msg4(batch)?)
+        })
+        .collect()
+}
+
+fn main() -> Result<()> {
+    let path = std::env::args()
+        .nth(1)
+        .ok_or("usage: ceremony <config.json>")?;
+    let config: Config = serde_json::from_slice(&fs::read(path)?)?;
+
+    let n = config.participants.len();
+    let t = config.threshold;
+    if t < 2 || t as usize > n {
+        return Err("invalid threshold".into());
+    }
+
+    let mut rng = rand::thread_rng();
+
+    let parties = match config.operation {
+        Operation::Keygen => (0..n)
+            .map(|id| {
+                let party = dkg::Party::new(n, t as usize, id)?;
+                Ok(dkg::State::new(party, &mut rng)?)
+            })
+            .collect::<Result<Vec<_>>>()?,
+
+        Operation::Refresh => config
+            .participants
+            .iter()
+            .enumerate()
+            .map(|(id, p)| {
+                let share: Keyshare =
+                    ciborium::from_reader(fs::read(&p.backup)?.as_slice())?;
+                if share.party_id as usize != id || share.threshold != t {
+                    return Err(format!(
+                        "{}: backup does not match config",
+                        p.name
+                    )
+                    .into());
+                }
+                Ok(dkg::State::key_rotation(&share, &mut rng)?)
+            })
+            .collect::<Result<Vec<_>>>()?,
+    };
+
+    let mut transcript = Transcript::create(&config.transcript)?;
+    let shares = run(parties, &mut transcript)?;
+
+    for (p, share) in config.participants.iter().zip(&shares) {
+        let mut backup = vec![];
+        ciborium::into_writer(share, &mut backup)?;
+        fs::write(&p.backup, backup)?;
+    }
+
+    let certificate = Certificate {
+        public_key: hex::encode(shares[0].public_key.to_bytes()),
+        threshold: t,
+        participants: config
+            .participants
+            .iter()
+            .zip(&shares)
+            .map(|(p, s)| CertificateParty {
+                name: p.name.clone(),
+                public: s.public_data(),
+            })
+            .collect(),
+        transcript_sha256: hex::encode(transcript.hash.finalize()),
+    };
+    fs::write(
+        &config.certificate,
+        serde_json::to_string_pretty(&certificate)?,
+    )?;
+
+    println!("public key {}", certificate.public_key);
+
+    Ok(())
+}
@@ -0,0 +1,419 @@
+// Copyright (c) Silence Laboratories Pte. Ltd. All Rights Reserved.
+// This software is licensed under the Silence Laboratories License Agreement.
+
+//! Soak/stress test for many concurrent sessions in one process.
+//!
+//! Each worker thread keeps its whole batch of sessions alive and
+//! advances all of them round by round, so the number of live
+//! sessions equals the requested number of sessions. Reports peak
+//! heap usage, allocation counts and per-round latency distributions.
+//!
+//! ```sh
+//! cargo run --release --features stress --example stress -- \
+//!     [keygen-sessions] [sign-sessions] [threads] [n] [t]
+//! ```
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    collections::BTreeMap,
+    str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use derivation_path::DerivationPath;
+
+use dkls23_ll::{
+    dkg::{self, Keyshare},
+    dsg,
+};
+
+struct CountingAlloc;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+static ALLOCS: AtomicUsize = AtomicUsize::new(0);
+
+fn track_alloc(size: usize) {
+    ALLOCS.fetch_add(1, Ordering::Relaxed);
+    let current = CURRENT.fetch_add(size, Ordering::Relaxed) + size;
+    PEAK.fetch_max(current, Ordering::Relaxed);
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            track_alloc(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(
+        &self,
+        ptr: *mut u8,
+        layout: Layout,
+        new_size: usize,
+    ) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+            track_alloc(new_size);
+        }
+        new_ptr
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// Latencies of individual handler calls grouped by round.
+#[derive(Default)]
+struct Latency(BTreeMap<&'static str, Vec<Duration>>);
+
+impl Latency {
+    fn measure<T>(
+        &mut self,
+        round: &'static str,
+        f: impl FnOnce() -> T,
+    ) -> T {
+        let start = Instant::now();
+        let res = f();
+        self.0.entry(round).or_default().push(start.elapsed());
+        res
+    }
+
+    fn merge(&mut self, other: Latency) {
+        for (round, mut samples) in other.0 {
+            self.0.entry(round).or_default().append(&mut samples);
+        }
+    }
+
+    fn report(&mut self) {
+        println!(
+            "  {:<14} {:>8} {:>10} {:>10} {:>10} {:>10}",
+            "round", "calls", "p50", "p90", "p99", "max"
+        );
+        for (round, samples) in self.0.iter_mut() {
+            samples.sort_unstable();
+            let p = |q: usize| samples[(samples.len() - 1) * q / 100];
+            println!(
+                "  {:<14} {:>8} {:>10.2?} {:>10.2?} {:>10.2?} {:>10.2?}",
+                round,
+                samples.len(),
+                p(50),
+                p(90),
+                p(99),
+                p(100)
+            );
+        }
+    }
+}
+
+/// Run one round for all sessions of a batch. Party `i` of a session
+/// receives messages of the previous round selected by `select`.
+/// `handle` is called with an index of the session, a party state and
+/// the selected messages.
+fn round<S, I: Clone, O>(
+    latency: &mut Latency,
+    name: &'static str,
+    sessions: &mut [Vec<S>],
+    input: &[Vec<I>],
+    select: impl Fn(&I, u8) -> bool,
+    mut handle: impl FnMut(usize, &mut S, Vec<I>) -> Vec<O>,
+) -> Vec<Vec<O>> {
+    sessions
+        .iter_mut()
+        .zip(input)
+        .enumerate()
+        .map(|(idx, (parties, msgs))| {
+            parties
+                .iter_mut()
+                .enumerate()
+                .flat_map(|(id, party)| {
+                    let batch = msgs
+                        .iter()
+                        .filter(|m| select(m, id as u8))
+                        .cloned()
+                        .collect();
+                    latency.measure(name, || handle(idx, party, batch))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Run `count` keygen sessions concurrently.
+/// Returns key shares of the first session, if any.
+fn keygen_batch(
+    count: usize,
+    n: u8,
+    t: u8,
+    latency: &mut Latency,
+) -> Option<Vec<Keyshare>> {
+    let mut rng = rand::thread_rng();
+
+    let mut sessions = (0..count)
+        .map(|_| {
+            (0..n)
+                .map(|party_id| {
+                    let party = dkg::Party {
+                        ranks: vec![0; n as usize],
+                        t,
+                        party_id,
+                    };
+                    dkg::State::new(party, &mut rng).unwrap()
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let msg1 = sessions
+        .iter_mut()
+        .map(|parties| {
+            parties
+                .iter_mut()
+                .map(|p| latency.measure("keygen msg1", || p.generate_msg1()))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let msg2 = round(
+        latency,
+        "keygen msg2",
+        &mut sessions,
+        &msg1,
+        |m: &dkg::KeygenMsg1, id| m.from_id != id,
+        |_, p, batch| p.handle_msg1(&mut rng, batch).unwrap(),
+    );
+    drop(msg1);
+
+    let msg3 = round(
+        latency,
+        "keygen msg3",
+        &mut sessions,
+        &msg2,
+        |m: &dkg::KeygenMsg2, id| m.to_id == id,
+        |_, p, batch| p.handle_msg2(&mut rng, batch).unwrap(),
+    );
+    drop(msg2);
+
+    let commitments = sessions
+        .iter()
+        .map(|parties| {
+            parties
+                .iter()
+                .map(|p| p.calculate_commitment_2())
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let msg4 = round(
+        latency,
+        "keygen msg4",
+        &mut sessions,
+        &msg3,
+        |m: &dkg::KeygenMsg3, id| m.to_id == id,
+        |idx, p, batch| {
+            vec![p.handle_msg3(&mut rng, batch, &commitments[idx]).unwrap()]
+        },
+    );
+    drop(msg3);
+
+    let mut shares = round(
+        latency,
+        "keygen share",
+        &mut sessions,
+        &msg4,
+        |m: &dkg::KeygenMsg4, id| m.from_id != id,
+        |_, p, batch| vec![p.handle_msg4(batch).unwrap()],
+    );
+
+    if shares.is_empty() {
+        None
+    } else {
+        Some(shares.swap_remove(0))
+    }
+}
+
+/// Run `count` signing sessions concurrently using the first `t`
+/// key shares.
+fn sign_batch(count: usize, shares: &[Keyshare], latency: &mut Latency) {
+    let mut rng = rand::thread_rng();
+    let chain_path = DerivationPath::from_str("m/0/1").unwrap();
+    let t = shares[0].threshold as usize;
+
+    let mut sessions = (0..count)
+        .map(|_| {
+            shares[..t]
+                .iter()
+                .map(|s| {
+                    dsg::State::new(&mut rng, s.clone(), &chain_path).unwrap()
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let msg1 = sessions
+        .iter_mut()
+        .map(|parties| {
+            parties
+                .iter_mut()
+                .map(|p| {
+                    latency
+                        .measure("sign msg1", || p.generate_msg1().unwrap())
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let msg2 = round(
+        latency,
+        "sign msg2",
+        &mut sessions,
+        &msg1,
+        |m: &dsg::SignMsg1, id| m.from_id != id,
+        |_, p, batch| p.handle_msg1(&mut rng, batch).unwrap(),
+    );
+    drop(msg1);
+
+    let msg3 = round(
+        latency,
+        "sign msg3",
+        &mut sessions,
+        &msg2,
+        |m: &dsg::SignMsg2, id| m.to_id == id,
+        |_, p, batch| p.handle_msg2(&mut rng, batch).unwrap(),
+    );
+    drop(msg2);
+
+    let pre_signs = round(
+        latency,
+        "sign presig",
+        &mut sessions,
+        &msg3,
+        |m: &dsg::SignMsg3, id| m.to_id == id,
+        |_, p, batch| vec![p.handle_msg3(batch).unwrap()],
+    );
+    drop(msg3);
+    drop(sessions);
+
+    for pre_signs in pre_signs {
+        let (partials, msg4): (Vec<_>, Vec<_>) = pre_signs
+            .into_iter()
+            .map(|pre| {
+                latency.measure("sign partial", || {
+                    dsg::create_partial_signature(pre, [1; 32]).unwrap()
+                })
+            })
+            .unzip();
+
+        for partial in partials {
+            let batch = msg4
+                .iter()
+                .filter(|m| m.from_id != partial.party_id)
+                .cloned()
+                .collect::<Vec<_>>();
+            latency.measure("sign combine", || {
+                dsg::combine_signatures(partial, batch).unwrap()
+            });
+        }
+    }
+}
+
+/// Split `total` sessions between `threads` workers, run `work` on
+/// each chunk and report resource usage.
+fn run_phase<T: Send>(
+    name: &str,
+    total: usize,
+    threads: usize,
+    work: impl Fn(usize, &mut Latency) -> T + Sync,
+) -> Vec<T> {
+    let allocs = ALLOCS.load(Ordering::Relaxed);
+    let base = CURRENT.load(Ordering::Relaxed);
+    PEAK.store(base, Ordering::Relaxed);
+
+    let start = Instant::now();
+
+    let results = thread::scope(|s| {
+        let handles = (0..threads)
+            .map(|i| {
+                let count =
+                    total / threads + usize::from(i < total % threads);
+                let work = &work;
+                s.spawn(move || {
+                    let mut latency = Latency::default();
+                    let res = work(count, &mut latency);
+                    (res, latency)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+
+    let elapsed = start.elapsed();
+
+    let mut latency = Latency::default();
+    let results = results
+        .into_iter()
+        .map(|(res, l)| {
+            latency.merge(l);
+            res
+        })
+        .collect();
+
+    println!("{name}: {total} sessions on {threads} threads");
+    println!(
+        "  wall time {:.2?}, {:.1} sessions/s",
+        elapsed,
+        total as f64 / elapsed.as_secs_f64()
+    );
+    println!(
+        "  allocations {}, peak heap {} KiB, retained {} KiB",
+        ALLOCS.load(Ordering::Relaxed) - allocs,
+        (PEAK.load(Ordering::Relaxed) - base) / 1024,
+        CURRENT.load(Ordering::Relaxed).saturating_sub(base) / 1024,
+    );
+    latency.report();
+
+    results
+}
+
+fn main() {
+    let args = std::env::args()
+        .skip(1)
+        .map(|a| a.parse::<usize>().expect("numeric argument"))
+        .collect::<Vec<_>>();
+    let arg = |idx: usize, default: usize| *args.get(idx).unwrap_or(&default);
+
+    let keygen_sessions = arg(0, 1000).max(1);
+    let sign_sessions = arg(1, 1000);
+    let threads =
+        arg(2, thread::available_parallelism().map_or(1, |n| n.get()));
+    let n = arg(3, 3) as u8;
+    let t = arg(4, 2) as u8;
+
+    let shares = run_phase("keygen", keygen_sessions, threads, |count, l| {
+        keygen_batch(count, n, t, l)
+    })
+    .into_iter()
+    .flatten()
+    .next()
+    .unwrap();
+
+    run_phase("sign", sign_sessions, threads, |count, l| {
+        if count > 0 {
+            sign_batch(count, &shares, l)
+        }
+    });
+}
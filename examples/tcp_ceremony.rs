@@ -0,0 +1,368 @@
+// Copyright (c) Silence Laboratories Pte. Ltd. All Rights Reserved.
+// This software is licensed under the Silence Laboratories License Agreement.
+
+//! End-to-end ceremony over TCP: keygen, key rotation and signing.
+//!
+//! A coordinator relays messages between parties using the routing
+//! metadata of the protocol messages (`from_id`, `to_id`); it never
+//! looks at payloads. Every party connects to the coordinator, runs a
+//! keygen and a key rotation with all other parties and then the first
+//! T parties sign a message.
+//!
+//! ```sh
+//! # everything in one process
+//! cargo run --example tcp_ceremony -- demo 3 2
+//!
+//! # or in separate terminals
+//! cargo run --example tcp_ceremony -- coordinator 127.0.0.1:7000 3
+//! cargo run --example tcp_ceremony -- party 127.0.0.1:7000 0 3 2
+//! cargo run --example tcp_ceremony -- party 127.0.0.1:7000 1 3 2
+//! cargo run --example tcp_ceremony -- party 127.0.0.1:7000 2 3 2
+//! ```
+
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use derivation_path::DerivationPath;
+use k256::ecdsa::{signature::hazmat::PrehashVerifier, VerifyingKey};
+use serde::{de::DeserializeOwned, Serialize};
+
+use dkls23_ll::{dkg, dsg};
+
+const BROADCAST: u8 = u8::MAX;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+/// Frame header: from, to, round, payload length (u32 BE).
+struct Frame {
+    from: u8,
+    to: u8,
+    round: u8,
+    payload: Vec<u8>,
+}
+
+impl Frame {
+    fn write(&self, mut w: impl Write) -> Result<()> {
+        let len = self.payload.len() as u32;
+        w.write_all(&[self.from, self.to, self.round])?;
+        w.write_all(&len.to_be_bytes())?;
+        w.write_all(&self.payload)?;
+        Ok(())
+    }
+
+    fn read(mut r: impl Read) -> Result<Self> {
+        let mut hdr = [0u8; 7];
+        r.read_exact(&mut hdr)?;
+        let len = u32::from_be_bytes(hdr[3..].try_into().unwrap());
+        let mut payload = vec![0u8; len as usize];
+        r.read_exact(&mut payload)?;
+        Ok(Frame {
+            from: hdr[0],
+            to: hdr[1],
+            round: hdr[2],
+            payload,
+        })
+    }
+}
+
+/// Routing metadata of a protocol message.
+trait MessageRouting {
+    fn src_party_id(&self) -> u8;
+    fn dst_party_id(&self) -> Option<u8>;
+}
+
+macro_rules! broadcast {
+    ($($t:ty),*) => {$(
+        impl MessageRouting for $t {
+            fn src_party_id(&self) -> u8 { self.from_id }
+            fn dst_party_id(&self) -> Option<u8> { None }
+        }
+    )*};
+}
+
+macro_rules! p2p {
+    ($($t:ty),*) => {$(
+        impl MessageRouting for $t {
+            fn src_party_id(&self) -> u8 { self.from_id }
+            fn dst_party_id(&self) -> Option<u8> { Some(self.to_id) }
+        }
+    )*};
+}
+
+broadcast!(
+    dkg::KeygenMsg1,
+    dkg::KeygenMsg4,
+    dsg::SignMsg1,
+    dsg::SignMsg4
+);
+p2p!(
+    dkg::KeygenMsg2,
+    dkg::KeygenMsg3,
+    dsg::SignMsg2,
+    dsg::SignMsg3
+);
+
+/// Chain code commitment, broadcast after the first keygen round.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Commitment {
+    from_id: u8,
+    commitment: [u8; 32],
+}
+
+broadcast!(Commitment);
+
+/// Connection of a party to the coordinator.
+struct Conn {
+    party_id: u8,
+    stream: TcpStream,
+    round: u8,
+    /// frames of future rounds received from faster parties
+    stash: Vec<Frame>,
+}
+
+impl Conn {
+    fn connect(addr: &str, party_id: u8) -> Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        let hello = Frame {
+            from: party_id,
+            to: BROADCAST,
+            round: 0,
+            payload: vec![],
+        };
+        hello.write(&stream)?;
+
+        Ok(Self {
+            party_id,
+            stream,
+            round: 0,
+            stash: vec![],
+        })
+    }
+
+    /// Send own messages of the next round and wait for `expected`
+    /// messages of the same round from other parties.
+    fn exchange<I, O>(
+        &mut self,
+        out: Vec<I>,
+        expected: usize,
+    ) -> Result<Vec<O>>
+    where
+        I: Serialize + MessageRouting,
+        O: DeserializeOwned,
+    {
+        self.round += 1;
+
+        for msg in out {
+            let mut payload = vec![];
+            ciborium::into_writer(&msg, &mut payload)?;
+            Frame {
+                from: msg.src_party_id(),
+                to: msg.dst_party_id().unwrap_or(BROADCAST),
+                round: self.round,
+                payload,
+            }
+            .write(&self.stream)?;
+        }
+
+        let (mut input, stash): (Vec<_>, Vec<_>) =
+            std::mem::take(&mut self.stash)
+                .into_iter()
+                .partition(|f| f.round == self.round);
+        self.stash = stash;
+
+        while input.len() < expected {
+            let frame = Frame::read(&self.stream)?;
+            if frame.round == self.round {
+                input.push(frame);
+            } else {
+                self.stash.push(frame);
+            }
+        }
+
+        input
+            .into_iter()
+            .map(|f| Ok(ciborium::from_reader(f.payload.as_slice())?))
+            .collect()
+    }
+}
+
+fn keygen(
+    conn: &mut Conn,
+    mut state: dkg::State,
+    n: usize,
+) -> Result<dkg::Keyshare> {
+    let mut rng = rand::thread_rng();
+
+    let msg1: Vec<dkg::KeygenMsg1> =
+        conn.exchange(vec![state.generate_msg1()], n - 1)?;
+    let msg2 = state.handle_msg1(&mut rng, msg1)?;
+
+    let commitment = Commitment {
+        from_id: conn.party_id,
+        commitment: state.calculate_commitment_2(),
+    };
+    let mut commitments: Vec<Commitment> =
+        conn.exchange(vec![commitment], n - 1)?;
+    commitments.push(Commitment {
+        from_id: conn.party_id,
+        commitment: state.calculate_commitment_2(),
+    });
+    commitments.sort_by_key(|c| c.from_id);
+    let commitments = commitments
+        .into_iter()
+        .map(|c| c.commitment)
+        .collect::<Vec<_>>();
+
+    let msg2: Vec<dkg::KeygenMsg2> = conn.exchange(msg2, n - 1)?;
+    let msg3 = state.handle_msg2(&mut rng, msg2)?;
+
+    let msg3: Vec<dkg::KeygenMsg3> = conn.exchange(msg3, n - 1)?;
+    let msg4 = state.handle_msg3(&mut rng, msg3, &commitments)?;
+
+    let msg4: Vec<dkg::KeygenMsg4> = conn.exchange(vec![msg4], n - 1)?;
+
+    Ok(state.handle_msg4(msg4)?)
+}
+
+fn sign(conn: &mut Conn, share: dkg::Keyshare, hash: [u8; 32]) -> Result<()> {
+    let mut rng = rand::thread_rng();
+    let t = share.threshold as usize;
+    let public_key = share.public_key;
+
+    let chain_path = DerivationPath::from_str("m")?;
+    let mut state = dsg::State::new(&mut rng, share, &chain_path)?;
+
+    let msg1: Vec<dsg::SignMsg1> =
+        conn.exchange(vec![state.generate_msg1()?], t - 1)?;
+    let msg2 = state.handle_msg1(&mut rng, msg1)?;
+
+    let msg2: Vec<dsg::SignMsg2> = conn.exchange(msg2, t - 1)?;
+    let msg3 = state.handle_msg2(&mut rng, msg2)?;
+
+    let msg3: Vec<dsg::SignMsg3> = conn.exchange(msg3, t - 1)?;
+    let pre = state.handle_msg3(msg3)?;
+
+    let (partial, msg4) = dsg::create_partial_signature(pre, hash)?;
+    let msg4: Vec<dsg::SignMsg4> = conn.exchange(vec![msg4], t - 1)?;
+    let (sign, recid) = dsg::combine_signatures(partial, msg4)?;
+
+    VerifyingKey::from_affine(public_key)?.verify_prehash(&hash, &sign)?;
+
+    println!(
+        "party {}: signature {} v {}",
+        conn.party_id,
+        sign,
+        recid.to_byte()
+    );
+
+    Ok(())
+}
+
+fn party(addr: &str, party_id: u8, n: u8, t: u8) -> Result<()> {
+    let mut rng = rand::thread_rng();
+    let mut conn = Conn::connect(addr, party_id)?;
+
+    let party = dkg::Party::new(n as usize, t as usize, party_id as usize)?;
+    let share =
+        keygen(&mut conn, dkg::State::new(party, &mut rng)?, n as usize)?;
+    println!("party {party_id}: keygen done");
+
+    let rotation = dkg::State::key_rotation(&share, &mut rng)?;
+    let share = keygen(&mut conn, rotation, n as usize)?;
+    println!("party {party_id}: key rotation done");
+
+    if party_id < t {
+        sign(&mut conn, share, [1; 32])?;
+    }
+
+    Ok(())
+}
+
+fn coordinator(listener: TcpListener, n: u8) -> Result<()> {
+    let mut conns = vec![];
+    while conns.len() < n as usize {
+        let (stream, _) = listener.accept()?;
+        let hello = Frame::read(&stream)?;
+        conns.push((hello.from, stream));
+    }
+
+    let writers = Arc::new(
+        conns
+            .iter()
+            .map(|(id, s)| Ok((*id, Mutex::new(s.try_clone()?))))
+            .collect::<Result<Vec<_>>>()?,
+    );
+
+    let relays = conns
+        .into_iter()
+        .map(|(id, stream)| {
+            let writers = writers.clone();
+            thread::spawn(move || {
+                // the party closes its connection when it is done
+                while let Ok(frame) = Frame::read(&stream) {
+                    for (dst, w) in writers.iter() {
+                        let deliver = *dst != id
+                            && (frame.to == BROADCAST || frame.to == *dst);
+                        if deliver {
+                            // a party that already finished is gone
+                            let _ = frame.write(&*w.lock().unwrap());
+                        }
+                    }
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for r in relays {
+        r.join().unwrap();
+    }
+
+    Ok(())
+}
+
+fn demo(n: u8, t: u8) -> Result<()> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?.to_string();
+
+    let coord = thread::spawn(move || coordinator(listener, n).unwrap());
+
+    let parties = (0..n)
+        .map(|id| {
+            let addr = addr.clone();
+            thread::spawn(move || party(&addr, id, n, t).unwrap())
+        })
+        .collect::<Vec<_>>();
+
+    for p in parties {
+        p.join().unwrap();
+    }
+    coord.join().unwrap();
+
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let args = args.iter().map(String::as_str).collect::<Vec<_>>();
+    let num = |s: &str| s.parse::<u8>();
+
+    match args.as_slice() {
+        [] => demo(3, 2),
+        ["demo", n, t] => demo(num(n)?, num(t)?),
+        ["coordinator", addr, n] => {
+            coordinator(TcpListener::bind(addr)?, num(n)?)
+        }
+        ["party", addr, id, n, t] => party(addr, num(id)?, num(n)?, num(t)?),
+        _ => {
+            eprintln!(
+                "usage: tcp_ceremony [demo N T | coordinator ADDR N | party ADDR ID N T]"
+            );
+            std::process::exit(1);
+        }
+    }
+}
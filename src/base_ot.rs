@@ -0,0 +1,124 @@
+// Copyright (c) Silence Laboratories Pte. Ltd. All Rights Reserved.
+// This software is licensed under the Silence Laboratories License Agreement.
+
+//! Base OT and PPRF steps of a pair of parties, shared by keygen, seed
+//! refresh and OT setup.
+//!
+//! Each party is the receiver of base OT with every counterparty:
+//! [`start`] creates the first OT message, the counterparty replies by
+//! [`reply`] with the OT response and the PPRF output, and [`finish`]
+//! evaluates the PPRF. The results are the all-but-one seeds of OT
+//! extension, see [`crate::dkg::Keyshare`].
+
+use rand::prelude::*;
+
+use sl_oblivious::{
+    endemic_ot::{
+        EndemicOTMsg1, EndemicOTMsg2, EndemicOTReceiver, EndemicOTSender,
+    },
+    soft_spoken::{
+        build_pprf, eval_pprf, PPRFOutput, ReceiverOTSeed, SenderOTSeed,
+    },
+};
+
+use crate::{
+    error::KeygenError,
+    utils::{get_all_but_one_session_id, get_base_ot_session_id, ZS},
+};
+
+/// Base OT response, PPRF output and all-but-one sender seed for one
+/// counterparty.
+pub(crate) type Reply = (ZS<EndemicOTMsg2>, ZS<PPRFOutput>, ZS<SenderOTSeed>);
+
+/// Start base OT of `party_id` as the receiver with `other`. Returns
+/// the receiver and the first OT message for `other`.
+pub(crate) fn start<R: RngCore + CryptoRng>(
+    final_session_id: &[u8; 32],
+    party_id: u8,
+    other: u8,
+    rng: &mut R,
+) -> (EndemicOTReceiver, ZS<EndemicOTMsg1>) {
+    let sid = get_base_ot_session_id(
+        party_id as usize,
+        other as usize,
+        final_session_id,
+    );
+
+    let mut msg1 = ZS::<EndemicOTMsg1>::default();
+    let receiver = EndemicOTReceiver::new(&sid, &mut msg1, rng);
+
+    (receiver, msg1)
+}
+
+/// Reply of `party_id` as the sender to the first OT message of
+/// `other`.
+pub(crate) fn reply<R: RngCore + CryptoRng>(
+    final_session_id: &[u8; 32],
+    party_id: u8,
+    other: u8,
+    msg1: &EndemicOTMsg1,
+    rng: &mut R,
+) -> Result<Reply, KeygenError> {
+    let sid = get_base_ot_session_id(
+        other as usize,
+        party_id as usize,
+        final_session_id,
+    );
+
+    let mut base_ot_msg2 = ZS::<EndemicOTMsg2>::default();
+    let sender_output =
+        EndemicOTSender::process(&sid, msg1, &mut base_ot_msg2, rng)
+            .map_err(|_| KeygenError::AbortProtocolAndBanParty(other))?;
+
+    let all_but_one_session_id = get_all_but_one_session_id(
+        party_id as usize,
+        other as usize,
+        final_session_id,
+    );
+
+    let mut sender_seed = ZS::<SenderOTSeed>::default();
+    let mut pprf_output = ZS::<PPRFOutput>::default();
+
+    build_pprf(
+        &all_but_one_session_id,
+        &sender_output,
+        &mut sender_seed,
+        &mut pprf_output,
+    );
+
+    Ok((base_ot_msg2, pprf_output, sender_seed))
+}
+
+/// Finish base OT of `party_id` with `other`: process the reply of
+/// `other` and return the all-but-one receiver seed.
+pub(crate) fn finish(
+    final_session_id: &[u8; 32],
+    party_id: u8,
+    other: u8,
+    receiver: EndemicOTReceiver,
+    base_ot_msg2: &EndemicOTMsg2,
+    pprf_output: &PPRFOutput,
+) -> Result<ZS<ReceiverOTSeed>, KeygenError> {
+    let ban = || KeygenError::AbortProtocolAndBanParty(other);
+
+    let receiver_output =
+        receiver.process(base_ot_msg2).map_err(|_| ban())?;
+
+    let all_but_one_session_id = get_all_but_one_session_id(
+        other as usize,
+        party_id as usize,
+        final_session_id,
+    );
+
+    let mut receiver_seed = ZS::<ReceiverOTSeed>::default();
+
+    eval_pprf(
+        &all_but_one_session_id,
+        &receiver_output,
+        pprf_output,
+        &mut receiver_seed,
+    )
+    .map_err(|_| ban())?;
+
+    Ok(receiver_seed)
+}
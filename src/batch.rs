@@ -0,0 +1,55 @@
+// Copyright (c) Silence Laboratories Pte. Ltd. All Rights Reserved.
+// This software is licensed under the Silence Laboratories License Agreement.
+
+//! Messages carrying `k` protocol messages of a party, one for each
+//! session of a batch, shared by [`crate::batch_keygen`] and
+//! [`crate::batch_sign`].
+
+/// Batch message of a round and the type of its inner messages.
+pub(crate) trait BatchMessage {
+    type Msg;
+
+    fn sender(&self) -> u8;
+    fn msgs(&self) -> &[Self::Msg];
+    fn inner_sender(msg: &Self::Msg) -> u8;
+}
+
+macro_rules! batch_message {
+    ($($t:ty => $m:ty),*) => {$(
+        impl BatchMessage for $t {
+            type Msg = $m;
+
+            fn sender(&self) -> u8 { self.from_id }
+            fn msgs(&self) -> &[$m] { &self.msgs }
+            fn inner_sender(msg: &$m) -> u8 { msg.from_id }
+        }
+    )*};
+}
+
+pub(crate) use batch_message;
+
+/// Split a batch of messages into `k` lists of messages, one list for
+/// each session. A message of a wrong size or with inner messages of
+/// another sender fails with `ban(sender)`.
+pub(crate) fn split<'a, B: BatchMessage, E>(
+    msgs: &[&'a B],
+    k: usize,
+    ban: impl Fn(u8) -> E,
+) -> Result<Vec<Vec<&'a B::Msg>>, E> {
+    let mut sessions = vec![Vec::with_capacity(msgs.len()); k];
+
+    for msg in msgs {
+        let from_id = msg.sender();
+        let valid = msg.msgs().len() == k
+            && msg.msgs().iter().all(|m| B::inner_sender(m) == from_id);
+        if !valid {
+            return Err(ban(from_id));
+        }
+
+        for (session, m) in sessions.iter_mut().zip(msg.msgs()) {
+            session.push(m);
+        }
+    }
+
+    Ok(sessions)
+}
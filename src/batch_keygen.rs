@@ -0,0 +1,352 @@
+// Copyright (c) Silence Laboratories Pte. Ltd. All Rights Reserved.
+// This software is licensed under the Silence Laboratories License Agreement.
+
+//! Generation of multiple independent keys in one protocol run.
+//!
+//! Every message of a batch carries the keygen messages of all `k`
+//! keys. Only the first key runs base OT, all other keys copy pairwise
+//! OT seeds and zeta seeds from it. Signing sessions of any key derive
+//! fresh OT extensions from their own final session ID, so sharing
+//! seeds between keys of the same set of parties is safe and saves
+//! `k - 1` base OT exchanges.
+
+use std::borrow::Borrow;
+
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    batch::{batch_message, split, BatchMessage},
+    dkg::{
+        KeygenMsg1, KeygenMsg2, KeygenMsg3, KeygenMsg4, Keyshare, Party,
+        State,
+    },
+    utils::borrow_msgs,
+};
+
+pub use crate::error::KeygenError;
+
+/// Broadcast message of the first round.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BatchKeygenMsg1 {
+    pub from_id: u8,
+    msgs: Vec<KeygenMsg1>,
+}
+
+/// P2P, encrypted message of the second round.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BatchKeygenMsg2 {
+    pub from_id: u8,
+    pub to_id: u8,
+    msgs: Vec<KeygenMsg2>,
+}
+
+/// P2P, encrypted message of the third round.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BatchKeygenMsg3 {
+    pub from_id: u8,
+    pub to_id: u8,
+    msgs: Vec<KeygenMsg3>,
+}
+
+/// Broadcast message of the fourth round.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BatchKeygenMsg4 {
+    pub from_id: u8,
+    msgs: Vec<KeygenMsg4>,
+}
+
+batch_message!(
+    BatchKeygenMsg1 => KeygenMsg1,
+    BatchKeygenMsg2 => KeygenMsg2,
+    BatchKeygenMsg3 => KeygenMsg3,
+    BatchKeygenMsg4 => KeygenMsg4
+);
+
+/// Batch keygen session of a party.
+#[derive(Serialize, Deserialize)]
+pub struct BatchState {
+    party_id: u8,
+    states: Vec<State>,
+}
+
+impl BatchState {
+    /// Initialize generation of `k` new distributed keys.
+    pub fn new<R: RngCore + CryptoRng>(
+        party: Party,
+        k: usize,
+        rng: &mut R,
+    ) -> Result<Self, KeygenError> {
+        if k == 0 {
+            return Err(KeygenError::InvalidParameters);
+        }
+
+        let party_id = party.party_id;
+        let states = (0..k)
+            .map(|idx| {
+                let mut state = State::new(party.clone(), rng)?;
+                state.skip_base_ot = idx > 0;
+                Ok(state)
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { party_id, states })
+    }
+
+    /// Number of keys in the batch.
+    pub fn size(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn generate_msg1(&mut self) -> BatchKeygenMsg1 {
+        BatchKeygenMsg1 {
+            from_id: self.party_id,
+            msgs: self.states.iter_mut().map(|s| s.generate_msg1()).collect(),
+        }
+    }
+
+    /// Round 1.
+    pub fn handle_msg1<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+        msgs: impl IntoIterator<Item = impl Borrow<BatchKeygenMsg1>>,
+    ) -> Result<Vec<BatchKeygenMsg2>, KeygenError> {
+        borrow_msgs!(msgs: BatchKeygenMsg1);
+
+        let keys =
+            split(&msgs, self.size(), KeygenError::AbortProtocolAndBanParty)?;
+
+        let mut output: Vec<BatchKeygenMsg2> = vec![];
+        for (state, msgs) in self.states.iter_mut().zip(keys) {
+            for msg in state.handle_msg1(rng, msgs)? {
+                match output.iter_mut().find(|b| b.to_id == msg.to_id) {
+                    Some(batch) => batch.msgs.push(msg),
+                    None => output.push(BatchKeygenMsg2 {
+                        from_id: self.party_id,
+                        to_id: msg.to_id,
+                        msgs: vec![msg],
+                    }),
+                }
+            }
+        }
+
+        Ok(output)
+    }
+
+    /// Chain code commitments of all keys, in order of keys. Parties
+    /// exchange these lists after the first round.
+    pub fn calculate_commitment_2(&self) -> Vec<[u8; 32]> {
+        self.states
+            .iter()
+            .map(|s| s.calculate_commitment_2())
+            .collect()
+    }
+
+    /// Round 2.
+    pub fn handle_msg2<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+        msgs: impl IntoIterator<Item = impl Borrow<BatchKeygenMsg2>>,
+    ) -> Result<Vec<BatchKeygenMsg3>, KeygenError> {
+        borrow_msgs!(msgs: BatchKeygenMsg2);
+
+        let keys =
+            split(&msgs, self.size(), KeygenError::AbortProtocolAndBanParty)?;
+
+        let mut output: Vec<BatchKeygenMsg3> = vec![];
+        for (state, msgs) in self.states.iter_mut().zip(keys) {
+            for msg in state.handle_msg2(rng, msgs)? {
+                match output.iter_mut().find(|b| b.to_id == msg.to_id) {
+                    Some(batch) => batch.msgs.push(msg),
+                    None => output.push(BatchKeygenMsg3 {
+                        from_id: self.party_id,
+                        to_id: msg.to_id,
+                        msgs: vec![msg],
+                    }),
+                }
+            }
+        }
+
+        Ok(output)
+    }
+
+    /// Round 3.
+    ///
+    /// `commitment_2_list` contains results of
+    /// [`BatchState::calculate_commitment_2`] of all parties, in
+    /// order of party IDs.
+    pub fn handle_msg3<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+        msgs: impl IntoIterator<Item = impl Borrow<BatchKeygenMsg3>>,
+        commitment_2_list: &[Vec<[u8; 32]>],
+    ) -> Result<BatchKeygenMsg4, KeygenError> {
+        borrow_msgs!(msgs: BatchKeygenMsg3);
+
+        let k = self.size();
+        if commitment_2_list.iter().any(|c| c.len() != k) {
+            return Err(KeygenError::InvalidMessage);
+        }
+
+        let keys = split(&msgs, k, KeygenError::AbortProtocolAndBanParty)?;
+
+        let msgs = self
+            .states
+            .iter_mut()
+            .zip(keys)
+            .enumerate()
+            .map(|(idx, (state, msgs))| {
+                let commitments: Vec<[u8; 32]> =
+                    commitment_2_list.iter().map(|c| c[idx]).collect();
+                state.handle_msg3(rng, msgs, &commitments)
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(BatchKeygenMsg4 {
+            from_id: self.party_id,
+            msgs,
+        })
+    }
+
+    /// Round 4. Returns key shares of all keys, in order of keys.
+    pub fn handle_msg4(
+        &mut self,
+        msgs: impl IntoIterator<Item = impl Borrow<BatchKeygenMsg4>>,
+    ) -> Result<Vec<Keyshare>, KeygenError> {
+        borrow_msgs!(msgs: BatchKeygenMsg4);
+
+        let keys =
+            split(&msgs, self.size(), KeygenError::AbortProtocolAndBanParty)?;
+
+        let mut shares = self
+            .states
+            .iter_mut()
+            .zip(keys)
+            .map(|(state, msgs)| state.handle_msg4(msgs))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let (first, rest) = shares.split_first_mut().unwrap();
+        for share in rest {
+            share.seed_ot_receivers = first.seed_ot_receivers.clone();
+            share.seed_ot_senders = first.seed_ot_senders.clone();
+            share.sent_seed_list = first.sent_seed_list.clone();
+            share.rec_seed_list = first.rec_seed_list.clone();
+            share.zeta_seeds = first.zeta_seeds.clone();
+        }
+
+        Ok(shares)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use k256::elliptic_curve::group::GroupEncoding;
+
+    use super::*;
+
+    use crate::dkg::tests::check_serde;
+
+    /// Run a batch keygen, returns key shares of each party.
+    pub fn batch_dkg(n: u8, t: u8, k: usize) -> Vec<Vec<Keyshare>> {
+        let mut rng = rand::thread_rng();
+
+        let mut parties = (0..n)
+            .map(|id| {
+                let party =
+                    Party::new(n as usize, t as usize, id as usize).unwrap();
+                BatchState::new(party, k, &mut rng).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let msg1: Vec<_> =
+            parties.iter_mut().map(|p| p.generate_msg1()).collect();
+        check_serde(&msg1);
+
+        let mut msg2 = vec![];
+        for party in parties.iter_mut() {
+            let party_id = party.party_id;
+            let batch = msg1.iter().filter(|m| m.from_id != party_id);
+            msg2.extend(party.handle_msg1(&mut rng, batch).unwrap());
+        }
+        check_serde(&msg2);
+
+        let commitments: Vec<_> =
+            parties.iter().map(|p| p.calculate_commitment_2()).collect();
+
+        let mut msg3 = vec![];
+        for party in parties.iter_mut() {
+            let party_id = party.party_id;
+            let batch = msg2.iter().filter(|m| m.to_id == party_id);
+            msg3.extend(party.handle_msg2(&mut rng, batch).unwrap());
+        }
+        check_serde(&msg3);
+
+        let mut msg4 = vec![];
+        for party in parties.iter_mut() {
+            let party_id = party.party_id;
+            let batch = msg3.iter().filter(|m| m.to_id == party_id);
+            msg4.push(
+                party.handle_msg3(&mut rng, batch, &commitments).unwrap(),
+            );
+        }
+        check_serde(&msg4);
+
+        parties
+            .iter_mut()
+            .map(|party| {
+                let party_id = party.party_id;
+                let batch = msg4.iter().filter(|m| m.from_id != party_id);
+                party.handle_msg4(batch).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn batch_keygen() {
+        let shares = batch_dkg(3, 2, 4);
+
+        for party in &shares {
+            assert_eq!(party.len(), 4);
+            for (share, first) in party.iter().zip(&shares[0]) {
+                assert_eq!(share.public_key, first.public_key);
+                assert_eq!(
+                    share.zeta_seeds.remove_ids(),
+                    party[0].zeta_seeds.remove_ids()
+                );
+            }
+        }
+
+        let mut keys = shares[0]
+            .iter()
+            .map(|s| s.public_key.to_bytes())
+            .collect::<Vec<_>>();
+        keys.sort_unstable();
+        keys.dedup();
+        assert_eq!(keys.len(), 4);
+    }
+
+    #[test]
+    fn invalid_batch_size() {
+        let mut rng = rand::thread_rng();
+
+        let mut parties = (0..2)
+            .map(|id| {
+                BatchState::new(Party::new(2, 2, id).unwrap(), 2, &mut rng)
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let mut msg1 = parties[1].generate_msg1();
+        msg1.msgs.pop();
+
+        assert!(matches!(
+            parties[0].handle_msg1(&mut rng, [msg1]),
+            Err(KeygenError::AbortProtocolAndBanParty(1))
+        ));
+
+        assert!(matches!(
+            BatchState::new(Party::new(2, 2, 0).unwrap(), 0, &mut rng),
+            Err(KeygenError::InvalidParameters)
+        ));
+    }
+}
@@ -0,0 +1,426 @@
+// Copyright (c) Silence Laboratories Pte. Ltd. All Rights Reserved.
+// This software is licensed under the Silence Laboratories License Agreement.
+
+//! Generation of multiple pre-signatures in one protocol run.
+//!
+//! Every message of a batch carries the signing messages of `k`
+//! independent sessions, each with its own nonce and session ID. One
+//! run of the first three rounds fills a pool of `k` pre-signatures,
+//! every one of them could later be completed by
+//! [`dsg::create_partial_signature`] and a single broadcast of
+//! [`dsg::SignMsg4`].
+//!
+//! All sessions of a batch are created from one
+//! [`dsg::DerivedKeyshare`]: they share the key share with its pairwise
+//! OT seeds, the setup of MtA, and the derivation of the path. Only
+//! the nonces and the OT extensions derived from the final session ID
+//! of each session are independent.
+//!
+//! Like any other pre-signature, each one of the pool must be used to
+//! sign only one message. Keep the pool in a
+//! [`dsg::PresignatureStore`] to enforce this.
+//!
+//! To sign many messages at once, [`create_partial_signatures`]
+//! completes a batch of pre-signatures with a single broadcast of
+//! [`BatchSignMsg4`], and [`combine_signatures`] combines and verifies
+//! all the signatures in one pass.
+
+use std::{borrow::Borrow, sync::Arc};
+
+use derivation_path::DerivationPath;
+use k256::ecdsa::{RecoveryId, Signature};
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    batch::{batch_message, split, BatchMessage},
+    dkg::Keyshare,
+    dsg::{
+        self, AbortCheck, AbortContext, DerivedKeyshare, PartialSignature,
+        PreSignature, SignMsg1, SignMsg2, SignMsg3, SignMsg4, State,
+    },
+    utils::borrow_msgs,
+};
+
+pub use crate::error::SignError;
+
+/// Broadcast message of the first round.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BatchSignMsg1 {
+    pub from_id: u8,
+    msgs: Vec<SignMsg1>,
+}
+
+/// P2P message of the second round.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BatchSignMsg2 {
+    pub from_id: u8,
+    pub to_id: u8,
+    msgs: Vec<SignMsg2>,
+}
+
+/// P2P message of the third round.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BatchSignMsg3 {
+    pub from_id: u8,
+    pub to_id: u8,
+    msgs: Vec<SignMsg3>,
+}
+
+/// Broadcast message of the last round, completing a batch of
+/// pre-signatures.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BatchSignMsg4 {
+    pub from_id: u8,
+    msgs: Vec<SignMsg4>,
+}
+
+batch_message!(
+    BatchSignMsg1 => SignMsg1,
+    BatchSignMsg2 => SignMsg2,
+    BatchSignMsg3 => SignMsg3,
+    BatchSignMsg4 => SignMsg4
+);
+
+/// Batch pre-signature session of a party.
+#[derive(Serialize, Deserialize)]
+pub struct BatchState {
+    party_id: u8,
+    states: Vec<State>,
+}
+
+impl BatchState {
+    /// Initialize generation of `k` pre-signatures for the given
+    /// derivation path.
+    pub fn new<R: RngCore + CryptoRng>(
+        rng: &mut R,
+        keyshare: impl Into<Arc<Keyshare>>,
+        chain_path: &DerivationPath,
+        k: usize,
+    ) -> Result<Self, SignError> {
+        let derived = DerivedKeyshare::new(keyshare, chain_path)?;
+
+        Self::new_derived(rng, &derived, k)
+    }
+
+    /// Initialize generation of `k` pre-signatures using cached
+    /// derivation results of the key share.
+    pub fn new_derived<R: RngCore + CryptoRng>(
+        rng: &mut R,
+        derived: &DerivedKeyshare,
+        k: usize,
+    ) -> Result<Self, SignError> {
+        if k == 0 {
+            return Err(SignError::FailedCheck("Empty batch"));
+        }
+
+        let party_id = derived.keyshare().party_id;
+        let states =
+            (0..k).map(|_| State::new_derived(rng, derived)).collect();
+
+        Ok(Self { party_id, states })
+    }
+
+    /// Number of pre-signatures in the batch.
+    pub fn size(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn generate_msg1(&mut self) -> Result<BatchSignMsg1, SignError> {
+        Ok(BatchSignMsg1 {
+            from_id: self.party_id,
+            msgs: self
+                .states
+                .iter_mut()
+                .map(|s| s.generate_msg1())
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Round 1.
+    pub fn handle_msg1<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+        msgs: impl IntoIterator<Item = impl Borrow<BatchSignMsg1>>,
+    ) -> Result<Vec<BatchSignMsg2>, SignError> {
+        borrow_msgs!(msgs: BatchSignMsg1);
+
+        let sessions = split(&msgs, self.size(), batch_size(1))?;
+
+        let mut output: Vec<BatchSignMsg2> = vec![];
+        for (state, msgs) in self.states.iter_mut().zip(sessions) {
+            for msg in state.handle_msg1(rng, msgs)? {
+                match output.iter_mut().find(|b| b.to_id == msg.to_id) {
+                    Some(batch) => batch.msgs.push(msg),
+                    None => output.push(BatchSignMsg2 {
+                        from_id: self.party_id,
+                        to_id: msg.to_id,
+                        msgs: vec![msg],
+                    }),
+                }
+            }
+        }
+
+        Ok(output)
+    }
+
+    /// Round 2.
+    pub fn handle_msg2<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+        msgs: impl IntoIterator<Item = impl Borrow<BatchSignMsg2>>,
+    ) -> Result<Vec<BatchSignMsg3>, SignError> {
+        borrow_msgs!(msgs: BatchSignMsg2);
+
+        let sessions = split(&msgs, self.size(), batch_size(2))?;
+
+        let mut output: Vec<BatchSignMsg3> = vec![];
+        for (state, msgs) in self.states.iter_mut().zip(sessions) {
+            for msg in state.handle_msg2(rng, msgs)? {
+                match output.iter_mut().find(|b| b.to_id == msg.to_id) {
+                    Some(batch) => batch.msgs.push(msg),
+                    None => output.push(BatchSignMsg3 {
+                        from_id: self.party_id,
+                        to_id: msg.to_id,
+                        msgs: vec![msg],
+                    }),
+                }
+            }
+        }
+
+        Ok(output)
+    }
+
+    /// Round 3. Returns the pool of pre-signatures, in order of
+    /// sessions.
+    pub fn handle_msg3(
+        &mut self,
+        msgs: impl IntoIterator<Item = impl Borrow<BatchSignMsg3>>,
+    ) -> Result<Vec<PreSignature>, SignError> {
+        borrow_msgs!(msgs: BatchSignMsg3);
+
+        let sessions = split(&msgs, self.size(), batch_size(3))?;
+
+        self.states
+            .iter_mut()
+            .zip(sessions)
+            .map(|(state, msgs)| state.handle_msg3(msgs))
+            .collect()
+    }
+}
+
+/// Ban a party for a batch message of `round` with a wrong number of
+/// session messages.
+fn batch_size(round: u8) -> impl Fn(u8) -> SignError {
+    move |party_id| {
+        AbortContext::error(party_id, round, AbortCheck::BatchSize, None)
+    }
+}
+
+/// Result of combining the signature of one session of a batch.
+pub type SignatureResult = Result<(Signature, RecoveryId), SignError>;
+
+/// Create partial signatures of `hashes`, the i-th hash signed by the
+/// i-th pre-signature of `pre_batch`.
+///
+/// All other signers must use their pre-signatures of the same sessions
+/// in the same order.
+pub fn create_partial_signatures(
+    pre_batch: Vec<PreSignature>,
+    hashes: &[[u8; 32]],
+) -> Result<(Vec<PartialSignature>, BatchSignMsg4), SignError> {
+    let Some(from_id) = pre_batch.first().map(|pre| pre.from_id) else {
+        return Err(SignError::FailedCheck("Empty batch"));
+    };
+
+    if pre_batch.len() != hashes.len() {
+        return Err(SignError::FailedCheck(
+            "Number of hashes does not match the batch",
+        ));
+    }
+
+    if pre_batch.iter().any(|pre| pre.from_id != from_id) {
+        return Err(SignError::FailedCheck(
+            "Pre-signatures of several parties",
+        ));
+    }
+
+    let (partials, msgs) = pre_batch
+        .into_iter()
+        .zip(hashes)
+        .map(|(pre, hash)| dsg::create_partial_signature(pre, *hash))
+        .collect::<Result<(Vec<_>, Vec<_>), _>>()?;
+
+    Ok((partials, BatchSignMsg4 { from_id, msgs }))
+}
+
+/// Combine partial signatures created by [`create_partial_signatures`]
+/// with the batch messages of the other signers.
+///
+/// A batch message of a wrong size fails the whole batch with
+/// [`SignError::AbortProtocolAndBanParty`]. Otherwise the results are
+/// returned in order of `partials`, see
+/// [`dsg::combine_signatures_batch`].
+pub fn combine_signatures<R: RngCore + CryptoRng>(
+    rng: &mut R,
+    partials: Vec<PartialSignature>,
+    msgs: impl IntoIterator<Item = impl Borrow<BatchSignMsg4>>,
+) -> Result<Vec<SignatureResult>, SignError> {
+    borrow_msgs!(msgs: BatchSignMsg4);
+
+    let sessions = split(&msgs, partials.len(), batch_size(4))?;
+
+    let batch = partials
+        .into_iter()
+        .zip(sessions)
+        .map(|(partial, msgs)| {
+            (partial, msgs.into_iter().cloned().collect::<Vec<_>>())
+        })
+        .collect();
+
+    Ok(dsg::combine_signatures_batch(rng, batch))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    use crate::dkg::tests::dkg;
+
+    fn run_pool(
+        shares: &[Keyshare],
+        chain_path: &DerivationPath,
+        k: usize,
+    ) -> (Vec<BatchState>, Vec<Vec<PreSignature>>) {
+        let mut rng = rand::thread_rng();
+
+        let mut parties = shares
+            .iter()
+            .map(|s| {
+                BatchState::new(&mut rng, s.clone(), chain_path, k).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let msg1: Vec<_> = parties
+            .iter_mut()
+            .map(|p| p.generate_msg1().unwrap())
+            .collect();
+        let msg2 = parties.iter_mut().fold(vec![], |mut out, p| {
+            let party_id = p.party_id;
+            let batch = msg1.iter().filter(|m| m.from_id != party_id);
+            out.extend(p.handle_msg1(&mut rng, batch).unwrap());
+            out
+        });
+        let msg3 = parties.iter_mut().fold(vec![], |mut out, p| {
+            let party_id = p.party_id;
+            let batch = msg2.iter().filter(|m| m.to_id == party_id);
+            out.extend(p.handle_msg2(&mut rng, batch).unwrap());
+            out
+        });
+        let pools = parties
+            .iter_mut()
+            .map(|p| {
+                let party_id = p.party_id;
+                let batch = msg3.iter().filter(|m| m.to_id == party_id);
+                p.handle_msg3(batch).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        (parties, pools)
+    }
+
+    #[test]
+    fn presignature_pool() {
+        let mut rng = rand::thread_rng();
+        let shares = dkg(3, 2);
+        let chain_path = DerivationPath::from_str("m/1").unwrap();
+
+        let (mut parties, mut pools) = run_pool(&shares[1..], &chain_path, 3);
+
+        assert!(pools.iter().all(|pool| pool.len() == 3));
+
+        // all sessions of a batch share one key share
+        let states = &parties[0].states;
+        assert!(states
+            .iter()
+            .all(|s| Arc::ptr_eq(&s.keyshare, &states[0].keyshare)));
+        assert_ne!(pools[0][0].public_nonce(), pools[0][1].public_nonce());
+
+        // each pre-signature is completed by a single broadcast
+        for hash in [[1; 32], [2; 32], [3; 32]] {
+            let (partials, msg4): (Vec<_>, Vec<_>) = pools
+                .iter_mut()
+                .map(|pool| {
+                    let pre = pool.remove(0);
+                    dsg::create_partial_signature(pre, hash).unwrap()
+                })
+                .unzip();
+
+            for p in partials {
+                let party_id = p.party_id;
+                let batch = msg4.iter().filter(|m| m.from_id != party_id);
+                dsg::combine_signatures(p, batch).unwrap();
+            }
+        }
+
+        let mut short = parties[0].generate_msg1().unwrap();
+        short.msgs.pop();
+        assert!(matches!(
+            parties[1].handle_msg1(&mut rng, [short]),
+            Err(SignError::AbortProtocolAndBanParty(ctx))
+                if ctx.party_id == 1 && ctx.check == AbortCheck::BatchSize
+        ));
+    }
+
+    #[test]
+    fn sign_many_messages() {
+        let mut rng = rand::thread_rng();
+        let shares = dkg(3, 2);
+        let chain_path = DerivationPath::from_str("m/2").unwrap();
+
+        let (_, pools) = run_pool(&shares[..2], &chain_path, 4);
+        let hashes: Vec<[u8; 32]> = (1..=4).map(|i| [i; 32]).collect();
+
+        let (partials, msg4): (Vec<_>, Vec<_>) = pools
+            .into_iter()
+            .map(|pool| create_partial_signatures(pool, &hashes).unwrap())
+            .unzip();
+
+        for p in partials {
+            let party_id = p[0].party_id;
+            let public_key = p[0].public_key;
+            let batch = msg4.iter().filter(|m| m.from_id != party_id);
+            let signatures = combine_signatures(&mut rng, p, batch).unwrap();
+
+            assert_eq!(signatures.len(), hashes.len());
+            for (res, hash) in signatures.into_iter().zip(&hashes) {
+                let (sign, recid) = res.unwrap();
+                let key = k256::ecdsa::VerifyingKey::recover_from_prehash(
+                    hash, &sign, recid,
+                )
+                .unwrap();
+                assert_eq!(key.as_affine(), &public_key);
+            }
+        }
+
+        let (_, mut pools) = run_pool(&shares[1..], &chain_path, 2);
+        assert!(matches!(
+            create_partial_signatures(pools.remove(0), &hashes),
+            Err(SignError::FailedCheck(_))
+        ));
+
+        let (partials, _) =
+            create_partial_signatures(pools.remove(0), &hashes[..2]).unwrap();
+        let short = BatchSignMsg4 {
+            from_id: 1,
+            msgs: vec![],
+        };
+        assert!(matches!(
+            combine_signatures(&mut rng, partials, [short]),
+            Err(SignError::AbortProtocolAndBanParty(ctx))
+                if ctx.party_id == 1 && ctx.check == AbortCheck::BatchSize
+        ));
+    }
+}
@@ -22,6 +22,27 @@ pub const DLOG_PROOF2_LABEL: Label = Label::new(VERSION, 104);
 /// LABEL to create dlog sessionID from final_session_id and root_chain_code
 pub const DLOG_SESSION_ID_WITH_CHAIN_CODE: Label = Label::new(VERSION, 105);
 
+/// LABEL for the digest of broadcast values echoed in the last round
+pub const BROADCAST_DIGEST_LABEL: Label = Label::new(VERSION, 106);
+
+/// LABEL for the final session ID of the keygen protocol
+pub const DKG_FINAL_SESSION_ID_LABEL: Label = Label::new(VERSION, 107);
+
+/// LABEL for the root chain code
+pub const ROOT_CHAIN_CODE_LABEL: Label = Label::new(VERSION, 108);
+
+/// LABEL for the checksum of a suspended keygen state
+pub const STATE_CHECKSUM_LABEL: Label = Label::new(VERSION, 109);
+
+/// LABEL for the round seeds of the deterministic keygen mode
+pub const DETERMINISTIC_RNG_LABEL: Label = Label::new(VERSION, 110);
+
+/// LABEL for the fingerprint of a distributed key
+pub const KEY_FINGERPRINT_LABEL: Label = Label::new(VERSION, 111);
+
+/// LABEL for the message signed by the identity key of a party
+pub const IDENTITY_SIGNATURE_LABEL: Label = Label::new(VERSION, 112);
+
 /// LABEL for the signature protocol
 pub const DSG_LABEL: Label = Label::new(VERSION, 200);
 
@@ -36,3 +57,42 @@ pub const PAIRWISE_MTA_LABEL: Label = Label::new(VERSION, 203);
 
 /// LABEL for Pairwise Randomization
 pub const PAIRWISE_RANDOMIZATION_LABEL: Label = Label::new(VERSION, 204);
+
+/// LABEL for the final session ID of the signature protocol
+pub const DSG_FINAL_SESSION_ID_LABEL: Label = Label::new(VERSION, 205);
+
+/// LABEL for the checksum of a suspended signing state
+pub const DSG_STATE_CHECKSUM_LABEL: Label = Label::new(VERSION, 206);
+
+/// LABEL for the seed refresh protocol
+pub const SEED_REFRESH_LABEL: Label = Label::new(VERSION, 300);
+
+/// LABEL for the final session ID of the seed refresh protocol
+pub const SEED_REFRESH_FINAL_SESSION_ID_LABEL: Label =
+    Label::new(VERSION, 301);
+
+/// LABEL for the standalone base OT setup protocol
+pub const OT_SETUP_LABEL: Label = Label::new(VERSION, 600);
+
+/// LABEL for the final session ID of the base OT setup protocol
+pub const OT_SETUP_FINAL_SESSION_ID_LABEL: Label = Label::new(VERSION, 601);
+
+/// LABEL for the threshold Schnorr signing protocol
+pub const TSG_LABEL: Label = Label::new(VERSION, 700);
+
+/// LABEL for the final session ID of the threshold Schnorr signing
+pub const TSG_FINAL_SESSION_ID_LABEL: Label = Label::new(VERSION, 701);
+
+/// LABEL for the nonce binding factors of the threshold Schnorr signing
+pub const TSG_BINDING_FACTOR_LABEL: Label = Label::new(VERSION, 702);
+
+/// LABEL for mixing of external entropy
+pub const ENTROPY_MIX_LABEL: Label = Label::new(VERSION, 500);
+
+/// LABEL for the key derivation of the P2P transport encryption
+#[cfg(feature = "transport")]
+pub const TRANSPORT_KEY_LABEL: Label = Label::new(VERSION, 400);
+
+/// LABEL for the transport encryption keys of signing messages
+#[cfg(feature = "transport")]
+pub const TRANSPORT_SIGN_LABEL: Label = Label::new(VERSION, 401);
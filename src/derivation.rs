@@ -0,0 +1,249 @@
+// Copyright (c) Silence Laboratories Pte. Ltd. All Rights Reserved.
+// This software is licensed under the Silence Laboratories License Agreement.
+
+//! Derivation of BIP32 child public keys from a key share, without
+//! running a signing session.
+//!
+//! Only non-hardened derivation is supported, like for signing. To get
+//! many addresses of the same account, derive the extended public key
+//! of the account once by [`Keyshare::xpub`] and then each address by
+//! [`ExtendedPublicKey::child`].
+
+use std::fmt;
+
+use derivation_path::{ChildIndex, DerivationPath};
+use k256::{
+    elliptic_curve::group::{prime::PrimeCurveAffine, GroupEncoding},
+    AffinePoint,
+};
+use ripemd::Ripemd160;
+use sha2::{Digest, Sha256};
+
+use sl_mpc_mate::bip32::derive_child_pubkey;
+
+use crate::{dkg::Keyshare, dsg::derive_with_offset, error::SignError};
+
+/// Version bytes of a mainnet `xpub`.
+const XPUB_VERSION: [u8; 4] = [0x04, 0x88, 0xb2, 0x1e];
+
+/// BIP32 extended public key.
+///
+/// Formats as a Base58Check encoded mainnet `xpub`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExtendedPublicKey {
+    /// Number of derivation steps from the root key.
+    pub depth: u8,
+    /// First 4 bytes of HASH160 of the parent public key, zeros for the
+    /// root key.
+    pub parent_fingerprint: [u8; 4],
+    /// Index of the last derivation step, 0 for the root key.
+    pub child_number: u32,
+    pub chain_code: [u8; 32],
+    pub public_key: AffinePoint,
+}
+
+impl ExtendedPublicKey {
+    /// Extended public key of the root of a key share. Fails for a
+    /// key share generated without a chain code.
+    pub fn root(keyshare: &Keyshare) -> Result<Self, SignError> {
+        let chain_code = keyshare
+            .root_chain_code
+            .ok_or(SignError::MissingChainCode)?;
+
+        Ok(Self {
+            depth: 0,
+            parent_fingerprint: [0; 4],
+            child_number: 0,
+            chain_code,
+            public_key: keyshare.public_key,
+        })
+    }
+
+    /// Key identifier fingerprint, first 4 bytes of HASH160 of the
+    /// compressed public key.
+    pub fn fingerprint(&self) -> [u8; 4] {
+        let hash =
+            Ripemd160::digest(Sha256::digest(self.public_key.to_bytes()));
+        let mut fingerprint = [0; 4];
+        fingerprint.copy_from_slice(&hash[..4]);
+        fingerprint
+    }
+
+    /// Derive a non-hardened child.
+    pub fn child(&self, index: u32) -> Result<Self, SignError> {
+        let (_, public_key, chain_code) = derive_child_pubkey(
+            &self.public_key.to_curve(),
+            self.chain_code,
+            &ChildIndex::from_bits(index),
+        )?;
+
+        Ok(Self {
+            depth: self.depth.wrapping_add(1),
+            parent_fingerprint: self.fingerprint(),
+            child_number: index,
+            chain_code,
+            public_key: public_key.to_affine(),
+        })
+    }
+
+    /// Derive a descendant by a path relative to this key.
+    pub fn derive(
+        &self,
+        chain_path: &DerivationPath,
+    ) -> Result<Self, SignError> {
+        chain_path
+            .path()
+            .iter()
+            .try_fold(*self, |xpub, child| xpub.child(child.to_bits()))
+    }
+
+    /// BIP32 serialization with the mainnet `xpub` version.
+    pub fn to_bytes(&self) -> [u8; 78] {
+        let mut bytes = [0; 78];
+        bytes[..4].copy_from_slice(&XPUB_VERSION);
+        bytes[4] = self.depth;
+        bytes[5..9].copy_from_slice(&self.parent_fingerprint);
+        bytes[9..13].copy_from_slice(&self.child_number.to_be_bytes());
+        bytes[13..45].copy_from_slice(&self.chain_code);
+        bytes[45..].copy_from_slice(&self.public_key.to_bytes());
+        bytes
+    }
+}
+
+impl fmt::Display for ExtendedPublicKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let encoded =
+            bs58::encode(self.to_bytes()).with_check().into_string();
+        f.write_str(&encoded)
+    }
+}
+
+impl Keyshare {
+    /// Public key of the derivation path.
+    pub fn derived_public_key(
+        &self,
+        chain_path: &DerivationPath,
+    ) -> Result<AffinePoint, SignError> {
+        let (_, public_key) = derive_with_offset(
+            &self.public_key.to_curve(),
+            self.root_chain_code.as_ref(),
+            chain_path,
+        )?;
+
+        Ok(public_key.to_affine())
+    }
+
+    /// Public keys of several derivation paths, in order of the paths.
+    pub fn derived_public_keys<'a>(
+        &self,
+        chain_paths: impl IntoIterator<Item = &'a DerivationPath>,
+    ) -> Result<Vec<AffinePoint>, SignError> {
+        chain_paths
+            .into_iter()
+            .map(|path| self.derived_public_key(path))
+            .collect()
+    }
+
+    /// Extended public key of the derivation path.
+    pub fn xpub(
+        &self,
+        chain_path: &DerivationPath,
+    ) -> Result<ExtendedPublicKey, SignError> {
+        ExtendedPublicKey::root(self)?.derive(chain_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use k256::{elliptic_curve::sec1::FromEncodedPoint, EncodedPoint};
+
+    use super::*;
+
+    use crate::dkg::tests::dkg;
+
+    fn point(hex: &str) -> AffinePoint {
+        let bytes = hex::decode(hex).unwrap();
+        let point = EncodedPoint::from_bytes(bytes).unwrap();
+        AffinePoint::from_encoded_point(&point).unwrap()
+    }
+
+    fn chain_code(hex: &str) -> [u8; 32] {
+        hex::decode(hex).unwrap().try_into().unwrap()
+    }
+
+    #[test]
+    fn bip32_test_vector_1() {
+        let mut keyshare = dkg(2, 2).remove(0);
+        keyshare.public_key = point(
+            "0339a36013301597daef41fbe593a02cc513d0b55527ec2df1050e2e8ff49c85c2",
+        );
+        keyshare.root_chain_code = Some(chain_code(
+            "873dff81c02f525623fd1fe5167eac3a55a049de3d314bb42ee227ffed37d508",
+        ));
+
+        let root = keyshare.xpub(&DerivationPath::from_str("m").unwrap());
+        assert_eq!(
+            root.unwrap().to_string(),
+            "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8"
+        );
+
+        // chain m/0H/1, continued from the public key of m/0H
+        let m_0h = ExtendedPublicKey {
+            depth: 1,
+            parent_fingerprint: [0x34, 0x42, 0x19, 0x3e],
+            child_number: 0x8000_0000,
+            chain_code: chain_code(
+                "47fdacbd0f1097043b78c63c20c34ef4ed9a111d980047ad16282c7ae6236141",
+            ),
+            public_key: point(
+                "035a784662a4a20a65bf6aab9ae98a6c068a81c52e4b032c0fb5400c706cfccc56",
+            ),
+        };
+        assert_eq!(
+            m_0h.to_string(),
+            "xpub68Gmy5EdvgibQVfPdqkBBCHxA5htiqg55crXYuXoQRKfDBFA1WEjWgP6LHhwBZeNK1VTsfTFUHCdrfp1bgwQ9xv5ski8PX9rL2dZXvgGDnw"
+        );
+        assert_eq!(
+            m_0h.child(1).unwrap().to_string(),
+            "xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ"
+        );
+
+        assert!(matches!(
+            m_0h.child(0x8000_0001),
+            Err(SignError::BIP32Error(_))
+        ));
+    }
+
+    #[test]
+    fn derive_without_signing_session() {
+        let shares = dkg(3, 2);
+
+        let account = DerivationPath::from_str("m/0/5").unwrap();
+        let paths = (0..4)
+            .map(|i| DerivationPath::from_str(&format!("m/0/5/{i}")).unwrap())
+            .collect::<Vec<_>>();
+
+        let keys = shares[1].derived_public_keys(&paths).unwrap();
+        let xpub = shares[2].xpub(&account).unwrap();
+        assert_eq!(xpub.depth, 2);
+        assert_eq!(
+            xpub.public_key,
+            shares[0].derived_public_key(&account).unwrap()
+        );
+
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(&xpub.child(i as u32).unwrap().public_key, key);
+        }
+
+        // the same key as of a signing session of the path
+        let session = crate::dsg::State::new(
+            &mut rand::thread_rng(),
+            shares[0].clone(),
+            &paths[3],
+        )
+        .unwrap();
+        assert_eq!(session.derived_public_key, keys[3]);
+    }
+}
@@ -7,39 +7,51 @@
 //! Proper validation of each input at each round is needed when deployed in a real world.
 #![allow(missing_docs)]
 
-use std::collections::HashSet;
+use std::{
+    borrow::Borrow, cmp::Ordering, collections::HashSet, iter, mem::size_of,
+    sync::Arc,
+};
 
 use k256::{
+    ecdsa::{
+        signature::{Signer, Verifier},
+        Signature, SigningKey, VerifyingKey,
+    },
     elliptic_curve::{
-        group::prime::PrimeCurveAffine, subtle::ConstantTimeEq, Group,
+        group::{prime::PrimeCurveAffine, GroupEncoding},
+        point::AffineCoordinates,
+        subtle::ConstantTimeEq,
+        Group,
     },
     AffinePoint, FieldBytes, NonZeroScalar, ProjectivePoint, Scalar,
     Secp256k1,
 };
 use merlin::Transcript;
 use rand::prelude::*;
-use serde::{Deserialize, Serialize};
+use rand_chacha::ChaCha20Rng;
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize};
 use sha2::{Digest, Sha256};
 
 use sl_mpc_mate::math::{
-    feldman_verify, polynomial_coeff_multipliers, GroupPolynomial, Polynomial,
+    birkhoff_coeffs, feldman_verify, polynomial_coeff_multipliers,
+    GroupPolynomial, Polynomial,
 };
 
 use sl_oblivious::{
     endemic_ot::EndemicOTMsg2,
-    endemic_ot::{EndemicOTMsg1, EndemicOTReceiver, EndemicOTSender},
-    soft_spoken::{build_pprf, eval_pprf},
+    endemic_ot::{EndemicOTMsg1, EndemicOTReceiver},
     soft_spoken::{PPRFOutput, ReceiverOTSeed, SenderOTSeed},
     utils::TranscriptProtocol,
     zkproofs::DLogProof,
 };
 use zeroize::{Zeroize, ZeroizeOnDrop};
 
-use crate::{constants::*, pairs::*, utils::*};
+use crate::{base_ot, constants::*, ot_setup::OtSeeds, pairs::*, utils::*};
 
-pub use crate::error::KeygenError;
+pub use crate::error::{KeygenError, KeyshareEncodingError};
 
 /// Description of a party
+#[derive(Clone)]
 pub struct Party {
     pub ranks: Vec<u8>, // ranks of parties
     pub t: u8,
@@ -61,8 +73,15 @@ pub struct KeyRefreshData {
     /// expected public key for key_refresh
     expected_public_key: AffinePoint,
 
-    /// root_chain_code
-    root_chain_code: [u8; 32],
+    /// root_chain_code, `None` if the party has to learn it from
+    /// other parties
+    root_chain_code: Option<[u8; 32]>,
+
+    /// Public pieces of an imported key, see [`split_key_for_import`].
+    /// Constant terms of polynomials of all parties must match them.
+    #[serde(default)]
+    #[zeroize(skip)]
+    import_commitments: Vec<AffinePoint>,
 }
 
 #[derive(Zeroize, ZeroizeOnDrop)]
@@ -76,8 +95,9 @@ pub struct RefreshShare {
     pub party_id: u8,
     /// Public key.
     pub public_key: AffinePoint,
-    /// Root chain code (used to derive child public keys)
-    pub root_chain_code: [u8; 32],
+    /// Root chain code (used to derive child public keys), `None` for
+    /// a key without a chain code or if party_i lost their key_share
+    pub root_chain_code: Option<[u8; 32]>,
     /// Private key additive share
     /// set s_i to None if party_i lost their key_share
     pub s_i: Option<Scalar>,
@@ -119,7 +139,7 @@ impl RefreshShare {
             threshold: party.t,
             party_id: party.party_id,
             public_key,
-            root_chain_code: [0u8; 32],
+            root_chain_code: None,
             s_i: None,
             x_i_list: None,
             lost_keyshare_party_ids,
@@ -131,19 +151,29 @@ impl RefreshShare {
 #[derive(Clone, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
 pub struct KeygenMsg1 {
     pub from_id: u8,
+    pub version: u8,
     session_id: [u8; 32],
     commitment: [u8; 32],
     x_i: NonZeroScalar,
 }
 
+impl KeygenMsg1 {
+    /// Upper bound of the encoded size of the message, see
+    /// [`KeygenMsg2::max_encoded_size`].
+    pub fn max_encoded_size() -> usize {
+        max_encoded_size([1, 1, 32, 32, SCALAR_SIZE])
+    }
+}
+
 /// P2P, encrypted message.
 #[derive(Clone, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
 pub struct KeygenMsg2 {
     pub from_id: u8,
     pub to_id: u8,
+    pub version: u8,
 
-    // P2P part
-    ot: ZS<EndemicOTMsg1>,
+    // P2P part, omitted by all but the first key of a batch
+    ot: Option<ZS<EndemicOTMsg1>>,
 
     // broadcast part, does not contain secret material
     #[zeroize(skip)]
@@ -152,6 +182,153 @@ pub struct KeygenMsg2 {
     r_i: [u8; 32],
     #[zeroize(skip)]
     dlog_proofs: Vec<DLogProof>,
+    #[zeroize(skip)]
+    identity_signature: Option<Signature>,
+}
+
+/// Broadcast part of [`KeygenMsg2`], the same for all recipients.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct KeygenMsg2Broadcast {
+    pub from_id: u8,
+    pub version: u8,
+
+    big_f_i_vec: GroupPolynomial<Secp256k1>,
+    r_i: [u8; 32],
+    dlog_proofs: Vec<DLogProof>,
+    identity_signature: Option<Signature>,
+}
+
+/// P2P part of [`KeygenMsg2`], encrypted.
+#[derive(Clone, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+pub struct KeygenMsg2P2P {
+    pub from_id: u8,
+    pub to_id: u8,
+    pub version: u8,
+
+    ot: Option<ZS<EndemicOTMsg1>>,
+}
+
+impl KeygenMsg2 {
+    /// Upper bound of the encoded size of the message of a key with
+    /// threshold `t`. Messages padded to this size reveal the round
+    /// and `t` only.
+    pub fn max_encoded_size(t: u8) -> usize {
+        let t = t as usize;
+        let proof = [0, POINT_SIZE, SCALAR_SIZE];
+
+        max_encoded_size(
+            [1, 1, 1, size_of::<EndemicOTMsg1>(), 0, 32, 0, 64]
+                .into_iter()
+                .chain(iter::repeat_n(POINT_SIZE, t))
+                .chain(iter::repeat_n(proof, t).flatten()),
+        )
+    }
+
+    /// Split the message into its broadcast and P2P parts.
+    pub fn split(&self) -> (KeygenMsg2Broadcast, KeygenMsg2P2P) {
+        let broadcast = KeygenMsg2Broadcast {
+            from_id: self.from_id,
+            version: self.version,
+            big_f_i_vec: self.big_f_i_vec.clone(),
+            r_i: self.r_i,
+            dlog_proofs: self.dlog_proofs.clone(),
+            identity_signature: self.identity_signature,
+        };
+
+        let p2p = KeygenMsg2P2P {
+            from_id: self.from_id,
+            to_id: self.to_id,
+            version: self.version,
+            ot: self.ot.clone(),
+        };
+
+        (broadcast, p2p)
+    }
+
+    /// Join broadcast and P2P parts of a message of the same party.
+    pub fn from_parts(
+        broadcast: &KeygenMsg2Broadcast,
+        p2p: &KeygenMsg2P2P,
+    ) -> Result<Self, KeygenError> {
+        if broadcast.from_id != p2p.from_id
+            || broadcast.version != p2p.version
+        {
+            return Err(KeygenError::AbortProtocolAndBanParty(p2p.from_id));
+        }
+
+        Ok(KeygenMsg2 {
+            from_id: p2p.from_id,
+            to_id: p2p.to_id,
+            version: p2p.version,
+            ot: p2p.ot.clone(),
+            big_f_i_vec: broadcast.big_f_i_vec.clone(),
+            r_i: broadcast.r_i,
+            dlog_proofs: broadcast.dlog_proofs.clone(),
+            identity_signature: broadcast.identity_signature,
+        })
+    }
+}
+
+/// Failed check reported by an abort message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AbortReason {
+    /// Commitment of the first round does not match values of the
+    /// second round.
+    InvalidCommitmentHash,
+
+    /// Invalid DLog proof of polynomial coefficients of the second
+    /// round.
+    InvalidDLogProof,
+
+    /// Any other error, could not be validated by other parties.
+    Other,
+}
+
+impl From<&KeygenError> for AbortReason {
+    fn from(err: &KeygenError) -> Self {
+        match err {
+            KeygenError::InvalidCommitmentHash(_) => {
+                Self::InvalidCommitmentHash
+            }
+            KeygenError::InvalidDLogProof(_) => Self::InvalidDLogProof,
+            _ => Self::Other,
+        }
+    }
+}
+
+/// Broadcast message of a party that stopped the keygen, carrying
+/// the failed check and the accused party.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct KeygenAbort {
+    pub from_id: u8,
+    pub accused: u8,
+    pub reason: AbortReason,
+}
+
+/// Result of validation of a [`KeygenAbort`] message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AbortVerdict {
+    /// Own view of the accused party confirms the accusation.
+    Confirmed,
+
+    /// Own view of the accused party passes the check.
+    Rejected,
+
+    /// The check could not be repeated by this party.
+    Unverifiable,
+}
+
+/// Secret piece of an imported private key, sent by the key holder
+/// to one party over a private channel.
+#[derive(Clone, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+pub struct KeyImportPiece {
+    pub to_id: u8,
+
+    /// Public pieces of all parties
+    #[zeroize(skip)]
+    big_x_list: Vec<AffinePoint>,
+    root_chain_code: [u8; 32],
+    x_i: Scalar,
 }
 
 /// Third DKG message
@@ -159,6 +336,7 @@ pub struct KeygenMsg2 {
 pub struct KeygenMsg3 {
     pub from_id: u8,
     pub to_id: u8,
+    pub version: u8,
 
     /// Participants Fi values
     /// in original protocol, this field is part
@@ -170,10 +348,10 @@ pub struct KeygenMsg3 {
     d_i: Scalar,
 
     /// base OT msg 2
-    base_ot_msg2: ZS<EndemicOTMsg2>,
+    base_ot_msg2: Option<ZS<EndemicOTMsg2>>,
 
     /// pprf outputs
-    pprf_output: ZS<PPRFOutput>,
+    pprf_output: Option<ZS<PPRFOutput>>,
 
     /// seed_i_j values
     seed_i_j: Option<[u8; 32]>,
@@ -189,10 +367,57 @@ pub struct KeygenMsg3 {
 #[derive(Clone, Serialize, Deserialize)]
 pub struct KeygenMsg4 {
     pub from_id: u8,
+    pub version: u8,
 
     public_key: AffinePoint,
     big_s_i: AffinePoint,
     proof: DLogProof,
+
+    /// Digest of all broadcast values received by the sender:
+    /// commitments, x_i values, big_f_vec and commitment_2 list.
+    broadcast_digest: [u8; 32],
+}
+
+impl KeygenMsg3 {
+    /// Upper bound of the encoded size of the message of a key with
+    /// threshold `t`, see [`KeygenMsg2::max_encoded_size`].
+    pub fn max_encoded_size(t: u8) -> usize {
+        let fields = [
+            1,
+            1,
+            1,
+            0,
+            SCALAR_SIZE,
+            size_of::<EndemicOTMsg2>(),
+            size_of::<PPRFOutput>(),
+            32,
+            32,
+            32,
+        ];
+
+        max_encoded_size(
+            fields
+                .into_iter()
+                .chain(iter::repeat_n(POINT_SIZE, t as usize)),
+        )
+    }
+}
+
+impl KeygenMsg4 {
+    /// Upper bound of the encoded size of the message, see
+    /// [`KeygenMsg2::max_encoded_size`].
+    pub fn max_encoded_size() -> usize {
+        max_encoded_size([
+            1,
+            1,
+            POINT_SIZE,
+            POINT_SIZE,
+            0,
+            POINT_SIZE,
+            SCALAR_SIZE,
+            32,
+        ])
+    }
 }
 
 /// Keyshare of a party.
@@ -209,17 +434,326 @@ pub struct Keyshare {
     pub party_id: u8,
     /// Public key of the generated key.
     pub public_key: AffinePoint,
-    /// Root chain code (used to derive child public keys)
-    pub root_chain_code: [u8; 32],
+    /// Root chain code (used to derive child public keys), `None` for
+    /// keys generated by [`State::without_chain_code`]
+    pub root_chain_code: Option<[u8; 32]>,
 
     pub(crate) final_session_id: [u8; 32],
     pub(crate) seed_ot_receivers: Vec<ZS<ReceiverOTSeed>>,
     pub(crate) seed_ot_senders: Vec<ZS<SenderOTSeed>>,
+    /// Zeta seeds of key shares created before `zeta_seeds`, by
+    /// position of the counterparty. Empty otherwise.
     pub(crate) sent_seed_list: Vec<[u8; 32]>,
     pub(crate) rec_seed_list: Vec<[u8; 32]>,
     pub(crate) s_i: Scalar,
     pub(crate) big_s_list: Vec<AffinePoint>,
     pub(crate) x_i_list: Vec<NonZeroScalar>,
+    /// Number of seed refreshes of this party with each party, by
+    /// party ID. Empty for key shares created before refreshes were
+    /// counted.
+    #[serde(default)]
+    pub(crate) seed_refresh_counters: Vec<u32>,
+    /// Pairwise zeta seeds by counterparty ID: sent to parties with a
+    /// greater ID, received from parties with a lower ID. Empty for
+    /// key shares created before the seeds were keyed by party ID.
+    #[serde(default)]
+    pub(crate) zeta_seeds: Pairs<[u8; 32]>,
+}
+
+/// Version of the compact key share encoding, see
+/// [`Keyshare::to_compact_bytes`].
+pub const KEYSHARE_COMPACT_VERSION: u8 = 3;
+
+/// Version of keygen messages. Handlers reject messages of other
+/// versions with [`KeygenError::VersionMismatch`].
+pub const KEYGEN_MSG_VERSION: u8 = 1;
+
+/// Mode flag of [`State::without_chain_code`].
+const KEYGEN_MODE_WITHOUT_CHAIN_CODE: u8 = 1;
+
+/// Mode flag of [`State::with_even_y`].
+const KEYGEN_MODE_EVEN_Y: u8 = 2;
+
+/// Version of the [`SuspendedState`] envelope.
+pub const STATE_SUSPEND_VERSION: u8 = 1;
+
+/// Round of a keygen state: the next expected batch of messages.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+pub enum KeygenRound {
+    #[default]
+    WaitMsg1,
+    WaitMsg2,
+    WaitMsg3,
+    WaitMsg4,
+    Finished,
+    /// A round handler failed on an accepted batch of messages, the
+    /// keygen can not continue.
+    Aborted,
+}
+
+/// Progress event of a keygen reported to a [`KeygenObserver`].
+#[derive(Debug)]
+pub enum KeygenEvent<'a> {
+    /// The work for the message of party `from_id` in round `round`
+    /// is done, the same as the progress of
+    /// [`State::handle_msg2_with_progress`]. The round could still
+    /// fail.
+    Progress { round: KeygenRound, from_id: u8 },
+
+    /// Message of party `from_id` handled in round `round` passed
+    /// all checks. Reported after the round handler succeeded.
+    MessageVerified { round: KeygenRound, from_id: u8 },
+
+    /// Round handler finished, the state is now in the given round.
+    RoundCompleted(KeygenRound),
+
+    /// Round handler failed.
+    Failed(&'a KeygenError),
+}
+
+/// Observer of keygen progress, see [`State::with_observer`].
+pub type KeygenObserver = Arc<dyn Fn(KeygenEvent<'_>) + Send + Sync>;
+
+fn emit(observer: &Option<KeygenObserver>, event: KeygenEvent) {
+    if let Some(on_event) = observer {
+        on_event(event);
+    }
+}
+
+/// Persistence envelope of a [`State`] between rounds, see
+/// [`State::suspend`] and [`State::resume`].
+///
+/// The envelope carries an unkeyed checksum of the parameters, session
+/// IDs and main secrets of the state. It detects accidental corruption
+/// and a state resumed in another round, not deliberate modification:
+/// store the envelope encrypted and authenticated.
+#[derive(Serialize, Deserialize)]
+pub struct SuspendedState {
+    version: u8,
+    round: KeygenRound,
+    checksum: [u8; 32],
+    state: State,
+}
+
+impl SuspendedState {
+    /// Round of the suspended state.
+    pub fn round(&self) -> KeygenRound {
+        self.round
+    }
+}
+
+/// Version of the [`KeysharePublic`] export schema.
+pub const KEYSHARE_PUBLIC_VERSION: u32 = 2;
+
+/// Non-secret part of a key share with a stable JSON representation.
+///
+/// Points are hex encoded 33 byte SEC1 compressed points, scalars are
+/// hex encoded 32 byte big-endian integers. Schema version 2:
+///
+/// ```json
+/// {
+///   "version": 2,
+///   "total_parties": 3,
+///   "threshold": 2,
+///   "rank_list": [0, 0, 0],
+///   "party_id": 0,
+///   "public_key": "02...",
+///   "root_chain_code": "...", // or null
+///   "big_s_list": ["02...", "03...", "02..."],
+///   "x_i_list": ["...", "...", "..."]
+/// }
+/// ```
+///
+/// Field names are fixed, new fields may be added only together with
+/// a new version number. Version 1 has no null `root_chain_code`.
+/// Decoding fails for an unknown version.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct KeysharePublic {
+    #[serde(deserialize_with = "keyshare_public_version")]
+    pub version: u32,
+    pub total_parties: u8,
+    pub threshold: u8,
+    pub rank_list: Vec<u8>,
+    pub party_id: u8,
+    #[serde(with = "hex_serde::point")]
+    pub public_key: AffinePoint,
+    #[serde(with = "hex_serde::opt_bytes32")]
+    pub root_chain_code: Option<[u8; 32]>,
+    #[serde(with = "hex_serde::points")]
+    pub big_s_list: Vec<AffinePoint>,
+    #[serde(with = "hex_serde::scalars")]
+    pub x_i_list: Vec<NonZeroScalar>,
+}
+
+fn keyshare_public_version<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<u32, D::Error> {
+    let version = u32::deserialize(deserializer)?;
+    if version == 0 || version > KEYSHARE_PUBLIC_VERSION {
+        return Err(D::Error::custom(format!(
+            "unsupported version {version}"
+        )));
+    }
+
+    Ok(version)
+}
+
+/// Key zeta seeds stored by position of the counterparty by party ID:
+/// `sent` lists seeds of parties with a greater ID, `rec` of parties
+/// with a lower ID, both in order of IDs.
+pub(crate) fn zeta_seeds_by_party(
+    party_id: u8,
+    sent: &[[u8; 32]],
+    rec: &[[u8; 32]],
+) -> Pairs<[u8; 32]> {
+    rec.iter()
+        .zip(0..)
+        .chain(sent.iter().zip(party_id + 1..))
+        .fold(Pairs::new(), |seeds, (seed, id)| seeds.add(id, *seed))
+}
+
+impl Keyshare {
+    /// Zeta seed shared with the party, `None` for own party ID or an
+    /// unknown party.
+    pub(crate) fn zeta_seed(&self, party_id: u8) -> Option<&[u8; 32]> {
+        if self.zeta_seeds.len() != 0 {
+            return self.zeta_seeds.find_pair_or_err(party_id, ()).ok();
+        }
+
+        // key share created before the seeds were keyed by party ID
+        match party_id.cmp(&self.party_id) {
+            Ordering::Less => self.rec_seed_list.get(party_id as usize),
+            Ordering::Equal => None,
+            Ordering::Greater => self
+                .sent_seed_list
+                .get((party_id - self.party_id - 1) as usize),
+        }
+    }
+
+    /// Move the zeta seeds of a key share created before the seeds
+    /// were keyed by party ID. Does nothing for other key shares.
+    ///
+    /// Key shares work without migration, this only settles the
+    /// encoding of a stored key share.
+    pub fn migrate_zeta_seeds(&mut self) {
+        if self.zeta_seeds.len() != 0 {
+            return;
+        }
+
+        self.zeta_seeds = zeta_seeds_by_party(
+            self.party_id,
+            &self.sent_seed_list,
+            &self.rec_seed_list,
+        );
+        self.sent_seed_list.zeroize();
+        self.rec_seed_list.zeroize();
+    }
+
+    /// Fingerprint of the distributed key, the same for key shares of
+    /// all parties and stable across key refreshes.
+    pub fn key_fingerprint(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(KEY_FINGERPRINT_LABEL);
+        hasher.update(self.public_key.to_bytes());
+        if let Some(root_chain_code) = &self.root_chain_code {
+            hasher.update(root_chain_code);
+        }
+        hasher.finalize().into()
+    }
+
+    /// Export non-secret data of the key share.
+    pub fn public_data(&self) -> KeysharePublic {
+        KeysharePublic {
+            version: KEYSHARE_PUBLIC_VERSION,
+            total_parties: self.total_parties,
+            threshold: self.threshold,
+            rank_list: self.rank_list.clone(),
+            party_id: self.party_id,
+            public_key: self.public_key,
+            root_chain_code: self.root_chain_code,
+            big_s_list: self.big_s_list.clone(),
+            x_i_list: self.x_i_list.clone(),
+        }
+    }
+
+    /// Check the key share against public data published by other
+    /// parties of the same keygen, see [`Keyshare::public_data`].
+    /// Could be called with data of any subset of the parties.
+    ///
+    /// Verifies that all parties agree on parameters, the public key,
+    /// `big_s_list` and `x_i_list`, that `s_i` matches own entry of
+    /// `big_s_list` and that `big_s_list` reconstructs the public key.
+    pub fn verify_against(
+        &self,
+        others: &[KeysharePublic],
+    ) -> Result<(), KeygenError> {
+        let n = self.total_parties as usize;
+        let own = self.public_data();
+
+        if self.rank_list.len() != n
+            || self.big_s_list.len() != n
+            || self.x_i_list.len() != n
+            || self.party_id as usize >= n
+        {
+            return Err(KeygenError::KeyshareMismatch(self.party_id));
+        }
+
+        let mut seen = HashSet::new();
+        for other in others {
+            let p = other.party_id;
+            if p == self.party_id || p as usize >= n {
+                return Err(KeygenError::InvalidSenderId(p));
+            }
+            if !seen.insert(p) {
+                return Err(KeygenError::DuplicateMessage(p));
+            }
+
+            let same = other.total_parties == own.total_parties
+                && other.threshold == own.threshold
+                && other.rank_list == own.rank_list
+                && other.public_key == own.public_key
+                && other.root_chain_code == own.root_chain_code
+                && other.big_s_list == own.big_s_list
+                && other
+                    .x_i_list
+                    .iter()
+                    .map(|x| x.to_bytes())
+                    .eq(own.x_i_list.iter().map(|x| x.to_bytes()));
+            if !same {
+                return Err(KeygenError::KeyshareMismatch(p));
+            }
+        }
+
+        let mut x_i_set = HashSet::<FieldBytes>::new();
+        for (p, x_i) in self.x_i_list.iter().enumerate() {
+            if !x_i_set.insert(x_i.to_bytes()) {
+                return Err(KeygenError::NotUniqueXiValues(p as u8));
+            }
+        }
+
+        let big_s_i = ProjectivePoint::GENERATOR * self.s_i;
+        if big_s_i.to_affine() != self.big_s_list[self.party_id as usize] {
+            return Err(KeygenError::BigSMismatch(self.party_id));
+        }
+
+        let big_s_list: Vec<ProjectivePoint> =
+            self.big_s_list.iter().map(|p| p.to_curve()).collect();
+
+        check_secret_recovery(
+            &self.x_i_list,
+            &self.rank_list,
+            &big_s_list,
+            &self.public_key.to_curve(),
+        )
+    }
+
+    /// X coordinate of the public key as used by BIP340. Meaningful
+    /// only for keys generated by [`State::with_even_y`].
+    pub fn x_only_public_key(&self) -> [u8; 32] {
+        self.public_key.x().into()
+    }
 }
 
 #[derive(Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
@@ -229,14 +763,38 @@ pub struct State {
     ranks: Vec<u8>,
     t: u8,
     key_refresh_data: Option<KeyRefreshData>,
+    #[serde(default)]
+    session_nonce: Option<[u8; 32]>,
+    /// Do not run base OT, OT seeds are provided by another key of
+    /// the same batch or by `ot_seeds`.
+    #[serde(default)]
+    pub(crate) skip_base_ot: bool,
+    #[serde(default)]
+    #[zeroize(skip)]
+    round: KeygenRound,
+    #[serde(default)]
+    skip_chain_code: bool,
+    #[serde(default)]
+    seed: Option<[u8; 32]>,
+    #[serde(default)]
+    even_y: bool,
+    #[serde(default)]
+    identity_keys: Option<Vec<Vec<u8>>>,
+    #[serde(default)]
+    identity_secret: Option<NonZeroScalar>,
+    #[serde(default)]
+    ot_seeds: Option<OtSeeds>,
+    #[serde(skip)]
+    #[zeroize(skip)]
+    observer: Option<KeygenObserver>,
 
     pub final_session_id: [u8; 32],
-    #[zeroize(skip)] // FIXME we must zeroize this field
-    pub polynomial: Polynomial<Secp256k1>,
+    pub broadcast_digest: [u8; 32],
+    pub polynomial: SecretPolynomial,
     #[zeroize(skip)]
     pub big_f_vec: GroupPolynomial<Secp256k1>,
     pub chain_code_sids: Pairs<[u8; 32]>,
-    pub root_chain_code: [u8; 32],
+    pub root_chain_code: Option<[u8; 32]>,
     pub r_i_2: [u8; 32],
     pub commitment_list: Pairs<[u8; 32]>,
     pub sid_i_list: Pairs<[u8; 32]>,
@@ -255,6 +813,22 @@ pub struct State {
     pub base_ot_receivers: Pairs<EndemicOTReceiver>,
 }
 
+fn round_rng(
+    seed: &[u8; 32],
+    party_id: u8,
+    round: KeygenRound,
+) -> ChaCha20Rng {
+    ChaCha20Rng::from_seed(hash_round_seed(seed, party_id, round as u8))
+}
+
+/// All parties of a key import except the dealer. They start with a
+/// zero share, like parties who lost their key shares.
+fn import_joiners(party: &Party, dealer_id: u8) -> Vec<u8> {
+    (0..party.ranks.len() as u8)
+        .filter(|&p| p != dealer_id)
+        .collect()
+}
+
 fn other_parties(
     ranks: &[u8],
     party_id: u8,
@@ -266,107 +840,472 @@ fn other_parties(
         .filter(move |p| *p != party_id)
 }
 
+fn check_versions(
+    msgs: impl Iterator<Item = (u8, u8)>,
+) -> Result<(), KeygenError> {
+    for (from_id, version) in msgs {
+        if version != KEYGEN_MSG_VERSION {
+            return Err(KeygenError::VersionMismatch(from_id, version));
+        }
+    }
+
+    Ok(())
+}
+
+/// IDs of all parties in `pairs` except `party_id`.
+fn pair_ids<T>(pairs: &Pairs<T>, party_id: u8) -> Vec<u8> {
+    pairs
+        .iter()
+        .map(|(p, _)| *p)
+        .filter(|p| *p != party_id)
+        .collect()
+}
+
 impl Party {
     /// Return a party definition with zero ranks.
-    pub fn new(n: usize, t: usize, party_id: usize) -> Self {
-        debug_assert!(t > 1 && t <= n);
-        Self {
+    pub fn new(
+        n: usize,
+        t: usize,
+        party_id: usize,
+    ) -> Result<Self, KeygenError> {
+        if n > u8::MAX as usize || t > n || party_id >= n {
+            return Err(KeygenError::InvalidParameters);
+        }
+
+        let party = Self {
             ranks: vec![0; n],
             t: t as u8,
             party_id: party_id as _,
+        };
+        party.validate()?;
+
+        Ok(party)
+    }
+
+    /// Check the threshold, party ID and ranks of the party.
+    pub fn validate(&self) -> Result<(), KeygenError> {
+        let n = self.ranks.len();
+        if self.t < 2
+            || self.t as usize > n
+            || n > u8::MAX as usize
+            || self.party_id as usize >= n
+        {
+            return Err(KeygenError::InvalidParameters);
+        }
+
+        if !valid_ranks(&self.ranks, self.t) {
+            return Err(KeygenError::InvalidRanks);
         }
+
+        Ok(())
     }
 }
 
 impl State {
     /// Initialize generation of a new distributed key
-    pub fn new<R: RngCore + CryptoRng>(party: Party, rng: &mut R) -> Self {
-        Self::new_with_refresh(party, rng, None).unwrap()
+    pub fn new<R: RngCore + CryptoRng>(
+        party: Party,
+        rng: &mut R,
+    ) -> Result<Self, KeygenError> {
+        Self::new_with_refresh(party, rng, None)
     }
 
-    fn new_with_refresh<R: RngCore + CryptoRng>(
+    /// Initialize generation of a new distributed key bound to a
+    /// session nonce agreed upon by all parties out of band.
+    ///
+    /// The nonce is mixed into the final session ID, messages of
+    /// a session with a different nonce fail verification.
+    pub fn new_with_session_nonce<R: RngCore + CryptoRng>(
         party: Party,
+        session_nonce: [u8; 32],
         rng: &mut R,
-        key_refresh_data: Option<KeyRefreshData>,
     ) -> Result<Self, KeygenError> {
-        let Party { party_id, ranks, t } = party;
+        Ok(Self::new(party, rng)?.with_session_nonce(session_nonce))
+    }
 
-        let my_party_id = party_id;
-        let n = ranks.len() as u8;
-        if let Some(v) = &key_refresh_data {
-            let cond1 = v.expected_public_key.is_identity().into();
-            let cond2 = v.lost_keyshare_party_ids.len() > (n - t) as usize;
-            let cond3 = v.s_i_0.is_zero().into()
-                && !v.lost_keyshare_party_ids.contains(&my_party_id);
-            if cond1 || cond2 || cond3 {
-                return Err(KeygenError::InvalidKeyRefresh);
-            }
-        }
+    /// Initialize generation of a new distributed key with the
+    /// evaluation point `x_i` provided by the caller, for example
+    /// derived from a stable identity of the device.
+    pub fn new_with_x_i<R: RngCore + CryptoRng>(
+        party: Party,
+        x_i: NonZeroScalar,
+        rng: &mut R,
+    ) -> Result<Self, KeygenError> {
+        Ok(Self::new(party, rng)?.with_x_i(x_i))
+    }
 
-        // currently we support only zero ranks in this impl.
-        assert!(ranks.iter().all(|&r| r == 0));
+    /// Replace the random evaluation point of a state created by any
+    /// constructor. Must be called before [`State::generate_msg1`].
+    ///
+    /// All parties must use distinct values, [`State::handle_msg1`]
+    /// fails otherwise.
+    pub fn with_x_i(mut self, x_i: NonZeroScalar) -> Self {
+        let party_id = self.party_id;
+        let rank = self.ranks[party_id as usize] as usize;
 
-        let r_i = rng.gen();
-        let session_id = rng.gen();
+        let d_i = self.polynomial.derivative_at(rank, &x_i);
 
-        // u_i_k
-        let mut polynomial = Polynomial::random(rng, t as usize - 1);
-        if let Some(v) = &key_refresh_data {
-            polynomial.set_constant(v.s_i_0);
+        self.x_i_list = Pairs::new_with_item(party_id, x_i);
+        self.d_i_list = Pairs::new_with_item(party_id, d_i);
+        self.update_own_commitment();
+
+        self
+    }
+
+    /// Bind the keygen to identity public keys of all participants,
+    /// indexed by party ID, for example long-term keys of enrolled
+    /// devices. The keys are SEC1 encoded ECDSA keys, `identity_key`
+    /// is the signing key of this party. Must be called before
+    /// [`State::generate_msg1`].
+    ///
+    /// The keys are hashed into the commitments of the first round
+    /// and into the final session ID, which is a part of the
+    /// resulting key share. Every party signs the final session ID
+    /// with its identity key in the second round, and
+    /// [`State::handle_msg2`] fails with
+    /// [`KeygenError::InvalidIdentitySignature`] unless the
+    /// signatures of all other parties are valid. All parties must
+    /// pass the same list.
+    pub fn with_identity_keys(
+        mut self,
+        identity_keys: Vec<Vec<u8>>,
+        identity_key: &SigningKey,
+    ) -> Result<Self, KeygenError> {
+        if identity_keys.len() != self.ranks.len()
+            || identity_keys
+                .iter()
+                .any(|key| VerifyingKey::from_sec1_bytes(key).is_err())
+        {
+            return Err(KeygenError::InvalidIdentityKeys);
         }
 
-        let x_i = NonZeroScalar::random(&mut *rng);
+        let own_key = VerifyingKey::from_sec1_bytes(
+            &identity_keys[self.party_id as usize],
+        );
+        if own_key.ok().as_ref() != Some(identity_key.verifying_key()) {
+            return Err(KeygenError::InvalidIdentityKeys);
+        }
 
-        let big_f_i_vec = polynomial.commit();
+        self.identity_keys = Some(identity_keys);
+        self.identity_secret = Some(*identity_key.as_nonzero_scalar());
+        self.update_own_commitment();
+
+        Ok(self)
+    }
+
+    fn identity_key(&self, party_id: u8) -> Option<&[u8]> {
+        self.identity_keys
+            .as_ref()
+            .map(|keys| keys[party_id as usize].as_slice())
+    }
+
+    fn verify_identity_signature(
+        &self,
+        party_id: u8,
+        key: &[u8],
+        msg: &KeygenMsg2,
+    ) -> Result<(), KeygenError> {
+        let err = || KeygenError::InvalidIdentitySignature(party_id);
+        let key = VerifyingKey::from_sec1_bytes(key).map_err(|_| err())?;
+        let signature = msg.identity_signature.as_ref().ok_or_else(err)?;
+        let msg = hash_identity_message(&self.final_session_id, party_id);
+
+        key.verify(&msg, signature).map_err(|_| err())
+    }
+
+    /// Options all parties must agree on, bound into the commitments
+    /// of the first round and the final session ID.
+    fn mode(&self) -> u8 {
+        let mut mode = 0;
+        if self.skip_chain_code {
+            mode |= KEYGEN_MODE_WITHOUT_CHAIN_CODE;
+        }
+        if self.even_y {
+            mode |= KEYGEN_MODE_EVEN_Y;
+        }
+        mode
+    }
+
+    fn update_own_commitment(&mut self) {
+        let party_id = self.party_id;
 
         let commitment = hash_commitment(
-            &session_id,
+            self.sid_i_list.find_pair(party_id),
             party_id as usize,
-            ranks[party_id as usize] as usize,
-            &x_i,
-            &big_f_i_vec,
-            &r_i,
+            self.ranks[party_id as usize] as usize,
+            self.x_i_list.find_pair(party_id),
+            self.big_f_i_vecs.find_pair(party_id),
+            self.r_i_list.find_pair(party_id),
+            self.identity_key(party_id),
+            self.mode(),
         );
 
-        let big_f_i_vec = polynomial.commit();
-        let d_i =
-            polynomial.derivative_at(ranks[party_id as usize] as usize, &x_i);
+        self.commitment_list = Pairs::new_with_item(party_id, commitment);
+    }
 
-        // generate chain_code_sid for root_chain_code or use already existed from key_refresh_data
-        let chain_code_sid = if let Some(v) = &key_refresh_data {
-            v.root_chain_code
-        } else {
-            rng.gen()
-        };
+    /// Initialize a keygen in deterministic mode: all randomness of
+    /// the party is derived from `seed`. Use [`State::round_rng`] as
+    /// the source of randomness of every round handler.
+    ///
+    /// Only for reproducible test vectors, never use a predictable
+    /// seed for real keys.
+    pub fn new_deterministic(
+        party: Party,
+        seed: [u8; 32],
+    ) -> Result<Self, KeygenError> {
+        let party_id = party.party_id;
+        let round = KeygenRound::WaitMsg1;
+        let mut rng = round_rng(&seed, party_id, round);
 
-        Ok(Self {
-            party_id,
-            ranks,
-            t,
-            key_refresh_data,
-            polynomial,
+        let mut state = Self::new(party, &mut rng)?;
+        state.seed = Some(seed);
 
-            r_i_2: rng.gen(),
-            sid_i_list: Pairs::new_with_item(party_id, session_id),
-            x_i_list: Pairs::new_with_item(party_id, x_i),
-            r_i_list: Pairs::new_with_item(party_id, r_i),
-            d_i_list: Pairs::new_with_item(party_id, d_i),
-            commitment_list: Pairs::new_with_item(party_id, commitment),
-            chain_code_sids: Pairs::new_with_item(party_id, chain_code_sid),
-            root_chain_code: [0; 32],
-            big_f_vec: GroupPolynomial::identity(t as usize),
-            big_f_i_vecs: Pairs::new_with_item(party_id, big_f_i_vec.clone()),
-            final_session_id: [0; 32],
-            base_ot_receivers: Pairs::new(),
-            dlog_proofs_i_list: Pairs::new(),
-            s_i: Scalar::ZERO,
-            rec_seed_list: Pairs::new(),
-            seed_ot_receivers: Pairs::new(),
+        Ok(state)
+    }
+
+    /// Source of randomness for the next round handler of a
+    /// deterministic keygen, `None` for a regular keygen.
+    pub fn round_rng(&self) -> Option<ChaCha20Rng> {
+        self.seed
+            .as_ref()
+            .map(|seed| round_rng(seed, self.party_id, self.round))
+    }
+
+    /// Generate a key without a root chain code, for keys which never
+    /// use BIP32 derivation. All parties must use this mode.
+    ///
+    /// The resulting key share has no `root_chain_code` and
+    /// [`State::handle_msg3`] ignores `commitment_2_list`, so parties
+    /// do not need to exchange chain code commitments. The mode is
+    /// bound into the commitments of the first round and the final
+    /// session ID, a party in another mode fails the second round.
+    pub fn without_chain_code(mut self) -> Self {
+        self.skip_chain_code = true;
+        self.update_own_commitment();
+        self
+    }
+
+    /// Generate a key with an even Y coordinate of the public key, as
+    /// required by BIP340 (Taproot). All parties must use this mode.
+    ///
+    /// If the jointly generated public key has an odd Y coordinate,
+    /// every party negates its share, so the resulting key share
+    /// signs for the negated key. Use [`Keyshare::x_only_public_key`]
+    /// to get the BIP340 public key. Keys derived by BIP32 from the
+    /// root key are not normalized.
+    ///
+    /// A key refresh of a key with an odd Y coordinate fails with
+    /// [`KeygenError::InvalidKeyRefresh`]. The mode is bound into the
+    /// commitments of the first round and the final session ID, a
+    /// party in another mode fails the second round.
+    pub fn with_even_y(mut self) -> Self {
+        self.even_y = true;
+        self.update_own_commitment();
+        self
+    }
+
+    /// Use OT seeds established by [`crate::ot_setup`] instead of
+    /// running base OT. All parties must pass seeds of the same setup,
+    /// otherwise signing with the key fails.
+    ///
+    /// Fails with [`KeygenError::InvalidOtSeeds`] if the seeds belong
+    /// to another party or another number of parties.
+    pub fn with_ot_seeds(
+        mut self,
+        seeds: OtSeeds,
+    ) -> Result<Self, KeygenError> {
+        if seeds.party_id != self.party_id
+            || seeds.total_parties as usize != self.ranks.len()
+        {
+            return Err(KeygenError::InvalidOtSeeds);
+        }
+
+        self.skip_base_ot = true;
+        self.ot_seeds = Some(seeds);
+
+        Ok(self)
+    }
+
+    /// Report progress of the keygen to `on_event`. The observer is
+    /// not serialized and is lost on [`State::suspend`].
+    pub fn with_observer(
+        mut self,
+        on_event: impl Fn(KeygenEvent<'_>) + Send + Sync + 'static,
+    ) -> Self {
+        self.observer = Some(Arc::new(on_event));
+        self
+    }
+
+    /// Report the result of a handler of `round`. Messages of all
+    /// other parties are verified only when the handler succeeded.
+    fn report<T>(
+        &self,
+        round: KeygenRound,
+        res: Result<T, KeygenError>,
+    ) -> Result<T, KeygenError> {
+        match &res {
+            Ok(_) => {
+                for from_id in 0..self.ranks.len() as u8 {
+                    if from_id != self.party_id {
+                        emit(
+                            &self.observer,
+                            KeygenEvent::MessageVerified { round, from_id },
+                        );
+                    }
+                }
+                emit(&self.observer, KeygenEvent::RoundCompleted(self.round));
+            }
+            Err(err) => emit(&self.observer, KeygenEvent::Failed(err)),
+        }
+
+        res
+    }
+
+    /// Run the handler of `round` on a batch of messages. A batch of
+    /// another round, of the wrong size or with unexpected senders is
+    /// rejected before the state is touched, the round could be
+    /// retried with a corrected batch. An error of the handler itself
+    /// aborts the keygen.
+    fn run_round<T>(
+        &mut self,
+        round: KeygenRound,
+        msgs: impl ExactSizeIterator<Item = (u8, Option<u8>, u8)> + Clone,
+        handler: impl FnOnce(&mut Self) -> Result<T, KeygenError>,
+    ) -> Result<T, KeygenError> {
+        self.expect_round(round)?;
+
+        if msgs.len() != self.ranks.len() - 1 {
+            return Err(KeygenError::MissingMessage);
+        }
+
+        self.check_messages(msgs)?;
+
+        let res = handler(self);
+        if res.is_err() {
+            self.round = KeygenRound::Aborted;
+        }
+
+        res
+    }
+
+    fn expect_round(&self, round: KeygenRound) -> Result<(), KeygenError> {
+        if self.round != round {
+            return Err(KeygenError::UnexpectedRound(self.round));
+        }
+
+        Ok(())
+    }
+
+    /// Bind a state created by any constructor to a session nonce.
+    /// Must be called before [`State::handle_msg1`].
+    pub fn with_session_nonce(mut self, session_nonce: [u8; 32]) -> Self {
+        self.session_nonce = Some(session_nonce);
+        self
+    }
+
+    fn new_with_refresh<R: RngCore + CryptoRng>(
+        party: Party,
+        rng: &mut R,
+        key_refresh_data: Option<KeyRefreshData>,
+    ) -> Result<Self, KeygenError> {
+        party.validate()?;
+
+        let Party { party_id, ranks, t } = party;
+
+        let my_party_id = party_id;
+        let n = ranks.len() as u8;
+        if let Some(v) = &key_refresh_data {
+            let cond1 = v.expected_public_key.is_identity().into();
+            let cond2 = v.s_i_0.is_zero().into()
+                && !v.lost_keyshare_party_ids.contains(&my_party_id);
+            let cond3 = v.lost_keyshare_party_ids.iter().any(|&p| p >= n);
+            if cond1 || cond2 || cond3 {
+                return Err(KeygenError::InvalidKeyRefresh);
+            }
+        }
+
+        let r_i = rng.gen();
+        let session_id = rng.gen();
+
+        // u_i_k
+        let mut polynomial =
+            SecretPolynomial::from(Polynomial::random(rng, t as usize - 1));
+        if let Some(v) = &key_refresh_data {
+            polynomial.set_constant(v.s_i_0);
+        }
+
+        let x_i = NonZeroScalar::random(&mut *rng);
+
+        let big_f_i_vec = polynomial.commit();
+
+        let commitment = hash_commitment(
+            &session_id,
+            party_id as usize,
+            ranks[party_id as usize] as usize,
+            &x_i,
+            &big_f_i_vec,
+            &r_i,
+            None,
+            0,
+        );
+
+        let big_f_i_vec = polynomial.commit();
+        let d_i =
+            polynomial.derivative_at(ranks[party_id as usize] as usize, &x_i);
+
+        // generate chain_code_sid for root_chain_code or use already existed from key_refresh_data
+        let chain_code_sid = if let Some(v) = &key_refresh_data {
+            v.root_chain_code.unwrap_or_default()
+        } else {
+            rng.gen()
+        };
+
+        Ok(Self {
+            party_id,
+            ranks,
+            t,
+            key_refresh_data,
+            session_nonce: None,
+            skip_base_ot: false,
+            round: KeygenRound::WaitMsg1,
+            skip_chain_code: false,
+            seed: None,
+            even_y: false,
+            identity_keys: None,
+            identity_secret: None,
+            ot_seeds: None,
+            observer: None,
+            polynomial,
+
+            r_i_2: rng.gen(),
+            sid_i_list: Pairs::new_with_item(party_id, session_id),
+            x_i_list: Pairs::new_with_item(party_id, x_i),
+            r_i_list: Pairs::new_with_item(party_id, r_i),
+            d_i_list: Pairs::new_with_item(party_id, d_i),
+            commitment_list: Pairs::new_with_item(party_id, commitment),
+            chain_code_sids: Pairs::new_with_item(party_id, chain_code_sid),
+            root_chain_code: None,
+            big_f_vec: GroupPolynomial::identity(t as usize),
+            big_f_i_vecs: Pairs::new_with_item(party_id, big_f_i_vec.clone()),
+            final_session_id: [0; 32],
+            broadcast_digest: [0; 32],
+            base_ot_receivers: Pairs::new(),
+            dlog_proofs_i_list: Pairs::new(),
+            s_i: Scalar::ZERO,
+            rec_seed_list: Pairs::new(),
+            seed_ot_receivers: Pairs::new(),
             seed_i_j_list: Pairs::new(),
             seed_ot_senders: Pairs::new(),
         })
     }
 
+    /// Initialize a refresh of an existing key.
+    ///
+    /// Old evaluation points in `refresh_share.x_i_list` are used only
+    /// to compute additive shares of the key. Every party samples a
+    /// fresh `x_i`, so evaluation points of the new key shares could
+    /// not be correlated with the old ones. Use [`State::with_x_i`]
+    /// to keep a stable evaluation point instead.
     pub fn key_refresh<R: RngCore + CryptoRng>(
         refresh_share: &RefreshShare,
         rng: &mut R,
@@ -376,18 +1315,32 @@ impl State {
             party_id: refresh_share.party_id,
             t: refresh_share.threshold,
         };
+        party.validate()?;
+
         let n = party.ranks.len();
         let my_party_id = party.party_id;
 
-        // currently we support only zero ranks in this impl.
-        assert!(party.ranks.iter().all(|&r| r == 0));
+        let mut lost = refresh_share.lost_keyshare_party_ids.clone();
+        lost.sort_unstable();
+        lost.dedup();
+
+        // enough parties should keep their shares to recover the key
+        if lost.len() > n - party.t as usize
+            || lost.len() != refresh_share.lost_keyshare_party_ids.len()
+        {
+            return Err(KeygenError::InvalidKeyRefresh);
+        }
 
         let mut s_i_0 = Scalar::ZERO;
-        if refresh_share.s_i.is_some() && refresh_share.x_i_list.is_some() {
+        if let (Some(s_i), Some(x_i_list)) =
+            (&refresh_share.s_i, &refresh_share.x_i_list)
+        {
+            if x_i_list.len() != n {
+                return Err(KeygenError::InvalidKeyRefresh);
+            }
+
             // calculate additive share s_i_0 of participant_i,
             // \sum_{i=0}^{n-1} s_i_0 = private_key
-            let s_i = &refresh_share.s_i.unwrap();
-            let x_i_list = &refresh_share.x_i_list.clone().unwrap();
             let x_i = &x_i_list[my_party_id as usize];
 
             let party_ids_with_keyshares = (0..n as u8)
@@ -396,8 +1349,17 @@ impl State {
                 })
                 .collect::<Vec<_>>();
 
-            let lambda =
-                get_lagrange_coeff(x_i, x_i_list, &party_ids_with_keyshares);
+            let lambda = if party.ranks.iter().all(|&r| r == 0) {
+                get_lagrange_coeff(x_i, x_i_list, &party_ids_with_keyshares)
+            } else {
+                get_birkhoff_coeff(
+                    my_party_id,
+                    x_i_list,
+                    &party.ranks,
+                    &party_ids_with_keyshares,
+                )
+                .ok_or(KeygenError::InvalidKeyRefresh)?
+            };
 
             s_i_0 = lambda * s_i;
         }
@@ -409,6 +1371,111 @@ impl State {
                 .clone(),
             expected_public_key: refresh_share.public_key,
             root_chain_code: refresh_share.root_chain_code,
+            import_commitments: vec![],
+        };
+
+        // a key without a chain code keeps this mode, parties which
+        // lost their shares must use State::without_chain_code
+        let skip_chain_code = refresh_share.s_i.is_some()
+            && refresh_share.root_chain_code.is_none();
+
+        let mut state =
+            Self::new_with_refresh(party, rng, Some(key_refresh_data))?;
+        state.skip_chain_code = skip_chain_code;
+        state.update_own_commitment();
+
+        Ok(state)
+    }
+
+    /// Initialize import of an existing private key by a trusted
+    /// dealer. The dealer is a regular participant of the ceremony
+    /// with party ID `party.party_id`, all other parties should call
+    /// [`State::key_import_join`].
+    ///
+    /// If `root_chain_code` is `None` a random one is generated.
+    pub fn key_import<R: RngCore + CryptoRng>(
+        party: Party,
+        private_key: &NonZeroScalar,
+        root_chain_code: Option<[u8; 32]>,
+        rng: &mut R,
+    ) -> Result<Self, KeygenError> {
+        let public_key =
+            (ProjectivePoint::GENERATOR * private_key.as_ref()).to_affine();
+
+        let key_refresh_data = KeyRefreshData {
+            s_i_0: *private_key.as_ref(),
+            lost_keyshare_party_ids: import_joiners(&party, party.party_id),
+            expected_public_key: public_key,
+            root_chain_code: Some(
+                root_chain_code.unwrap_or_else(|| rng.gen()),
+            ),
+            import_commitments: vec![],
+        };
+
+        Self::new_with_refresh(party, rng, Some(key_refresh_data))
+    }
+
+    /// Join import of a private key distributed by the dealer
+    /// `dealer_id`. The key share and root chain code are
+    /// contributed by the dealer, `public_key` is the public key of
+    /// the imported private key.
+    pub fn key_import_join<R: RngCore + CryptoRng>(
+        party: Party,
+        dealer_id: u8,
+        public_key: AffinePoint,
+        rng: &mut R,
+    ) -> Result<Self, KeygenError> {
+        if dealer_id == party.party_id
+            || dealer_id as usize >= party.ranks.len()
+        {
+            return Err(KeygenError::InvalidKeyRefresh);
+        }
+
+        let key_refresh_data = KeyRefreshData {
+            s_i_0: Scalar::ZERO,
+            lost_keyshare_party_ids: import_joiners(&party, dealer_id),
+            expected_public_key: public_key,
+            root_chain_code: None,
+            import_commitments: vec![],
+        };
+
+        Self::new_with_refresh(party, rng, Some(key_refresh_data))
+    }
+
+    /// Join import of a private key split by a key holder who does not
+    /// take part in the ceremony, see [`split_key_for_import`].
+    ///
+    /// `public_key` is the expected public key of the imported key,
+    /// obtained independently from the key holder. The ceremony fails
+    /// unless the pieces of all parties sum up to its private key.
+    pub fn key_import_piece<R: RngCore + CryptoRng>(
+        party: Party,
+        piece: &KeyImportPiece,
+        public_key: AffinePoint,
+        rng: &mut R,
+    ) -> Result<Self, KeygenError> {
+        let n = party.ranks.len();
+
+        if piece.to_id != party.party_id || piece.big_x_list.len() != n {
+            return Err(KeygenError::InvalidKeyRefresh);
+        }
+
+        let big_x_i = ProjectivePoint::GENERATOR * piece.x_i;
+        let sum: ProjectivePoint =
+            piece.big_x_list.iter().map(|p| p.to_curve()).sum();
+
+        if big_x_i != piece.big_x_list[party.party_id as usize]
+            || sum != public_key
+        {
+            return Err(KeygenError::PublicKeyMismatch);
+        }
+
+        let key_refresh_data = KeyRefreshData {
+            s_i_0: piece.x_i,
+            lost_keyshare_party_ids: vec![],
+            expected_public_key: public_key,
+            root_chain_code: Some(piece.root_chain_code),
+            import_commitments: piece.big_x_list.clone(),
         };
 
         Self::new_with_refresh(party, rng, Some(key_refresh_data))
@@ -423,9 +1490,103 @@ impl State {
         Self::key_refresh(&refresh_share, &mut *rng)
     }
 
+    /// Initialize a rotation of an existing key share which at the same
+    /// time recovers key shares of all parties in `lost_party_ids`.
+    ///
+    /// Parties which lost their key shares should join the same
+    /// ceremony with [`State::key_recovery`] and the same list of lost
+    /// parties. Afterwards all participants have fresh key shares of
+    /// the same public key and the old key shares become useless.
+    pub fn key_rotation_with_recovery<R: RngCore + CryptoRng>(
+        oldshare: &Keyshare,
+        lost_party_ids: &[u8],
+        rng: &mut R,
+    ) -> Result<Self, KeygenError> {
+        if lost_party_ids.contains(&oldshare.party_id) {
+            return Err(KeygenError::InvalidKeyRefresh);
+        }
+
+        let refresh_share =
+            RefreshShare::from_keyshare(oldshare, Some(lost_party_ids));
+        Self::key_refresh(&refresh_share, &mut *rng)
+    }
+
+    /// Initialize recovery of a lost key share of `party` in a
+    /// ceremony with parties running
+    /// [`State::key_rotation_with_recovery`].
+    pub fn key_recovery<R: RngCore + CryptoRng>(
+        party: Party,
+        public_key: AffinePoint,
+        lost_party_ids: &[u8],
+        rng: &mut R,
+    ) -> Result<Self, KeygenError> {
+        if !lost_party_ids.contains(&party.party_id) {
+            return Err(KeygenError::InvalidKeyRefresh);
+        }
+
+        let refresh_share = RefreshShare::from_lost_keyshare(
+            party,
+            public_key,
+            lost_party_ids.to_vec(),
+        );
+        Self::key_refresh(&refresh_share, &mut *rng)
+    }
+
+    /// Initialize resharing of an existing distributed key to a smaller
+    /// set of parties, for example to remove a party.
+    ///
+    /// `remaining` lists old party ids of all parties keeping the key,
+    /// at least the old threshold of them. Every remaining party gets
+    /// a new party id, its index in the sorted list, and a new key
+    /// share for `threshold` out of `remaining.len()` parties. The
+    /// public key and the root chain code stay the same, key shares of
+    /// removed parties could not be combined with the new ones.
+    pub fn key_reshare<R: RngCore + CryptoRng>(
+        oldshare: &Keyshare,
+        remaining: &[u8],
+        threshold: u8,
+        rng: &mut R,
+    ) -> Result<Self, KeygenError> {
+        let mut remaining = remaining.to_vec();
+        remaining.sort_unstable();
+        remaining.dedup();
+
+        let n = remaining.len();
+        let party_id = remaining.iter().position(|p| *p == oldshare.party_id);
+
+        let cond = n < oldshare.threshold as usize
+            || threshold < 2
+            || threshold as usize > n
+            || remaining.iter().any(|p| *p >= oldshare.total_parties)
+            || oldshare.rank_list.iter().any(|&r| r != 0);
+
+        let Some(party_id) = party_id.filter(|_| !cond) else {
+            return Err(KeygenError::InvalidKeyRefresh);
+        };
+
+        let refresh_share = RefreshShare {
+            rank_list: vec![0; n],
+            threshold,
+            party_id: party_id as u8,
+            public_key: oldshare.public_key,
+            root_chain_code: oldshare.root_chain_code,
+            s_i: Some(oldshare.s_i),
+            x_i_list: Some(
+                remaining
+                    .iter()
+                    .map(|p| oldshare.x_i_list[*p as usize])
+                    .collect(),
+            ),
+            lost_keyshare_party_ids: vec![],
+        };
+
+        Self::key_refresh(&refresh_share, &mut *rng)
+    }
+
     pub fn generate_msg1(&self) -> KeygenMsg1 {
         KeygenMsg1 {
             from_id: self.party_id,
+            version: KEYGEN_MSG_VERSION,
             session_id: *self.sid_i_list.find_pair(self.party_id),
             commitment: *self.commitment_list.find_pair(self.party_id),
             x_i: *self.x_i_list.find_pair(self.party_id),
@@ -441,35 +1602,46 @@ impl State {
     pub fn handle_msg1<R: RngCore + CryptoRng>(
         &mut self,
         rng: &mut R,
-        msgs: Vec<KeygenMsg1>,
+        msgs: impl IntoIterator<Item = impl Borrow<KeygenMsg1>>,
     ) -> Result<Vec<KeygenMsg2>, KeygenError> {
-        if msgs.len() != self.ranks.len() - 1 {
-            return Err(KeygenError::MissingMessage);
-        }
+        borrow_msgs!(msgs: KeygenMsg1);
+
+        let round = self.round;
+        let res = self.run_round(
+            KeygenRound::WaitMsg1,
+            msgs.iter().map(|m| (m.from_id, None, m.version)),
+            |state| state.handle_msg1_inner(rng, &msgs),
+        );
+        self.report(round, res)
+    }
+
+    fn handle_msg1_inner<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+        msgs: &[&KeygenMsg1],
+    ) -> Result<Vec<KeygenMsg2>, KeygenError> {
+        // Check that x_i_list contains unique elements
+        let mut x_i_set = HashSet::<FieldBytes>::from_iter(
+            self.x_i_list.iter().map(|(_, x)| x.to_bytes()),
+        );
 
         for msg in msgs {
+            if !x_i_set.insert(msg.x_i.to_bytes()) {
+                return Err(KeygenError::NotUniqueXiValues(msg.from_id));
+            }
+
             self.sid_i_list.push(msg.from_id, msg.session_id);
             self.x_i_list.push(msg.from_id, msg.x_i);
             self.commitment_list.push(msg.from_id, msg.commitment);
         }
 
-        // Check that x_i_list contains unique elements
-        if HashSet::<FieldBytes>::from_iter(
-            self.x_i_list.iter().map(|(_, x)| x.to_bytes()),
-        )
-        .len()
-            != self.x_i_list.len()
-        {
-            return Err(KeygenError::NotUniqueXiValues);
-        }
-
         // TODO: Should parties be initialized with rank_list and x_i_list? Ask Vlad.
-        self.final_session_id = self
-            .sid_i_list
-            .iter()
-            .fold(Sha256::new(), |hash, (_, sid)| hash.chain_update(sid))
-            .finalize()
-            .into();
+        self.final_session_id = hash_dkg_final_session_id(
+            self.sid_i_list.iter().map(|(p, sid)| (*p, sid)),
+            self.session_nonce.as_ref(),
+            self.identity_keys.as_deref(),
+            self.mode(),
+        );
 
         let dlog_proofs = {
             // Setup transcript for DLog proofs.
@@ -493,26 +1665,31 @@ impl State {
                 .collect::<Vec<_>>()
         };
 
+        let identity_signature = self.identity_secret.map(|secret| {
+            let msg =
+                hash_identity_message(&self.final_session_id, self.party_id);
+            SigningKey::from(secret).sign(&msg)
+        });
+
         let mut output = vec![];
 
         self.base_ot_receivers = other_parties(&self.ranks, self.party_id)
-            .map(|p| {
-                let base_ot_session_id = get_base_ot_session_id(
-                    self.party_id as usize,
-                    p as usize,
-                    &self.final_session_id,
-                );
+            .filter_map(|p| {
+                let receiver = (!self.skip_base_ot).then(|| {
+                    base_ot::start(
+                        &self.final_session_id,
+                        self.party_id,
+                        p,
+                        rng,
+                    )
+                });
 
-                let mut msg1 = ZS::<EndemicOTMsg1>::default();
-                let receiver = EndemicOTReceiver::new(
-                    &base_ot_session_id,
-                    &mut msg1,
-                    rng,
-                );
+                let (receiver, msg1) = receiver.unzip();
 
                 output.push(KeygenMsg2 {
                     from_id: self.party_id,
                     to_id: p,
+                    version: KEYGEN_MSG_VERSION,
                     ot: msg1,
 
                     r_i: *self.r_i_list.find_pair(self.party_id),
@@ -521,13 +1698,16 @@ impl State {
                         .big_f_i_vecs
                         .find_pair(self.party_id)
                         .clone(),
+                    identity_signature,
                 });
 
-                Ok((p, receiver))
+                receiver.map(|receiver| (p, receiver))
             })
-            .collect::<Result<Vec<_>, KeygenError>>()?
+            .collect::<Vec<_>>()
             .into();
 
+        self.round = KeygenRound::WaitMsg2;
+
         Ok(output)
     }
 
@@ -535,37 +1715,119 @@ impl State {
     pub fn handle_msg2<R: RngCore + CryptoRng>(
         &mut self,
         rng: &mut R,
-        msgs: Vec<KeygenMsg2>,
+        msgs: impl IntoIterator<Item = impl Borrow<KeygenMsg2>>,
     ) -> Result<Vec<KeygenMsg3>, KeygenError> {
-        // FIXME: proper validation
-        if msgs.len() != self.ranks.len() - 1 {
-            return Err(KeygenError::MissingMessage);
-        }
+        self.handle_msg2_with_progress(rng, msgs, |_| {})
+    }
 
-        for msg in &msgs {
-            if msg.big_f_i_vec.coeffs.len() != self.t as usize {
-                return Err(KeygenError::InvalidMessage);
-            }
-            if msg.dlog_proofs.len() != self.t as usize {
-                return Err(KeygenError::InvalidMessage);
-            }
+    /// Same as [`State::handle_msg1`] but returns the second round
+    /// messages split into one broadcast message and P2P messages
+    /// without the broadcast part, to reduce bandwidth of the round
+    /// from O(n²·t) to O(n·t) points. Handle them with
+    /// [`State::handle_msg2_split`].
+    pub fn handle_msg1_split<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+        msgs: impl IntoIterator<Item = impl Borrow<KeygenMsg1>>,
+    ) -> Result<(KeygenMsg2Broadcast, Vec<KeygenMsg2P2P>), KeygenError> {
+        let msgs = self.handle_msg1(rng, msgs)?;
 
-            self.r_i_list.push(msg.from_id, msg.r_i);
-            self.big_f_i_vecs.push(msg.from_id, msg.big_f_i_vec.clone());
-            self.dlog_proofs_i_list
-                .push(msg.from_id, msg.dlog_proofs.clone());
-        }
+        let broadcast = msgs[0].split().0;
+        let p2p = msgs.iter().map(|msg| msg.split().1).collect();
 
-        for party_id in 0..self.ranks.len() as u8 {
-            if party_id == self.party_id {
-                continue;
-            }
+        Ok((broadcast, p2p))
+    }
 
-            let x_i = self.x_i_list.find_pair(party_id);
-            let r_i = self.r_i_list.find_pair(party_id);
-            let sid = self.sid_i_list.find_pair(party_id);
-            let commitment = self.commitment_list.find_pair(party_id);
-            let big_f_i_vector = self.big_f_i_vecs.find_pair(party_id);
+    /// Same as [`State::handle_msg2`] for messages split by
+    /// [`State::handle_msg1_split`]: one broadcast message and one
+    /// P2P message of each other party.
+    pub fn handle_msg2_split<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+        broadcast: impl IntoIterator<Item = impl Borrow<KeygenMsg2Broadcast>>,
+        p2p: impl IntoIterator<Item = impl Borrow<KeygenMsg2P2P>>,
+    ) -> Result<Vec<KeygenMsg3>, KeygenError> {
+        borrow_msgs!(broadcast: KeygenMsg2Broadcast);
+
+        let msgs = p2p
+            .into_iter()
+            .map(|p2p| {
+                let p2p = p2p.borrow();
+                let b = broadcast
+                    .iter()
+                    .find(|b| b.from_id == p2p.from_id)
+                    .ok_or(KeygenError::MissingMessage)?;
+                KeygenMsg2::from_parts(b, p2p)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if msgs.len() != broadcast.len() {
+            return Err(KeygenError::MissingMessage);
+        }
+
+        self.handle_msg2(rng, msgs)
+    }
+
+    /// Same as handle_msg2() but calls `progress` with the party ID
+    /// of each counterparty as soon as its base OT message is
+    /// processed.
+    pub fn handle_msg2_with_progress<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+        msgs: impl IntoIterator<Item = impl Borrow<KeygenMsg2>>,
+        mut progress: impl FnMut(u8),
+    ) -> Result<Vec<KeygenMsg3>, KeygenError> {
+        borrow_msgs!(msgs: KeygenMsg2);
+
+        let (round, observer) = (self.round, self.observer.clone());
+        let res = self.run_round(
+            KeygenRound::WaitMsg2,
+            msgs.iter().map(|m| (m.from_id, Some(m.to_id), m.version)),
+            |state| {
+                state.handle_msg2_inner(rng, &msgs, |from_id| {
+                    emit(&observer, KeygenEvent::Progress { round, from_id });
+                    progress(from_id);
+                })
+            },
+        );
+        self.report(round, res)
+    }
+
+    fn handle_msg2_inner<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+        msgs: &[&KeygenMsg2],
+        mut progress: impl FnMut(u8),
+    ) -> Result<Vec<KeygenMsg3>, KeygenError> {
+        for msg in msgs {
+            if msg.big_f_i_vec.coeffs.len() != self.t as usize
+                || msg.dlog_proofs.len() != self.t as usize
+            {
+                return Err(KeygenError::AbortProtocolAndBanParty(
+                    msg.from_id,
+                ));
+            }
+
+            if let Some(key) = self.identity_key(msg.from_id) {
+                self.verify_identity_signature(msg.from_id, key, msg)?;
+            }
+
+            self.r_i_list.push(msg.from_id, msg.r_i);
+            self.big_f_i_vecs.push(msg.from_id, msg.big_f_i_vec.clone());
+            self.dlog_proofs_i_list
+                .push(msg.from_id, msg.dlog_proofs.clone());
+        }
+
+        for party_id in 0..self.ranks.len() as u8 {
+            if party_id == self.party_id {
+                continue;
+            }
+
+            let x_i = self.x_i_list.find_pair(party_id);
+            let r_i = self.r_i_list.find_pair(party_id);
+            let sid = self.sid_i_list.find_pair(party_id);
+            let commitment = self.commitment_list.find_pair(party_id);
+            let big_f_i_vector = self.big_f_i_vecs.find_pair(party_id);
 
             let commit_hash = hash_commitment(
                 sid,
@@ -574,10 +1836,23 @@ impl State {
                 x_i,
                 big_f_i_vector,
                 r_i,
+                self.identity_key(party_id),
+                self.mode(),
             );
 
             if commit_hash.ct_ne(commitment).into() {
-                return Err(KeygenError::InvalidCommitmentHash);
+                return Err(KeygenError::InvalidCommitmentHash(party_id));
+            }
+
+            if let Some(v) = &self.key_refresh_data {
+                let expected = v.import_commitments.get(party_id as usize);
+                if expected.is_some_and(|p| {
+                    big_f_i_vector.get_constant() != p.to_curve()
+                }) {
+                    return Err(KeygenError::AbortProtocolAndBanParty(
+                        party_id,
+                    ));
+                }
             }
 
             {
@@ -586,12 +1861,16 @@ impl State {
                     if v.lost_keyshare_party_ids.contains(&party_id) {
                         // for participant who lost their key_share, first point should be IDENTITY
                         if points.next() != Some(&ProjectivePoint::IDENTITY) {
-                            return Err(KeygenError::InvalidPolynomialPoint);
+                            return Err(KeygenError::InvalidPolynomialPoint(
+                                party_id,
+                            ));
                         }
                     }
                 }
                 if points.any(|p| p.is_identity().into()) {
-                    return Err(KeygenError::InvalidPolynomialPoint);
+                    return Err(KeygenError::InvalidPolynomialPoint(
+                        party_id,
+                    ));
                 }
             }
 
@@ -616,61 +1895,63 @@ impl State {
             }
         }
 
-        msgs.into_iter()
-            .map(|msg| {
-                assert_eq!(msg.to_id, self.party_id);
-
-                let rank = self.ranks[msg.from_id as usize];
-
-                let sid = get_base_ot_session_id(
-                    msg.from_id as usize,
-                    self.party_id as usize,
-                    &self.final_session_id,
-                );
-                let mut base_ot_msg2 = ZS::<EndemicOTMsg2>::default();
-
-                let sender_output = EndemicOTSender::process(
-                    &sid,
-                    &msg.ot,
-                    &mut base_ot_msg2,
-                    rng,
+        // the base OT of each pair is independent, one RNG per pair
+        let base_ots = if self.skip_base_ot {
+            vec![]
+        } else {
+            let msgs: Vec<(&KeygenMsg2, [u8; 32])> =
+                msgs.iter().map(|msg| (*msg, rng.gen())).collect();
+            let (party_id, final_session_id) =
+                (self.party_id, self.final_session_id);
+            par_map(msgs, |(msg, seed)| {
+                let ot = msg.ot.as_ref().ok_or(
+                    KeygenError::AbortProtocolAndBanParty(msg.from_id),
+                )?;
+                let mut rng = ChaCha20Rng::from_seed(seed);
+                base_ot::reply(
+                    &final_session_id,
+                    party_id,
+                    msg.from_id,
+                    ot,
+                    &mut rng,
                 )
-                .map_err(|_| KeygenError::InvalidMessage)?;
-
-                let mut all_but_one_sender_seed =
-                    ZS::<SenderOTSeed>::default();
-                let mut pprf_output = ZS::<PPRFOutput>::default();
-
-                let all_but_one_session_id = get_all_but_one_session_id(
-                    self.party_id as usize,
-                    msg.from_id as usize,
-                    &self.final_session_id,
-                );
-
-                build_pprf(
-                    &all_but_one_session_id,
-                    &sender_output,
-                    &mut all_but_one_sender_seed,
-                    &mut pprf_output,
-                );
+            })
+        };
+        let mut base_ots = base_ots.into_iter();
 
-                self.seed_ot_senders
-                    .push(msg.from_id, all_but_one_sender_seed);
+        let output = msgs
+            .iter()
+            .map(|msg| {
+                let rank = self.ranks[msg.from_id as usize];
 
-                let seed_i_j = if msg.from_id > self.party_id {
-                    let seed_i_j = rng.gen();
-                    self.seed_i_j_list.push(msg.from_id, seed_i_j);
-                    Some(seed_i_j)
-                } else {
-                    None
+                let (base_ot_msg2, pprf_output) = match base_ots.next() {
+                    None => (None, None),
+                    Some(base_ot) => {
+                        let (base_ot_msg2, pprf_output, sender_seed) =
+                            base_ot?;
+                        self.seed_ot_senders.push(msg.from_id, sender_seed);
+                        (Some(base_ot_msg2), Some(pprf_output))
+                    }
                 };
 
+                let seed_i_j =
+                    if msg.from_id > self.party_id && !self.skip_base_ot {
+                        let seed_i_j = rng.gen();
+                        self.seed_i_j_list.push(msg.from_id, seed_i_j);
+                        Some(seed_i_j)
+                    } else {
+                        None
+                    };
+
                 let x_i = self.x_i_list.find_pair(msg.from_id);
                 let d_i = self.polynomial.derivative_at(rank as usize, x_i);
 
+                progress(msg.from_id);
+
                 Ok(KeygenMsg3 {
                     from_id: self.party_id,
                     to_id: msg.from_id,
+                    version: KEYGEN_MSG_VERSION,
 
                     base_ot_msg2,
                     pprf_output,
@@ -683,74 +1964,136 @@ impl State {
                     r_i_2: self.r_i_2,
                 })
             })
-            .collect::<Result<Vec<_>, _>>()
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.round = KeygenRound::WaitMsg3;
+
+        Ok(output)
+    }
+
+    /// Validate routing metadata of a batch of messages: each
+    /// `(from_id, to_id, version)` must come from a distinct
+    /// participant other than this party, be addressed to this party
+    /// (for P2P messages) and have a supported version, see
+    /// [`check_versions`].
+    fn check_messages(
+        &self,
+        msgs: impl Iterator<Item = (u8, Option<u8>, u8)> + Clone,
+    ) -> Result<(), KeygenError> {
+        let mut seen = HashSet::new();
+
+        for (from_id, to_id, _) in msgs.clone() {
+            if from_id == self.party_id
+                || from_id as usize >= self.ranks.len()
+            {
+                return Err(KeygenError::InvalidSenderId(from_id));
+            }
+
+            if to_id.is_some_and(|to_id| to_id != self.party_id) {
+                return Err(KeygenError::InvalidRecipientId(from_id));
+            }
+
+            if !seen.insert(from_id) {
+                return Err(KeygenError::DuplicateMessage(from_id));
+            }
+        }
+
+        check_versions(msgs.map(|(from_id, _, version)| (from_id, version)))
     }
 
     /// Round 3.
     pub fn handle_msg3<R: RngCore + CryptoRng>(
         &mut self,
         rng: &mut R,
-        msgs: Vec<KeygenMsg3>,
+        msgs: impl IntoIterator<Item = impl Borrow<KeygenMsg3>>,
         commitment_2_list: &[[u8; 32]],
     ) -> Result<KeygenMsg4, KeygenError> {
-        if msgs.len() != self.ranks.len() - 1 {
-            return Err(KeygenError::MissingMessage);
-        }
+        borrow_msgs!(msgs: KeygenMsg3);
+
+        let round = self.round;
+        let res = self.run_round(
+            KeygenRound::WaitMsg3,
+            msgs.iter().map(|m| (m.from_id, Some(m.to_id), m.version)),
+            |state| state.handle_msg3_inner(rng, &msgs, commitment_2_list),
+        );
+        self.report(round, res)
+    }
 
+    fn handle_msg3_inner<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+        msgs: &[&KeygenMsg3],
+        commitment_2_list: &[[u8; 32]],
+    ) -> Result<KeygenMsg4, KeygenError> {
         if let Some(v) = &self.key_refresh_data {
             if v.lost_keyshare_party_ids.contains(&self.party_id) {
                 self.chain_code_sids = Pairs::new();
             }
         }
 
+        let receiver_seeds = if self.skip_base_ot {
+            vec![]
+        } else {
+            let receivers: Vec<_> = msgs
+                .iter()
+                .map(|msg3| {
+                    (*msg3, self.base_ot_receivers.pop_pair(msg3.from_id))
+                })
+                .collect();
+            let (party_id, final_session_id) =
+                (self.party_id, self.final_session_id);
+            par_map(receivers, |(msg3, receiver)| {
+                let (Some(base_ot_msg2), Some(pprf_output)) =
+                    (&msg3.base_ot_msg2, &msg3.pprf_output)
+                else {
+                    return Err(KeygenError::AbortProtocolAndBanParty(
+                        msg3.from_id,
+                    ));
+                };
+
+                base_ot::finish(
+                    &final_session_id,
+                    party_id,
+                    msg3.from_id,
+                    receiver,
+                    base_ot_msg2,
+                    pprf_output,
+                )
+            })
+        };
+        let mut receiver_seeds = receiver_seeds.into_iter();
+
         for msg3 in msgs {
             if msg3.big_f_vec != self.big_f_vec {
-                return Err(KeygenError::BigFVecMismatch);
+                return Err(KeygenError::BigFVecMismatch(msg3.from_id));
             }
 
             self.d_i_list.push(msg3.from_id, msg3.d_i);
 
-            let receiver = self.base_ot_receivers.pop_pair(msg3.from_id);
-            let receiver_output = receiver
-                .process(&msg3.base_ot_msg2)
-                .map_err(|_| KeygenError::InvalidMessage)?;
-
-            let mut all_but_one_receiver_seed =
-                ZS::<ReceiverOTSeed>::default();
-
-            let all_but_one_session_id = get_all_but_one_session_id(
-                msg3.from_id as usize,
-                self.party_id as usize,
-                &self.final_session_id,
-            );
-
-            eval_pprf(
-                &all_but_one_session_id,
-                &receiver_output,
-                &msg3.pprf_output,
-                &mut all_but_one_receiver_seed,
-            )
-            .map_err(KeygenError::PPRFError)?;
-
-            self.seed_ot_receivers
-                .push(msg3.from_id, all_but_one_receiver_seed);
-            if let Some(seed_j_i) = msg3.seed_i_j {
-                self.rec_seed_list.push(msg3.from_id, seed_j_i);
+            if let Some(receiver_seed) = receiver_seeds.next() {
+                self.seed_ot_receivers.push(msg3.from_id, receiver_seed?);
+                if let Some(seed_j_i) = msg3.seed_i_j {
+                    self.rec_seed_list.push(msg3.from_id, seed_j_i);
+                }
             }
 
             // Verify commitments
-            let commitment_2 = commitment_2_list
-                .get(msg3.from_id as usize)
-                .ok_or(KeygenError::InvalidMessage)?;
+            if !self.skip_chain_code {
+                let commitment_2 = commitment_2_list
+                    .get(msg3.from_id as usize)
+                    .ok_or(KeygenError::InvalidChainCodeCommitments)?;
 
-            let commit_hash = hash_commitment_2(
-                &self.final_session_id,
-                &msg3.chain_code_sid,
-                &msg3.r_i_2,
-            );
+                let commit_hash = hash_commitment_2(
+                    &self.final_session_id,
+                    &msg3.chain_code_sid,
+                    &msg3.r_i_2,
+                );
 
-            if commit_hash.ct_ne(commitment_2).into() {
-                return Err(KeygenError::InvalidCommitmentHash);
+                if commit_hash.ct_ne(commitment_2).into() {
+                    return Err(KeygenError::InvalidCommitmentHash(
+                        msg3.from_id,
+                    ));
+                }
             }
 
             if let Some(v) = &self.key_refresh_data {
@@ -763,7 +2106,9 @@ impl State {
             }
         }
 
-        if self.key_refresh_data.is_some() {
+        if self.skip_chain_code {
+            self.root_chain_code = None;
+        } else if self.key_refresh_data.is_some() {
             let chain_code_sids = self.chain_code_sids.remove_ids();
             if chain_code_sids.is_empty() {
                 println!("error1");
@@ -775,18 +2120,15 @@ impl State {
                 return Err(KeygenError::InvalidKeyRefresh);
             }
             // Use already existing root_chain_code
-            self.root_chain_code = root_chain_code;
+            self.root_chain_code = Some(root_chain_code);
         } else {
             // Generate common root_chain_code from chain_code_sids
-            self.root_chain_code = self
-                .chain_code_sids
-                .iter()
-                .fold(Sha256::new(), |hash, (_, sid)| hash.chain_update(sid))
-                .finalize()
-                .into();
+            self.root_chain_code = Some(hash_root_chain_code(
+                self.chain_code_sids.iter().map(|(p, sid)| (*p, sid)),
+            ));
         }
 
-        for ((_, big_f_i_vec), (_, f_i_val)) in
+        for ((party_id, big_f_i_vec), (_, f_i_val)) in
             self.big_f_i_vecs.iter().zip(self.d_i_list.iter())
         {
             let coeffs = big_f_i_vec.derivative_coeffs(
@@ -800,13 +2142,28 @@ impl State {
             );
 
             if !valid {
-                return Err(KeygenError::FailedFelmanVerify);
+                return Err(KeygenError::FailedFelmanVerify(*party_id));
             }
         }
 
         self.s_i = self.d_i_list.iter().map(|(_, s)| s).sum();
         let big_s_i = ProjectivePoint::GENERATOR * self.s_i;
 
+        // Echo everything we received as a broadcast, parties compare
+        // these digests in the last round to detect equivocation.
+        // This includes the broadcast parts of P2P KeygenMsg2: r_i and
+        // big_f_i_vec. DLog proofs are not echoed, they are verified
+        // against the echoed big_f_i_vec and any valid proof will do.
+        self.broadcast_digest = hash_broadcast_digest(
+            &self.final_session_id,
+            self.commitment_list.iter().map(|(p, c)| (*p, c)),
+            self.x_i_list.iter().map(|(_, x_i)| x_i),
+            self.r_i_list.iter().map(|(p, r_i)| (*p, r_i)),
+            self.big_f_i_vecs.iter().map(|(p, v)| (*p, v)),
+            &self.big_f_vec,
+            commitment_2_list,
+        );
+
         // Use the root_chain_code in the final dlog proof
         // so that all parties are sure they generated the same root_chain_code
         let final_session_id_with_root_chain_code = {
@@ -814,8 +2171,10 @@ impl State {
             let mut transcript = Transcript::new(&DKG_LABEL);
             transcript
                 .append_message(b"final_session_id", &self.final_session_id);
-            transcript
-                .append_message(b"root_chain_code", &self.root_chain_code);
+            if let Some(root_chain_code) = &self.root_chain_code {
+                transcript
+                    .append_message(b"root_chain_code", root_chain_code);
+            }
             transcript
                 .challenge_bytes(&DLOG_SESSION_ID_WITH_CHAIN_CODE, &mut buf);
             buf
@@ -836,34 +2195,57 @@ impl State {
             )
         };
 
+        self.round = KeygenRound::WaitMsg4;
+
         Ok(KeygenMsg4 {
             from_id: self.party_id,
+            version: KEYGEN_MSG_VERSION,
             proof,
             big_s_i: big_s_i.to_affine(),
             public_key: self.big_f_vec.get_constant().to_affine(),
+            broadcast_digest: self.broadcast_digest,
         })
     }
 
     /// Round 4.
     pub fn handle_msg4(
         &mut self,
-        msgs: Vec<KeygenMsg4>,
+        msgs: impl IntoIterator<Item = impl Borrow<KeygenMsg4>>,
     ) -> Result<Keyshare, KeygenError> {
-        if msgs.len() != self.ranks.len() - 1 {
-            return Err(KeygenError::MissingMessage);
-        }
+        borrow_msgs!(msgs: KeygenMsg4);
+
+        let round = self.round;
+        let res = self.run_round(
+            KeygenRound::WaitMsg4,
+            msgs.iter().map(|m| (m.from_id, None, m.version)),
+            |state| state.handle_msg4_inner(&msgs),
+        );
+        self.report(round, res)
+    }
 
+    fn handle_msg4_inner(
+        &mut self,
+        msgs: &[&KeygenMsg4],
+    ) -> Result<Keyshare, KeygenError> {
         let public_key = self.big_f_vec.get_constant().to_affine();
         let mut big_s_list = Pairs::new();
         let mut proof_list = Pairs::new();
 
         for msg in msgs {
             if msg.public_key != public_key {
-                return Err(KeygenError::PublicKeyMismatch);
+                return Err(KeygenError::AbortProtocolAndBanParty(
+                    msg.from_id,
+                ));
+            }
+
+            if msg.broadcast_digest.ct_ne(&self.broadcast_digest).into() {
+                return Err(KeygenError::BroadcastDigestMismatch(
+                    msg.from_id,
+                ));
             }
 
             big_s_list.push(msg.from_id, msg.big_s_i.to_curve());
-            proof_list.push(msg.from_id, msg.proof);
+            proof_list.push(msg.from_id, msg.proof.clone());
         }
 
         let final_session_id_with_root_chain_code = {
@@ -871,8 +2253,10 @@ impl State {
             let mut transcript = Transcript::new(&DKG_LABEL);
             transcript
                 .append_message(b"final_session_id", &self.final_session_id);
-            transcript
-                .append_message(b"root_chain_code", &self.root_chain_code);
+            if let Some(root_chain_code) = &self.root_chain_code {
+                transcript
+                    .append_message(b"root_chain_code", root_chain_code);
+            }
             transcript
                 .challenge_bytes(&DLOG_SESSION_ID_WITH_CHAIN_CODE, &mut buf);
             buf
@@ -892,7 +2276,7 @@ impl State {
                 .unwrap_u8()
                 == 0
             {
-                return Err(KeygenError::InvalidDLogProof);
+                return Err(KeygenError::InvalidDLogProof(*party_id));
             }
         }
 
@@ -917,7 +2301,7 @@ impl State {
                 .sum();
 
             if expected_point != *big_s_list.find_pair(*party_id) {
-                return Err(KeygenError::BigSMismatch);
+                return Err(KeygenError::BigSMismatch(*party_id));
             }
         }
 
@@ -930,7 +2314,22 @@ impl State {
             &public_key.to_curve(),
         )?;
 
-        let share = Keyshare {
+        let negate = self.even_y && bool::from(public_key.y_is_odd());
+        if negate && self.key_refresh_data.is_some() {
+            return Err(KeygenError::InvalidKeyRefresh);
+        }
+
+        let (public_key, s_i, big_s_list) = if negate {
+            (
+                -public_key,
+                -self.s_i,
+                big_s_list.remove_ids().into_iter().map(|p| -p).collect(),
+            )
+        } else {
+            (public_key, self.s_i, big_s_list.remove_ids())
+        };
+
+        let mut share = Keyshare {
             total_parties: self.ranks.len() as u8,
             threshold: self.t,
             party_id: self.party_id,
@@ -938,186 +2337,1880 @@ impl State {
             public_key,
             root_chain_code: self.root_chain_code,
             x_i_list: self.x_i_list.remove_ids(),
-            big_s_list: big_s_list
-                .remove_ids()
-                .iter()
-                .map(|p| p.to_affine())
-                .collect(),
-            s_i: self.s_i,
-            sent_seed_list: self.seed_i_j_list.remove_ids(),
+            big_s_list: big_s_list.iter().map(|p| p.to_affine()).collect(),
+            s_i,
+            sent_seed_list: vec![],
             seed_ot_receivers: self.seed_ot_receivers.remove_ids(),
             seed_ot_senders: self.seed_ot_senders.remove_ids(),
-            rec_seed_list: self.rec_seed_list.remove_ids(),
+            rec_seed_list: vec![],
             final_session_id: self.final_session_id,
+            seed_refresh_counters: vec![0; self.ranks.len()],
+            zeta_seeds: self
+                .seed_i_j_list
+                .iter()
+                .chain(self.rec_seed_list.iter())
+                .fold(Pairs::new(), |seeds, (id, seed)| {
+                    seeds.add(*id, *seed)
+                }),
         };
 
-        Ok(share)
-    }
-}
-
-fn get_lagrange_coeff(
-    x_i: &NonZeroScalar,
-    x_i_list: &[NonZeroScalar],
-    party_ids: &[u8],
-) -> Scalar {
-    let mut coeff = Scalar::ONE;
-    let x_i = x_i as &Scalar;
-    for &party_id in party_ids {
-        let x_j = &x_i_list[party_id as usize] as &Scalar;
-        if x_i.ct_ne(x_j).into() {
-            let sub = x_j - x_i;
-            coeff *= x_j * &sub.invert().unwrap();
+        if let Some(seeds) = &self.ot_seeds {
+            seeds.apply(&mut share);
         }
-    }
 
-    coeff
-}
-
-#[cfg(test)]
-pub mod tests {
-    use serde::de::DeserializeOwned;
-
-    use super::*;
+        self.round = KeygenRound::Finished;
 
-    fn check_bincode<T: Serialize + DeserializeOwned>(v: &T) {
-        let bytes =
-            bincode::serde::encode_to_vec(v, bincode::config::standard())
-                .unwrap();
-        let _: (T, _) = bincode::serde::decode_from_slice(
-            &bytes,
-            bincode::config::standard(),
-        )
-        .unwrap();
+        Ok(share)
     }
 
-    fn check_json<T: Serialize + DeserializeOwned>(v: &T) {
-        let bytes = serde_json::to_string(v).unwrap();
-        let _: T = serde_json::from_str(&bytes).unwrap();
+    /// Current round of the state.
+    pub fn round(&self) -> KeygenRound {
+        self.round
     }
 
-    fn check_cbor<T: Serialize + DeserializeOwned>(v: &T) {
-        let mut w = vec![];
-        ciborium::into_writer(v, &mut w).unwrap();
+    /// ID of the party.
+    pub fn party_id(&self) -> u8 {
+        self.party_id
+    }
 
-        let _: T = ciborium::from_reader(w.as_ref() as &[u8]).unwrap();
+    /// Threshold of the key.
+    pub fn threshold(&self) -> u8 {
+        self.t
     }
 
-    pub fn check_serde<T: Serialize + DeserializeOwned>(messages: &[T]) {
-        for msg in messages {
-            check_bincode(msg);
-            check_json(msg);
-            check_cbor(msg);
+    /// Number of messages expected by the handler of the current
+    /// round, zero when the keygen is finished or aborted.
+    pub fn expected_message_count(&self) -> usize {
+        match self.round {
+            KeygenRound::Finished | KeygenRound::Aborted => 0,
+            _ => self.ranks.len() - 1,
         }
     }
 
-    fn init_states(n: u8, t: u8) -> Vec<State> {
-        let mut rng = rand::thread_rng();
-
-        (0..n)
-            .map(|party_id| {
-                State::new(
-                    Party {
-                        ranks: vec![0u8; n as usize],
-                        party_id,
-                        t,
-                    },
-                    &mut rng, // different seed for each party
-                )
-            })
-            .collect()
+    /// IDs of the parties which messages of the previous round were
+    /// processed, in ascending order.
+    pub fn received_from(&self) -> Vec<u8> {
+        let party_id = self.party_id;
+
+        match self.round {
+            KeygenRound::WaitMsg1 | KeygenRound::Aborted => vec![],
+            KeygenRound::WaitMsg2 => pair_ids(&self.sid_i_list, party_id),
+            KeygenRound::WaitMsg3 => pair_ids(&self.r_i_list, party_id),
+            KeygenRound::WaitMsg4 => pair_ids(&self.d_i_list, party_id),
+            KeygenRound::Finished => {
+                other_parties(&self.ranks, party_id).collect()
+            }
+        }
     }
 
-    pub fn dkg(n: u8, t: u8) -> Vec<Keyshare> {
-        let parties = init_states(n, t);
+    /// Restart a stalled keygen from the first round without the
+    /// parties in `excluded`, for example without a party which went
+    /// silent. Could be called in any round before the keygen is
+    /// finished. All remaining parties must restart with the same
+    /// list of excluded parties.
+    ///
+    /// The remaining parties get new party IDs, their indices in
+    /// ascending order of the old IDs, and keep their ranks and the
+    /// threshold. The new state keeps the evaluation point `x_i` and
+    /// the options of this state (session nonce, identity keys of the
+    /// remaining parties, chain code and even Y modes). Everything
+    /// else is sampled afresh: the secret polynomial is not reused,
+    /// since shares of it could already have been sent. A restarted
+    /// deterministic keygen uses `rng` as a regular keygen.
+    ///
+    /// A key refresh could not be restarted this way, because the
+    /// additive shares of the key depend on the set of parties; start
+    /// a new [`State::key_refresh`] instead.
+    ///
+    /// Returns [`KeygenError::InvalidParameters`] if `excluded`
+    /// contains the own ID, an ID out of range or an ID twice.
+    pub fn restart_without<R: RngCore + CryptoRng>(
+        &self,
+        excluded: &[u8],
+        rng: &mut R,
+    ) -> Result<Self, KeygenError> {
+        if self.key_refresh_data.is_some() {
+            return Err(KeygenError::InvalidKeyRefresh);
+        }
 
-        dkg_inner(parties)
-    }
+        let n = self.ranks.len() as u8;
+        for (idx, &p) in excluded.iter().enumerate() {
+            if p == self.party_id || p >= n || excluded[..idx].contains(&p) {
+                return Err(KeygenError::InvalidParameters);
+            }
+        }
 
-    pub fn dkg_inner(mut parties: Vec<State>) -> Vec<Keyshare> {
-        let mut rng = rand::thread_rng();
+        let remaining: Vec<u8> =
+            (0..n).filter(|p| !excluded.contains(p)).collect();
 
-        let msg1: Vec<KeygenMsg1> =
-            parties.iter_mut().map(|p| p.generate_msg1()).collect();
+        let party = Party {
+            ranks: remaining
+                .iter()
+                .map(|&p| self.ranks[p as usize])
+                .collect(),
+            t: self.t,
+            party_id: remaining
+                .iter()
+                .position(|&p| p == self.party_id)
+                .unwrap() as u8,
+        };
 
-        check_serde(&msg1);
+        if remaining.len() < self.t as usize {
+            return Err(KeygenError::InvalidRanks);
+        }
 
-        let mut msg2: Vec<KeygenMsg2> = vec![];
+        let mut state = Self::new(party, rng)?
+            .with_x_i(*self.x_i_list.find_pair(self.party_id));
 
-        for party in &mut parties {
-            let batch: Vec<KeygenMsg1> = msg1
+        state.session_nonce = self.session_nonce;
+        state.skip_chain_code = self.skip_chain_code;
+        state.even_y = self.even_y;
+        state.update_own_commitment();
+
+        if let (Some(keys), Some(secret)) =
+            (&self.identity_keys, self.identity_secret)
+        {
+            let keys = remaining
                 .iter()
-                .filter(|msg| msg.from_id != party.party_id)
-                .cloned()
+                .map(|&p| keys[p as usize].clone())
                 .collect();
-            msg2.extend(party.handle_msg1(&mut rng, batch).unwrap());
+            state =
+                state.with_identity_keys(keys, &SigningKey::from(secret))?;
         }
 
-        check_serde(&msg2);
-
-        let mut msg3: Vec<KeygenMsg3> = vec![];
+        Ok(state)
+    }
 
-        for party in &mut parties {
-            let batch: Vec<KeygenMsg2> = msg2
-                .iter()
-                .filter(|msg| msg.to_id == party.party_id)
-                .cloned()
-                .collect();
+    /// Wrap the state into a persistence envelope. The envelope is
+    /// serializable and contains secret material of the party.
+    pub fn suspend(self) -> SuspendedState {
+        SuspendedState {
+            version: STATE_SUSPEND_VERSION,
+            round: self.round,
+            checksum: self.checksum(STATE_SUSPEND_VERSION, self.round),
+            state: self,
+        }
+    }
 
-            msg3.extend(party.handle_msg2(&mut rng, batch).unwrap());
+    /// Restore a state suspended by [`State::suspend`]. Returns an
+    /// error if the envelope has unsupported version, the state is in
+    /// a round other than `round` or the checksum does not match.
+    pub fn resume(
+        suspended: SuspendedState,
+        round: KeygenRound,
+    ) -> Result<Self, KeygenError> {
+        let SuspendedState {
+            version,
+            round: suspended_round,
+            checksum,
+            state,
+        } = suspended;
+
+        if version != STATE_SUSPEND_VERSION {
+            return Err(KeygenError::UnsupportedStateVersion(version));
         }
 
-        check_serde(&msg3);
+        let valid = suspended_round == round
+            && state.round == round
+            && state.checksum(version, round).ct_eq(&checksum).into();
+        if !valid {
+            return Err(KeygenError::InvalidSuspendedState);
+        }
 
-        let mut msg4: Vec<KeygenMsg4> = vec![];
+        Ok(state)
+    }
 
-        let commitment_2_list = parties
+    fn checksum(&self, version: u8, round: KeygenRound) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(DKG_LABEL);
+        hasher.update([version, round as u8, self.party_id, self.t]);
+        hasher.update(&self.ranks);
+        hasher.update(self.session_nonce.unwrap_or_default());
+        for identity_key in self.identity_keys.iter().flatten() {
+            hasher.update((identity_key.len() as u64).to_be_bytes());
+            hasher.update(identity_key);
+        }
+        hasher.update(self.final_session_id);
+        hasher.update(self.broadcast_digest);
+        hasher.update(self.root_chain_code.unwrap_or_default());
+        for (party_id, sid) in self.sid_i_list.iter() {
+            hasher.update([*party_id]);
+            hasher.update(sid);
+        }
+        for (party_id, commitment) in self.commitment_list.iter() {
+            hasher.update([*party_id]);
+            hasher.update(commitment);
+        }
+        for (party_id, x_i) in self.x_i_list.iter() {
+            hasher.update([*party_id]);
+            hasher.update(x_i.to_bytes());
+        }
+        for point in self.big_f_vec.points() {
+            hasher.update(point.to_bytes());
+        }
+        hasher.update(self.s_i.to_bytes());
+        hasher.update(STATE_CHECKSUM_LABEL);
+        hasher.finalize().into()
+    }
+
+    /// Create an abort message blaming party `accused` for the error
+    /// `err` returned by one of the round handlers.
+    pub fn abort_message(
+        &self,
+        accused: u8,
+        err: &KeygenError,
+    ) -> KeygenAbort {
+        KeygenAbort {
+            from_id: self.party_id,
+            accused,
+            reason: AbortReason::from(err),
+        }
+    }
+
+    /// Validate an accusation of another party against own view of
+    /// the broadcast values of the accused party.
+    ///
+    /// [`AbortVerdict::Rejected`] means that the accused party sent
+    /// valid values to this party: either the accuser lies or the
+    /// accused party sent different values to different parties.
+    pub fn handle_abort(&self, msg: &KeygenAbort) -> AbortVerdict {
+        let accused = msg.accused;
+        if accused == self.party_id || accused as usize >= self.ranks.len() {
+            return AbortVerdict::Rejected;
+        }
+
+        match msg.reason {
+            AbortReason::InvalidCommitmentHash => {
+                let view = (
+                    self.sid_i_list.find_pair_or_err(accused, ()),
+                    self.x_i_list.find_pair_or_err(accused, ()),
+                    self.r_i_list.find_pair_or_err(accused, ()),
+                    self.commitment_list.find_pair_or_err(accused, ()),
+                    self.big_f_i_vecs.find_pair_or_err(accused, ()),
+                );
+                let (Ok(sid), Ok(x_i), Ok(r_i), Ok(commitment), Ok(big_f)) =
+                    view
+                else {
+                    return AbortVerdict::Unverifiable;
+                };
+
+                let commit_hash = hash_commitment(
+                    sid,
+                    accused as usize,
+                    self.ranks[accused as usize] as usize,
+                    x_i,
+                    big_f,
+                    r_i,
+                    self.identity_key(accused),
+                    self.mode(),
+                );
+
+                if commit_hash.ct_ne(commitment).into() {
+                    AbortVerdict::Confirmed
+                } else {
+                    AbortVerdict::Rejected
+                }
+            }
+
+            AbortReason::InvalidDLogProof => {
+                let view = (
+                    self.dlog_proofs_i_list.find_pair_or_err(accused, ()),
+                    self.big_f_i_vecs.find_pair_or_err(accused, ()),
+                );
+                let (Ok(proofs), Ok(big_f)) = view else {
+                    return AbortVerdict::Unverifiable;
+                };
+
+                let res = verify_dlog_proofs(
+                    &self.final_session_id,
+                    accused as usize,
+                    proofs,
+                    big_f.points(),
+                );
+
+                match res {
+                    Ok(()) => AbortVerdict::Rejected,
+                    Err(_) => AbortVerdict::Confirmed,
+                }
+            }
+
+            AbortReason::Other => AbortVerdict::Unverifiable,
+        }
+    }
+}
+
+/// Split a private key into random additive pieces for an import
+/// ceremony of `n` parties, one piece per party.
+///
+/// Unlike [`State::key_import`] the key holder does not take part in
+/// the ceremony and does not keep a key share. Every party passes its
+/// piece to [`State::key_import_piece`], all parties contribute fresh
+/// randomness to the resulting key shares. The pieces carry public
+/// commitments, so parties could check that the imported key matches
+/// the expected public key.
+///
+/// If `root_chain_code` is `None` a random one is generated.
+pub fn split_key_for_import<R: RngCore + CryptoRng>(
+    private_key: &NonZeroScalar,
+    root_chain_code: Option<[u8; 32]>,
+    n: u8,
+    rng: &mut R,
+) -> Vec<KeyImportPiece> {
+    let root_chain_code = root_chain_code.unwrap_or_else(|| rng.gen());
+
+    let mut pieces = (1..n)
+        .map(|_| *NonZeroScalar::random(&mut *rng))
+        .collect::<Vec<_>>();
+    let sum: Scalar = pieces.iter().sum();
+    pieces.insert(0, *private_key.as_ref() - sum);
+
+    let big_x_list = pieces
+        .iter()
+        .map(|x_i| (ProjectivePoint::GENERATOR * x_i).to_affine())
+        .collect::<Vec<_>>();
+
+    pieces
+        .into_iter()
+        .zip(0..)
+        .map(|(x_i, to_id)| KeyImportPiece {
+            to_id,
+            big_x_list: big_x_list.clone(),
+            root_chain_code,
+            x_i,
+        })
+        .collect()
+}
+
+/// Split an existing private key into key shares of `ranks.len()`
+/// parties with threshold `t` by a trusted dealer.
+///
+/// All parties of a key import ceremony are run locally, so the
+/// resulting key shares have pairwise OT seeds set up and could be
+/// used for signing immediately. The root chain code is random.
+/// The caller must distribute the key shares and erase the private
+/// key.
+///
+/// Returns [`KeygenError::InvalidParameters`] for less than two or
+/// more than 255 parties, or a threshold out of range.
+pub fn import_key<R: RngCore + CryptoRng>(
+    private_key: &NonZeroScalar,
+    ranks: Vec<u8>,
+    t: u8,
+    rng: &mut R,
+) -> Result<Vec<Keyshare>, KeygenError> {
+    if ranks.len() < 2 || ranks.len() > u8::MAX as usize {
+        return Err(KeygenError::InvalidParameters);
+    }
+    let n = ranks.len() as u8;
+
+    let public_key =
+        (ProjectivePoint::GENERATOR * private_key.as_ref()).to_affine();
+
+    let mut parties = (0..n)
+        .map(|party_id| {
+            let party = Party {
+                ranks: ranks.clone(),
+                t,
+                party_id,
+            };
+            if party_id == 0 {
+                State::key_import(party, private_key, None, rng)
+            } else {
+                State::key_import_join(party, 0, public_key, rng)
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let msg1: Vec<_> = parties.iter().map(|p| p.generate_msg1()).collect();
+
+    let mut msg2 = vec![];
+    for p in &mut parties {
+        let party_id = p.party_id;
+        let batch = msg1.iter().filter(|m| m.from_id != party_id);
+        msg2.extend(p.handle_msg1(rng, batch)?);
+    }
+
+    let commitments: Vec<_> =
+        parties.iter().map(|p| p.calculate_commitment_2()).collect();
+
+    let mut msg3 = vec![];
+    for p in &mut parties {
+        let party_id = p.party_id;
+        let batch = msg2.iter().filter(|m| m.to_id == party_id);
+        msg3.extend(p.handle_msg2(rng, batch)?);
+    }
+
+    let mut msg4 = vec![];
+    for p in &mut parties {
+        let party_id = p.party_id;
+        let batch = msg3.iter().filter(|m| m.to_id == party_id);
+        msg4.push(p.handle_msg3(rng, batch, &commitments)?);
+    }
+
+    parties
+        .iter_mut()
+        .map(|p| {
+            let party_id = p.party_id;
+            p.handle_msg4(msg4.iter().filter(|m| m.from_id != party_id))
+        })
+        .collect()
+}
+
+fn get_lagrange_coeff(
+    x_i: &NonZeroScalar,
+    x_i_list: &[NonZeroScalar],
+    party_ids: &[u8],
+) -> Scalar {
+    let mut coeff = Scalar::ONE;
+    let x_i = x_i as &Scalar;
+    for &party_id in party_ids {
+        let x_j = &x_i_list[party_id as usize] as &Scalar;
+        if x_i.ct_ne(x_j).into() {
+            let sub = x_j - x_i;
+            coeff *= x_j * &sub.invert().unwrap();
+        }
+    }
+
+    coeff
+}
+
+/// Birkhoff interpolation coefficient of `party_id` for the set of
+/// `party_ids` of hierarchical key shares, `None` if the party is not
+/// in the set.
+fn get_birkhoff_coeff(
+    party_id: u8,
+    x_i_list: &[NonZeroScalar],
+    ranks: &[u8],
+    party_ids: &[u8],
+) -> Option<Scalar> {
+    let params = party_ids
+        .iter()
+        .map(|p| (x_i_list[*p as usize], ranks[*p as usize] as usize))
+        .collect::<Vec<_>>();
+
+    let coeffs = birkhoff_coeffs(&params);
+
+    party_ids
+        .iter()
+        .zip(coeffs)
+        .find(|(p, _)| **p == party_id)
+        .map(|(_, c)| c)
+}
+
+/// Check that a key with the given ranks and threshold could be
+/// reconstructed: every rank is less than the threshold and the
+/// `t` parties of lowest ranks satisfy the Polya condition, i.e.
+/// the k-th lowest rank is not greater than k.
+fn valid_ranks(ranks: &[u8], t: u8) -> bool {
+    let mut sorted = ranks.to_vec();
+    sorted.sort_unstable();
+
+    sorted.len() >= t as usize
+        && sorted.iter().all(|&r| r < t)
+        && sorted
+            .iter()
+            .take(t as usize)
+            .zip(0..)
+            .all(|(&r, k)| r <= k)
+}
+
+/// Several party ids of one DKG ceremony controlled by one operator.
+///
+/// Weighted voting could be expressed by giving one participant more
+/// than one share, e.g. a custodian worth 2 votes holds 2 party ids.
+/// The container runs a `State` for each of its ids, delivers messages
+/// between them internally and exchanges only messages to and from
+/// other participants.
+pub struct MultiPartyState {
+    states: Vec<State>,
+    own_msg1: Vec<KeygenMsg1>,
+    own_msg2: Vec<KeygenMsg2>,
+    own_msg3: Vec<KeygenMsg3>,
+    own_msg4: Vec<KeygenMsg4>,
+}
+
+impl MultiPartyState {
+    /// Initialize key generation for all passed party ids.
+    ///
+    /// Returns [`KeygenError::InvalidParameters`] if `party_ids` is
+    /// empty, contains an ID twice or an ID out of range.
+    pub fn new<R: RngCore + CryptoRng>(
+        ranks: Vec<u8>,
+        t: u8,
+        party_ids: &[u8],
+        rng: &mut R,
+    ) -> Result<Self, KeygenError> {
+        let mut ids = party_ids.to_vec();
+        ids.sort_unstable();
+        ids.dedup();
+
+        let cond = ids.is_empty()
+            || ids.len() != party_ids.len()
+            || ids.iter().any(|p| *p as usize >= ranks.len());
+        if cond {
+            return Err(KeygenError::InvalidParameters);
+        }
+
+        let states = ids
+            .into_iter()
+            .map(|party_id| {
+                let party = Party {
+                    ranks: ranks.clone(),
+                    t,
+                    party_id,
+                };
+                State::new(party, rng)
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            states,
+            own_msg1: vec![],
+            own_msg2: vec![],
+            own_msg3: vec![],
+            own_msg4: vec![],
+        })
+    }
+
+    /// Party ids controlled by the container in ascending order.
+    pub fn party_ids(&self) -> impl Iterator<Item = u8> + '_ {
+        self.states.iter().map(|s| s.party_id)
+    }
+
+    fn is_own(&self, party_id: u8) -> bool {
+        self.states.iter().any(|s| s.party_id == party_id)
+    }
+
+    /// First messages of all own parties, to be broadcast to all other
+    /// participants.
+    pub fn generate_msg1(&mut self) -> Vec<KeygenMsg1> {
+        self.own_msg1 =
+            self.states.iter().map(|s| s.generate_msg1()).collect();
+        self.own_msg1.clone()
+    }
+
+    /// Handle first messages of all other participants. Returns
+    /// messages for other participants only.
+    pub fn handle_msg1<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+        msgs: impl IntoIterator<Item = impl Borrow<KeygenMsg1>>,
+    ) -> Result<Vec<KeygenMsg2>, KeygenError> {
+        borrow_msgs!(msgs: KeygenMsg1);
+        let mut output = vec![];
+
+        for state in &mut self.states {
+            let party_id = state.party_id;
+            let batch = msgs
+                .iter()
+                .copied()
+                .chain(&self.own_msg1)
+                .filter(|m| m.from_id != party_id);
+
+            output.extend(state.handle_msg1(rng, batch)?);
+        }
+
+        let (own, other) =
+            output.into_iter().partition(|m| self.is_own(m.to_id));
+        self.own_msg2 = own;
+
+        Ok(other)
+    }
+
+    /// Handle messages addressed to own parties. Returns messages for
+    /// other participants only.
+    pub fn handle_msg2<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+        msgs: impl IntoIterator<Item = impl Borrow<KeygenMsg2>>,
+    ) -> Result<Vec<KeygenMsg3>, KeygenError> {
+        borrow_msgs!(msgs: KeygenMsg2);
+        let own_msg2 = std::mem::take(&mut self.own_msg2);
+        let mut output = vec![];
+
+        for state in &mut self.states {
+            let party_id = state.party_id;
+            let batch = msgs
+                .iter()
+                .copied()
+                .chain(&own_msg2)
+                .filter(|m| m.to_id == party_id);
+
+            output.extend(state.handle_msg2(rng, batch)?);
+        }
+
+        let (own, other) =
+            output.into_iter().partition(|m| self.is_own(m.to_id));
+        self.own_msg3 = own;
+
+        Ok(other)
+    }
+
+    /// Commitments of all own parties as pairs (party_id, commitment).
+    pub fn calculate_commitment_2(&self) -> Vec<(u8, [u8; 32])> {
+        self.states
+            .iter()
+            .map(|s| (s.party_id, s.calculate_commitment_2()))
+            .collect()
+    }
+
+    /// Handle messages addressed to own parties. Returns broadcast
+    /// messages of all own parties.
+    pub fn handle_msg3<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+        msgs: impl IntoIterator<Item = impl Borrow<KeygenMsg3>>,
+        commitment_2_list: &[[u8; 32]],
+    ) -> Result<Vec<KeygenMsg4>, KeygenError> {
+        borrow_msgs!(msgs: KeygenMsg3);
+        let own_msg3 = std::mem::take(&mut self.own_msg3);
+        let mut output = vec![];
+
+        for state in &mut self.states {
+            let party_id = state.party_id;
+            let batch = msgs
+                .iter()
+                .copied()
+                .chain(&own_msg3)
+                .filter(|m| m.to_id == party_id);
+
+            output.push(state.handle_msg3(rng, batch, commitment_2_list)?);
+        }
+
+        self.own_msg4 = output.clone();
+
+        Ok(output)
+    }
+
+    /// Handle last messages of all other participants and return key
+    /// shares of all own parties.
+    pub fn handle_msg4(
+        &mut self,
+        msgs: impl IntoIterator<Item = impl Borrow<KeygenMsg4>>,
+    ) -> Result<Vec<Keyshare>, KeygenError> {
+        borrow_msgs!(msgs: KeygenMsg4);
+        let own_msg4 = std::mem::take(&mut self.own_msg4);
+
+        self.states
+            .iter_mut()
+            .map(|state| {
+                let party_id = state.party_id;
+                let batch = msgs
+                    .iter()
+                    .copied()
+                    .chain(&own_msg4)
+                    .filter(|m| m.from_id != party_id);
+
+                state.handle_msg4(batch)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use k256::elliptic_curve::group::GroupEncoding;
+    use serde::de::DeserializeOwned;
+
+    use super::*;
+
+    fn check_bincode<T: Serialize + DeserializeOwned>(v: &T) {
+        let bytes =
+            bincode::serde::encode_to_vec(v, bincode::config::standard())
+                .unwrap();
+        let _: (T, _) = bincode::serde::decode_from_slice(
+            &bytes,
+            bincode::config::standard(),
+        )
+        .unwrap();
+    }
+
+    fn check_json<T: Serialize + DeserializeOwned>(v: &T) {
+        let bytes = serde_json::to_string(v).unwrap();
+        let _: T = serde_json::from_str(&bytes).unwrap();
+    }
+
+    fn check_cbor<T: Serialize + DeserializeOwned>(v: &T) {
+        let mut w = vec![];
+        ciborium::into_writer(v, &mut w).unwrap();
+
+        let _: T = ciborium::from_reader(w.as_ref() as &[u8]).unwrap();
+    }
+
+    pub fn check_serde<T: Serialize + DeserializeOwned>(messages: &[T]) {
+        for msg in messages {
+            check_bincode(msg);
+            check_json(msg);
+            check_cbor(msg);
+        }
+    }
+
+    pub fn check_encoded_size<T: Serialize>(messages: &[T], max: usize) {
+        for msg in messages {
+            let mut cbor = vec![];
+            ciborium::into_writer(msg, &mut cbor).unwrap();
+            assert!(cbor.len() <= max);
+
+            let bincode = bincode::serde::encode_to_vec(
+                msg,
+                bincode::config::standard(),
+            )
+            .unwrap();
+            assert!(bincode.len() <= max);
+        }
+    }
+
+    pub fn init_states(ranks: &[u8], t: u8) -> Vec<State> {
+        let mut rng = rand::thread_rng();
+
+        (0..ranks.len() as u8)
+            .map(|party_id| {
+                State::new(
+                    Party {
+                        ranks: ranks.to_vec(),
+                        party_id,
+                        t,
+                    },
+                    &mut rng, // different seed for each party
+                )
+                .unwrap()
+            })
+            .collect()
+    }
+
+    pub fn dkg(n: u8, t: u8) -> Vec<Keyshare> {
+        dkg_with_ranks(&vec![0; n as usize], t)
+    }
+
+    pub fn dkg_with_ranks(ranks: &[u8], t: u8) -> Vec<Keyshare> {
+        let parties = init_states(ranks, t);
+
+        dkg_inner(parties)
+    }
+
+    /// Run first two rounds and return KeygenMsg3 messages and the
+    /// list of chain code commitments.
+    fn dkg_round2(parties: &mut [State]) -> (Vec<KeygenMsg3>, Vec<[u8; 32]>) {
+        let mut rng = rand::thread_rng();
+
+        let msg1: Vec<KeygenMsg1> =
+            parties.iter_mut().map(|p| p.generate_msg1()).collect();
+
+        check_serde(&msg1);
+
+        let mut msg2: Vec<KeygenMsg2> = vec![];
+
+        for party in parties.iter_mut() {
+            let party_id = party.party_id;
+            let batch = msg1.iter().filter(|msg| msg.from_id != party_id);
+            msg2.extend(party.handle_msg1(&mut rng, batch).unwrap());
+        }
+
+        check_serde(&msg2);
+
+        let mut msg3: Vec<KeygenMsg3> = vec![];
+
+        for party in parties.iter_mut() {
+            let party_id = party.party_id;
+            let batch = msg2.iter().filter(|msg| msg.to_id == party_id);
+
+            msg3.extend(party.handle_msg2(&mut rng, batch).unwrap());
+        }
+
+        check_serde(&msg3);
+
+        let commitment_2_list = parties
             .iter()
             .map(|p| p.calculate_commitment_2())
             .collect::<Vec<_>>();
 
-        for party in &mut parties {
-            let batch: Vec<KeygenMsg3> = msg3
-                .iter()
-                .filter(|msg| msg.to_id == party.party_id)
-                .cloned()
-                .collect();
+        (msg3, commitment_2_list)
+    }
+
+    pub fn dkg_inner(mut parties: Vec<State>) -> Vec<Keyshare> {
+        let mut rng = rand::thread_rng();
+
+        let (msg3, commitment_2_list) = dkg_round2(&mut parties);
+
+        let mut msg4: Vec<KeygenMsg4> = vec![];
+
+        for party in &mut parties {
+            let party_id = party.party_id;
+            let batch = msg3.iter().filter(|msg| msg.to_id == party_id);
+
+            msg4.push(
+                party
+                    .handle_msg3(&mut rng, batch, &commitment_2_list)
+                    .unwrap(),
+            );
+        }
+
+        check_serde(&msg4);
+
+        parties
+            .into_iter()
+            .map(|mut party| {
+                let party_id = party.party_id;
+                let batch = msg4.iter().filter(|msg| msg.from_id != party_id);
+
+                party.handle_msg4(batch).unwrap()
+            })
+            .collect()
+    }
+
+    fn deterministic_dkg(seed: u8) -> Vec<Keyshare> {
+        let mut parties = (0..3)
+            .map(|id| {
+                State::new_deterministic(
+                    Party::new(3, 2, id).unwrap(),
+                    [seed + id as u8; 32],
+                )
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let msg1: Vec<KeygenMsg1> =
+            parties.iter_mut().map(|p| p.generate_msg1()).collect();
+
+        let mut msg2: Vec<KeygenMsg2> = vec![];
+        for party in parties.iter_mut() {
+            let party_id = party.party_id;
+            let mut rng = party.round_rng().unwrap();
+            let batch = msg1.iter().filter(|msg| msg.from_id != party_id);
+            msg2.extend(party.handle_msg1(&mut rng, batch).unwrap());
+        }
+
+        let commitment_2_list: Vec<[u8; 32]> =
+            parties.iter().map(|p| p.calculate_commitment_2()).collect();
+
+        let mut msg3: Vec<KeygenMsg3> = vec![];
+        for party in parties.iter_mut() {
+            let party_id = party.party_id;
+            let mut rng = party.round_rng().unwrap();
+            let batch = msg2.iter().filter(|msg| msg.to_id == party_id);
+            msg3.extend(party.handle_msg2(&mut rng, batch).unwrap());
+        }
+
+        let mut msg4: Vec<KeygenMsg4> = vec![];
+        for party in parties.iter_mut() {
+            let party_id = party.party_id;
+            let mut rng = party.round_rng().unwrap();
+            let batch = msg3.iter().filter(|msg| msg.to_id == party_id);
+            msg4.push(
+                party
+                    .handle_msg3(&mut rng, batch, &commitment_2_list)
+                    .unwrap(),
+            );
+        }
+
+        parties
+            .iter_mut()
+            .map(|party| {
+                let party_id = party.party_id;
+                let batch = msg4.iter().filter(|msg| msg.from_id != party_id);
+                party.handle_msg4(batch).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn deterministic_keygen() {
+        let a = deterministic_dkg(1);
+        let b = deterministic_dkg(1);
+        let c = deterministic_dkg(2);
+
+        for (a, b) in a.iter().zip(&b) {
+            assert_eq!(a.to_compact_bytes(), b.to_compact_bytes());
+        }
+        assert_ne!(a[0].public_key, c[0].public_key);
+
+        let mut rng = rand::thread_rng();
+        assert!(State::new(Party::new(3, 2, 0).unwrap(), &mut rng)
+            .unwrap()
+            .round_rng()
+            .is_none());
+    }
+
+    #[test]
+    fn dkg2_out_of_2() {
+        dkg(2, 2);
+    }
+
+    #[test]
+    fn dkg2_out_of_3() {
+        dkg(3, 2);
+    }
+
+    #[test]
+    fn dkg_3_out_of_3() {
+        dkg(3, 3);
+    }
+
+    #[test]
+    fn weighted_party_invalid_ids() {
+        let mut rng = rand::thread_rng();
+
+        for ids in [&[][..], &[0, 0], &[1, 4]] {
+            assert!(matches!(
+                MultiPartyState::new(vec![0; 4], 3, ids, &mut rng),
+                Err(KeygenError::InvalidParameters)
+            ));
+        }
+    }
+
+    #[test]
+    fn dkg_weighted_party() {
+        let mut rng = rand::thread_rng();
+
+        // party 0 holds two votes out of 4, threshold is 3
+        let mut custodian =
+            MultiPartyState::new(vec![0; 4], 3, &[0, 1], &mut rng).unwrap();
+        let mut parties = (2..4)
+            .map(|id| {
+                State::new(Party::new(4, 3, id).unwrap(), &mut rng).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let mut msg1 = custodian.generate_msg1();
+        msg1.extend(parties.iter().map(|p| p.generate_msg1()));
+
+        // the custodian gets only messages of the other participants
+        let mut msg2 = custodian
+            .handle_msg1(&mut rng, msg1.iter().filter(|m| m.from_id >= 2))
+            .unwrap();
+        for p in parties.iter_mut() {
+            let party_id = p.party_id;
+            let batch = msg1.iter().filter(|m| m.from_id != party_id);
+            msg2.extend(p.handle_msg1(&mut rng, batch).unwrap());
+        }
+        // messages between own parties are not exposed
+        assert!(msg2.iter().all(|m| m.from_id >= 2 || m.to_id >= 2));
+
+        let mut msg3 = custodian
+            .handle_msg2(&mut rng, msg2.iter().filter(|m| m.to_id < 2))
+            .unwrap();
+        for p in parties.iter_mut() {
+            let party_id = p.party_id;
+            let batch = msg2.iter().filter(|m| m.to_id == party_id);
+            msg3.extend(p.handle_msg2(&mut rng, batch).unwrap());
+        }
+
+        let mut commitments = custodian
+            .calculate_commitment_2()
+            .into_iter()
+            .map(|(_, c)| c)
+            .collect::<Vec<_>>();
+        commitments
+            .extend(parties.iter().map(|p| p.calculate_commitment_2()));
+
+        let mut msg4 = custodian
+            .handle_msg3(
+                &mut rng,
+                msg3.iter().filter(|m| m.to_id < 2),
+                &commitments,
+            )
+            .unwrap();
+        for p in parties.iter_mut() {
+            let party_id = p.party_id;
+            let batch = msg3.iter().filter(|m| m.to_id == party_id);
+            msg4.push(p.handle_msg3(&mut rng, batch, &commitments).unwrap());
+        }
+
+        let mut shares = custodian
+            .handle_msg4(msg4.iter().filter(|m| m.from_id >= 2))
+            .unwrap();
+        for mut p in parties {
+            let party_id = p.party_id;
+            let batch = msg4.iter().filter(|m| m.from_id != party_id);
+            shares.push(p.handle_msg4(batch).unwrap());
+        }
+
+        assert_eq!(shares.len(), 4);
+        assert!(shares.iter().all(|s| s.public_key == shares[0].public_key));
+    }
+
+    #[test]
+    fn keyshare_compact_encoding() {
+        for share in dkg(3, 2) {
+            let bytes = share.to_compact_bytes();
+            assert_eq!(bytes.len(), Keyshare::compact_size(3));
+
+            let mut cbor = vec![];
+            ciborium::into_writer(&share, &mut cbor).unwrap();
+            assert!(bytes.len() < cbor.len());
+
+            let decoded = Keyshare::from_compact_bytes(&bytes).unwrap();
+            assert_eq!(decoded.to_compact_bytes(), bytes);
+
+            assert!(matches!(
+                Keyshare::from_compact_bytes(&bytes[..bytes.len() - 1]),
+                Err(KeyshareEncodingError::InvalidLength)
+            ));
+
+            // version 2 has no chain code flag
+            let flag = 4 + 3 + 33;
+            assert_eq!(bytes[flag], 1);
+            let mut v2 = bytes.clone();
+            v2.remove(flag);
+            v2[0] = 2;
+            let decoded = Keyshare::from_compact_bytes(&v2).unwrap();
+            assert_eq!(decoded.to_compact_bytes(), bytes);
+
+            // version 1 has no seed refresh counters
+            let mut v1 = v2[..v2.len() - 3 * 4].to_vec();
+            v1[0] = 1;
+            let decoded = Keyshare::from_compact_bytes(&v1).unwrap();
+            assert_eq!(decoded.to_compact_bytes(), bytes);
+        }
+    }
+
+    #[test]
+    fn keyshare_public_json() {
+        let shares = dkg(3, 2);
+
+        let json = serde_json::to_value(shares[1].public_data()).unwrap();
+        assert_eq!(json["version"], KEYSHARE_PUBLIC_VERSION);
+        assert_eq!(json["party_id"], 1);
+        assert_eq!(
+            json["public_key"],
+            hex::encode(GroupEncoding::to_bytes(&shares[1].public_key))
+        );
+
+        let public: KeysharePublic =
+            serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(public.public_key, shares[1].public_key);
+        assert_eq!(public.big_s_list, shares[1].big_s_list);
+        assert!(public
+            .x_i_list
+            .iter()
+            .zip(&shares[1].x_i_list)
+            .all(|(a, b)| a.to_bytes() == b.to_bytes()));
+
+        // only known versions are accepted
+        let versions = [(0, false), (1, true), (2, true), (3, false)];
+        for (version, known) in versions {
+            let mut json = json.clone();
+            json["version"] = version.into();
+            let public = serde_json::from_value::<KeysharePublic>(json);
+            assert_eq!(public.is_ok(), known);
+        }
+    }
+
+    #[test]
+    fn keyshare_verify_against() {
+        let shares = dkg(3, 2);
+        let public: Vec<_> = shares.iter().map(|s| s.public_data()).collect();
+
+        for share in &shares {
+            let others: Vec<_> = public
+                .iter()
+                .filter(|p| p.party_id != share.party_id)
+                .cloned()
+                .collect();
+            share.verify_against(&others).unwrap();
+        }
+
+        let mut tampered = public[2].clone();
+        tampered.big_s_list[0] = tampered.big_s_list[1];
+        assert!(matches!(
+            shares[0].verify_against(&[public[1].clone(), tampered]),
+            Err(KeygenError::KeyshareMismatch(2))
+        ));
+
+        assert!(matches!(
+            shares[0].verify_against(&[public[0].clone()]),
+            Err(KeygenError::InvalidSenderId(0))
+        ));
+
+        let mut share = shares[0].clone();
+        share.s_i += Scalar::ONE;
+        assert!(matches!(
+            share.verify_against(&public[1..]),
+            Err(KeygenError::BigSMismatch(0))
+        ));
+
+        // a consistent but wrong list does not reconstruct the key
+        let other = dkg(3, 2);
+        let mut share = shares[0].clone();
+        share.big_s_list[1] = other[0].big_s_list[1];
+        assert!(matches!(
+            share.verify_against(&[]),
+            Err(KeygenError::PublicKeyMismatch)
+        ));
+    }
+
+    #[test]
+    fn broadcast_digest_mismatch() {
+        let mut rng = rand::thread_rng();
+        let mut parties = init_states(&[0; 3], 2);
+
+        let (msg3, commitment_2_list) = dkg_round2(&mut parties);
+
+        let msg4 = parties
+            .iter_mut()
+            .map(|party| {
+                // party 0 sees a different commitment for itself
+                let mut commitments = commitment_2_list.clone();
+                if party.party_id == 0 {
+                    commitments[0] = [0; 32];
+                }
+
+                let batch: Vec<KeygenMsg3> = msg3
+                    .iter()
+                    .filter(|msg| msg.to_id == party.party_id)
+                    .cloned()
+                    .collect();
+
+                party.handle_msg3(&mut rng, batch, &commitments).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let batch: Vec<KeygenMsg4> = msg4
+            .iter()
+            .filter(|msg| msg.from_id != 1)
+            .cloned()
+            .collect();
+
+        assert!(matches!(
+            parties[1].handle_msg4(batch),
+            Err(KeygenError::BroadcastDigestMismatch(0))
+        ));
+    }
+
+    #[test]
+    fn echo_of_p2p_broadcast_values() {
+        let mut rng = rand::thread_rng();
+        let mut parties = init_states(&[0; 3], 2);
+
+        let (msg3, commitment_2_list) = dkg_round2(&mut parties);
+
+        // party 2 received a different r_i from party 0
+        let mut r_i_list = Pairs::new();
+        for (party_id, r_i) in parties[2].r_i_list.iter() {
+            let r_i = if *party_id == 0 { [1; 32] } else { *r_i };
+            r_i_list.push(*party_id, r_i);
+        }
+        parties[2].r_i_list = r_i_list;
+
+        let msg4 = parties
+            .iter_mut()
+            .map(|party| {
+                let party_id = party.party_id;
+                let batch = msg3.iter().filter(|msg| msg.to_id == party_id);
+                party
+                    .handle_msg3(&mut rng, batch, &commitment_2_list)
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let batch = msg4.iter().filter(|msg| msg.from_id != 1);
+        assert!(matches!(
+            parties[1].handle_msg4(batch),
+            Err(KeygenError::BroadcastDigestMismatch(2))
+        ));
+    }
+
+    #[test]
+    fn dkg_with_identity_keys() {
+        let mut rng = rand::thread_rng();
+        let signing_keys: Vec<SigningKey> =
+            (0..4).map(|_| SigningKey::random(&mut rng)).collect();
+        let public = |key: &SigningKey| {
+            key.verifying_key()
+                .to_encoded_point(true)
+                .as_bytes()
+                .to_vec()
+        };
+        let identity_keys: Vec<Vec<u8>> =
+            signing_keys[..3].iter().map(public).collect();
+
+        let init = |keys: &[Vec<u8>]| -> Vec<State> {
+            init_states(&[0; 3], 2)
+                .into_iter()
+                .map(|p| {
+                    let key = &signing_keys[p.party_id as usize];
+                    p.with_identity_keys(keys.to_vec(), key).unwrap()
+                })
+                .collect()
+        };
+
+        let shares = dkg_inner(init(&identity_keys));
+        assert!(shares
+            .iter()
+            .all(|s| s.final_session_id == shares[0].final_session_id));
+
+        let round1 = |parties: &mut [State]| {
+            let mut rng = rand::thread_rng();
+            let msg1: Vec<KeygenMsg1> =
+                parties.iter().map(|p| p.generate_msg1()).collect();
+            let mut msg2 = vec![];
+            for party in parties.iter_mut() {
+                let party_id = party.party_id;
+                let batch = msg1.iter().filter(|m| m.from_id != party_id);
+                msg2.extend(party.handle_msg1(&mut rng, batch).unwrap());
+            }
+            msg2.retain(|m| m.to_id == 2);
+            msg2
+        };
+
+        // a relay replays the signature of party 0 as the one of
+        // party 1
+        let mut parties = init(&identity_keys);
+        let mut msg2 = round1(&mut parties);
+        msg2[1].identity_signature = msg2[0].identity_signature;
+        assert!(matches!(
+            parties[2].handle_msg2(&mut rng, &msg2),
+            Err(KeygenError::InvalidIdentitySignature(1))
+        ));
+
+        // a message without a signature
+        let mut parties = init(&identity_keys);
+        let mut msg2 = round1(&mut parties);
+        msg2[1].identity_signature = None;
+        assert!(matches!(
+            parties[2].handle_msg2(&mut rng, &msg2),
+            Err(KeygenError::InvalidIdentitySignature(1))
+        ));
+
+        // party 2 has a different identity key of party 1, so its
+        // final session ID differs from the one signed by others
+        let mut substituted = identity_keys.clone();
+        substituted[1] = public(&signing_keys[3]);
+        let mut parties = init(&identity_keys);
+        parties[2] = init_states(&[0; 3], 2)
+            .remove(2)
+            .with_identity_keys(substituted, &signing_keys[2])
+            .unwrap();
+        let msg2 = round1(&mut parties);
+        assert!(matches!(
+            parties[2].handle_msg2(&mut rng, &msg2),
+            Err(KeygenError::InvalidIdentitySignature(0))
+        ));
+
+        let state = || {
+            State::new(Party::new(3, 2, 0).unwrap(), &mut rand::thread_rng())
+        };
+        assert!(matches!(
+            state().unwrap().with_identity_keys(
+                identity_keys[..1].to_vec(),
+                &signing_keys[0]
+            ),
+            Err(KeygenError::InvalidIdentityKeys)
+        ));
+        assert!(matches!(
+            state()
+                .unwrap()
+                .with_identity_keys(identity_keys.clone(), &signing_keys[1]),
+            Err(KeygenError::InvalidIdentityKeys)
+        ));
+    }
+
+    #[test]
+    fn restart_without_silent_party() {
+        let mut rng = rand::thread_rng();
+        let mut parties = init_states(&[0; 4], 2);
+
+        // party 1 goes silent after the first round
+        let msg1: Vec<KeygenMsg1> =
+            parties.iter().map(|p| p.generate_msg1()).collect();
+        for party in &mut parties {
+            let party_id = party.party_id;
+            let batch = msg1.iter().filter(|msg| msg.from_id != party_id);
+            party.handle_msg1(&mut rng, batch).unwrap();
+        }
+
+        let restarted = parties
+            .iter()
+            .filter(|p| p.party_id != 1)
+            .map(|p| p.restart_without(&[1], &mut rng).unwrap())
+            .collect::<Vec<_>>();
+
+        assert!(restarted.iter().all(|p| p.round() == KeygenRound::WaitMsg1));
+        assert_eq!(
+            restarted.iter().map(|p| p.party_id()).collect::<Vec<_>>(),
+            [0, 1, 2]
+        );
+        assert_eq!(
+            restarted[2].x_i_list.find_pair(2).to_bytes(),
+            parties[3].x_i_list.find_pair(3).to_bytes()
+        );
+
+        let shares = dkg_inner(restarted);
+        assert!(shares.iter().all(|s| s.total_parties == 3));
+
+        assert!(matches!(
+            parties[0].restart_without(&[0], &mut rng),
+            Err(KeygenError::InvalidParameters)
+        ));
+        assert!(matches!(
+            parties[0].restart_without(&[1, 1], &mut rng),
+            Err(KeygenError::InvalidParameters)
+        ));
+        assert!(matches!(
+            parties[0].restart_without(&[1, 2, 3], &mut rng),
+            Err(KeygenError::InvalidRanks)
+        ));
+    }
+
+    #[test]
+    fn dkg_with_split_msg2() {
+        let mut rng = rand::thread_rng();
+        let mut parties = init_states(&[0; 3], 2);
+
+        let msg1: Vec<KeygenMsg1> =
+            parties.iter().map(|p| p.generate_msg1()).collect();
+
+        let mut broadcast = vec![];
+        let mut p2p = vec![];
+        for party in &mut parties {
+            let party_id = party.party_id;
+            let batch = msg1.iter().filter(|msg| msg.from_id != party_id);
+            let (b, msgs) = party.handle_msg1_split(&mut rng, batch).unwrap();
+            broadcast.push(b);
+            p2p.extend(msgs);
+        }
+
+        check_serde(&broadcast);
+        check_serde(&p2p);
+
+        let commitment_2_list = parties
+            .iter()
+            .map(|p| p.calculate_commitment_2())
+            .collect::<Vec<_>>();
+
+        let mut msg3 = vec![];
+        for party in &mut parties {
+            let party_id = party.party_id;
+            let b = broadcast.iter().filter(|msg| msg.from_id != party_id);
+            let batch = p2p.iter().filter(|msg| msg.to_id == party_id);
+            msg3.extend(party.handle_msg2_split(&mut rng, b, batch).unwrap());
+        }
+
+        let mut msg4 = vec![];
+        for party in &mut parties {
+            let party_id = party.party_id;
+            let batch = msg3.iter().filter(|msg| msg.to_id == party_id);
+            msg4.push(
+                party
+                    .handle_msg3(&mut rng, batch, &commitment_2_list)
+                    .unwrap(),
+            );
+        }
+
+        for party in &mut parties {
+            let party_id = party.party_id;
+            let batch = msg4.iter().filter(|msg| msg.from_id != party_id);
+            party.handle_msg4(batch).unwrap();
+        }
+
+        // a P2P part without the broadcast part
+        let mut parties = init_states(&[0; 3], 2);
+        let msg1: Vec<KeygenMsg1> =
+            parties.iter().map(|p| p.generate_msg1()).collect();
+        let (b, p2p): (Vec<_>, Vec<_>) = parties
+            .iter_mut()
+            .map(|party| {
+                let party_id = party.party_id;
+                let batch = msg1.iter().filter(|msg| msg.from_id != party_id);
+                party.handle_msg1_split(&mut rng, batch).unwrap()
+            })
+            .unzip();
+        let p2p = p2p.into_iter().flatten().filter(|msg| msg.to_id == 0);
+        assert!(matches!(
+            parties[0].handle_msg2_split(&mut rng, &b[1..2], p2p),
+            Err(KeygenError::MissingMessage)
+        ));
+    }
+
+    #[test]
+    fn keygen_events() {
+        use std::sync::mpsc;
+
+        let mut rng = rand::thread_rng();
+        let (tx, rx) = mpsc::channel();
+
+        let mut parties = init_states(&[0; 3], 2);
+        let party = parties.remove(0).with_observer(move |event| {
+            let event = match event {
+                KeygenEvent::Progress { round, from_id } => {
+                    format!("progress {round:?} {from_id}")
+                }
+                KeygenEvent::MessageVerified { round, from_id } => {
+                    format!("{round:?} {from_id}")
+                }
+                KeygenEvent::RoundCompleted(round) => format!("{round:?}"),
+                KeygenEvent::Failed(err) => format!("{err}"),
+            };
+            tx.send(event).unwrap();
+        });
+        parties.insert(0, party);
+
+        dkg_inner(parties);
+
+        let events: Vec<String> = rx.try_iter().collect();
+        assert_eq!(
+            events,
+            [
+                "WaitMsg1 1",
+                "WaitMsg1 2",
+                "WaitMsg2",
+                "progress WaitMsg2 1",
+                "progress WaitMsg2 2",
+                "WaitMsg2 1",
+                "WaitMsg2 2",
+                "WaitMsg3",
+                "WaitMsg3 1",
+                "WaitMsg3 2",
+                "WaitMsg4",
+                "WaitMsg4 1",
+                "WaitMsg4 2",
+                "Finished",
+            ]
+        );
+
+        let (tx, rx) = mpsc::channel();
+        let mut party = State::new(Party::new(3, 2, 0).unwrap(), &mut rng)
+            .unwrap()
+            .with_observer(move |event| {
+                if let KeygenEvent::Failed(err) = event {
+                    tx.send(err.to_string()).unwrap();
+                }
+            });
+        assert!(party.handle_msg1(&mut rng, &[] as &[KeygenMsg1]).is_err());
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), ["Missing message"]);
+    }
+
+    #[test]
+    fn keygen_round2_progress() {
+        fn assert_sync<T: Sync>() {}
+
+        // an observer keeps the state Sync
+        assert_sync::<State>();
+
+        let mut rng = rand::thread_rng();
+        let mut parties = init_states(&[0; 3], 2);
+
+        let msg1: Vec<KeygenMsg1> =
+            parties.iter_mut().map(|p| p.generate_msg1()).collect();
+
+        let mut msg2: Vec<KeygenMsg2> = vec![];
+        for party in parties.iter_mut() {
+            let party_id = party.party_id;
+            let batch = msg1.iter().filter(|msg| msg.from_id != party_id);
+            msg2.extend(party.handle_msg1(&mut rng, batch).unwrap());
+        }
+
+        let batch = msg2.iter().filter(|msg| msg.to_id == 0);
+        let mut done = vec![];
+        parties[0]
+            .handle_msg2_with_progress(&mut rng, batch, |id| done.push(id))
+            .unwrap();
+
+        // progress is reported once for every counterparty
+        assert_eq!(done, [1, 2]);
+    }
+
+    #[test]
+    fn dkg_with_caller_x_i() {
+        let mut rng = rand::thread_rng();
+        let x_i =
+            |p: u8| NonZeroScalar::new(Scalar::from(p as u64 + 1)).unwrap();
+
+        let parties = init_states(&[0; 3], 2)
+            .into_iter()
+            .map(|p| {
+                let party_id = p.party_id;
+                p.with_x_i(x_i(party_id))
+            })
+            .collect();
+        let shares = dkg_inner(parties);
+
+        for share in &shares {
+            let x_i_list: Vec<_> =
+                share.x_i_list.iter().map(|x| x.to_bytes()).collect();
+            let expected: Vec<_> =
+                (0..3).map(|p| x_i(p).to_bytes()).collect();
+            assert_eq!(x_i_list, expected);
+        }
+
+        let mut parties: Vec<State> = init_states(&[0; 3], 2)
+            .into_iter()
+            .map(|p| p.with_x_i(x_i(0)))
+            .collect();
 
-            msg4.push(
-                party
-                    .handle_msg3(&mut rng, batch, &commitment_2_list)
-                    .unwrap(),
-            );
+        let msg1: Vec<KeygenMsg1> =
+            parties.iter_mut().map(|p| p.generate_msg1()).collect();
+        let batch = msg1.iter().filter(|msg| msg.from_id != 0);
+        assert!(matches!(
+            parties[0].handle_msg1(&mut rng, batch),
+            Err(KeygenError::NotUniqueXiValues(1))
+        ));
+    }
+
+    #[test]
+    fn round_introspection() {
+        let mut rng = rand::thread_rng();
+        let mut parties = init_states(&[0; 3], 2);
+
+        assert_eq!(parties[0].expected_message_count(), 2);
+        assert!(parties[0].received_from().is_empty());
+
+        let (msg3, commitment_2_list) = dkg_round2(&mut parties);
+        assert_eq!(parties[1].round(), KeygenRound::WaitMsg3);
+        assert_eq!(parties[1].received_from(), [0, 2]);
+
+        let batch = msg3.iter().filter(|msg| msg.to_id == 2);
+        parties[2]
+            .handle_msg3(&mut rng, batch, &commitment_2_list)
+            .unwrap();
+        assert_eq!(parties[2].round(), KeygenRound::WaitMsg4);
+        assert_eq!(parties[2].received_from(), [0, 1]);
+        assert_eq!(parties[2].expected_message_count(), 2);
+    }
+
+    #[test]
+    fn reject_handler_of_another_round() {
+        let mut rng = rand::thread_rng();
+        let mut parties = init_states(&[0; 3], 2);
+
+        let (msg3, commitment_2_list) = dkg_round2(&mut parties);
+
+        // handlers of other rounds are rejected
+        assert!(matches!(
+            parties[0].handle_msg2(&mut rng, Vec::<KeygenMsg2>::new()),
+            Err(KeygenError::UnexpectedRound(KeygenRound::WaitMsg3))
+        ));
+        assert!(matches!(
+            parties[0].handle_msg4(Vec::<KeygenMsg4>::new()),
+            Err(KeygenError::UnexpectedRound(KeygenRound::WaitMsg3))
+        ));
+
+        // the state is not affected
+        let batch = msg3.iter().filter(|msg| msg.to_id == 0);
+        parties[0]
+            .handle_msg3(&mut rng, batch, &commitment_2_list)
+            .unwrap();
+        assert_eq!(parties[0].round(), KeygenRound::WaitMsg4);
+    }
+
+    #[test]
+    fn retry_round_after_rejected_batch() {
+        let mut rng = rand::thread_rng();
+        let mut parties = init_states(&[0; 3], 2);
+
+        let msg1: Vec<KeygenMsg1> =
+            parties.iter_mut().map(|p| p.generate_msg1()).collect();
+
+        // a rejected batch leaves the round usable
+        assert!(matches!(
+            parties[0].handle_msg1(&mut rng, &msg1[1..2]),
+            Err(KeygenError::MissingMessage)
+        ));
+        assert!(matches!(
+            parties[0].handle_msg1(&mut rng, [&msg1[1], &msg1[1]]),
+            Err(KeygenError::DuplicateMessage(1))
+        ));
+        assert_eq!(parties[0].round(), KeygenRound::WaitMsg1);
+        parties[0].handle_msg1(&mut rng, &msg1[1..]).unwrap();
+        assert_eq!(parties[0].round(), KeygenRound::WaitMsg2);
+
+        // a failed handler aborts the keygen
+        let mut msg1 = msg1.clone();
+        msg1[2].x_i = msg1[0].x_i;
+        assert!(matches!(
+            parties[1].handle_msg1(&mut rng, [&msg1[0], &msg1[2]]),
+            Err(KeygenError::NotUniqueXiValues(_))
+        ));
+        assert_eq!(parties[1].round(), KeygenRound::Aborted);
+        assert_eq!(parties[1].expected_message_count(), 0);
+        assert!(matches!(
+            parties[1].handle_msg1(&mut rng, [&msg1[0], &msg1[2]]),
+            Err(KeygenError::UnexpectedRound(KeygenRound::Aborted))
+        ));
+    }
+
+    #[test]
+    fn message_version_mismatch() {
+        let mut rng = rand::thread_rng();
+        let mut parties = init_states(&[0; 3], 2);
+
+        let mut msg1: Vec<KeygenMsg1> =
+            parties.iter_mut().map(|p| p.generate_msg1()).collect();
+        assert!(msg1.iter().all(|m| m.version == KEYGEN_MSG_VERSION));
+
+        msg1[1].version = KEYGEN_MSG_VERSION + 1;
+
+        let batch = msg1.iter().filter(|msg| msg.from_id != 0);
+        assert!(matches!(
+            parties[0].handle_msg1(&mut rng, batch),
+            Err(KeygenError::VersionMismatch(1, v)) if v == KEYGEN_MSG_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn invalid_message_routing() {
+        let mut rng = rand::thread_rng();
+        let mut parties = init_states(&[0; 3], 2);
+
+        let msg1: Vec<KeygenMsg1> =
+            parties.iter_mut().map(|p| p.generate_msg1()).collect();
+
+        let mut out_of_range = msg1[1].clone();
+        out_of_range.from_id = 3;
+        let batch = [&out_of_range, &msg1[2]];
+        assert!(matches!(
+            parties[0].handle_msg1(&mut rng, batch),
+            Err(KeygenError::InvalidSenderId(3))
+        ));
+
+        let batch = [&msg1[0], &msg1[2]];
+        assert!(matches!(
+            parties[0].handle_msg1(&mut rng, batch),
+            Err(KeygenError::InvalidSenderId(0))
+        ));
+
+        let batch = [&msg1[1], &msg1[1]];
+        assert!(matches!(
+            parties[0].handle_msg1(&mut rng, batch),
+            Err(KeygenError::DuplicateMessage(1))
+        ));
+
+        let mut msg2 = vec![];
+        for (id, party) in parties.iter_mut().enumerate() {
+            let batch = msg1.iter().filter(|msg| msg.from_id as usize != id);
+            msg2.extend(party.handle_msg1(&mut rng, batch).unwrap());
         }
 
-        check_serde(&msg4);
+        let mut batch: Vec<KeygenMsg2> =
+            msg2.into_iter().filter(|msg| msg.to_id == 0).collect();
+        batch[0].to_id = 2;
+        assert!(matches!(
+            parties[0].handle_msg2(&mut rng, batch),
+            Err(KeygenError::InvalidRecipientId(_))
+        ));
+    }
 
-        parties
+    #[test]
+    fn suspend_and_resume() {
+        let mut rng = rand::thread_rng();
+        let mut parties = init_states(&[0; 3], 2);
+
+        let msg1: Vec<KeygenMsg1> =
+            parties.iter_mut().map(|p| p.generate_msg1()).collect();
+
+        let mut party = parties.remove(0);
+        let batch = msg1.iter().filter(|msg| msg.from_id != 0);
+        party.handle_msg1(&mut rng, batch).unwrap();
+        assert_eq!(party.round(), KeygenRound::WaitMsg2);
+
+        let mut bytes = vec![];
+        ciborium::into_writer(&party.suspend(), &mut bytes).unwrap();
+
+        let decode = |bytes: &[u8]| -> SuspendedState {
+            ciborium::from_reader(bytes).unwrap()
+        };
+
+        assert!(matches!(
+            State::resume(decode(&bytes), KeygenRound::WaitMsg3),
+            Err(KeygenError::InvalidSuspendedState)
+        ));
+
+        let mut suspended = decode(&bytes);
+        suspended.round = KeygenRound::WaitMsg3;
+        assert!(matches!(
+            State::resume(suspended, KeygenRound::WaitMsg3),
+            Err(KeygenError::InvalidSuspendedState)
+        ));
+
+        let mut suspended = decode(&bytes);
+        suspended.checksum[0] ^= 1;
+        assert!(State::resume(suspended, KeygenRound::WaitMsg2).is_err());
+
+        let mut suspended = decode(&bytes);
+        suspended.version = 2;
+        assert!(matches!(
+            State::resume(suspended, KeygenRound::WaitMsg2),
+            Err(KeygenError::UnsupportedStateVersion(2))
+        ));
+
+        let party =
+            State::resume(decode(&bytes), KeygenRound::WaitMsg2).unwrap();
+        assert_eq!(party.round(), KeygenRound::WaitMsg2);
+    }
+
+    #[test]
+    fn dkg_with_session_nonce() {
+        let mut rng = rand::thread_rng();
+
+        let parties = init_states(&[0; 3], 2)
             .into_iter()
-            .map(|mut party| {
-                let batch: Vec<KeygenMsg4> = msg4
-                    .iter()
-                    .filter(|msg| msg.from_id != party.party_id)
-                    .cloned()
-                    .collect();
+            .map(|p| p.with_session_nonce([7; 32]))
+            .collect();
+        let shares = dkg_inner(parties);
+        assert_eq!(shares.len(), 3);
 
-                party.handle_msg4(batch).unwrap()
+        // party 2 joins a different session
+        let mut parties: Vec<State> = init_states(&[0; 3], 2)
+            .into_iter()
+            .map(|p| {
+                let nonce = if p.party_id == 2 { [8; 32] } else { [7; 32] };
+                p.with_session_nonce(nonce)
             })
-            .collect()
+            .collect();
+
+        let msg1: Vec<KeygenMsg1> =
+            parties.iter_mut().map(|p| p.generate_msg1()).collect();
+
+        let mut msg2: Vec<KeygenMsg2> = vec![];
+        for party in parties.iter_mut() {
+            let party_id = party.party_id;
+            let batch = msg1.iter().filter(|msg| msg.from_id != party_id);
+            msg2.extend(party.handle_msg1(&mut rng, batch).unwrap());
+        }
+
+        let batch = msg2.iter().filter(|msg| msg.to_id == 0);
+        assert!(matches!(
+            parties[0].handle_msg2(&mut rng, batch),
+            Err(KeygenError::InvalidDLogProof(2))
+        ));
     }
 
     #[test]
-    fn dkg2_out_of_2() {
-        dkg(2, 2);
+    fn max_encoded_size() {
+        let mut rng = rand::thread_rng();
+
+        for t in [2, 4] {
+            let mut parties = init_states(&[0; 4], t);
+
+            let msg1: Vec<KeygenMsg1> =
+                parties.iter_mut().map(|p| p.generate_msg1()).collect();
+            check_encoded_size(&msg1, KeygenMsg1::max_encoded_size());
+
+            let mut msg2: Vec<KeygenMsg2> = vec![];
+            for party in parties.iter_mut() {
+                let party_id = party.party_id;
+                let batch = msg1.iter().filter(|msg| msg.from_id != party_id);
+                msg2.extend(party.handle_msg1(&mut rng, batch).unwrap());
+            }
+            check_encoded_size(&msg2, KeygenMsg2::max_encoded_size(t));
+
+            let mut msg3: Vec<KeygenMsg3> = vec![];
+            for party in parties.iter_mut() {
+                let party_id = party.party_id;
+                let batch = msg2.iter().filter(|msg| msg.to_id == party_id);
+                msg3.extend(party.handle_msg2(&mut rng, batch).unwrap());
+            }
+            check_encoded_size(&msg3, KeygenMsg3::max_encoded_size(t));
+
+            let commitment_2_list: Vec<[u8; 32]> =
+                parties.iter().map(|p| p.calculate_commitment_2()).collect();
+
+            let msg4: Vec<KeygenMsg4> = parties
+                .iter_mut()
+                .map(|party| {
+                    let party_id = party.party_id;
+                    let batch =
+                        msg3.iter().filter(|msg| msg.to_id == party_id);
+                    party
+                        .handle_msg3(&mut rng, batch, &commitment_2_list)
+                        .unwrap()
+                })
+                .collect();
+            check_encoded_size(&msg4, KeygenMsg4::max_encoded_size());
+        }
     }
 
     #[test]
-    fn dkg2_out_of_3() {
-        dkg(3, 2);
+    fn reject_chain_code_mode_mismatch() {
+        let mut rng = rand::thread_rng();
+        let mut parties: Vec<State> = init_states(&[0; 3], 2)
+            .into_iter()
+            .map(|p| {
+                if p.party_id == 1 {
+                    p
+                } else {
+                    p.without_chain_code()
+                }
+            })
+            .collect();
+
+        let msg1: Vec<KeygenMsg1> =
+            parties.iter_mut().map(|p| p.generate_msg1()).collect();
+
+        let mut msg2: Vec<KeygenMsg2> = vec![];
+        for party in parties.iter_mut() {
+            let party_id = party.party_id;
+            let batch = msg1.iter().filter(|msg| msg.from_id != party_id);
+            msg2.extend(party.handle_msg1(&mut rng, batch).unwrap());
+        }
+
+        let batch = msg2.iter().filter(|msg| msg.to_id == 0);
+        assert!(parties[0].handle_msg2(&mut rng, batch).is_err());
     }
 
     #[test]
-    fn dkg_3_out_of_3() {
-        dkg(3, 3);
+    fn reject_even_y_mode_mismatch() {
+        let mut rng = rand::thread_rng();
+        let mut parties: Vec<State> = init_states(&[0; 3], 2)
+            .into_iter()
+            .map(|p| if p.party_id == 2 { p } else { p.with_even_y() })
+            .collect();
+
+        let msg1: Vec<KeygenMsg1> =
+            parties.iter_mut().map(|p| p.generate_msg1()).collect();
+
+        let mut msg2: Vec<KeygenMsg2> = vec![];
+        for party in parties.iter_mut() {
+            let party_id = party.party_id;
+            let batch = msg1.iter().filter(|msg| msg.from_id != party_id);
+            msg2.extend(party.handle_msg1(&mut rng, batch).unwrap());
+        }
+
+        let batch = msg2.iter().filter(|msg| msg.to_id == 0);
+        assert!(parties[0].handle_msg2(&mut rng, batch).is_err());
+    }
+
+    #[test]
+    fn abort_with_blame() {
+        let mut rng = rand::thread_rng();
+        let mut parties = init_states(&[0; 3], 2);
+
+        let mut msg1: Vec<KeygenMsg1> =
+            parties.iter_mut().map(|p| p.generate_msg1()).collect();
+        msg1[1].commitment = [0; 32];
+
+        let mut msg2: Vec<KeygenMsg2> = vec![];
+        for party in parties.iter_mut() {
+            let party_id = party.party_id;
+            let batch = msg1.iter().filter(|msg| msg.from_id != party_id);
+            msg2.extend(party.handle_msg1(&mut rng, batch).unwrap());
+        }
+
+        let batch = msg2.iter().filter(|msg| msg.to_id == 0);
+        let err = parties[0].handle_msg2(&mut rng, batch).err().unwrap();
+        assert!(matches!(err, KeygenError::InvalidCommitmentHash(1)));
+        assert_eq!(err.party_id(), Some(1));
+
+        let abort = parties[0].abort_message(1, &err);
+        check_serde(std::slice::from_ref(&abort));
+
+        let batch = msg2.iter().filter(|msg| msg.to_id == 2);
+        assert!(parties[2].handle_msg2(&mut rng, batch).is_err());
+        assert_eq!(parties[2].handle_abort(&abort), AbortVerdict::Confirmed);
+
+        // party 0 is not guilty
+        let false_abort = parties[1].abort_message(0, &err);
+        assert_eq!(
+            parties[2].handle_abort(&false_abort),
+            AbortVerdict::Rejected
+        );
+
+        let other =
+            parties[1].abort_message(0, &KeygenError::BigSMismatch(0));
+        assert_eq!(
+            parties[2].handle_abort(&other),
+            AbortVerdict::Unverifiable
+        );
     }
 
     #[test]
@@ -1134,6 +4227,185 @@ pub mod tests {
         let _new_shares = dkg_inner(rotation_states);
     }
 
+    #[test]
+    fn key_rotation_fresh_evaluation_points() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(3, 2);
+
+        let rotation_states = shares
+            .iter()
+            .map(|s| State::key_rotation(s, &mut rng).unwrap())
+            .collect::<Vec<_>>();
+
+        let new_shares = dkg_inner(rotation_states);
+
+        for (old, new) in shares.iter().zip(&new_shares) {
+            assert_eq!(old.public_key, new.public_key);
+            for (old_x, new_x) in old.x_i_list.iter().zip(&new.x_i_list) {
+                assert_ne!(old_x.to_bytes(), new_x.to_bytes());
+            }
+        }
+    }
+
+    #[test]
+    fn dkg_with_non_zero_ranks() {
+        let mut rng = rand::thread_rng();
+
+        for (ranks, t) in [(vec![0, 1, 1], 2), (vec![0, 0, 1, 2], 3)] {
+            let shares = dkg_with_ranks(&ranks, t);
+            assert!(shares.iter().all(|s| s.rank_list == ranks));
+
+            let rotation_states = shares
+                .iter()
+                .map(|s| State::key_rotation(s, &mut rng).unwrap())
+                .collect::<Vec<_>>();
+            let new_shares = dkg_inner(rotation_states);
+            assert_eq!(new_shares[0].public_key, shares[0].public_key);
+        }
+
+        for ranks in [[1, 1, 1], [0, 2, 0], [0, 1, 2]] {
+            let party = Party {
+                ranks: ranks.to_vec(),
+                t: 2,
+                party_id: 0,
+            };
+            assert!(matches!(
+                State::new_with_refresh(party, &mut rng, None),
+                Err(KeygenError::InvalidRanks)
+            ));
+        }
+    }
+
+    #[test]
+    fn invalid_party_parameters() {
+        let mut rng = rand::thread_rng();
+
+        for (n, t, party_id) in [(3, 1, 0), (3, 4, 0), (3, 2, 3), (256, 2, 0)]
+        {
+            assert!(matches!(
+                Party::new(n, t, party_id),
+                Err(KeygenError::InvalidParameters)
+            ));
+        }
+
+        // fields of a party are public, State::new() checks them too
+        for (ranks, t, party_id) in
+            [(vec![0; 3], 0, 0), (vec![0; 2], 3, 0), (vec![0; 3], 2, 5)]
+        {
+            let party = Party { ranks, t, party_id };
+            assert!(matches!(
+                State::new(party, &mut rng),
+                Err(KeygenError::InvalidParameters)
+            ));
+        }
+    }
+
+    #[test]
+    fn key_import_by_pieces() {
+        let mut rng = rand::thread_rng();
+
+        let private_key = NonZeroScalar::random(&mut rng);
+        let public_key =
+            (ProjectivePoint::GENERATOR * private_key.as_ref()).to_affine();
+
+        let pieces = split_key_for_import(&private_key, None, 3, &mut rng);
+
+        let parties = pieces
+            .iter()
+            .map(|piece| {
+                let party = Party::new(3, 2, piece.to_id as usize).unwrap();
+                State::key_import_piece(party, piece, public_key, &mut rng)
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let shares = dkg_inner(parties);
+        assert!(shares.iter().all(|s| s.public_key == public_key));
+
+        // pieces of another key do not match the expected public key
+        let other = NonZeroScalar::random(&mut rng);
+        let pieces = split_key_for_import(&other, None, 3, &mut rng);
+        assert!(matches!(
+            State::key_import_piece(
+                Party::new(3, 2, 0).unwrap(),
+                &pieces[0],
+                public_key,
+                &mut rng
+            ),
+            Err(KeygenError::PublicKeyMismatch)
+        ));
+    }
+
+    #[test]
+    fn key_reshare() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(4, 3);
+        let remaining = [0u8, 2, 3];
+
+        let states = remaining
+            .iter()
+            .map(|p| {
+                State::key_reshare(
+                    &shares[*p as usize],
+                    &remaining,
+                    2,
+                    &mut rng,
+                )
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+        let new_shares = dkg_inner(states);
+
+        for (id, s) in new_shares.iter().enumerate() {
+            assert_eq!(s.party_id as usize, id);
+            assert_eq!(s.total_parties, 3);
+            assert_eq!(s.threshold, 2);
+            assert_eq!(s.public_key, shares[0].public_key);
+            assert_eq!(s.root_chain_code, shares[0].root_chain_code);
+        }
+
+        // not enough parties to keep the key
+        assert!(State::key_reshare(&shares[0], &[0, 1], 2, &mut rng).is_err());
+        // the party is not in the remaining set
+        assert!(
+            State::key_reshare(&shares[1], &remaining, 2, &mut rng).is_err()
+        );
+    }
+
+    #[test]
+    fn key_import() {
+        let mut rng = rand::thread_rng();
+
+        let private_key = NonZeroScalar::random(&mut rng);
+        let public_key =
+            (ProjectivePoint::GENERATOR * private_key.as_ref()).to_affine();
+
+        let parties = (0..3)
+            .map(|party_id| {
+                let party = Party {
+                    ranks: vec![0; 3],
+                    t: 2,
+                    party_id,
+                };
+                if party_id == 1 {
+                    State::key_import(party, &private_key, None, &mut rng)
+                } else {
+                    State::key_import_join(party, 1, public_key, &mut rng)
+                }
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let shares = dkg_inner(parties);
+
+        for share in &shares {
+            assert_eq!(share.public_key, public_key);
+            assert_eq!(share.root_chain_code, shares[1].root_chain_code);
+        }
+    }
+
     #[test]
     fn recover_lost_share() {
         let mut rng = rand::thread_rng();
@@ -1150,7 +4422,7 @@ pub mod tests {
             party_id: 0,
         };
 
-        let refresh_shares = vec![
+        let refresh_shares = [
             RefreshShare::from_lost_keyshare(
                 party_with_lost_keyshare,
                 public_key,
@@ -1173,4 +4445,137 @@ pub mod tests {
 
         let _new_shares = dkg_inner(rotation_states);
     }
+
+    #[test]
+    fn rotate_and_recover_lost_shares() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(5, 3);
+        let public_key = shares[0].public_key;
+        let lost = [1, 3];
+
+        let states = shares
+            .iter()
+            .map(|share| {
+                if lost.contains(&share.party_id) {
+                    let party =
+                        Party::new(5, 3, share.party_id as usize).unwrap();
+                    State::key_recovery(party, public_key, &lost, &mut rng)
+                } else {
+                    State::key_rotation_with_recovery(share, &lost, &mut rng)
+                }
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let new_shares = dkg_inner(states);
+
+        for (old, new) in shares.iter().zip(&new_shares) {
+            assert_eq!(new.public_key, public_key);
+            assert_eq!(new.root_chain_code, old.root_chain_code);
+            assert_ne!(new.s_i, old.s_i);
+        }
+
+        assert!(matches!(
+            State::key_rotation_with_recovery(&shares[1], &lost, &mut rng),
+            Err(KeygenError::InvalidKeyRefresh)
+        ));
+        assert!(matches!(
+            State::key_rotation_with_recovery(&shares[0], &[1, 1], &mut rng),
+            Err(KeygenError::InvalidKeyRefresh)
+        ));
+        assert!(matches!(
+            State::key_recovery(
+                Party::new(5, 3, 0).unwrap(),
+                public_key,
+                &lost,
+                &mut rng
+            ),
+            Err(KeygenError::InvalidKeyRefresh)
+        ));
+    }
+
+    #[test]
+    fn key_refresh_invalid_share() {
+        let mut rng = rand::thread_rng();
+        let shares = dkg(3, 2);
+
+        let share = || RefreshShare::from_keyshare(&shares[0], None);
+
+        let mut invalid = share();
+        invalid.threshold = 4;
+        assert!(matches!(
+            State::key_refresh(&invalid, &mut rng),
+            Err(KeygenError::InvalidParameters)
+        ));
+
+        let mut invalid = share();
+        invalid.party_id = 3;
+        assert!(matches!(
+            State::key_refresh(&invalid, &mut rng),
+            Err(KeygenError::InvalidParameters)
+        ));
+
+        let mut invalid = share();
+        invalid.x_i_list.as_mut().unwrap().pop();
+        assert!(matches!(
+            State::key_refresh(&invalid, &mut rng),
+            Err(KeygenError::InvalidKeyRefresh)
+        ));
+
+        State::key_refresh(&share(), &mut rng).unwrap();
+    }
+
+    #[test]
+    fn zeta_seeds_by_party_id() {
+        use std::str::FromStr;
+
+        use derivation_path::DerivationPath;
+
+        use crate::dsg;
+
+        let shares = dkg(4, 2);
+
+        // layout of key shares created before the seeds were keyed by
+        // party ID
+        let mut old = shares[3].clone();
+        old.rec_seed_list =
+            (0..3).map(|p| *shares[3].zeta_seed(p).unwrap()).collect();
+        old.zeta_seeds = Pairs::new();
+        for p in 0..3 {
+            assert_eq!(old.zeta_seed(p), shares[3].zeta_seed(p));
+        }
+        assert_eq!(old.zeta_seed(3), None);
+
+        // signers are not a prefix of the parties, one share is old
+        let mut rng = rand::thread_rng();
+        let chain_path = DerivationPath::from_str("m").unwrap();
+        let mut parties = [shares[1].clone(), old.clone()]
+            .into_iter()
+            .map(|s| dsg::State::new(&mut rng, s, &chain_path).unwrap())
+            .collect::<Vec<_>>();
+        let (mut partials, mut msg4): (Vec<_>, Vec<_>) =
+            dsg::tests::run_presign(&mut parties)
+                .into_iter()
+                .map(|pre| {
+                    dsg::create_partial_signature(pre, [1; 32]).unwrap()
+                })
+                .unzip();
+        dsg::combine_signatures(partials.remove(1), [msg4.remove(0)])
+            .unwrap();
+
+        let decoded =
+            Keyshare::from_compact_bytes(&old.to_compact_bytes()).unwrap();
+        assert_eq!(
+            decoded.zeta_seeds.remove_ids(),
+            shares[3].zeta_seeds.remove_ids()
+        );
+
+        old.migrate_zeta_seeds();
+        assert!(old.rec_seed_list.is_empty());
+        assert_eq!(
+            old.zeta_seeds.remove_ids(),
+            shares[3].zeta_seeds.remove_ids()
+        );
+    }
 }
@@ -2,22 +2,32 @@
 // This software is licensed under the Silence Laboratories License Agreement.
 
 //! The structs and functions for implementing DKLS23 signing operations
-//! Presignatures should be used only for one message signature
-use derivation_path::DerivationPath;
+//! Presignatures should be used only for one message signature, see
+//! [`PresignatureStore`]
+use std::{borrow::Borrow, collections::HashSet, mem::size_of, sync::Arc};
+
+use derivation_path::{ChildIndex, DerivationPath};
 use k256::{
-    ecdsa::{signature::hazmat::PrehashVerifier, Signature, VerifyingKey},
+    ecdsa::{
+        signature::hazmat::PrehashVerifier, RecoveryId, Signature,
+        VerifyingKey,
+    },
     elliptic_curve::{
-        group::prime::PrimeCurveAffine, ops::Reduce,
-        point::AffineCoordinates, subtle::ConstantTimeEq,
+        group::{prime::PrimeCurveAffine, GroupEncoding},
+        ops::Reduce,
+        point::AffineCoordinates,
+        subtle::ConstantTimeEq,
+        Field, Group, PrimeField,
     },
-    AffinePoint, ProjectivePoint, Scalar, U256,
+    AffinePoint, NonZeroScalar, ProjectivePoint, Scalar, U256,
 };
 use rand::prelude::*;
+use rand_chacha::ChaCha20Rng;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use zeroize::{Zeroize, ZeroizeOnDrop};
 
-use sl_mpc_mate::bip32::{derive_child_pubkey, BIP32Error};
+use sl_mpc_mate::{bip32::derive_child_pubkey, math::birkhoff_coeffs};
 
 use sl_oblivious::{
     rvole::{RVOLEOutput, RVOLEReceiver, RVOLESender},
@@ -26,7 +36,7 @@ use sl_oblivious::{
 
 use crate::{constants::*, dkg::Keyshare, pairs::*, utils::*};
 
-pub use crate::error::SignError;
+pub use crate::error::{AbortCheck, AbortContext, SignError};
 
 /// Type for the sign gen message 1.
 #[derive(Clone, Serialize, Deserialize)]
@@ -34,6 +44,10 @@ pub struct SignMsg1 {
     pub from_id: u8,
     pub session_id: [u8; 32],
     pub commitment_r_i: [u8; 32],
+
+    /// Hash of the message to sign, if bound in round 1.
+    #[serde(default)]
+    pub message_hash: Option<[u8; 32]>,
 }
 
 #[derive(Clone, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
@@ -76,6 +90,155 @@ pub struct SignMsg4 {
     pub s_1: Scalar,
 }
 
+impl SignMsg1 {
+    /// Upper bound of the encoded size of the message. Messages
+    /// padded to this size reveal the round only.
+    pub fn max_encoded_size() -> usize {
+        max_encoded_size([1, 32, 32, 32])
+    }
+}
+
+impl SignMsg2 {
+    /// Upper bound of the encoded size of the message, see
+    /// [`SignMsg1::max_encoded_size`].
+    pub fn max_encoded_size() -> usize {
+        max_encoded_size([1, 1, 32, size_of::<Round1Output>()])
+    }
+}
+
+impl SignMsg3 {
+    /// Upper bound of the encoded size of the message, see
+    /// [`SignMsg1::max_encoded_size`].
+    pub fn max_encoded_size() -> usize {
+        max_encoded_size([
+            1,
+            1,
+            32,
+            size_of::<RVOLEOutput>(),
+            32,
+            POINT_SIZE,
+            POINT_SIZE,
+            32,
+            POINT_SIZE,
+            POINT_SIZE,
+            SCALAR_SIZE,
+        ])
+    }
+}
+
+impl SignMsg4 {
+    /// Upper bound of the encoded size of the message, see
+    /// [`SignMsg1::max_encoded_size`].
+    pub fn max_encoded_size() -> usize {
+        max_encoded_size([1, 32, SCALAR_SIZE, SCALAR_SIZE])
+    }
+}
+
+/// Reason of a [`SignAbort`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SignAbortReason {
+    /// The signing policy of the party refused the message.
+    PolicyRejected,
+
+    /// The party cancelled the session.
+    Cancelled,
+}
+
+/// Broadcast message of a party that does not proceed with a session,
+/// so that other parties stop instead of waiting for its next message.
+/// See [`State::abort`] and [`State::handle_abort`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SignAbort {
+    pub from_id: u8,
+    /// Session ID of the first message of the party.
+    pub session_id: [u8; 32],
+    pub reason: SignAbortReason,
+}
+
+/// Message of any of the first three rounds, accepted one at a time
+/// by [`State::push_msg`].
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Serialize, Deserialize)]
+pub enum SignRoundMsg {
+    Msg1(SignMsg1),
+    Msg2(SignMsg2),
+    Msg3(SignMsg3),
+}
+
+impl From<SignMsg1> for SignRoundMsg {
+    fn from(msg: SignMsg1) -> Self {
+        Self::Msg1(msg)
+    }
+}
+
+impl From<SignMsg2> for SignRoundMsg {
+    fn from(msg: SignMsg2) -> Self {
+        Self::Msg2(msg)
+    }
+}
+
+impl From<SignMsg3> for SignRoundMsg {
+    fn from(msg: SignMsg3) -> Self {
+        Self::Msg3(msg)
+    }
+}
+
+/// Output of [`State::finish_round`].
+#[allow(clippy::large_enum_variant)]
+pub enum SignRoundOutput {
+    /// P2P messages of round 2, output of round 1.
+    Msg2(Vec<SignMsg2>),
+
+    /// P2P messages of round 3, output of round 2.
+    Msg3(Vec<SignMsg3>),
+
+    /// Pre-signature, output of round 3.
+    PreSignature(PreSignature),
+}
+
+/// Round of a signing state: the next expected batch of messages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SignRound {
+    WaitMsg1,
+    WaitMsg2,
+    WaitMsg3,
+
+    /// The pre-signature is produced.
+    Finished,
+
+    /// A round handler failed and the session is wiped.
+    Aborted,
+}
+
+/// Version of the [`SuspendedState`] envelope.
+pub const STATE_SUSPEND_VERSION: u8 = 1;
+
+/// Persistence envelope of a [`State`] between rounds, see
+/// [`State::suspend`] and [`State::resume`].
+///
+/// The envelope carries an unkeyed checksum of the parameters, session
+/// IDs and main secrets of the state. It detects accidental corruption
+/// and a state resumed in another round, not deliberate modification:
+/// store the envelope encrypted and authenticated.
+#[derive(Serialize, Deserialize)]
+pub struct SuspendedState {
+    version: u8,
+    round: SignRound,
+    checksum: [u8; 32],
+    state: State,
+}
+
+impl SuspendedState {
+    /// Round of the suspended state.
+    pub fn round(&self) -> SignRound {
+        self.round
+    }
+}
+
+/// Version of the [`PreSignature`] format. Pre-signatures serialized
+/// before the format was versioned decode as version 0.
+pub const PRESIGNATURE_VERSION: u8 = 1;
+
 /// Result after pre-signature of party_i
 #[derive(Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
 pub struct PreSignature {
@@ -86,6 +249,227 @@ pub struct PreSignature {
     pub s_1: Scalar,
     pub r: AffinePoint,
     pub phi_i: Scalar,
+
+    /// Hash of the message bound in round 1, if any, see
+    /// [`PreSignature::message_hash`]. Pre-signatures serialized before
+    /// binding was supported decode as unbound.
+    #[serde(default)]
+    message_hash: Option<[u8; 32]>,
+
+    /// Root chain code of the key share if the pre-signature was
+    /// generated by a path-agnostic session, see
+    /// `State::new_path_agnostic`.
+    #[serde(default)]
+    pub root_chain_code: Option<[u8; 32]>,
+
+    /// Format version, see [`PRESIGNATURE_VERSION`].
+    #[serde(default)]
+    pub version: u8,
+
+    /// Fingerprint of the key, see [`Keyshare::key_fingerprint`].
+    /// Zero for version 0.
+    #[serde(default)]
+    pub key_fingerprint: [u8; 32],
+
+    /// Final session ID of the key share the pre-signature was
+    /// generated with. Changes on every key refresh. Zero for
+    /// version 0.
+    #[serde(default)]
+    pub keyshare_epoch: [u8; 32],
+
+    /// Number of signers of the session. Zero for pre-signatures
+    /// serialized before the field was added.
+    #[serde(default)]
+    pub threshold: u8,
+}
+
+/// Version of the [`SignAuditRecord`] encoding.
+pub const AUDIT_RECORD_VERSION: u8 = 1;
+
+/// Record of a completed signature to archive for audit, see
+/// [`State::audit_record`].
+///
+/// The serde encoding is stable: fields are only added with a serde
+/// default and [`AUDIT_RECORD_VERSION`] is bumped on any other change.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignAuditRecord {
+    pub version: u8,
+    /// Final session ID of the signing session.
+    pub session_id: [u8; 32],
+    /// Party that produced the record.
+    pub party_id: u8,
+    /// Ordered IDs of all signers.
+    pub signers: Vec<u8>,
+    /// Derivation path, `None` for sessions created without one.
+    pub chain_path: Option<String>,
+    /// Public key of the signature.
+    pub public_key: AffinePoint,
+    /// Nonce point of the signature.
+    pub big_r: AffinePoint,
+    pub message_hash: [u8; 32],
+    /// Start and completion time of the session as supplied by the
+    /// caller, e.g. seconds since the Unix epoch.
+    pub started_at: u64,
+    pub completed_at: u64,
+}
+
+/// Public part of a pre-signature. Could be exchanged between
+/// parties to check that all of them computed the same pre-signature.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PreSignaturePublic {
+    pub from_id: u8,
+    pub final_session_id: [u8; 32],
+    pub public_key: AffinePoint,
+    pub r: AffinePoint,
+}
+
+/// Size of an encoded [`PreSignatureAdvert`].
+pub const PRESIGNATURE_ADVERT_SIZE: usize = 32 + 33 + 33;
+
+/// Compact advertisement of a pre-signature: a coordinator could bind
+/// the nonce R to a pending transaction before the message is chosen.
+/// The same for all parties of the session.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PreSignatureAdvert {
+    /// ID of the pre-signature, see [`PreSignature::id`].
+    pub id: [u8; 32],
+    pub public_key: AffinePoint,
+    pub r: AffinePoint,
+}
+
+impl PreSignatureAdvert {
+    /// The `r` value of any signature created with the pre-signature.
+    pub fn signature_r(&self) -> [u8; 32] {
+        let r_x: Scalar = Reduce::<U256>::reduce_bytes(&self.r.x());
+        r_x.to_bytes().into()
+    }
+
+    /// Encode as `id || public_key || R`, points in SEC1 compressed
+    /// form.
+    pub fn to_bytes(&self) -> [u8; PRESIGNATURE_ADVERT_SIZE] {
+        let mut bytes = [0; PRESIGNATURE_ADVERT_SIZE];
+        bytes[..32].copy_from_slice(&self.id);
+        bytes[32..65].copy_from_slice(&self.public_key.to_bytes());
+        bytes[65..].copy_from_slice(&self.r.to_bytes());
+        bytes
+    }
+
+    /// Decode an advertisement encoded by
+    /// [`PreSignatureAdvert::to_bytes`].
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SignError> {
+        let point = |bytes: &[u8]| {
+            let bytes: [u8; 33] = bytes.try_into().unwrap();
+            Option::<AffinePoint>::from(AffinePoint::from_bytes(
+                &bytes.into(),
+            ))
+            .ok_or(SignError::InvalidPreSignatureAdvert)
+        };
+
+        if bytes.len() != PRESIGNATURE_ADVERT_SIZE {
+            return Err(SignError::InvalidPreSignatureAdvert);
+        }
+
+        Ok(Self {
+            id: bytes[..32].try_into().unwrap(),
+            public_key: point(&bytes[32..65])?,
+            r: point(&bytes[65..])?,
+        })
+    }
+}
+
+impl PreSignature {
+    /// ID of the pre-signature, the final session ID of the session
+    /// that generated it. The same for all parties of the session.
+    pub fn id(&self) -> [u8; 32] {
+        self.final_session_id
+    }
+
+    /// Hash of the message bound in round 1. A bound pre-signature
+    /// could be used only to sign this message.
+    pub fn message_hash(&self) -> Option<[u8; 32]> {
+        self.message_hash
+    }
+
+    /// Check that the pre-signature was generated with a key share of
+    /// the same party and key as `keyshare`.
+    ///
+    /// A pre-signature of version 0 carries no key fingerprint, only
+    /// the party ID is checked.
+    pub fn check_keyshare(
+        &self,
+        keyshare: &Keyshare,
+    ) -> Result<(), SignError> {
+        if self.version > PRESIGNATURE_VERSION {
+            return Err(SignError::UnsupportedPreSignatureVersion(
+                self.version,
+            ));
+        }
+
+        let cond = self.from_id != keyshare.party_id
+            || (self.version > 0
+                && self.key_fingerprint != keyshare.key_fingerprint());
+        if cond {
+            return Err(SignError::PreSignatureKeyMismatch);
+        }
+
+        Ok(())
+    }
+
+    /// Public nonce R of the pre-signature.
+    pub fn public_nonce(&self) -> AffinePoint {
+        self.r
+    }
+
+    /// Advertisement of the nonce R and public key of the
+    /// pre-signature.
+    pub fn advert(&self) -> PreSignatureAdvert {
+        PreSignatureAdvert {
+            id: self.final_session_id,
+            public_key: self.public_key,
+            r: self.r,
+        }
+    }
+
+    /// Non-secret data of the pre-signature.
+    pub fn public_data(&self) -> PreSignaturePublic {
+        PreSignaturePublic {
+            from_id: self.from_id,
+            final_session_id: self.final_session_id,
+            public_key: self.public_key,
+            r: self.r,
+        }
+    }
+}
+
+/// Check that pre-signatures of all members of a quorum agree on
+/// the nonce R, session id and public key.
+///
+/// Call it before creating any partial signature: a divergent
+/// pre-signature could not produce a valid signature and the
+/// message 4 would be wasted.
+pub fn check_presignatures(
+    presigs: &[PreSignaturePublic],
+) -> Result<(), SignError> {
+    let Some(first) = presigs.first() else {
+        return Err(SignError::MissingMessage);
+    };
+
+    for (idx, p) in presigs.iter().enumerate() {
+        if presigs[..idx].iter().any(|q| q.from_id == p.from_id) {
+            return Err(SignError::FailedCheck(
+                "Duplicate pre-signature party id",
+            ));
+        }
+
+        let cond = (p.final_session_id != first.final_session_id)
+            || (p.public_key != first.public_key)
+            || (p.r != first.r);
+        if cond {
+            return Err(SignError::FailedCheck("Pre-signatures diverge"));
+        }
+    }
+
+    Ok(())
 }
 
 /// Partial signature of party_i
@@ -100,11 +484,25 @@ pub struct PartialSignature {
     pub s_0: Scalar,
     pub s_1: Scalar,
     pub r: AffinePoint,
+
+    /// Number of signers, see [`PreSignature::threshold`].
+    #[serde(default)]
+    pub threshold: u8,
 }
 
+/// Signing session of a party.
+///
+/// An error of a round handler aborts the session and wipes its secret
+/// values. A batch of the wrong size, from unexpected or duplicate
+/// senders or of another session is rejected before the round starts,
+/// and like a message rejected by [`State::push_msg`] leaves the
+/// session intact.
 #[derive(Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
 pub struct State {
-    pub keyshare: Keyshare,
+    /// Key share of the session, shared by all sessions created from
+    /// the same `Arc`.
+    #[zeroize(skip)]
+    pub keyshare: Arc<Keyshare>,
     pub sid_list: Pairs<[u8; 32]>,
     pub phi_i: Scalar,
     pub r_i: Scalar,
@@ -119,6 +517,147 @@ pub struct State {
     pub additive_offset: Scalar,
     pub derived_public_key: AffinePoint,
     pub sender_additive_shares: Vec<[Scalar; 2]>,
+    #[serde(default)]
+    message_hash: Option<[u8; 32]>,
+    #[serde(default)]
+    pub signers: Option<SignerSet>,
+    #[serde(default)]
+    pub path_agnostic: bool,
+
+    /// Messages of the current round received by
+    /// [`State::push_msg`].
+    #[serde(default)]
+    #[zeroize(skip)]
+    pub pending: Vec<SignRoundMsg>,
+
+    /// Derivation path of the session as bits of child indices, `None`
+    /// if the session was not created for a path.
+    #[serde(default)]
+    chain_path: Option<Vec<u32>>,
+
+    /// Not serialized, set again after decoding a session.
+    #[serde(skip)]
+    #[zeroize(skip)]
+    policy: Option<Arc<dyn SignPolicy>>,
+
+    /// Own session ID, kept after the session is wiped to identify
+    /// a [`SignAbort`].
+    #[serde(default)]
+    #[zeroize(skip)]
+    session_id: [u8; 32],
+
+    /// Multiplicative tweak of the key, see [`State::new_tweaked`].
+    #[serde(default)]
+    multiplicative_tweak: Option<Scalar>,
+
+    /// The first message is generated, the message hash can not be
+    /// bound anymore.
+    #[serde(default)]
+    #[zeroize(skip)]
+    msg1_generated: bool,
+}
+
+/// Tweak of the key of a signing session: the session signs with
+/// `multiplicative * x + additive` for the secret key `x` of the key
+/// share. A BIP32 derivation is an additive tweak, see
+/// [`derive_with_offset`].
+#[derive(Clone, Copy, Default)]
+pub struct KeyTweak {
+    pub additive: Scalar,
+    /// Multiplicative tweak, `None` for 1.
+    pub multiplicative: Option<NonZeroScalar>,
+}
+
+impl KeyTweak {
+    /// Public key of the tweaked key for the key share public key.
+    pub fn public_key(&self, public_key: &AffinePoint) -> AffinePoint {
+        let public_key = match self.multiplicative {
+            Some(m) => public_key.to_curve() * *m,
+            None => public_key.to_curve(),
+        };
+
+        (public_key + ProjectivePoint::GENERATOR * self.additive).to_affine()
+    }
+}
+
+/// Policy of a signing session, checked before the session produces
+/// its final message.
+///
+/// Lets a wallet enforce allowlists or limits inside the signing layer.
+/// A closure with the same arguments as [`SignPolicy::allow`] is a
+/// policy.
+pub trait SignPolicy: Send + Sync {
+    /// Allow signing of `hash` by the key `public_key`, derived by
+    /// `chain_path`. The path is `None` for sessions created without
+    /// one, e.g. by [`State::new_path_agnostic`].
+    fn allow(
+        &self,
+        hash: &[u8; 32],
+        chain_path: Option<&DerivationPath>,
+        public_key: &AffinePoint,
+    ) -> bool;
+}
+
+impl<F> SignPolicy for F
+where
+    F: Fn(&[u8; 32], Option<&DerivationPath>, &AffinePoint) -> bool
+        + Send
+        + Sync,
+{
+    fn allow(
+        &self,
+        hash: &[u8; 32],
+        chain_path: Option<&DerivationPath>,
+        public_key: &AffinePoint,
+    ) -> bool {
+        self(hash, chain_path, public_key)
+    }
+}
+
+/// Explicit set of parties participating in a signing session.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Zeroize)]
+pub struct SignerSet {
+    party_ids: Vec<u8>,
+}
+
+impl SignerSet {
+    /// Create a signer set for the given key share.
+    ///
+    /// The set must contain exactly `threshold` distinct party ids,
+    /// including the id of the key share owner, and every id must be
+    /// less than the total number of parties. Returns
+    /// [`SignError::DuplicateParty`] if an id is listed twice.
+    pub fn new(
+        keyshare: &Keyshare,
+        party_ids: &[u8],
+    ) -> Result<Self, SignError> {
+        let n = keyshare.rank_list.len();
+
+        let mut party_ids = party_ids.to_vec();
+        party_ids.sort_unstable();
+        if let Some(w) = party_ids.windows(2).find(|w| w[0] == w[1]) {
+            return Err(SignError::DuplicateParty(w[0]));
+        }
+
+        let cond = party_ids.len() != keyshare.threshold as usize
+            || party_ids.iter().any(|p| *p as usize >= n)
+            || !party_ids.contains(&keyshare.party_id);
+        if cond {
+            return Err(SignError::InvalidSignerSet);
+        }
+
+        Ok(Self { party_ids })
+    }
+
+    /// Ordered list of party ids.
+    pub fn party_ids(&self) -> &[u8] {
+        &self.party_ids
+    }
+
+    /// Check if the party is a member of the set.
+    pub fn contains(&self, party_id: u8) -> bool {
+        self.party_ids.binary_search(&party_id).is_ok()
+    }
 }
 
 fn other_parties<T>(
@@ -134,9 +673,34 @@ fn other_parties<T>(
 impl State {
     pub fn new<R: RngCore + CryptoRng>(
         rng: &mut R,
-        keyshare: Keyshare,
+        keyshare: impl Into<Arc<Keyshare>>,
         chain_path: &DerivationPath,
-    ) -> Result<Self, BIP32Error> {
+    ) -> Result<Self, SignError> {
+        let keyshare = keyshare.into();
+        let (additive_offset, derived_public_key) = derive_with_offset(
+            &keyshare.public_key.to_curve(),
+            keyshare.root_chain_code.as_ref(),
+            chain_path,
+        )?;
+
+        let mut state = Self::with_offset(
+            rng,
+            keyshare,
+            additive_offset,
+            derived_public_key.to_affine(),
+        );
+        state.chain_path =
+            Some(chain_path.path().iter().map(|c| c.to_bits()).collect());
+
+        Ok(state)
+    }
+
+    pub(crate) fn with_offset<R: RngCore + CryptoRng>(
+        rng: &mut R,
+        keyshare: Arc<Keyshare>,
+        additive_offset: Scalar,
+        derived_public_key: AffinePoint,
+    ) -> Self {
         let party_id = keyshare.party_id;
 
         let session_id: [u8; 32] = rng.gen();
@@ -148,23 +712,19 @@ impl State {
         let commitment_r_i =
             hash_commitment_r_i(&session_id, &big_r_i, &blind_factor);
 
-        let (additive_offset, derived_public_key) = derive_with_offset(
-            &keyshare.public_key.to_curve(),
-            &keyshare.root_chain_code,
-            chain_path,
-        )?;
-
         // can not fail because T != 0
         let threshold_inv =
             Scalar::from(keyshare.threshold as u32).invert().unwrap();
         let additive_offset = additive_offset * threshold_inv;
 
-        Ok(Self {
-            sender_additive_shares: Vec::with_capacity(
-                keyshare.threshold as usize - 1,
-            ),
+        // The per-party collections below are sized from T, so the
+        // rounds do not reallocate them.
+        let t = keyshare.threshold as usize;
+
+        Self {
+            sender_additive_shares: Vec::with_capacity(t - 1),
             keyshare,
-            sid_list: Pairs::new_with_item(party_id, session_id),
+            sid_list: Pairs::with_capacity(t).add(party_id, session_id),
             phi_i,
             r_i,
             sk_i: Scalar::ZERO,
@@ -172,273 +732,591 @@ impl State {
             pk_i: AffinePoint::IDENTITY,
             blind_factor,
             additive_offset,
-            derived_public_key: derived_public_key.to_affine(),
-            commitment_r_i_list: Pairs::new_with_item(
-                party_id,
-                commitment_r_i,
-            ),
+            derived_public_key,
+            commitment_r_i_list: Pairs::with_capacity(t)
+                .add(party_id, commitment_r_i),
             final_session_id: [0u8; 32],
             digest_i: [0; 32],
-            mta_receiver_list: Pairs::new(),
-        })
+            mta_receiver_list: Pairs::with_capacity(t - 1),
+            message_hash: None,
+            signers: None,
+            path_agnostic: false,
+            pending: vec![],
+            chain_path: None,
+            policy: None,
+            session_id,
+            multiplicative_tweak: None,
+            msg1_generated: false,
+        }
     }
 
-    //Round 1
-    pub fn generate_msg1(&mut self) -> SignMsg1 {
-        let party_id = self.keyshare.party_id;
+    /// Create a signing session of the key share key with an arbitrary
+    /// tweak instead of a derivation path, e.g. for keys that commit
+    /// to data like Taproot outputs.
+    pub fn new_tweaked<R: RngCore + CryptoRng>(
+        rng: &mut R,
+        keyshare: impl Into<Arc<Keyshare>>,
+        tweak: &KeyTweak,
+    ) -> Self {
+        let keyshare = keyshare.into();
+        let derived_public_key = tweak.public_key(&keyshare.public_key);
+
+        let mut state = Self::with_offset(
+            rng,
+            keyshare,
+            tweak.additive,
+            derived_public_key,
+        );
+        state.multiplicative_tweak = tweak.multiplicative.map(|m| *m);
 
-        SignMsg1 {
-            from_id: party_id,
-            session_id: *self.sid_list.find_pair(party_id),
-            commitment_r_i: *self.commitment_r_i_list.find_pair(party_id),
-        }
+        state
     }
 
-    /// Round 1
-    pub fn handle_msg1<R: RngCore + CryptoRng>(
-        &mut self,
+    /// Create a signing session for the root key whose pre-signature
+    /// could be used to sign for any derivation path.
+    ///
+    /// The additive offset of the path is applied when the partial
+    /// signature is created by [`create_partial_signature_for_path`].
+    ///
+    /// Insecure, see [`create_partial_signature_for_path`].
+    #[cfg(feature = "insecure-path-agnostic")]
+    pub fn new_path_agnostic<R: RngCore + CryptoRng>(
         rng: &mut R,
-        msgs: Vec<SignMsg1>,
-    ) -> Result<Vec<SignMsg2>, SignError> {
-        if msgs.len() != self.keyshare.threshold as usize - 1 {
-            return Err(SignError::MissingMessage);
-        }
+        keyshare: impl Into<Arc<Keyshare>>,
+    ) -> Self {
+        let keyshare = keyshare.into();
+        let public_key = keyshare.public_key;
+        let mut state =
+            Self::with_offset(rng, keyshare, Scalar::ZERO, public_key);
+        state.path_agnostic = true;
+
+        state
+    }
 
-        for msg in msgs {
-            // make sure msg is unique
-            if self
-                .sid_list
+    /// Create a one-shot signing session of the message hash.
+    ///
+    /// The hash is bound from round 1, see
+    /// [`State::bind_message_hash`], and the session is finished by
+    /// [`State::handle_msg3_and_sign`] without an intermediate
+    /// pre-signature.
+    pub fn sign<R: RngCore + CryptoRng>(
+        rng: &mut R,
+        keyshare: impl Into<Arc<Keyshare>>,
+        chain_path: &DerivationPath,
+        hash: [u8; 32],
+    ) -> Result<Self, SignError> {
+        let mut state = Self::new(rng, keyshare, chain_path)?;
+        state.bind_message_hash(hash)?;
+
+        Ok(state)
+    }
+
+    /// Create a signing session using cached derivation results of
+    /// the key share.
+    pub fn new_derived<R: RngCore + CryptoRng>(
+        rng: &mut R,
+        derived: &DerivedKeyshare,
+    ) -> Self {
+        let mut state = Self::with_offset(
+            rng,
+            derived.keyshare.clone(),
+            derived.additive_offset,
+            derived.derived_public_key,
+        );
+        state.chain_path = Some(
+            derived
+                .chain_path
+                .path()
                 .iter()
-                .any(|(p, v)| (p != &msg.from_id) && (v == &msg.session_id))
-                || self
-                    .commitment_r_i_list
-                    .iter()
-                    .any(|(_, v)| v == &msg.commitment_r_i)
-            {
-                return Err(SignError::MissingMessage);
-            }
+                .map(|c| c.to_bits())
+                .collect(),
+        );
 
-            self.sid_list.push(msg.from_id, msg.session_id);
-            self.commitment_r_i_list
-                .push(msg.from_id, msg.commitment_r_i);
-        }
+        state
+    }
 
-        self.final_session_id = self
-            .sid_list
-            .iter()
-            .fold(Sha256::new(), |hash, (_, sid)| hash.chain_update(sid))
-            .chain_update(self.keyshare.final_session_id)
-            .finalize()
-            .into();
-
-        self.digest_i = {
-            let mut h = Sha256::new();
-            h.update(DSG_LABEL);
-            for (key, commitment_i) in self.commitment_r_i_list.iter() {
-                h.update((*key as u32).to_be_bytes());
-                h.update(self.sid_list.find_pair(*key));
-                h.update(commitment_i);
-            }
-            h.update(DIGEST_I_LABEL);
-            h.finalize().into()
-        };
+    /// Create a signing session restricted to the given signer set.
+    ///
+    /// Handlers reject messages from parties outside of the set with
+    /// [`SignError::UnexpectedParty`].
+    pub fn new_with_signers<R: RngCore + CryptoRng>(
+        rng: &mut R,
+        keyshare: impl Into<Arc<Keyshare>>,
+        chain_path: &DerivationPath,
+        signers: SignerSet,
+    ) -> Result<Self, SignError> {
+        let mut state = Self::new(rng, keyshare, chain_path)?;
+        state.signers = Some(signers);
 
-        let party_id = self.keyshare.party_id;
+        Ok(state)
+    }
 
-        Ok(other_parties(&self.sid_list, party_id)
-            .map(|sender_id| {
-                let sid = mta_session_id(
-                    &self.final_session_id,
-                    sender_id,
-                    party_id,
-                );
+    /// Derivation path of the session, `None` if the session was not
+    /// created for a path.
+    pub fn chain_path(&self) -> Option<DerivationPath> {
+        let path = self.chain_path.as_ref()?;
 
-                let sender_ot_results = &self.keyshare.seed_ot_senders
-                    [get_idx_from_id(self.keyshare.party_id, sender_id)
-                        as usize];
+        Some(DerivationPath::new(
+            path.iter()
+                .map(|bits| ChildIndex::from_bits(*bits))
+                .collect::<Vec<_>>(),
+        ))
+    }
 
-                let mut mta_msg_1 = ZS::<Round1Output>::default();
-                let (mta_receiver, chi_i_j) = RVOLEReceiver::new(
-                    sid,
-                    sender_ot_results,
-                    &mut mta_msg_1,
-                    rng,
-                );
+    /// Set the policy checked by [`State::handle_msg3_and_sign`] and
+    /// [`State::create_partial_signature`]. The policy is not
+    /// serialized with the session.
+    pub fn set_policy(&mut self, policy: impl SignPolicy + 'static) {
+        self.policy = Some(Arc::new(policy));
+    }
 
-                self.mta_receiver_list
-                    .push(sender_id, (mta_receiver.into(), chi_i_j));
+    /// Audit record of a signature completed by a partial signature of
+    /// this session.
+    ///
+    /// The crate does not read a clock, timestamps are supplied by the
+    /// caller.
+    pub fn audit_record(
+        &self,
+        partial: &PartialSignature,
+        started_at: u64,
+        completed_at: u64,
+    ) -> Result<SignAuditRecord, SignError> {
+        let cond = partial.party_id != self.keyshare.party_id
+            || partial.final_session_id != self.final_session_id
+            || self.round() != SignRound::Finished;
+        if cond {
+            return Err(SignError::InvalidFinalSessionID);
+        }
 
-                SignMsg2 {
-                    from_id: party_id,
-                    to_id: sender_id,
-                    final_session_id: self.final_session_id,
+        let mut signers: Vec<u8> =
+            self.sid_list.iter().map(|(p, _)| *p).collect();
+        signers.sort_unstable();
+
+        Ok(SignAuditRecord {
+            version: AUDIT_RECORD_VERSION,
+            session_id: self.final_session_id,
+            party_id: partial.party_id,
+            signers,
+            chain_path: self.chain_path().map(|p| p.to_string()),
+            public_key: partial.public_key,
+            big_r: partial.r,
+            message_hash: partial.message_hash,
+            started_at,
+            completed_at,
+        })
+    }
 
-                    mta_msg_1,
-                }
-            })
-            .collect())
+    /// Check the message hash against the policy of the session, if
+    /// any.
+    pub fn check_policy(&self, hash: &[u8; 32]) -> Result<(), SignError> {
+        let Some(policy) = &self.policy else {
+            return Ok(());
+        };
+
+        let chain_path = self.chain_path();
+        if policy.allow(hash, chain_path.as_ref(), &self.derived_public_key) {
+            Ok(())
+        } else {
+            Err(SignError::PolicyRejected)
+        }
     }
 
-    /// Round 2
-    /// Handle first P2P message from each party.
-    pub fn handle_msg2<R: RngCore + CryptoRng>(
-        &mut self,
-        rng: &mut R,
-        msgs: Vec<SignMsg2>,
-    ) -> Result<Vec<SignMsg3>, SignError> {
-        if msgs.len() != self.keyshare.threshold as usize - 1 {
-            return Err(SignError::MissingMessage);
+    /// Create a partial signature of the message by a pre-signature of
+    /// this session, see [`create_partial_signature`]. The
+    /// pre-signature must be generated by this party in this session,
+    /// then the policy of the session is checked.
+    pub fn create_partial_signature(
+        &self,
+        pre: PreSignature,
+        message: impl Into<MessageToSign>,
+    ) -> Result<(PartialSignature, SignMsg4), SignError> {
+        pre.check_keyshare(&self.keyshare)?;
+        if pre.final_session_id != self.final_session_id {
+            return Err(SignError::InvalidFinalSessionID);
         }
 
-        let my_party_id = self.keyshare.party_id;
+        let message = message.into();
+        self.check_policy(&message.hash())?;
 
-        let zeta_i = get_zeta_i(
-            &self.keyshare,
-            &self.digest_i,
-            other_parties(&self.sid_list, my_party_id),
-        );
+        create_partial_signature(pre, message)
+    }
 
-        let coeff = if self.keyshare.rank_list.iter().all(|&r| r == 0) {
-            get_lagrange_coeff(
-                &self.keyshare,
-                other_parties(&self.sid_list, my_party_id),
-            )
-        } else {
-            // let betta_coeffs = get_birkhoff_coefficients(&self.keyshare, &party_idx_to_id_map);
-            // *betta_coeffs
-            //     .get(&(my_party_id as usize))
-            //     .expect("betta_i not found") // FIXME
+    /// Stop the session and return the message that notifies the other
+    /// parties. Secret values of the session are wiped and all
+    /// following rounds fail with [`SignError::SessionAborted`].
+    ///
+    /// Could be called in any round, including after a round handler
+    /// failed, e.g. with [`SignError::PolicyRejected`].
+    pub fn abort(&mut self, reason: SignAbortReason) -> SignAbort {
+        self.zeroize();
+        self.pending.clear();
+
+        SignAbort {
+            from_id: self.keyshare.party_id,
+            session_id: self.session_id,
+            reason,
+        }
+    }
 
-            unimplemented!()
-        };
+    /// Handle an abort message of another party of the session. On
+    /// success the session is wiped as by [`State::abort`].
+    ///
+    /// A message from an unexpected party or with a session ID other
+    /// than the one of the party's first message leaves the session
+    /// intact.
+    pub fn handle_abort(&mut self, msg: &SignAbort) -> Result<(), SignError> {
+        if msg.from_id == self.keyshare.party_id
+            || msg.from_id >= self.keyshare.total_parties
+        {
+            return Err(SignError::UnexpectedParty(msg.from_id));
+        }
 
-        self.sk_i = coeff * self.keyshare.s_i + self.additive_offset + zeta_i;
-        self.pk_i = (ProjectivePoint::GENERATOR * self.sk_i).to_affine();
+        self.check_signer(msg.from_id)?;
 
-        let output: Vec<SignMsg3> = msgs
-            .into_iter()
-            .map(|msg| {
-                if msg.final_session_id.ct_ne(&self.final_session_id).into() {
-                    return Err(SignError::InvalidFinalSessionID);
-                }
+        // the first message of the party may not have arrived yet
+        let session_id = self
+            .sid_list
+            .iter()
+            .find_map(|(p, sid)| (*p == msg.from_id).then_some(sid));
+
+        if let Some(session_id) = session_id {
+            if session_id.ct_ne(&msg.session_id).into() {
+                return Err(SignError::SessionIdMismatch(msg.from_id));
+            }
+        }
+
+        self.zeroize();
+        self.pending.clear();
+
+        Ok(())
+    }
+
+    fn check_signer(&self, party_id: u8) -> Result<(), SignError> {
+        match &self.signers {
+            Some(signers) if !signers.contains(party_id) => {
+                Err(SignError::UnexpectedParty(party_id))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Bind the session to a message hash before the nonce is fixed.
+    ///
+    /// The hash is sent in the first message and all parties must
+    /// bind the same hash. A pre-signature created by such a session
+    /// could be used only to sign this message, see
+    /// [`create_partial_signature`].
+    ///
+    /// Must be called once, before [`State::generate_msg1`]. Returns
+    /// [`SignError::InvalidMessageHashBinding`] otherwise.
+    pub fn bind_message_hash(
+        &mut self,
+        hash: [u8; 32],
+    ) -> Result<(), SignError> {
+        if self.message_hash.is_some()
+            || self.msg1_generated
+            || self.round() != SignRound::WaitMsg1
+        {
+            return Err(SignError::InvalidMessageHashBinding);
+        }
+
+        self.message_hash = Some(hash);
+
+        Ok(())
+    }
+
+    /// Message hash bound by [`State::bind_message_hash`], if any.
+    pub fn message_hash(&self) -> Option<[u8; 32]> {
+        self.message_hash
+    }
+
+    //Round 1
+    /// Returns [`SignError::SessionAborted`] if the session is wiped
+    /// by an error of a round handler or by [`State::abort`].
+    pub fn generate_msg1(&mut self) -> Result<SignMsg1, SignError> {
+        if self.is_aborted() {
+            return Err(SignError::SessionAborted);
+        }
+
+        let party_id = self.keyshare.party_id;
+        self.msg1_generated = true;
+
+        Ok(SignMsg1 {
+            from_id: party_id,
+            session_id: *self.sid_list.find_pair(party_id),
+            commitment_r_i: *self.commitment_r_i_list.find_pair(party_id),
+            message_hash: self.message_hash,
+        })
+    }
+
+    /// Round 1
+    pub fn handle_msg1<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+        msgs: impl IntoIterator<Item = impl Borrow<SignMsg1>>,
+    ) -> Result<Vec<SignMsg2>, SignError> {
+        borrow_msgs!(msgs: SignMsg1);
+
+        self.check_batch(
+            SignRound::WaitMsg1,
+            msgs.iter().map(|m| (m.from_id, None, &m.session_id)),
+        )?;
+
+        self.run_round(|state| state.round1(rng, msgs))
+    }
+
+    fn round1<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+        msgs: Vec<&SignMsg1>,
+    ) -> Result<Vec<SignMsg2>, SignError> {
+        for msg in msgs {
+            if msg.message_hash != self.message_hash {
+                return Err(SignError::MessageHashMismatch);
+            }
+
+            // make sure msg is unique
+            if self
+                .commitment_r_i_list
+                .iter()
+                .any(|(_, v)| v == &msg.commitment_r_i)
+            {
+                return Err(SignError::MissingMessage);
+            }
+
+            self.sid_list.push(msg.from_id, msg.session_id);
+            self.commitment_r_i_list
+                .push(msg.from_id, msg.commitment_r_i);
+        }
+
+        self.final_session_id = hash_dsg_final_session_id(
+            self.sid_list.iter().map(|(p, sid)| (*p, sid)),
+            &self.keyshare.final_session_id,
+            self.message_hash.as_ref(),
+        );
+
+        self.digest_i = hash_digest_i(self.commitment_r_i_list.iter().map(
+            |(p, commitment_i)| {
+                (*p, self.sid_list.find_pair(*p), commitment_i)
+            },
+        ));
 
-                let party_id = msg.from_id;
+        let party_id = self.keyshare.party_id;
 
+        Ok(other_parties(&self.sid_list, party_id)
+            .map(|sender_id| {
                 let sid = mta_session_id(
                     &self.final_session_id,
-                    my_party_id,
+                    sender_id,
                     party_id,
                 );
 
-                let seed_ot_results = &self.keyshare.seed_ot_receivers
-                    [get_idx_from_id(my_party_id, party_id) as usize];
-
-                let mut mta_msg2 = ZS::<RVOLEOutput>::default();
+                let sender_ot_results = &self.keyshare.seed_ot_senders
+                    [get_idx_from_id(self.keyshare.party_id, sender_id)
+                        as usize];
 
-                let [c_u, c_v] = RVOLESender::process(
-                    &sid,
-                    seed_ot_results,
-                    &[self.r_i, self.sk_i],
-                    &msg.mta_msg_1,
-                    &mut mta_msg2,
+                let mut mta_msg_1 = ZS::<Round1Output>::default();
+                let (mta_receiver, chi_i_j) = RVOLEReceiver::new(
+                    sid,
+                    sender_ot_results,
+                    &mut mta_msg_1,
                     rng,
-                )
-                .map_err(|_| SignError::AbortProtocolAndBanParty(party_id))?;
-
-                let gamma_u = ProjectivePoint::GENERATOR * c_u;
-                let gamma_v = ProjectivePoint::GENERATOR * c_v;
-                let (_mta_receiver, chi_i_j) =
-                    self.mta_receiver_list.find_pair(party_id);
-                let psi = self.phi_i - chi_i_j;
-
-                self.sender_additive_shares.push([c_u, c_v]);
+                );
 
-                Ok(SignMsg3 {
-                    from_id: self.keyshare.party_id,
-                    to_id: party_id,
+                self.mta_receiver_list
+                    .push(sender_id, (mta_receiver.into(), chi_i_j));
 
+                SignMsg2 {
+                    from_id: party_id,
+                    to_id: sender_id,
                     final_session_id: self.final_session_id,
-                    mta_msg2,
-                    digest_i: self.digest_i,
-                    pk_i: self.pk_i,
-                    big_r_i: self.big_r_i,
-                    blind_factor: self.blind_factor,
-                    gamma_v: gamma_v.to_affine(),
-                    gamma_u: gamma_u.to_affine(),
-                    psi,
-                })
+
+                    mta_msg_1,
+                }
             })
-            .collect::<Result<Vec<_>, SignError>>()?;
+            .collect())
+    }
+
+    /// Round 2
+    /// Handle first P2P message from each party.
+    pub fn handle_msg2<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+        msgs: impl IntoIterator<Item = impl Borrow<SignMsg2>>,
+    ) -> Result<Vec<SignMsg3>, SignError> {
+        self.handle_msg2_with_progress(rng, msgs, |_| {})
+    }
+
+    /// Same as handle_msg2() but calls `progress` with the party ID
+    /// of each counterparty as soon as its MtA message is processed.
+    pub fn handle_msg2_with_progress<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+        msgs: impl IntoIterator<Item = impl Borrow<SignMsg2>>,
+        progress: impl FnMut(u8),
+    ) -> Result<Vec<SignMsg3>, SignError> {
+        borrow_msgs!(msgs: SignMsg2);
+
+        self.check_batch(
+            SignRound::WaitMsg2,
+            msgs.iter()
+                .map(|m| (m.from_id, Some(m.to_id), &m.final_session_id)),
+        )?;
+
+        self.run_round(|state| state.round2(rng, msgs, progress))
+    }
+
+    fn round2<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+        msgs: Vec<&SignMsg2>,
+        mut progress: impl FnMut(u8),
+    ) -> Result<Vec<SignMsg3>, SignError> {
+        let my_party_id = self.keyshare.party_id;
+
+        let zeta_i = get_zeta_i(
+            &self.keyshare,
+            &self.digest_i,
+            other_parties(&self.sid_list, my_party_id),
+        )?;
+
+        let coeff = interpolation_coeff(
+            &self.keyshare,
+            my_party_id,
+            self.sid_list.iter().map(|(p, _)| *p),
+        )?;
+
+        let s_i = match self.multiplicative_tweak {
+            Some(m) => m * self.keyshare.s_i,
+            None => self.keyshare.s_i,
+        };
+
+        self.sk_i = coeff * s_i + self.additive_offset + zeta_i;
+        self.pk_i = (ProjectivePoint::GENERATOR * self.sk_i).to_affine();
+
+        // one RNG per counterparty, the MtA of each pair is independent
+        let msgs: Vec<(&SignMsg2, [u8; 32])> =
+            msgs.into_iter().map(|msg| (msg, rng.gen())).collect();
+
+        let results = par_map(msgs, |(msg, seed)| {
+            self.mta_send(msg, &mut ChaCha20Rng::from_seed(seed))
+        });
+
+        let mut output = Vec::with_capacity(results.len());
+        for result in results {
+            let (msg3, shares) = result?;
+            self.sender_additive_shares.push(shares);
+            progress(msg3.to_id);
+            output.push(msg3);
+        }
 
         Ok(output)
     }
 
+    /// MtA sender side of round 2 for one counterparty.
+    fn mta_send(
+        &self,
+        msg: &SignMsg2,
+        rng: &mut ChaCha20Rng,
+    ) -> Result<(SignMsg3, [Scalar; 2]), SignError> {
+        let my_party_id = self.keyshare.party_id;
+        let party_id = msg.from_id;
+
+        let sid =
+            mta_session_id(&self.final_session_id, my_party_id, party_id);
+
+        let seed_ot_results = &self.keyshare.seed_ot_receivers
+            [get_idx_from_id(my_party_id, party_id) as usize];
+
+        let mut mta_msg2 = ZS::<RVOLEOutput>::default();
+
+        let [c_u, c_v] = RVOLESender::process(
+            &sid,
+            seed_ot_results,
+            &[self.r_i, self.sk_i],
+            &msg.mta_msg_1,
+            &mut mta_msg2,
+            rng,
+        )
+        .map_err(|_| {
+            AbortContext::error(
+                party_id,
+                2,
+                AbortCheck::OtConsistency,
+                Some(self.final_session_id),
+            )
+        })?;
+
+        let gamma_u = ProjectivePoint::GENERATOR * c_u;
+        let gamma_v = ProjectivePoint::GENERATOR * c_v;
+        let (_mta_receiver, chi_i_j) =
+            self.mta_receiver_list.find_pair(party_id);
+        let psi = self.phi_i - chi_i_j;
+
+        let msg3 = SignMsg3 {
+            from_id: my_party_id,
+            to_id: party_id,
+
+            final_session_id: self.final_session_id,
+            mta_msg2,
+            digest_i: self.digest_i,
+            pk_i: self.pk_i,
+            big_r_i: self.big_r_i,
+            blind_factor: self.blind_factor,
+            gamma_v: gamma_v.to_affine(),
+            gamma_u: gamma_u.to_affine(),
+            psi,
+        };
+
+        Ok((msg3, [c_u, c_v]))
+    }
+
     /// Round 3 returns the presigs
     /// Handle second P2P message from each party.
     /// FIXME: add comment about using
     pub fn handle_msg3(
         &mut self,
-        msgs: Vec<SignMsg3>,
+        msgs: impl IntoIterator<Item = impl Borrow<SignMsg3>>,
     ) -> Result<PreSignature, SignError> {
-        if msgs.len() != self.keyshare.threshold as usize - 1 {
-            return Err(SignError::MissingMessage);
-        }
+        borrow_msgs!(msgs: SignMsg3);
+
+        self.check_batch(
+            SignRound::WaitMsg3,
+            msgs.iter()
+                .map(|m| (m.from_id, Some(m.to_id), &m.final_session_id)),
+        )?;
+
+        self.run_round(|state| state.round3(msgs))
+    }
 
+    fn round3(
+        &mut self,
+        msgs: Vec<&SignMsg3>,
+    ) -> Result<PreSignature, SignError> {
         let mut big_r_star = ProjectivePoint::IDENTITY;
         let mut sum_pk_j = ProjectivePoint::IDENTITY;
         let mut sum_psi_j_i = Scalar::ZERO;
 
-        let mut receiver_additive_shares = vec![];
-
-        for msg3 in msgs {
-            if msg3.final_session_id.ct_ne(&self.final_session_id).into() {
-                return Err(SignError::InvalidFinalSessionID);
-            }
+        let mut receiver_additive_shares =
+            Vec::with_capacity(self.keyshare.threshold as usize - 1);
 
-            let party_id = msg3.from_id;
+        let mut pairs = Vec::with_capacity(msgs.len());
+        for &msg3 in &msgs {
             let (mta_receiver, chi_i_j) =
-                self.mta_receiver_list.pop_pair(party_id);
-
-            let [d_u, d_v] = mta_receiver
-                .process(&msg3.mta_msg2)
-                .map_err(|_| SignError::AbortProtocolAndBanParty(party_id))?;
-
-            receiver_additive_shares.push([d_u, d_v]);
-
-            let commitment = self.commitment_r_i_list.find_pair(party_id);
-            let sid_i = self.sid_list.find_pair(party_id);
-
-            if !verify_commitment_r_i(
-                sid_i,
-                &msg3.big_r_i.to_curve(),
-                &msg3.blind_factor,
-                commitment,
-            ) {
-                return Err(SignError::InvalidCommitment);
-            }
+                self.mta_receiver_list.pop_pair(msg3.from_id);
+            pairs.push((msg3, mta_receiver, chi_i_j));
+        }
 
-            if self.digest_i.ct_ne(&msg3.digest_i).into() {
-                return Err(SignError::InvalidDigest);
-            }
+        let results = par_map(pairs, |(msg3, mta_receiver, chi_i_j)| {
+            self.mta_receive(msg3, &mta_receiver, &chi_i_j)
+        });
 
-            let big_r_j = msg3.big_r_i.to_curve();
-            let pk_j = msg3.pk_i.to_curve();
+        for (msg3, result) in msgs.into_iter().zip(results) {
+            receiver_additive_shares.push(result?);
 
-            big_r_star += big_r_j;
-            sum_pk_j += pk_j;
+            big_r_star += msg3.big_r_i.to_curve();
+            sum_pk_j += msg3.pk_i.to_curve();
             sum_psi_j_i += &msg3.psi;
-
-            let cond1 = (big_r_j * chi_i_j)
-                == (ProjectivePoint::GENERATOR * d_u + msg3.gamma_u);
-            if !cond1 {
-                return Err(SignError::AbortProtocolAndBanParty(party_id));
-            }
-
-            let cond2 = (pk_j * chi_i_j)
-                == (ProjectivePoint::GENERATOR * d_v + msg3.gamma_v);
-            if !cond2 {
-                return Err(SignError::AbortProtocolAndBanParty(party_id));
-            }
         }
 
         // new var
@@ -476,361 +1354,3256 @@ impl State {
             r: r_point,
             s_0,
             s_1,
+            message_hash: self.message_hash,
+            root_chain_code: self
+                .keyshare
+                .root_chain_code
+                .filter(|_| self.path_agnostic),
+            version: PRESIGNATURE_VERSION,
+            key_fingerprint: self.keyshare.key_fingerprint(),
+            keyshare_epoch: self.keyshare.final_session_id,
+            threshold: self.keyshare.threshold,
         };
 
         Ok(pre_sign_result)
     }
-}
 
-pub fn create_partial_signature(
-    pre: PreSignature,
-    hash: [u8; 32],
-) -> (PartialSignature, SignMsg4) {
-    let m = Scalar::reduce(U256::from_be_slice(&hash));
-    let s_0 = m * pre.phi_i + pre.s_0;
-
-    let partial = PartialSignature {
-        party_id: pre.from_id,
-        final_session_id: pre.final_session_id,
-        public_key: pre.public_key,
-        message_hash: hash,
-        s_0,
-        s_1: pre.s_1,
-        r: pre.r,
-    };
+    /// MtA receiver side and checks of round 3 for one counterparty.
+    fn mta_receive(
+        &self,
+        msg3: &SignMsg3,
+        mta_receiver: &ZS<RVOLEReceiver>,
+        chi_i_j: &Scalar,
+    ) -> Result<[Scalar; 2], SignError> {
+        let party_id = msg3.from_id;
+
+        let abort = |check| {
+            AbortContext::error(
+                party_id,
+                3,
+                check,
+                Some(self.final_session_id),
+            )
+        };
 
-    let msg4 = SignMsg4 {
-        from_id: pre.from_id,
-        session_id: partial.final_session_id,
-        s_0: partial.s_0,
-        s_1: partial.s_1,
-    };
+        let [d_u, d_v] = mta_receiver
+            .process(&msg3.mta_msg2)
+            .map_err(|_| abort(AbortCheck::OtConsistency))?;
 
-    (partial, msg4)
-}
+        let commitment = self.commitment_r_i_list.find_pair(party_id);
+        let sid_i = self.sid_list.find_pair(party_id);
 
-/// Partial signature of party_i
-#[derive(Zeroize, ZeroizeOnDrop)]
-struct PS {
-    /// final_session_id
-    pub final_session_id: [u8; 32],
+        if !verify_commitment_r_i(
+            sid_i,
+            &msg3.big_r_i.to_curve(),
+            &msg3.blind_factor,
+            commitment,
+        ) {
+            return Err(abort(AbortCheck::Commitment));
+        }
 
-    /// public_key
-    pub public_key: ProjectivePoint,
+        if self.digest_i.ct_ne(&msg3.digest_i).into() {
+            return Err(abort(AbortCheck::Digest));
+        }
 
-    /// 32 bytes message_hash
-    pub message_hash: [u8; 32],
+        for check in [MtaCheck::Nonce, MtaCheck::PublicKey] {
+            if !check.holds(msg3, chi_i_j, &d_u, &d_v) {
+                let blame = SignBlameClaim {
+                    accuser: self.keyshare.party_id,
+                    accused: party_id,
+                    check,
+                    session_id: self.final_session_id,
+                };
+                return Err(SignError::MtaCheckFailed(Box::new(blame)));
+            }
+        }
 
-    /// s_0 Scalar
-    pub s_0: Scalar,
+        Ok([d_u, d_v])
+    }
 
-    /// s_1 Scalar
-    pub s_1: Scalar,
+    /// Accept one message of the current round. Checks that could be
+    /// done without the rest of the batch are done immediately.
+    ///
+    /// Returns true once the batch of the round is complete and
+    /// [`State::finish_round`] could be called.
+    pub fn push_msg(
+        &mut self,
+        msg: impl Into<SignRoundMsg>,
+    ) -> Result<bool, SignError> {
+        if self.is_aborted() {
+            return Err(SignError::SessionAborted);
+        }
 
-    /// R point
-    pub r: ProjectivePoint,
-}
+        let msg = msg.into();
+        let party_id = self.keyshare.party_id;
+        let t = self.keyshare.threshold as usize;
 
-//Round 4: final round to compute the ECDSA signature from the presigs and the message
-pub fn combine_signatures(
-    partial: PartialSignature,
-    msgs: Vec<SignMsg4>,
-) -> Result<Signature, SignError> {
-    let t = msgs.len() + 1;
+        let (from_id, round) = match &msg {
+            SignRoundMsg::Msg1(m) => (m.from_id, SignRound::WaitMsg1),
+            SignRoundMsg::Msg2(m) => (m.from_id, SignRound::WaitMsg2),
+            SignRoundMsg::Msg3(m) => (m.from_id, SignRound::WaitMsg3),
+        };
 
-    let mut partial_signatures = Vec::with_capacity(t);
+        if round != self.round() {
+            return Err(SignError::FailedCheck("Message of another round"));
+        }
 
-    partial_signatures.push(PS {
-        final_session_id: partial.final_session_id,
-        public_key: partial.public_key.to_curve(),
-        message_hash: partial.message_hash,
-        s_0: partial.s_0,
-        s_1: partial.s_1,
-        r: partial.r.to_curve(),
-    });
+        self.check_signer(from_id)?;
 
-    for msg in msgs {
-        partial_signatures.push(PS {
-            final_session_id: msg.session_id,
-            s_0: msg.s_0,
-            s_1: msg.s_1,
+        if from_id == party_id
+            || from_id as usize >= self.keyshare.rank_list.len()
+        {
+            return Err(SignError::UnexpectedParty(from_id));
+        }
 
-            public_key: partial.public_key.to_curve(),
-            message_hash: partial.message_hash,
-            r: partial.r.to_curve(),
+        let duplicate = self.pending.iter().any(|m| match m {
+            SignRoundMsg::Msg1(m) => m.from_id == from_id,
+            SignRoundMsg::Msg2(m) => m.from_id == from_id,
+            SignRoundMsg::Msg3(m) => m.from_id == from_id,
         });
+        if duplicate || self.pending.len() == t - 1 {
+            return Err(SignError::MissingMessage);
+        }
+
+        match &msg {
+            SignRoundMsg::Msg1(m) => {
+                if m.message_hash != self.message_hash {
+                    return Err(SignError::MessageHashMismatch);
+                }
+            }
+
+            SignRoundMsg::Msg2(SignMsg2 {
+                to_id,
+                final_session_id,
+                ..
+            })
+            | SignRoundMsg::Msg3(SignMsg3 {
+                to_id,
+                final_session_id,
+                ..
+            }) => {
+                if *to_id != party_id
+                    || !self
+                        .mta_receiver_list
+                        .iter()
+                        .any(|(p, _)| *p == from_id)
+                {
+                    return Err(SignError::UnexpectedParty(from_id));
+                }
+
+                if final_session_id.ct_ne(&self.final_session_id).into() {
+                    return Err(SignError::InvalidFinalSessionID);
+                }
+            }
+        }
+
+        self.pending.push(msg);
+
+        Ok(self.pending.len() == t - 1)
     }
 
-    combine_partial_signature(partial_signatures, t)
-}
+    /// Handle the batch of messages collected by [`State::push_msg`].
+    pub fn finish_round<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+    ) -> Result<SignRoundOutput, SignError> {
+        if self.is_aborted() {
+            return Err(SignError::SessionAborted);
+        }
 
-// TODO: remove vectors
-fn get_zeta_i(
-    keyshare: &Keyshare,
-    sig_id: &[u8; 32],
-    partys: impl Iterator<Item = u8>,
-) -> Scalar {
-    let mut p_0_list = Vec::new();
-    let mut p_1_list = Vec::new();
+        if self.pending.len() != self.keyshare.threshold as usize - 1 {
+            return Err(SignError::MissingMessage);
+        }
 
-    for party_id in partys {
-        if party_id < keyshare.party_id {
-            p_0_list.push(party_id);
+        let pending = std::mem::take(&mut self.pending);
+
+        match self.round() {
+            SignRound::WaitMsg1 => {
+                let msgs = pending.iter().filter_map(|m| match m {
+                    SignRoundMsg::Msg1(m) => Some(m),
+                    _ => None,
+                });
+                self.handle_msg1(rng, msgs).map(SignRoundOutput::Msg2)
+            }
+
+            SignRound::WaitMsg2 => {
+                let msgs = pending.iter().filter_map(|m| match m {
+                    SignRoundMsg::Msg2(m) => Some(m),
+                    _ => None,
+                });
+                self.handle_msg2(rng, msgs).map(SignRoundOutput::Msg3)
+            }
+
+            SignRound::WaitMsg3 => {
+                let msgs = pending.iter().filter_map(|m| match m {
+                    SignRoundMsg::Msg3(m) => Some(m),
+                    _ => None,
+                });
+                self.handle_msg3(msgs).map(SignRoundOutput::PreSignature)
+            }
+
+            _ => Err(SignError::FailedCheck("Session is finished")),
+        }
+    }
+
+    /// Current round of the state.
+    pub fn round(&self) -> SignRound {
+        if self.is_aborted() {
+            SignRound::Aborted
+        } else if self.sid_list.len() == 1 {
+            SignRound::WaitMsg1
+        } else if self.sender_additive_shares.is_empty() {
+            SignRound::WaitMsg2
+        } else if self.mta_receiver_list.len() != 0 {
+            SignRound::WaitMsg3
+        } else {
+            SignRound::Finished
+        }
+    }
+
+    /// Wrap the state into a persistence envelope. The envelope is
+    /// serializable and contains secret material of the party.
+    pub fn suspend(self) -> SuspendedState {
+        let round = self.round();
+        SuspendedState {
+            version: STATE_SUSPEND_VERSION,
+            round,
+            checksum: self.checksum(STATE_SUSPEND_VERSION, round),
+            state: self,
+        }
+    }
+
+    /// Restore a state suspended by [`State::suspend`]. Returns an
+    /// error if the envelope has unsupported version, the state is in
+    /// a round other than `round` or the checksum does not match.
+    pub fn resume(
+        suspended: SuspendedState,
+        round: SignRound,
+    ) -> Result<Self, SignError> {
+        let SuspendedState {
+            version,
+            round: suspended_round,
+            checksum,
+            state,
+        } = suspended;
+
+        if version != STATE_SUSPEND_VERSION {
+            return Err(SignError::UnsupportedStateVersion(version));
         }
-        if party_id > keyshare.party_id {
-            p_1_list.push(party_id);
+
+        let valid = suspended_round == round
+            && state.round() == round
+            && state.checksum(version, round).ct_eq(&checksum).into();
+        if !valid {
+            return Err(SignError::InvalidSuspendedState);
         }
+
+        Ok(state)
     }
 
-    let mut sum_p_0 = Scalar::ZERO;
-    for p_0_party in &p_0_list {
-        let seed_j_i = keyshare.rec_seed_list[*p_0_party as usize];
+    fn checksum(&self, version: u8, round: SignRound) -> [u8; 32] {
         let mut hasher = Sha256::new();
         hasher.update(DSG_LABEL);
-        hasher.update(seed_j_i);
-        hasher.update(sig_id);
-        hasher.update(PAIRWISE_RANDOMIZATION_LABEL);
-        let value = Scalar::reduce(U256::from_be_slice(&hasher.finalize()));
-        sum_p_0 += value;
+        hasher.update([version, round as u8, self.keyshare.party_id]);
+        hasher.update(self.keyshare.final_session_id);
+        for (party_id, sid) in self.sid_list.iter() {
+            hasher.update([*party_id]);
+            hasher.update(sid);
+        }
+        for (party_id, commitment) in self.commitment_r_i_list.iter() {
+            hasher.update([*party_id]);
+            hasher.update(commitment);
+        }
+        for (party_id, (receiver, chi)) in self.mta_receiver_list.iter() {
+            hasher.update([*party_id]);
+            hasher.update(bytemuck::bytes_of(&**receiver));
+            hasher.update(chi.to_bytes());
+        }
+        for [c_u, c_v] in &self.sender_additive_shares {
+            hasher.update(c_u.to_bytes());
+            hasher.update(c_v.to_bytes());
+        }
+        for scalar in [self.phi_i, self.r_i, self.sk_i, self.additive_offset]
+        {
+            hasher.update(scalar.to_bytes());
+        }
+        if let Some(m) = self.multiplicative_tweak {
+            hasher.update(m.to_bytes());
+        }
+        for point in [self.big_r_i, self.pk_i, self.derived_public_key] {
+            hasher.update(point.to_bytes());
+        }
+        hasher.update(self.blind_factor);
+        hasher.update(self.final_session_id);
+        hasher.update(self.digest_i);
+        hasher.update(self.message_hash.unwrap_or_default());
+        if let Some(signers) = &self.signers {
+            hasher.update(signers.party_ids());
+        }
+        hasher.update([self.path_agnostic as u8]);
+        for bits in self.chain_path.iter().flatten() {
+            hasher.update(bits.to_be_bytes());
+        }
+        hasher.update(DSG_STATE_CHECKSUM_LABEL);
+        hasher.finalize().into()
+    }
+
+    /// Round 3 of a session bound to a message hash. Returns the
+    /// partial signature and the last message to broadcast.
+    ///
+    /// Returns [`SignError::UnboundMessageHash`] if no message hash
+    /// was bound before round 1 and [`SignError::PolicyRejected`] if
+    /// the policy of the session refuses the hash. Both abort the
+    /// session.
+    pub fn handle_msg3_and_sign(
+        &mut self,
+        msgs: impl IntoIterator<Item = impl Borrow<SignMsg3>>,
+    ) -> Result<(PartialSignature, SignMsg4), SignError> {
+        let Some(hash) = self.message_hash else {
+            return self.run_round(|_| Err(SignError::UnboundMessageHash));
+        };
+        self.run_round(|state| state.check_policy(&hash))?;
+        let pre = self.handle_msg3(msgs)?;
+
+        create_partial_signature(pre, hash)
+    }
+
+    /// Run a round handler on a checked batch. Any error ends the
+    /// session: secret values of the session are wiped and all
+    /// following rounds fail with [`SignError::SessionAborted`].
+    fn run_round<T>(
+        &mut self,
+        round: impl FnOnce(&mut Self) -> Result<T, SignError>,
+    ) -> Result<T, SignError> {
+        if self.is_aborted() {
+            return Err(SignError::SessionAborted);
+        }
+
+        let res = round(self);
+        if res.is_err() {
+            self.zeroize();
+            self.pending.clear();
+        }
+
+        res
+    }
+
+    /// Check a batch of messages before a round handler touches the
+    /// state: the round, the number of messages, the senders and the
+    /// session of each message. Each item is the sender, the receiver
+    /// of a P2P message and the (final) session ID of the message.
+    ///
+    /// A batch rejected here leaves the session in its current round,
+    /// the round can be retried with a corrected batch.
+    fn check_batch<'a>(
+        &self,
+        round: SignRound,
+        msgs: impl ExactSizeIterator<Item = (u8, Option<u8>, &'a [u8; 32])>,
+    ) -> Result<(), SignError> {
+        if self.is_aborted() {
+            return Err(SignError::SessionAborted);
+        }
+
+        if round != self.round() {
+            return Err(SignError::FailedCheck("Message of another round"));
+        }
+
+        if msgs.len() != self.keyshare.threshold as usize - 1 {
+            return Err(SignError::MissingMessage);
+        }
+
+        let party_id = self.keyshare.party_id;
+        let mut senders = Vec::with_capacity(msgs.len());
+        let mut session_ids = Vec::with_capacity(msgs.len());
+
+        for (from_id, to_id, session_id) in msgs {
+            self.check_signer(from_id)?;
+
+            if from_id as usize >= self.keyshare.rank_list.len() {
+                return Err(SignError::UnexpectedParty(from_id));
+            }
+
+            if senders.contains(&from_id) {
+                return Err(SignError::DuplicateParty(from_id));
+            }
+            senders.push(from_id);
+
+            match to_id {
+                // broadcast of round 1: a new party with a fresh session ID
+                None => {
+                    if self.sid_list.iter().any(|(p, _)| *p == from_id) {
+                        return Err(SignError::DuplicateParty(from_id));
+                    }
+
+                    if session_ids.contains(&session_id)
+                        || self.sid_list.iter().any(|(_, s)| s == session_id)
+                    {
+                        return Err(SignError::DuplicateSessionId(from_id));
+                    }
+                    session_ids.push(session_id);
+                }
+
+                // P2P message of an MtA counterparty of this session
+                Some(to_id) => {
+                    if to_id != party_id
+                        || !self
+                            .mta_receiver_list
+                            .iter()
+                            .any(|(p, _)| *p == from_id)
+                    {
+                        return Err(SignError::UnexpectedParty(from_id));
+                    }
+
+                    if session_id.ct_ne(&self.final_session_id).into() {
+                        return Err(SignError::InvalidFinalSessionID);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn is_aborted(&self) -> bool {
+        // a session always contains own session ID, unless wiped
+        self.sid_list.len() == 0
+    }
+}
+
+/// MtA consistency check of the third round.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MtaCheck {
+    /// `chi * R_j == d_u * G + gamma_u`
+    Nonce,
+
+    /// `chi * PK_j == d_v * G + gamma_v`
+    PublicKey,
+}
+
+/// Claim that a message 3 failed an MtA consistency check.
+///
+/// The claim is not evidence. Messages are not signed, and the check
+/// depends on the secret MtA input and outputs of the accuser, which
+/// are not disclosed. Other parties could not tell a true claim from a
+/// false one and should weigh it against the accused's own view, for
+/// example with an honest majority vote.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SignBlameClaim {
+    /// Party that detected the failure.
+    pub accuser: u8,
+
+    /// Sender of the offending message.
+    pub accused: u8,
+
+    /// Failed check.
+    pub check: MtaCheck,
+
+    /// Final session ID of the aborted session.
+    pub session_id: [u8; 32],
+}
+
+impl MtaCheck {
+    fn holds(
+        &self,
+        msg: &SignMsg3,
+        chi: &Scalar,
+        d_u: &Scalar,
+        d_v: &Scalar,
+    ) -> bool {
+        let (point, d, gamma) = match self {
+            Self::Nonce => (&msg.big_r_i, d_u, &msg.gamma_u),
+            Self::PublicKey => (&msg.pk_i, d_v, &msg.gamma_v),
+        };
+
+        point.to_curve() * chi == ProjectivePoint::GENERATOR * d + gamma
+    }
+}
+
+/// Signing sessions prepared in advance.
+///
+/// `State::new()` walks the BIP32 derivation path and generates the
+/// round 1 nonce and commitment. `Precomputed` does this work when a
+/// keyshare is loaded, for a set of likely derivation paths, so that
+/// a session taken for one of these paths could emit its first message
+/// immediately. Derivation results of the paths are cached by
+/// [`DerivedKeyshare`].
+pub struct Precomputed {
+    keyshare: Arc<Keyshare>,
+    paths: Vec<DerivedKeyshare>,
+    ready: Vec<(usize, State)>,
+    sessions_per_path: usize,
+}
+
+impl Precomputed {
+    /// Derive offsets for all passed paths and prepare
+    /// `sessions_per_path` signing sessions for each of them.
+    pub fn new<R: RngCore + CryptoRng>(
+        rng: &mut R,
+        keyshare: impl Into<Arc<Keyshare>>,
+        chain_paths: &[DerivationPath],
+        sessions_per_path: usize,
+    ) -> Result<Self, SignError> {
+        let keyshare = keyshare.into();
+        let paths = chain_paths
+            .iter()
+            .map(|path| DerivedKeyshare::new(keyshare.clone(), path))
+            .collect::<Result<Vec<_>, SignError>>()?;
+
+        let mut precomputed = Self {
+            keyshare,
+            paths,
+            ready: vec![],
+            sessions_per_path,
+        };
+
+        precomputed.refill(rng);
+
+        Ok(precomputed)
+    }
+
+    /// Number of prepared sessions for given derivation path.
+    pub fn available(&self, chain_path: &DerivationPath) -> usize {
+        match self.position(chain_path) {
+            Some(idx) => self.ready.iter().filter(|(i, _)| *i == idx).count(),
+            None => 0,
+        }
+    }
+
+    /// Top up prepared sessions to `sessions_per_path` for each path.
+    /// Call this method outside of latency critical path, for
+    /// example after a signing session is finished.
+    pub fn refill<R: RngCore + CryptoRng>(&mut self, rng: &mut R) {
+        for (idx, derived) in self.paths.iter().enumerate() {
+            let ready = self.ready.iter().filter(|(i, _)| *i == idx).count();
+
+            for _ in ready..self.sessions_per_path {
+                let state = State::new_derived(rng, derived);
+                self.ready.push((idx, state));
+            }
+        }
+    }
+
+    /// Take a prepared session for the given derivation path.
+    ///
+    /// If there is no prepared session, a new one is created using
+    /// cached derivation results if the path is known, or by calling
+    /// `State::new()` otherwise.
+    pub fn take<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+        chain_path: &DerivationPath,
+    ) -> Result<State, SignError> {
+        let Some(idx) = self.position(chain_path) else {
+            return State::new(rng, self.keyshare.clone(), chain_path);
+        };
+
+        if let Some(pos) = self.ready.iter().position(|(i, _)| *i == idx) {
+            return Ok(self.ready.swap_remove(pos).1);
+        }
+
+        Ok(State::new_derived(rng, &self.paths[idx]))
+    }
+
+    fn position(&self, chain_path: &DerivationPath) -> Option<usize> {
+        self.paths.iter().position(|d| d.chain_path() == chain_path)
+    }
+}
+
+/// Key share together with cached derivation results for one path.
+///
+/// `State::new()` walks the BIP32 derivation path on every call. A
+/// `DerivedKeyshare` walks it once and could be passed to
+/// [`State::new_derived`] for every following signing session.
+///
+/// Cached values depend only on the public key and the root chain code
+/// of the key share, a key refresh keeps them. They are derived again
+/// by [`DerivedKeyshare::update`] when a key share of another key is
+/// passed.
+#[derive(Clone)]
+pub struct DerivedKeyshare {
+    keyshare: Arc<Keyshare>,
+    chain_path: DerivationPath,
+    additive_offset: Scalar,
+    derived_public_key: AffinePoint,
+    chain_code: Option<[u8; 32]>,
+}
+
+impl DerivedKeyshare {
+    /// Derive the key of the given path.
+    pub fn new(
+        keyshare: impl Into<Arc<Keyshare>>,
+        chain_path: &DerivationPath,
+    ) -> Result<Self, SignError> {
+        let keyshare = keyshare.into();
+        let (additive_offset, derived_public_key, chain_code) = derive_path(
+            &keyshare.public_key.to_curve(),
+            keyshare.root_chain_code.as_ref(),
+            chain_path,
+        )?;
+
+        Ok(Self {
+            keyshare,
+            chain_path: chain_path.clone(),
+            additive_offset,
+            derived_public_key: derived_public_key.to_affine(),
+            chain_code,
+        })
+    }
+
+    /// Replace the key share, for example after a key refresh.
+    /// Cached values are derived again if the key share has another
+    /// public key or root chain code.
+    pub fn update(
+        &mut self,
+        keyshare: impl Into<Arc<Keyshare>>,
+    ) -> Result<(), SignError> {
+        let keyshare = keyshare.into();
+        if keyshare.public_key != self.keyshare.public_key
+            || keyshare.root_chain_code != self.keyshare.root_chain_code
+        {
+            *self = Self::new(keyshare, &self.chain_path)?;
+        } else {
+            self.keyshare = keyshare;
+        }
+
+        Ok(())
+    }
+
+    /// Underlying key share.
+    pub fn keyshare(&self) -> &Keyshare {
+        &self.keyshare
+    }
+
+    /// Derivation path.
+    pub fn chain_path(&self) -> &DerivationPath {
+        &self.chain_path
+    }
+
+    /// Public key of the path.
+    pub fn derived_public_key(&self) -> AffinePoint {
+        self.derived_public_key
+    }
+
+    /// Chain code of the path, `None` for a key share without a
+    /// chain code.
+    pub fn chain_code(&self) -> Option<[u8; 32]> {
+        self.chain_code
+    }
+}
+
+/// Pool of pre-signatures of a party that lets each one of them
+/// sign only one message.
+///
+/// The store remembers IDs of all pre-signatures taken out of it and
+/// refuses to accept them again. Persist the store as a whole instead
+/// of single pre-signatures: the set of used IDs survives
+/// serialization, so a copy of a pre-signature restored from an old
+/// backup is rejected by [`PresignatureStore::insert`].
+#[derive(Default, Serialize, Deserialize)]
+pub struct PresignatureStore {
+    available: Vec<PreSignature>,
+    used: HashSet<[u8; 32]>,
+}
+
+impl PresignatureStore {
+    /// Create an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a pre-signature to the store.
+    ///
+    /// Returns [`SignError::PreSignatureReuse`] if a pre-signature
+    /// with the same ID is already in the store or was used.
+    pub fn insert(&mut self, pre: PreSignature) -> Result<(), SignError> {
+        let id = pre.id();
+        if self.used.contains(&id)
+            || self.available.iter().any(|p| p.id() == id)
+        {
+            return Err(SignError::PreSignatureReuse);
+        }
+
+        self.available.push(pre);
+
+        Ok(())
+    }
+
+    /// Number of pre-signatures that are not used yet.
+    pub fn len(&self) -> usize {
+        self.available.len()
+    }
+
+    /// True if there is no pre-signature to use.
+    pub fn is_empty(&self) -> bool {
+        self.available.is_empty()
+    }
+
+    /// IDs of pre-signatures that are not used yet.
+    pub fn ids(&self) -> impl Iterator<Item = [u8; 32]> + '_ {
+        self.available.iter().map(PreSignature::id)
+    }
+
+    /// True if the pre-signature with the given ID was taken out of
+    /// the store.
+    pub fn is_used(&self, id: &[u8; 32]) -> bool {
+        self.used.contains(id)
+    }
+
+    /// Take a pre-signature out of the store and mark it as used.
+    pub fn take(&mut self, id: &[u8; 32]) -> Result<PreSignature, SignError> {
+        if self.used.contains(id) {
+            return Err(SignError::PreSignatureReuse);
+        }
+
+        let pos = self
+            .available
+            .iter()
+            .position(|p| &p.id() == id)
+            .ok_or(SignError::UnknownPreSignature)?;
+
+        self.used.insert(*id);
+
+        Ok(self.available.swap_remove(pos))
+    }
+
+    /// Take the pre-signature with the given ID and create a partial
+    /// signature of the message, see [`create_partial_signature`].
+    ///
+    /// The pre-signature is marked as used even if creation of the
+    /// partial signature fails.
+    pub fn create_partial_signature(
+        &mut self,
+        id: &[u8; 32],
+        message: impl Into<MessageToSign>,
+    ) -> Result<(PartialSignature, SignMsg4), SignError> {
+        create_partial_signature(self.take(id)?, message)
+    }
+}
+
+/// Create a partial signature of the message.
+///
+/// A plain `[u8; 32]` converts to [`MessageToSign::Prehashed`] and is
+/// signed as is.
+///
+/// Returns [`SignError::MessageHashMismatch`] if the pre-signature was
+/// generated by a session bound to a different message hash, and
+/// [`SignError::UnsupportedPreSignatureVersion`] for a pre-signature of
+/// a newer format. Use [`PreSignature::check_keyshare`] to make sure a
+/// stored pre-signature belongs to the expected key share.
+pub fn create_partial_signature(
+    pre: PreSignature,
+    message: impl Into<MessageToSign>,
+) -> Result<(PartialSignature, SignMsg4), SignError> {
+    let hash = message.into().hash();
+
+    if pre.version > PRESIGNATURE_VERSION {
+        return Err(SignError::UnsupportedPreSignatureVersion(pre.version));
+    }
+
+    if pre.message_hash.is_some_and(|h| h != hash) {
+        return Err(SignError::MessageHashMismatch);
+    }
+
+    let m = Scalar::reduce(U256::from_be_slice(&hash));
+    let s_0 = m * pre.phi_i + pre.s_0;
+
+    let partial = PartialSignature {
+        party_id: pre.from_id,
+        final_session_id: pre.final_session_id,
+        public_key: pre.public_key,
+        message_hash: hash,
+        s_0,
+        s_1: pre.s_1,
+        r: pre.r,
+        threshold: pre.threshold,
+    };
+
+    let msg4 = SignMsg4 {
+        from_id: pre.from_id,
+        session_id: partial.final_session_id,
+        s_0: partial.s_0,
+        s_1: partial.s_1,
+    };
+
+    Ok((partial, msg4))
+}
+
+/// Hash function applied to a message before signing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HashAlgorithm {
+    /// SHA-256
+    Sha256,
+
+    /// Keccak-256, as used by Ethereum
+    Keccak256,
+
+    /// SHA-256 applied twice, as used by Bitcoin
+    DoubleSha256,
+}
+
+impl HashAlgorithm {
+    /// Hash the message.
+    pub fn digest(&self, message: &[u8]) -> [u8; 32] {
+        match self {
+            Self::Sha256 => Sha256::digest(message).into(),
+            Self::Keccak256 => keccak256(message),
+            Self::DoubleSha256 => {
+                Sha256::digest(Sha256::digest(message)).into()
+            }
+        }
+    }
+}
+
+/// Message signed by [`create_partial_signature`].
+///
+/// Only a value of exactly 32 bytes could be signed as a hash. Any
+/// other message must name the hash function to apply, instead of
+/// being truncated or padded by the caller.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MessageToSign {
+    /// Hash of the message, signed as is.
+    Prehashed([u8; 32]),
+
+    /// Message hashed by the hash function before signing.
+    Raw(Vec<u8>, HashAlgorithm),
+}
+
+impl MessageToSign {
+    /// The 32 byte hash to sign.
+    pub fn hash(&self) -> [u8; 32] {
+        match self {
+            Self::Prehashed(hash) => *hash,
+            Self::Raw(message, hash_fn) => hash_fn.digest(message),
+        }
+    }
+}
+
+impl From<[u8; 32]> for MessageToSign {
+    fn from(hash: [u8; 32]) -> Self {
+        Self::Prehashed(hash)
+    }
+}
+
+/// Create a partial signature of the message hashed by the given hash
+/// function, see [`create_partial_signature`].
+pub fn create_partial_signature_for_message(
+    pre: PreSignature,
+    message: &[u8],
+    hash_fn: HashAlgorithm,
+) -> Result<(PartialSignature, SignMsg4), SignError> {
+    create_partial_signature(
+        pre,
+        MessageToSign::Raw(message.to_vec(), hash_fn),
+    )
+}
+
+/// Online phase of signing with a pre-signature computed in advance.
+///
+/// Holds only the partial signature of the party, all the interactive
+/// rounds are already done. [`OnlineSession::start`] signs the message
+/// hash and returns the only message of the phase, [`SignMsg4`] to
+/// broadcast; [`OnlineSession::finish`] combines the signature once the
+/// messages of the other signers arrive.
+#[derive(Serialize, Deserialize)]
+pub struct OnlineSession {
+    partial: PartialSignature,
+}
+
+impl OnlineSession {
+    /// Sign `message` by a stored pre-signature, see
+    /// [`create_partial_signature`].
+    pub fn start(
+        pre: PreSignature,
+        message: impl Into<MessageToSign>,
+    ) -> Result<(Self, SignMsg4), SignError> {
+        let (partial, msg4) = create_partial_signature(pre, message)?;
+
+        Ok((Self { partial }, msg4))
+    }
+
+    /// Final session ID of the pre-signature. Messages of the other
+    /// signers carry the same ID.
+    pub fn session_id(&self) -> &[u8; 32] {
+        &self.partial.final_session_id
+    }
+
+    /// Combine the signature from messages of all other signers.
+    pub fn finish(
+        self,
+        msgs: impl IntoIterator<Item = impl Borrow<SignMsg4>>,
+    ) -> Result<(Signature, RecoveryId), SignError> {
+        self.finish_with(msgs, SNormalization::LowS)
     }
 
-    let mut sum_p_1 = Scalar::ZERO;
-    for p_1_party in &p_1_list {
-        let seed_i_j = keyshare.sent_seed_list
-            [*p_1_party as usize - keyshare.party_id as usize - 1];
-        let mut hasher = Sha256::new();
-        hasher.update(DSG_LABEL);
-        hasher.update(seed_i_j);
-        hasher.update(sig_id);
-        hasher.update(PAIRWISE_RANDOMIZATION_LABEL);
-        let value = Scalar::reduce(U256::from_be_slice(&hasher.finalize()));
-        sum_p_1 += value;
+    /// Same as [`OnlineSession::finish`] with the given normalization
+    /// of `s`.
+    pub fn finish_with(
+        self,
+        msgs: impl IntoIterator<Item = impl Borrow<SignMsg4>>,
+        normalization: SNormalization,
+    ) -> Result<(Signature, RecoveryId), SignError> {
+        combine_signatures_with(self.partial, msgs, normalization)
+    }
+}
+
+/// Create a partial signature of the message hash for a key derived
+/// from the root key by the given path.
+///
+/// The pre-signature must be generated by a session created by
+/// [`State::new_path_agnostic`], otherwise
+/// [`SignError::PathBoundPreSignature`] is returned. All parties must
+/// pass the same derivation path.
+///
+/// # Security
+///
+/// The offset of the path is applied after the nonce R is fixed. Who
+/// chooses the path after seeing R obtains signatures of related keys
+/// under the same nonce and could forge signatures for other keys
+/// (Groth and Shoup, "On the security of ECDSA with additive key
+/// derivation and presignatures", 2022). Only use it if the path is
+/// agreed before the pre-signature is generated and could not be
+/// changed afterwards; otherwise pass the path to [`State::new`].
+#[cfg(feature = "insecure-path-agnostic")]
+pub fn create_partial_signature_for_path(
+    mut pre: PreSignature,
+    chain_path: &DerivationPath,
+    hash: [u8; 32],
+) -> Result<(PartialSignature, SignMsg4), SignError> {
+    let Some(root_chain_code) = pre.root_chain_code else {
+        return Err(SignError::PathBoundPreSignature);
+    };
+
+    let (additive_offset, derived_public_key) = derive_with_offset(
+        &pre.public_key.to_curve(),
+        Some(&root_chain_code),
+        chain_path,
+    )?;
+
+    // Sum of s_0 over all parties is (m + r_x * sk) * phi. The key of
+    // the path is sk + offset, so every party adds its share of
+    // r_x * offset * phi.
+    let r_x: Scalar = Reduce::<U256>::reduce_bytes(&pre.r.x());
+    pre.s_0 += r_x * additive_offset * pre.phi_i;
+    pre.public_key = derived_public_key.to_affine();
+
+    create_partial_signature(pre, hash)
+}
+
+/// Partial signature of party_i
+#[derive(Zeroize, ZeroizeOnDrop)]
+struct PS {
+    /// final_session_id
+    pub final_session_id: [u8; 32],
+
+    /// public_key
+    pub public_key: ProjectivePoint,
+
+    /// 32 bytes message_hash
+    pub message_hash: [u8; 32],
+
+    /// s_0 Scalar
+    pub s_0: Scalar,
+
+    /// s_1 Scalar
+    pub s_1: Scalar,
+
+    /// R point
+    pub r: ProjectivePoint,
+}
+
+/// Normalization of `s` of a combined signature.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SNormalization {
+    /// Return `s` in the lower half of the scalar field, as required by
+    /// Bitcoin and Ethereum.
+    #[default]
+    LowS,
+
+    /// Return `s` as computed by the protocol, it could be high.
+    Raw,
+}
+
+//Round 4: final round to compute the ECDSA signature from the presigs and the message
+/// Returns the signature together with its recovery ID, the `v` of
+/// Ethereum signatures.
+pub fn combine_signatures(
+    partial: PartialSignature,
+    msgs: impl IntoIterator<Item = impl Borrow<SignMsg4>>,
+) -> Result<(Signature, RecoveryId), SignError> {
+    combine_signatures_with(partial, msgs, SNormalization::LowS)
+}
+
+/// Same as [`combine_signatures`] with the given normalization of `s`.
+///
+/// The recovery ID always matches the returned `s`: the parity of the
+/// nonce point flips together with the sign of `s`.
+pub fn combine_signatures_with(
+    partial: PartialSignature,
+    msgs: impl IntoIterator<Item = impl Borrow<SignMsg4>>,
+    normalization: SNormalization,
+) -> Result<(Signature, RecoveryId), SignError> {
+    let partial_signatures = collect_partial_signatures(partial, msgs)?;
+    let t = partial_signatures.len();
+
+    combine_partial_signature(partial_signatures, t, normalization)
+}
+
+/// Combine partial signatures of several independent signing sessions.
+///
+/// All resulting signatures are verified at once using a random linear
+/// combination of the verification equations. Only if the batch check
+/// fails, every signature is verified individually to find out which
+/// of them are invalid.
+///
+/// Results are returned in the same order as the input.
+pub fn combine_signatures_batch<R: RngCore + CryptoRng>(
+    rng: &mut R,
+    batch: Vec<(PartialSignature, Vec<SignMsg4>)>,
+) -> Vec<Result<(Signature, RecoveryId), SignError>> {
+    let combined = batch
+        .into_iter()
+        .map(|(partial, msgs)| {
+            let partial_signatures =
+                collect_partial_signatures(partial, msgs)?;
+            let t = partial_signatures.len();
+            sum_partial_signatures(
+                partial_signatures,
+                t,
+                SNormalization::LowS,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let batch_ok =
+        batch_verify(rng, combined.iter().filter_map(|c| c.as_ref().ok()));
+
+    combined
+        .into_iter()
+        .map(|c| {
+            let c = c?;
+            if !batch_ok {
+                c.verify()?;
+            }
+            Ok((c.sign, c.recovery_id()))
+        })
+        .collect()
+}
+
+/// Collect the own partial signature and the messages of the other
+/// signers.
+///
+/// Rejects a message from the party itself, a duplicate sender and,
+/// unless the partial signature predates the field, a number of
+/// signers other than its threshold.
+fn collect_partial_signatures(
+    partial: PartialSignature,
+    msgs: impl IntoIterator<Item = impl Borrow<SignMsg4>>,
+) -> Result<Vec<PS>, SignError> {
+    let msgs = msgs.into_iter();
+    let t = match partial.threshold {
+        0 => msgs.size_hint().0 + 1,
+        t => t as usize,
+    };
+
+    let wrong_count =
+        || SignError::FailedCheck("Invalid number of partial signatures");
+
+    let mut senders = Vec::with_capacity(t);
+    let mut partial_signatures = Vec::with_capacity(t);
+
+    senders.push(partial.party_id);
+    partial_signatures.push(PS {
+        final_session_id: partial.final_session_id,
+        public_key: partial.public_key.to_curve(),
+        message_hash: partial.message_hash,
+        s_0: partial.s_0,
+        s_1: partial.s_1,
+        r: partial.r.to_curve(),
+    });
+
+    for msg in msgs {
+        let msg = msg.borrow();
+
+        if msg.from_id == partial.party_id {
+            return Err(SignError::UnexpectedParty(msg.from_id));
+        }
+
+        if senders.contains(&msg.from_id) {
+            return Err(SignError::MissingMessage);
+        }
+
+        if partial.threshold != 0 && senders.len() == t {
+            return Err(wrong_count());
+        }
+
+        senders.push(msg.from_id);
+        partial_signatures.push(PS {
+            final_session_id: msg.session_id,
+            s_0: msg.s_0,
+            s_1: msg.s_1,
+
+            public_key: partial.public_key.to_curve(),
+            message_hash: partial.message_hash,
+            r: partial.r.to_curve(),
+        });
+    }
+
+    if partial.threshold != 0 && partial_signatures.len() != t {
+        return Err(wrong_count());
+    }
+
+    Ok(partial_signatures)
+}
+
+fn get_zeta_i(
+    keyshare: &Keyshare,
+    sig_id: &[u8; 32],
+    partys: impl Iterator<Item = u8>,
+) -> Result<Scalar, SignError> {
+    let mut zeta_i = Scalar::ZERO;
+
+    for party_id in partys.filter(|p| *p != keyshare.party_id) {
+        let seed = keyshare
+            .zeta_seed(party_id)
+            .ok_or(SignError::FailedCheck("Missing zeta seed"))?;
+
+        let mut hasher = Sha256::new();
+        hasher.update(DSG_LABEL);
+        hasher.update(seed);
+        hasher.update(sig_id);
+        hasher.update(PAIRWISE_RANDOMIZATION_LABEL);
+        let value = Scalar::reduce(U256::from_be_slice(&hasher.finalize()));
+
+        // the seed is received from a party with a lower ID and sent to
+        // a party with a greater ID
+        if party_id < keyshare.party_id {
+            zeta_i += value;
+        } else {
+            zeta_i -= value;
+        }
+    }
+
+    Ok(zeta_i)
+}
+
+/// Interpolation coefficient of the party `party_id` for the set of
+/// signing parties: Lagrange coefficient if all ranks are zero,
+/// Birkhoff coefficient otherwise.
+pub(crate) fn interpolation_coeff(
+    keyshare: &Keyshare,
+    party_id: u8,
+    parties: impl Iterator<Item = u8>,
+) -> Result<Scalar, SignError> {
+    if keyshare.rank_list.iter().all(|&r| r == 0) {
+        Ok(get_lagrange_coeff(keyshare, party_id, parties))
+    } else {
+        get_birkhoff_coeff(keyshare, party_id, parties)
+    }
+}
+
+/// Birkhoff interpolation coefficient of the party for the set of
+/// signing parties with hierarchical ranks. Returns
+/// [`SignError::InvalidSignerSet`] if the party is not in the set.
+fn get_birkhoff_coeff(
+    keyshare: &Keyshare,
+    party_id: u8,
+    parties: impl Iterator<Item = u8>,
+) -> Result<Scalar, SignError> {
+    let mut parties = parties.collect::<Vec<_>>();
+    parties.sort_by_key(|p| keyshare.rank_list[*p as usize]);
+
+    // Polya condition: the k-th lowest rank must not be greater than k,
+    // otherwise the parties could not reconstruct the key.
+    let poised = parties
+        .iter()
+        .zip(0..)
+        .all(|(p, k)| keyshare.rank_list[*p as usize] <= k);
+    if !poised {
+        return Err(SignError::FailedCheck(
+            "Ranks of signing parties do not allow to reconstruct the key",
+        ));
+    }
+
+    let params = parties
+        .iter()
+        .map(|p| {
+            (
+                keyshare.x_i_list[*p as usize],
+                keyshare.rank_list[*p as usize] as usize,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let coeffs = birkhoff_coeffs(&params);
+
+    let idx = parties
+        .iter()
+        .position(|p| *p == party_id)
+        .ok_or(SignError::InvalidSignerSet)?;
+
+    Ok(coeffs[idx])
+}
+
+fn get_lagrange_coeff(
+    keyshare: &Keyshare,
+    party_id: u8,
+    parties: impl Iterator<Item = u8>,
+) -> Scalar {
+    let mut coeff = Scalar::from(1u64);
+    let x_i = &keyshare.x_i_list[party_id as usize] as &Scalar;
+
+    for party_id in parties {
+        let x_j = &*keyshare.x_i_list[party_id as usize]; //  as &Scalar;
+        if x_i.ct_ne(x_j).into() {
+            let sub = x_j - x_i; // x_j != xi_i => sub != 0
+            coeff *= x_j * &sub.invert().unwrap(); //
+        }
+    }
+
+    coeff
+}
+
+/// Locally combine list of t partial signatures into a final signature
+fn combine_partial_signature(
+    partial_signatures: Vec<PS>,
+    t: usize,
+    normalization: SNormalization,
+) -> Result<(Signature, RecoveryId), SignError> {
+    let combined =
+        sum_partial_signatures(partial_signatures, t, normalization)?;
+
+    combined.verify()?;
+
+    Ok((combined.sign, combined.recovery_id()))
+}
+
+/// Unverified signature together with data required to verify it.
+struct Combined {
+    sign: Signature,
+    public_key: ProjectivePoint,
+    message_hash: [u8; 32],
+    /// Nonce point matching the (possibly normalized) `s` of `sign`
+    big_r: ProjectivePoint,
+}
+
+impl Combined {
+    fn verify(&self) -> Result<(), SignError> {
+        // the verifier accepts only low `s`
+        let sign = self.sign.normalize_s().unwrap_or(self.sign);
+        VerifyingKey::from_affine(self.public_key.to_affine())?
+            .verify_prehash(&self.message_hash, &sign)?;
+
+        Ok(())
+    }
+
+    /// Recovery ID of the signature. Uses the nonce point matching
+    /// `s`, so the parity is already flipped if the normalization
+    /// negated `s`.
+    fn recovery_id(&self) -> RecoveryId {
+        let big_r = self.big_r.to_affine();
+        let x_reduced = Scalar::from_repr(big_r.x()).is_none().into();
+
+        RecoveryId::new(big_r.y_is_odd().into(), x_reduced)
+    }
+}
+
+/// Sum list of t partial signatures into a signature without
+/// verifying it.
+fn sum_partial_signatures(
+    partial_signatures: Vec<PS>,
+    t: usize,
+    normalization: SNormalization,
+) -> Result<Combined, SignError> {
+    if partial_signatures.len() != t {
+        return Err(SignError::FailedCheck(
+            "Invalid number of partial signatures",
+        ));
+    }
+
+    let final_session_id = partial_signatures[0].final_session_id;
+    let public_key = partial_signatures[0].public_key;
+    let message_hash = partial_signatures[0].message_hash;
+    let big_r = partial_signatures[0].r;
+
+    let mut sum_s_0 = Scalar::ZERO;
+    let mut sum_s_1 = Scalar::ZERO;
+    for partial_sign in partial_signatures.into_iter() {
+        let cond = (partial_sign.final_session_id != final_session_id)
+            || (partial_sign.public_key != public_key)
+            || (partial_sign.r != big_r)
+            || (partial_sign.message_hash != message_hash);
+        if cond {
+            return Err(SignError::FailedCheck(
+                "Invalid list of partial signatures",
+            ));
+        }
+        sum_s_0 += partial_sign.s_0;
+        sum_s_1 += partial_sign.s_1;
+    }
+
+    let r = big_r.to_affine().x();
+    let sum_s_1_inv = Option::<Scalar>::from(sum_s_1.invert())
+        .ok_or(SignError::FailedCheck("Invalid partial signatures"))?;
+    let s = sum_s_0 * sum_s_1_inv;
+
+    let sign = Signature::from_scalars(r, s)?;
+
+    // s * R = z * G + r * PK; negating s requires negating R too
+    let normalized = match normalization {
+        SNormalization::LowS => sign.normalize_s(),
+        SNormalization::Raw => None,
+    };
+    let (sign, big_r) = match normalized {
+        Some(normalized) => (normalized, -big_r),
+        None => (sign, big_r),
+    };
+
+    Ok(Combined {
+        sign,
+        public_key,
+        message_hash,
+        big_r,
+    })
+}
+
+/// Check that sum of a_i * (s_i * R_i - z_i * G - r_i * PK_i) is the
+/// identity for random a_i. Returns false if any signature is invalid.
+fn batch_verify<'a, R: RngCore + CryptoRng>(
+    rng: &mut R,
+    batch: impl Iterator<Item = &'a Combined>,
+) -> bool {
+    let mut sum = ProjectivePoint::IDENTITY;
+    let mut sum_z = Scalar::ZERO;
+
+    for c in batch {
+        if c.public_key.is_identity().into() {
+            return false;
+        }
+
+        let a = Scalar::random(&mut *rng);
+        let z =
+            <Scalar as Reduce<U256>>::reduce_bytes(&c.message_hash.into());
+
+        sum += c.big_r * (a * c.sign.s().as_ref())
+            - c.public_key * (a * c.sign.r().as_ref());
+        sum_z += a * z;
+    }
+
+    (sum - ProjectivePoint::GENERATOR * sum_z)
+        .is_identity()
+        .into()
+}
+
+/// Get the additive offset of a key share for a given derivation path
+pub fn derive_with_offset(
+    public_key: &ProjectivePoint,
+    root_chain_code: Option<&[u8; 32]>,
+    chain_path: &DerivationPath,
+) -> Result<(Scalar, ProjectivePoint), SignError> {
+    let (additive_offset, pubkey, _) =
+        derive_path(public_key, root_chain_code, chain_path)?;
+
+    Ok((additive_offset, pubkey))
+}
+
+fn derive_path(
+    public_key: &ProjectivePoint,
+    root_chain_code: Option<&[u8; 32]>,
+    chain_path: &DerivationPath,
+) -> Result<(Scalar, ProjectivePoint, Option<[u8; 32]>), SignError> {
+    // key shares generated without a chain code support only the
+    // root path
+    let Some(root_chain_code) = root_chain_code else {
+        if chain_path.into_iter().next().is_some() {
+            return Err(SignError::MissingChainCode);
+        }
+        return Ok((Scalar::ZERO, *public_key, None));
+    };
+
+    let mut pubkey = *public_key;
+    let mut chain_code = *root_chain_code;
+    let mut additive_offset = Scalar::ZERO;
+    for child_num in chain_path {
+        let (il_int, child_pubkey, child_chain_code) =
+            derive_child_pubkey(&pubkey, chain_code, child_num)?;
+        pubkey = child_pubkey;
+        chain_code = child_chain_code;
+        additive_offset += il_int;
+    }
+
+    // Perform the mod q operation to get the additive offset
+    Ok((additive_offset, pubkey, Some(chain_code)))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use crate::dkg::{Party, RefreshShare};
+    use std::str::FromStr;
+
+    use super::*;
+
+    use crate::dkg::tests::{
+        check_encoded_size, check_serde, dkg, dkg_inner, dkg_with_ranks,
+    };
+
+    fn dsg(shares: &[Keyshare]) {
+        let mut rng = rand::thread_rng();
+
+        let chain_path = DerivationPath::from_str("m").unwrap();
+        let parties = shares
+            .iter()
+            .map(|s| State::new(&mut rng, s.clone(), &chain_path).unwrap())
+            .collect::<Vec<_>>();
+
+        run_dsg(parties);
+    }
+
+    fn run_dsg(mut parties: Vec<State>) {
+        let vk =
+            VerifyingKey::from_affine(parties[0].derived_public_key).unwrap();
+        let pre_signs = run_presign(&mut parties);
+
+        let hash = [255; 32];
+
+        let (partials, msg4): (Vec<_>, Vec<_>) = pre_signs
+            .into_iter()
+            .map(|pre| create_partial_signature(pre, hash).unwrap())
+            .unzip();
+        // at this point the partial signatures are created you can store them for later usage
+        // an example of a final signature is shown below.
+        let sigs = partials
+            .into_iter()
+            .map(|p| {
+                let party_id = p.party_id;
+                let batch = msg4.iter().filter(|msg| msg.from_id != party_id);
+
+                combine_signatures(p, batch)
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        for (sign, recid) in sigs {
+            let recovered =
+                VerifyingKey::recover_from_prehash(&hash, &sign, recid)
+                    .unwrap();
+            assert_eq!(recovered, vk);
+        }
+    }
+
+    /// Run the first two rounds and return messages of round 3.
+    fn dsg_round2(parties: &mut [State]) -> Vec<SignMsg3> {
+        let mut rng = rand::thread_rng();
+
+        let msg1: Vec<SignMsg1> = parties
+            .iter_mut()
+            .map(|p| p.generate_msg1().unwrap())
+            .collect();
+
+        check_serde(&msg1);
+        check_encoded_size(&msg1, SignMsg1::max_encoded_size());
+
+        let msg2 = parties.iter_mut().fold(vec![], |mut msg2, party| {
+            let party_id = party.keyshare.party_id;
+            let batch = msg1.iter().filter(|msg| msg.from_id != party_id);
+            msg2.extend(party.handle_msg1(&mut rng, batch).unwrap());
+            msg2
+        });
+
+        check_serde(&msg2);
+        check_encoded_size(&msg2, SignMsg2::max_encoded_size());
+
+        let msg3 = parties.iter_mut().fold(vec![], |mut msg3, party| {
+            let party_id = party.keyshare.party_id;
+            let batch = msg2.iter().filter(|msg| msg.to_id == party_id);
+            msg3.extend(party.handle_msg2(&mut rng, batch).unwrap());
+            msg3
+        });
+
+        check_serde(&msg3);
+        check_encoded_size(&msg3, SignMsg3::max_encoded_size());
+
+        msg3
+    }
+
+    pub fn run_presign(parties: &mut [State]) -> Vec<PreSignature> {
+        let msg3 = dsg_round2(parties);
+
+        let pre_signs = parties
+            .iter_mut()
+            .map(|party| {
+                let party_id = party.keyshare.party_id;
+                let batch = msg3.iter().filter(|msg| msg.to_id == party_id);
+
+                party.handle_msg3(batch).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        check_serde(&pre_signs);
+
+        pre_signs
+    }
+
+    // partial signature of the first party and message from the second
+    // one for each of `n` independent signing sessions
+    fn partials_batch(
+        shares: &[Keyshare],
+        n: u8,
+    ) -> Vec<(PartialSignature, Vec<SignMsg4>)> {
+        let mut rng = rand::thread_rng();
+        let chain_path = DerivationPath::from_str("m").unwrap();
+
+        (0..n)
+            .map(|i| {
+                let mut parties = shares
+                    .iter()
+                    .map(|s| {
+                        State::new(&mut rng, s.clone(), &chain_path).unwrap()
+                    })
+                    .collect::<Vec<_>>();
+
+                let (mut partials, mut msg4): (Vec<_>, Vec<_>) =
+                    run_presign(&mut parties)
+                        .into_iter()
+                        .map(|pre| {
+                            create_partial_signature(pre, [i; 32]).unwrap()
+                        })
+                        .unzip();
+
+                (partials.remove(0), vec![msg4.remove(1)])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sign_with_bound_message_hash() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(3, 2);
+        let chain_path = DerivationPath::from_str("m").unwrap();
+
+        let new_parties = |rng: &mut ThreadRng, hashes: [[u8; 32]; 2]| {
+            shares[..2]
+                .iter()
+                .zip(hashes)
+                .map(|(s, hash)| {
+                    let mut p =
+                        State::new(rng, s.clone(), &chain_path).unwrap();
+                    p.bind_message_hash(hash).unwrap();
+                    p
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // parties disagree on the message
+        let mut parties = new_parties(&mut rng, [[1; 32], [2; 32]]);
+        let msg1 = parties[1].generate_msg1().unwrap();
+        assert!(matches!(
+            parties[0].handle_msg1(&mut rng, vec![msg1]),
+            Err(SignError::MessageHashMismatch)
+        ));
+
+        let mut parties = new_parties(&mut rng, [[1; 32]; 2]);
+        let mut pre_signs = run_presign(&mut parties);
+
+        assert!(matches!(
+            create_partial_signature(pre_signs.pop().unwrap(), [2; 32]),
+            Err(SignError::MessageHashMismatch)
+        ));
+        assert!(create_partial_signature(pre_signs.pop().unwrap(), [1; 32])
+            .is_ok());
+    }
+
+    #[test]
+    fn reject_message_hash_rebinding() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(3, 2);
+        let chain_path = DerivationPath::from_str("m").unwrap();
+
+        let mut state =
+            State::new(&mut rng, shares[0].clone(), &chain_path).unwrap();
+        state.bind_message_hash([1; 32]).unwrap();
+        assert!(matches!(
+            state.bind_message_hash([2; 32]),
+            Err(SignError::InvalidMessageHashBinding)
+        ));
+        assert_eq!(state.message_hash(), Some([1; 32]));
+
+        // too late after the first message
+        let mut state =
+            State::new(&mut rng, shares[0].clone(), &chain_path).unwrap();
+        state.generate_msg1().unwrap();
+        assert!(matches!(
+            state.bind_message_hash([1; 32]),
+            Err(SignError::InvalidMessageHashBinding)
+        ));
+        assert_eq!(state.message_hash(), None);
+    }
+
+    #[test]
+    fn sign_one_shot() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(3, 2);
+        let vk = VerifyingKey::from_affine(shares[0].public_key).unwrap();
+        let chain_path = DerivationPath::from_str("m").unwrap();
+        let hash = [3; 32];
+
+        let mut parties = shares[..2]
+            .iter()
+            .map(|s| {
+                State::sign(&mut rng, s.clone(), &chain_path, hash).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let msg3 = dsg_round2(&mut parties);
+
+        let (partials, msg4): (Vec<_>, Vec<_>) = parties
+            .iter_mut()
+            .map(|p| {
+                let party_id = p.keyshare.party_id;
+                let batch = msg3.iter().filter(|m| m.to_id == party_id);
+                p.handle_msg3_and_sign(batch).unwrap()
+            })
+            .unzip();
+
+        for p in partials {
+            let party_id = p.party_id;
+            let batch = msg4.iter().filter(|m| m.from_id != party_id);
+            let (sign, _) = combine_signatures(p, batch).unwrap();
+            vk.verify_prehash(&hash, &sign).unwrap();
+        }
+
+        let mut unbound =
+            State::new(&mut rng, shares[0].clone(), &chain_path).unwrap();
+        assert!(matches!(
+            unbound.handle_msg3_and_sign(Vec::<SignMsg3>::new()),
+            Err(SignError::UnboundMessageHash)
+        ));
+    }
+
+    #[test]
+    fn reject_duplicate_sender() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(3, 3);
+        let chain_path = DerivationPath::from_str("m").unwrap();
+        let mut parties = shares
+            .iter()
+            .map(|s| State::new(&mut rng, s.clone(), &chain_path).unwrap())
+            .collect::<Vec<_>>();
+
+        let msg1: Vec<_> = parties
+            .iter_mut()
+            .map(|p| p.generate_msg1().unwrap())
+            .collect();
+
+        assert!(matches!(
+            parties[0].handle_msg1(&mut rng, [&msg1[1], &msg1[1]]),
+            Err(SignError::DuplicateParty(1))
+        ));
+    }
+
+    #[test]
+    fn sign_with_signer_set() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(3, 2);
+        let chain_path = DerivationPath::from_str("m").unwrap();
+
+        assert!(SignerSet::new(&shares[0], &[0]).is_err());
+        assert!(SignerSet::new(&shares[0], &[1, 2]).is_err());
+        assert!(SignerSet::new(&shares[0], &[0, 3]).is_err());
+        assert!(matches!(
+            SignerSet::new(&shares[0], &[0, 1, 1]),
+            Err(SignError::DuplicateParty(1))
+        ));
+
+        let parties = shares[..2]
+            .iter()
+            .map(|s| {
+                let signers = SignerSet::new(s, &[1, 0]).unwrap();
+                State::new_with_signers(
+                    &mut rng,
+                    s.clone(),
+                    &chain_path,
+                    signers,
+                )
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+        run_dsg(parties);
+
+        let signers = SignerSet::new(&shares[0], &[0, 1]).unwrap();
+        let mut party = State::new_with_signers(
+            &mut rng,
+            shares[0].clone(),
+            &chain_path,
+            signers,
+        )
+        .unwrap();
+        let msg1 = State::new(&mut rng, shares[2].clone(), &chain_path)
+            .unwrap()
+            .generate_msg1()
+            .unwrap();
+        assert!(matches!(
+            party.handle_msg1(&mut rng, vec![msg1]),
+            Err(SignError::UnexpectedParty(2))
+        ));
+    }
+
+    #[test]
+    fn max_encoded_size() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(3, 2);
+        let chain_path = DerivationPath::from_str("m").unwrap();
+        let hash = [255; 32];
+
+        let mut parties = shares[..2]
+            .iter()
+            .map(|s| {
+                let mut p =
+                    State::new(&mut rng, s.clone(), &chain_path).unwrap();
+                p.bind_message_hash(hash).unwrap();
+                p
+            })
+            .collect::<Vec<_>>();
+
+        // messages of rounds 1-3 are checked by run_presign
+        let msg4: Vec<SignMsg4> = run_presign(&mut parties)
+            .into_iter()
+            .map(|pre| create_partial_signature(pre, hash).unwrap().1)
+            .collect();
+        check_encoded_size(&msg4, SignMsg4::max_encoded_size());
+    }
+
+    #[test]
+    fn presized_state_collections() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(5, 3);
+        let chain_path = DerivationPath::from_str("m").unwrap();
+
+        let mut parties = shares[..3]
+            .iter()
+            .map(|s| State::new(&mut rng, s.clone(), &chain_path).unwrap())
+            .collect::<Vec<_>>();
+
+        let capacities = |p: &State| {
+            [
+                p.sid_list.capacity(),
+                p.commitment_r_i_list.capacity(),
+                p.mta_receiver_list.capacity(),
+                p.sender_additive_shares.capacity(),
+            ]
+        };
+
+        let before = parties.iter().map(capacities).collect::<Vec<_>>();
+        run_presign(&mut parties);
+        let after = parties.iter().map(capacities).collect::<Vec<_>>();
+
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn presignatures_consistency() {
+        let shares = dkg(3, 2);
+        let chain_path = DerivationPath::from_str("m").unwrap();
+
+        let mut rng = rand::thread_rng();
+        let mut parties = shares[..2]
+            .iter()
+            .map(|s| State::new(&mut rng, s.clone(), &chain_path).unwrap())
+            .collect::<Vec<_>>();
+
+        let pre_signs = run_presign(&mut parties);
+        assert_eq!(pre_signs[0].public_nonce(), pre_signs[1].public_nonce());
+
+        let mut public = pre_signs
+            .iter()
+            .map(|p| p.public_data())
+            .collect::<Vec<_>>();
+        check_presignatures(&public).unwrap();
+
+        public[1].r = AffinePoint::GENERATOR;
+        assert!(check_presignatures(&public).is_err());
+
+        public[1] = public[0].clone();
+        assert!(check_presignatures(&public).is_err());
+    }
+
+    #[test]
+    fn presignature_advert() {
+        let shares = dkg(3, 2);
+        let chain_path = DerivationPath::from_str("m/1").unwrap();
+
+        let mut rng = rand::thread_rng();
+        let mut parties = shares[1..]
+            .iter()
+            .map(|s| State::new(&mut rng, s.clone(), &chain_path).unwrap())
+            .collect::<Vec<_>>();
+        let public_key = parties[0].derived_public_key;
+
+        let pre_signs = run_presign(&mut parties);
+        let advert = pre_signs[0].advert();
+        assert_eq!(advert, pre_signs[1].advert());
+        assert_eq!(advert.public_key, public_key);
+
+        let bytes = advert.to_bytes();
+        assert_eq!(PreSignatureAdvert::from_bytes(&bytes).unwrap(), advert);
+        assert!(PreSignatureAdvert::from_bytes(&bytes[1..]).is_err());
+
+        let mut bad = bytes;
+        bad[65] = 4;
+        assert!(matches!(
+            PreSignatureAdvert::from_bytes(&bad),
+            Err(SignError::InvalidPreSignatureAdvert)
+        ));
+
+        // R is fixed before the message is chosen
+        let hash = [7; 32];
+        let (partials, msg4): (Vec<_>, Vec<_>) = pre_signs
+            .into_iter()
+            .map(|pre| create_partial_signature(pre, hash).unwrap())
+            .unzip();
+        let partial = partials.into_iter().next().unwrap();
+        let (sign, _) =
+            combine_signatures(partial, msg4.into_iter().skip(1)).unwrap();
+        let (r, _) = sign.split_bytes();
+        assert_eq!(<[u8; 32]>::from(r), advert.signature_r());
+    }
+
+    #[test]
+    fn sign_full_message() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(3, 2);
+        let vk = VerifyingKey::from_affine(shares[0].public_key).unwrap();
+        let chain_path = DerivationPath::from_str("m").unwrap();
+        let message = b"hello world";
+
+        for hash_fn in [
+            HashAlgorithm::Sha256,
+            HashAlgorithm::Keccak256,
+            HashAlgorithm::DoubleSha256,
+        ] {
+            let mut parties = shares[..2]
+                .iter()
+                .map(|s| {
+                    State::new(&mut rng, s.clone(), &chain_path).unwrap()
+                })
+                .collect::<Vec<_>>();
+
+            let (mut partials, mut msg4): (Vec<_>, Vec<_>) =
+                run_presign(&mut parties)
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, pre)| {
+                        // the other signer hashes the message itself
+                        if i == 0 {
+                            create_partial_signature_for_message(
+                                pre, message, hash_fn,
+                            )
+                        } else {
+                            let hash = hash_fn.digest(message);
+                            create_partial_signature(
+                                pre,
+                                MessageToSign::Prehashed(hash),
+                            )
+                        }
+                        .unwrap()
+                    })
+                    .unzip();
+
+            let (sign, _) =
+                combine_signatures(partials.remove(0), vec![msg4.remove(1)])
+                    .unwrap();
+
+            vk.verify_prehash(&hash_fn.digest(message), &sign).unwrap();
+        }
+
+        let sha256 = HashAlgorithm::Sha256.digest(message);
+        assert_eq!(
+            HashAlgorithm::DoubleSha256.digest(message),
+            HashAlgorithm::Sha256.digest(&sha256)
+        );
+    }
+
+    #[test]
+    fn blame_mta_check_failure() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(3, 2);
+        let chain_path = DerivationPath::from_str("m").unwrap();
+        let mut parties = shares[..2]
+            .iter()
+            .map(|s| State::new(&mut rng, s.clone(), &chain_path).unwrap())
+            .collect::<Vec<_>>();
+
+        let mut msg3 = dsg_round2(&mut parties);
+
+        let idx = msg3.iter().position(|m| m.to_id == 0).unwrap();
+        msg3[idx].gamma_u = ProjectivePoint::GENERATOR.to_affine();
+
+        let Err(SignError::MtaCheckFailed(blame)) =
+            parties[0].handle_msg3([&msg3[idx]])
+        else {
+            panic!("expected MtA check failure");
+        };
+
+        assert_eq!(blame.accuser, 0);
+        assert_eq!(blame.accused, 1);
+        assert_eq!(blame.check, MtaCheck::Nonce);
+        assert_eq!(blame.session_id, msg3[idx].final_session_id);
+    }
+
+    #[test]
+    fn wipe_session_on_error() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(3, 2);
+        let chain_path = DerivationPath::from_str("m").unwrap();
+        let mut parties = shares[..2]
+            .iter()
+            .map(|s| State::new(&mut rng, s.clone(), &chain_path).unwrap())
+            .collect::<Vec<_>>();
+
+        let msg1: Vec<_> = parties
+            .iter_mut()
+            .map(|p| p.generate_msg1().unwrap())
+            .collect();
+
+        // a rejected message does not affect the session
+        assert!(matches!(
+            parties[0].push_msg(msg1[0].clone()),
+            Err(SignError::UnexpectedParty(0))
+        ));
+        assert_ne!(parties[0].r_i, Scalar::ZERO);
+
+        // so does a rejected batch
+        assert!(matches!(
+            parties[0].handle_msg1(&mut rng, &msg1[..0]),
+            Err(SignError::MissingMessage)
+        ));
+        assert!(matches!(
+            parties[0].handle_msg1(&mut rng, [&msg1[1], &msg1[1]]),
+            Err(SignError::MissingMessage)
+        ));
+        assert_eq!(parties[0].round(), SignRound::WaitMsg1);
+        assert_ne!(parties[0].r_i, Scalar::ZERO);
+
+        let mut bad = msg1[1].clone();
+        bad.message_hash = Some([1; 32]);
+        assert!(matches!(
+            parties[0].handle_msg1(&mut rng, [bad]),
+            Err(SignError::MessageHashMismatch)
+        ));
+        assert_eq!(parties[0].r_i, Scalar::ZERO);
+        assert_eq!(parties[0].phi_i, Scalar::ZERO);
+        assert_eq!(parties[0].blind_factor, [0; 32]);
+        assert_eq!(parties[0].round(), SignRound::Aborted);
+
+        assert!(matches!(
+            parties[0].handle_msg1(&mut rng, &msg1[1..]),
+            Err(SignError::SessionAborted)
+        ));
+        assert!(matches!(
+            parties[0].push_msg(msg1[1].clone()),
+            Err(SignError::SessionAborted)
+        ));
+        assert!(matches!(
+            parties[0].generate_msg1(),
+            Err(SignError::SessionAborted)
+        ));
+    }
+
+    #[test]
+    fn retry_round_after_rejected_batch() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(3, 3);
+        let chain_path = DerivationPath::from_str("m").unwrap();
+        let mut parties = shares
+            .iter()
+            .map(|s| State::new(&mut rng, s.clone(), &chain_path).unwrap())
+            .collect::<Vec<_>>();
+
+        let msg1: Vec<_> = parties
+            .iter_mut()
+            .map(|p| p.generate_msg1().unwrap())
+            .collect();
+
+        let msg2 = parties.iter_mut().fold(vec![], |mut msg2, party| {
+            let party_id = party.keyshare.party_id;
+            let batch = msg1.iter().filter(|msg| msg.from_id != party_id);
+            msg2.extend(party.handle_msg1(&mut rng, batch).unwrap());
+            msg2
+        });
+
+        let batch = |to_id| msg2.iter().filter(move |m| m.to_id == to_id);
+
+        let mut bad: Vec<_> = batch(0).cloned().collect();
+        bad[0].final_session_id = [0; 32];
+        assert!(matches!(
+            parties[0].handle_msg2(&mut rng, &bad),
+            Err(SignError::InvalidFinalSessionID)
+        ));
+        assert!(matches!(
+            parties[0].handle_msg2(&mut rng, batch(1)),
+            Err(SignError::UnexpectedParty(_))
+        ));
+        assert!(matches!(
+            parties[0].handle_msg1(&mut rng, &msg1[1..]),
+            Err(SignError::FailedCheck(_))
+        ));
+        assert_eq!(parties[0].round(), SignRound::WaitMsg2);
+
+        let msg3 = parties.iter_mut().fold(vec![], |mut msg3, party| {
+            let party_id = party.keyshare.party_id;
+            msg3.extend(
+                party.handle_msg2(&mut rng, batch(party_id)).unwrap(),
+            );
+            msg3
+        });
+
+        for party in &mut parties {
+            let party_id = party.keyshare.party_id;
+            let batch = msg3.iter().filter(|msg| msg.to_id == party_id);
+            party.handle_msg3(batch).unwrap();
+        }
+    }
+
+    #[test]
+    fn abort_context() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(3, 2);
+        let chain_path = DerivationPath::from_str("m").unwrap();
+        let mut parties = shares[..2]
+            .iter()
+            .map(|s| State::new(&mut rng, s.clone(), &chain_path).unwrap())
+            .collect::<Vec<_>>();
+
+        let msg3 = dsg_round2(&mut parties);
+
+        let mut bad_digest =
+            msg3.iter().find(|m| m.to_id == 0).unwrap().clone();
+        bad_digest.digest_i[0] ^= 1;
+        let mut bad_commitment =
+            msg3.iter().find(|m| m.to_id == 1).unwrap().clone();
+        bad_commitment.blind_factor[0] ^= 1;
+
+        for (p, msg, check) in [
+            (0, bad_digest, AbortCheck::Digest),
+            (1, bad_commitment, AbortCheck::Commitment),
+        ] {
+            let session_id = parties[p].final_session_id;
+            let Err(err) = parties[p].handle_msg3([msg]) else {
+                panic!("tampered message 3 is accepted");
+            };
+
+            assert_eq!(err.party_id(), Some(1 - p as u8));
+            let SignError::AbortProtocolAndBanParty(ctx) = err else {
+                panic!("unexpected error {err}");
+            };
+            assert_eq!(ctx.round, 3);
+            assert_eq!(ctx.check, check);
+            assert_eq!(ctx.session_id, Some(session_id));
+        }
+    }
+
+    #[test]
+    fn abort_session() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(3, 3);
+        let chain_path = DerivationPath::from_str("m").unwrap();
+        let mut parties = shares
+            .iter()
+            .map(|s| State::new(&mut rng, s.clone(), &chain_path).unwrap())
+            .collect::<Vec<_>>();
+
+        let msg1: Vec<SignMsg1> = parties
+            .iter_mut()
+            .map(|p| p.generate_msg1().unwrap())
+            .collect();
+        for (pid, party) in parties[..2].iter_mut().enumerate() {
+            let batch = msg1.iter().filter(|m| m.from_id as usize != pid);
+            party.handle_msg1(&mut rng, batch).unwrap();
+        }
+
+        // a session wiped by an error still identifies itself
+        let abort = parties[2].abort(SignAbortReason::PolicyRejected);
+        assert_eq!(abort.from_id, 2);
+        assert_eq!(abort.session_id, msg1[2].session_id);
+        assert_eq!(parties[2].round(), SignRound::Aborted);
+
+        let forged = SignAbort {
+            session_id: [0; 32],
+            ..abort.clone()
+        };
+        assert!(matches!(
+            parties[0].handle_abort(&forged),
+            Err(SignError::SessionIdMismatch(2))
+        ));
+        let own = SignAbort {
+            from_id: 0,
+            ..abort.clone()
+        };
+        assert!(matches!(
+            parties[0].handle_abort(&own),
+            Err(SignError::UnexpectedParty(0))
+        ));
+        assert_eq!(parties[0].round(), SignRound::WaitMsg2);
+
+        parties[0].handle_abort(&abort).unwrap();
+        assert_eq!(parties[0].round(), SignRound::Aborted);
+        assert!(matches!(
+            parties[0].handle_msg2(&mut rng, Vec::<SignMsg2>::new()),
+            Err(SignError::SessionAborted)
+        ));
+
+        // before the first message of the party
+        let mut late =
+            State::new(&mut rng, shares[0].clone(), &chain_path).unwrap();
+        late.handle_abort(&abort).unwrap();
+        assert_eq!(late.round(), SignRound::Aborted);
+    }
+
+    #[test]
+    fn first_message_after_abort() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(2, 2);
+        let chain_path = DerivationPath::from_str("m").unwrap();
+        let mut parties = shares
+            .iter()
+            .map(|s| State::new(&mut rng, s.clone(), &chain_path).unwrap())
+            .collect::<Vec<_>>();
+
+        // own abort before the first message
+        let abort = parties[1].abort(SignAbortReason::Cancelled);
+        assert!(matches!(
+            parties[1].generate_msg1(),
+            Err(SignError::SessionAborted)
+        ));
+
+        // abort of another party before the first message
+        parties[0].handle_abort(&abort).unwrap();
+        assert!(matches!(
+            parties[0].generate_msg1(),
+            Err(SignError::SessionAborted)
+        ));
+    }
+
+    #[test]
+    fn sign_with_tweak() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(3, 2);
+        let root = shares[0].public_key;
+
+        // an additive tweak is the same as a derivation path
+        let chain_path = DerivationPath::from_str("m/3/5").unwrap();
+        let (additive, derived) = derive_with_offset(
+            &root.to_curve(),
+            shares[0].root_chain_code.as_ref(),
+            &chain_path,
+        )
+        .unwrap();
+        let tweak = KeyTweak {
+            additive,
+            multiplicative: None,
+        };
+        assert_eq!(tweak.public_key(&root), derived.to_affine());
+
+        let tweak = KeyTweak {
+            additive: Scalar::random(&mut rng),
+            multiplicative: Some(NonZeroScalar::random(&mut rng)),
+        };
+        let public_key = tweak.public_key(&root);
+
+        let mut parties = shares[1..]
+            .iter()
+            .map(|s| State::new_tweaked(&mut rng, s.clone(), &tweak))
+            .collect::<Vec<_>>();
+        assert_eq!(parties[0].derived_public_key, public_key);
+
+        let hash = [9; 32];
+        let (partials, msg4): (Vec<_>, Vec<_>) = run_presign(&mut parties)
+            .into_iter()
+            .map(|pre| create_partial_signature(pre, hash).unwrap())
+            .unzip();
+
+        for (idx, partial) in partials.into_iter().enumerate() {
+            let party_id = partial.party_id;
+            let batch = msg4.iter().filter(|m| m.from_id != party_id);
+            let (sign, _) = combine_signatures(partial, batch).unwrap();
+
+            VerifyingKey::from_affine(public_key)
+                .unwrap()
+                .verify_prehash(&hash, &sign)
+                .unwrap_or_else(|_| panic!("invalid signature of {idx}"));
+        }
+    }
+
+    #[test]
+    fn sign_with_policy() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(3, 2);
+        let chain_path = DerivationPath::from_str("m/0/1").unwrap();
+        let allowed = [1; 32];
+        let mut parties = shares[..2]
+            .iter()
+            .map(|s| {
+                let mut state =
+                    State::new(&mut rng, s.clone(), &chain_path).unwrap();
+                state.set_policy(
+                    move |hash: &[u8; 32],
+                          path: Option<&DerivationPath>,
+                          _: &AffinePoint| {
+                        *hash == allowed
+                            && path.map(|p| p.to_string())
+                                == Some("m/0/1".into())
+                    },
+                );
+                state
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(parties[0].chain_path(), Some(chain_path.clone()));
+
+        let mut pre_signs = run_presign(&mut parties);
+
+        let pre = pre_signs.pop().unwrap();
+        assert!(matches!(
+            parties[1].create_partial_signature(pre, [2; 32]),
+            Err(SignError::PolicyRejected)
+        ));
+
+        // pre-signature of another party of the session
+        let pre = pre_signs.pop().unwrap();
+        assert!(matches!(
+            parties[1].create_partial_signature(pre, allowed),
+            Err(SignError::PreSignatureKeyMismatch)
+        ));
+
+        // a session bound to a refused hash aborts before round 3
+        let mut bound =
+            State::new(&mut rng, shares[0].clone(), &chain_path).unwrap();
+        bound.set_policy(
+            |_: &[u8; 32], _: Option<&DerivationPath>, _: &AffinePoint| false,
+        );
+        bound.bind_message_hash(allowed).unwrap();
+        assert!(matches!(
+            bound.handle_msg3_and_sign(Vec::<SignMsg3>::new()),
+            Err(SignError::PolicyRejected)
+        ));
+        assert_eq!(bound.round(), SignRound::Aborted);
+
+        let mut parties = shares[1..]
+            .iter()
+            .map(|s| State::new(&mut rng, s.clone(), &chain_path).unwrap())
+            .collect::<Vec<_>>();
+        parties[0].set_policy(
+            move |hash: &[u8; 32],
+                  _: Option<&DerivationPath>,
+                  _: &AffinePoint| { *hash == allowed },
+        );
+        let pre_signs = run_presign(&mut parties);
+        let (mut partials, mut msg4): (Vec<_>, Vec<_>) = parties
+            .iter()
+            .zip(pre_signs)
+            .map(|(p, pre)| p.create_partial_signature(pre, allowed).unwrap())
+            .unzip();
+
+        let (sign, _) =
+            combine_signatures(partials.remove(0), vec![msg4.remove(1)])
+                .unwrap();
+        let vk =
+            VerifyingKey::from_affine(parties[0].derived_public_key).unwrap();
+        vk.verify_prehash(&allowed, &sign).unwrap();
+    }
+
+    #[test]
+    fn suspend_and_resume() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(3, 2);
+        let chain_path = DerivationPath::from_str("m").unwrap();
+        let mut parties = shares[..2]
+            .iter()
+            .map(|s| State::new(&mut rng, s.clone(), &chain_path).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(parties[0].round(), SignRound::WaitMsg1);
+
+        let msg1: Vec<_> = parties
+            .iter_mut()
+            .map(|p| p.generate_msg1().unwrap())
+            .collect();
+        let msg2 = parties.iter_mut().fold(vec![], |mut out, p| {
+            let party_id = p.keyshare.party_id;
+            let batch = msg1.iter().filter(|m| m.from_id != party_id);
+            out.extend(p.handle_msg1(&mut rng, batch).unwrap());
+            out
+        });
+        assert_eq!(parties[0].round(), SignRound::WaitMsg2);
+
+        let mut bytes = vec![];
+        ciborium::into_writer(&parties.remove(0).suspend(), &mut bytes)
+            .unwrap();
+
+        let decode = |bytes: &[u8]| -> SuspendedState {
+            ciborium::from_reader(bytes).unwrap()
+        };
+
+        assert_eq!(decode(&bytes).round(), SignRound::WaitMsg2);
+        assert!(matches!(
+            State::resume(decode(&bytes), SignRound::WaitMsg3),
+            Err(SignError::InvalidSuspendedState)
+        ));
+
+        let mut suspended = decode(&bytes);
+        suspended.round = SignRound::WaitMsg3;
+        assert!(matches!(
+            State::resume(suspended, SignRound::WaitMsg3),
+            Err(SignError::InvalidSuspendedState)
+        ));
+
+        let mut suspended = decode(&bytes);
+        suspended.state.r_i += Scalar::ONE;
+        assert!(matches!(
+            State::resume(suspended, SignRound::WaitMsg2),
+            Err(SignError::InvalidSuspendedState)
+        ));
+
+        let mut suspended = decode(&bytes);
+        suspended.version = 2;
+        assert!(matches!(
+            State::resume(suspended, SignRound::WaitMsg2),
+            Err(SignError::UnsupportedStateVersion(2))
+        ));
+
+        let party = State::resume(decode(&bytes), SignRound::WaitMsg2);
+        parties.insert(0, party.unwrap());
+
+        let msg3 = parties.iter_mut().fold(vec![], |mut out, p| {
+            let party_id = p.keyshare.party_id;
+            let batch = msg2.iter().filter(|m| m.to_id == party_id);
+            out.extend(p.handle_msg2(&mut rng, batch).unwrap());
+            out
+        });
+        let (partials, msg4): (Vec<_>, Vec<_>) = parties
+            .iter_mut()
+            .map(|p| {
+                let party_id = p.keyshare.party_id;
+                let batch = msg3.iter().filter(|m| m.to_id == party_id);
+                let pre = p.handle_msg3(batch).unwrap();
+                create_partial_signature(pre, [1; 32]).unwrap()
+            })
+            .unzip();
+        assert_eq!(parties[0].round(), SignRound::Finished);
+
+        for p in partials {
+            let party_id = p.party_id;
+            let batch = msg4.iter().filter(|m| m.from_id != party_id);
+            combine_signatures(p, batch).unwrap();
+        }
+    }
+
+    #[test]
+    fn combine_validates_signers() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(3, 2);
+        let chain_path = DerivationPath::from_str("m").unwrap();
+        let mut parties = shares[..2]
+            .iter()
+            .map(|s| State::new(&mut rng, s.clone(), &chain_path).unwrap())
+            .collect::<Vec<_>>();
+
+        let (partials, msg4): (Vec<_>, Vec<_>) = run_presign(&mut parties)
+            .into_iter()
+            .map(|pre| {
+                assert_eq!(pre.threshold, 2);
+                create_partial_signature(pre, [1; 32]).unwrap()
+            })
+            .unzip();
+        let partial = serde_json::to_vec(&partials[0]).unwrap();
+        let partial = || -> PartialSignature {
+            serde_json::from_slice(&partial).unwrap()
+        };
+
+        assert!(matches!(
+            combine_signatures(partial(), &msg4[..1]),
+            Err(SignError::UnexpectedParty(0))
+        ));
+        assert!(matches!(
+            combine_signatures(partial(), [&msg4[1], &msg4[1]]),
+            Err(SignError::MissingMessage)
+        ));
+        assert!(matches!(
+            combine_signatures(partial(), Vec::<SignMsg4>::new()),
+            Err(SignError::FailedCheck(_))
+        ));
+
+        let mut extra = msg4[1].clone();
+        extra.from_id = 2;
+        assert!(matches!(
+            combine_signatures(partial(), [&msg4[1], &extra]),
+            Err(SignError::FailedCheck(_))
+        ));
+
+        // without a threshold the number of messages is trusted
+        let mut legacy = partial();
+        legacy.threshold = 0;
+        combine_signatures(legacy, &msg4[1..]).unwrap();
+        combine_signatures(partial(), msg4[1..].iter()).unwrap();
+    }
+
+    #[test]
+    fn audit_record() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(3, 2);
+        let chain_path = DerivationPath::from_str("m/1").unwrap();
+        let mut parties = shares[1..]
+            .iter()
+            .map(|s| State::new(&mut rng, s.clone(), &chain_path).unwrap())
+            .collect::<Vec<_>>();
+
+        let (partials, _): (Vec<_>, Vec<_>) = run_presign(&mut parties)
+            .into_iter()
+            .map(|pre| create_partial_signature(pre, [3; 32]).unwrap())
+            .unzip();
+
+        let record = parties[0].audit_record(&partials[0], 10, 12).unwrap();
+        assert_eq!(record.version, AUDIT_RECORD_VERSION);
+        assert_eq!(record.session_id, parties[1].final_session_id);
+        assert_eq!(record.party_id, 1);
+        assert_eq!(record.signers, vec![1, 2]);
+        assert_eq!(record.chain_path.as_deref(), Some("m/1"));
+        assert_eq!(record.public_key, parties[0].derived_public_key);
+        assert_eq!(record.big_r, partials[1].r);
+        assert_eq!(record.message_hash, [3; 32]);
+        assert_eq!((record.started_at, record.completed_at), (10, 12));
+
+        let value = serde_json::to_value(&record).unwrap();
+        let mut fields: Vec<_> =
+            value.as_object().unwrap().keys().cloned().collect();
+        fields.sort();
+        assert_eq!(
+            fields,
+            [
+                "big_r",
+                "chain_path",
+                "completed_at",
+                "message_hash",
+                "party_id",
+                "public_key",
+                "session_id",
+                "signers",
+                "started_at",
+                "version"
+            ]
+        );
+        assert_eq!(
+            serde_json::from_value::<SignAuditRecord>(value).unwrap(),
+            record
+        );
+
+        assert!(matches!(
+            parties[1].audit_record(&partials[0], 10, 12),
+            Err(SignError::InvalidFinalSessionID)
+        ));
+    }
+
+    #[test]
+    fn sign_online() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(3, 2);
+        let chain_path = DerivationPath::from_str("m/0").unwrap();
+        let mut parties = shares[1..]
+            .iter()
+            .map(|s| State::new(&mut rng, s.clone(), &chain_path).unwrap())
+            .collect::<Vec<_>>();
+
+        // pre-signatures are stored ahead of time
+        let stored = run_presign(&mut parties)
+            .iter()
+            .map(|pre| serde_json::to_vec(pre).unwrap())
+            .collect::<Vec<_>>();
+
+        let hash = [9; 32];
+        let (sessions, msg4): (Vec<_>, Vec<_>) = stored
+            .iter()
+            .map(|bytes| {
+                let pre = serde_json::from_slice(bytes).unwrap();
+                OnlineSession::start(pre, hash).unwrap()
+            })
+            .unzip();
+        assert_eq!(sessions[0].session_id(), sessions[1].session_id());
+
+        let vk =
+            VerifyingKey::from_affine(parties[0].derived_public_key).unwrap();
+        for session in sessions {
+            let party_id = session.partial.party_id;
+            let batch = msg4.iter().filter(|m| m.from_id != party_id);
+            let (sign, _) = session.finish(batch).unwrap();
+            vk.verify_prehash(&hash, &sign).unwrap();
+        }
+
+        let pre = serde_json::from_slice(&stored[0]).unwrap();
+        let (session, _) = OnlineSession::start(pre, hash).unwrap();
+        assert!(matches!(
+            session.finish([&msg4[1], &msg4[1]]),
+            Err(SignError::MissingMessage)
+        ));
+
+        let pre = serde_json::from_slice(&stored[0]).unwrap();
+        let (session, _) = OnlineSession::start(pre, hash).unwrap();
+        assert!(matches!(
+            session.finish(&msg4[..1]),
+            Err(SignError::UnexpectedParty(1))
+        ));
+    }
+
+    #[test]
+    fn combine_with_raw_s() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(2, 2);
+        let chain_path = DerivationPath::from_str("m").unwrap();
+        let hash = [3; 32];
+
+        // s is high in about half of the sessions
+        let mut seen_high = false;
+        for _ in 0..32 {
+            let mut parties = shares
+                .iter()
+                .map(|s| {
+                    State::new(&mut rng, s.clone(), &chain_path).unwrap()
+                })
+                .collect::<Vec<_>>();
+
+            let (mut partials, msg4): (Vec<_>, Vec<_>) =
+                run_presign(&mut parties)
+                    .into_iter()
+                    .map(|pre| create_partial_signature(pre, hash).unwrap())
+                    .unzip();
+
+            let (raw, raw_recid) = combine_signatures_with(
+                partials.remove(0),
+                &msg4[1..],
+                SNormalization::Raw,
+            )
+            .unwrap();
+            let (low, low_recid) =
+                combine_signatures(partials.remove(0), &msg4[..1]).unwrap();
+
+            assert_eq!(raw.normalize_s().unwrap_or(raw), low);
+
+            let key =
+                VerifyingKey::recover_from_prehash(&hash, &low, low_recid)
+                    .unwrap();
+            assert_eq!(key.as_affine(), &shares[0].public_key);
+
+            if raw != low {
+                seen_high = true;
+                assert!(raw.normalize_s().is_some());
+                assert_ne!(raw_recid.is_y_odd(), low_recid.is_y_odd());
+                assert_eq!(
+                    raw_recid.is_x_reduced(),
+                    low_recid.is_x_reduced()
+                );
+            } else {
+                assert_eq!(raw_recid, low_recid);
+            }
+
+            if seen_high {
+                break;
+            }
+        }
+
+        assert!(seen_high);
+    }
+
+    #[test]
+    fn combine_batch() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(3, 2);
+        let vk = VerifyingKey::from_affine(shares[0].public_key).unwrap();
+
+        let sigs = combine_signatures_batch(
+            &mut rng,
+            partials_batch(&shares[..2], 3),
+        );
+        for (i, sig) in sigs.into_iter().enumerate() {
+            let (sign, recid) = sig.unwrap();
+            let hash = [i as u8; 32];
+            vk.verify_prehash(&hash, &sign).unwrap();
+            assert_eq!(
+                VerifyingKey::recover_from_prehash(&hash, &sign, recid)
+                    .unwrap(),
+                vk
+            );
+        }
+
+        let mut batch = partials_batch(&shares[..2], 3);
+        batch[1].1[0].s_0 += Scalar::ONE;
+
+        let sigs = combine_signatures_batch(&mut rng, batch);
+        assert!(sigs[0].is_ok());
+        assert!(sigs[1].is_err());
+        assert!(sigs[2].is_ok());
+    }
+
+    #[cfg(feature = "insecure-path-agnostic")]
+    #[test]
+    fn sign_with_path_agnostic_presignatures() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(3, 2);
+        let chain_path = DerivationPath::from_str("m/0/7").unwrap();
+        let hash = [7; 32];
+
+        let (_, derived_public_key) = derive_with_offset(
+            &shares[0].public_key.to_curve(),
+            shares[0].root_chain_code.as_ref(),
+            &chain_path,
+        )
+        .unwrap();
+
+        let mut parties = shares[..2]
+            .iter()
+            .map(|s| State::new_path_agnostic(&mut rng, s.clone()))
+            .collect::<Vec<_>>();
+
+        let (mut partials, mut msg4): (Vec<_>, Vec<_>) =
+            run_presign(&mut parties)
+                .into_iter()
+                .map(|pre| {
+                    create_partial_signature_for_path(pre, &chain_path, hash)
+                        .unwrap()
+                })
+                .unzip();
+
+        let (sign, _) =
+            combine_signatures(partials.remove(0), vec![msg4.remove(1)])
+                .unwrap();
+
+        VerifyingKey::from_affine(derived_public_key.to_affine())
+            .unwrap()
+            .verify_prehash(&hash, &sign)
+            .unwrap();
+
+        // pre-signatures of State::new() are bound to their path
+        let root = DerivationPath::from_str("m").unwrap();
+        let mut parties = shares[..2]
+            .iter()
+            .map(|s| State::new(&mut rng, s.clone(), &root).unwrap())
+            .collect::<Vec<_>>();
+        let pre = run_presign(&mut parties).remove(0);
+        assert!(matches!(
+            create_partial_signature_for_path(pre, &chain_path, hash),
+            Err(SignError::PathBoundPreSignature)
+        ));
+    }
+
+    #[test]
+    fn sign_with_derived_keyshare() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(3, 2);
+        let chain_path = DerivationPath::from_str("m/1/2/3").unwrap();
+
+        let derived = shares[..2]
+            .iter()
+            .map(|s| DerivedKeyshare::new(s.clone(), &chain_path).unwrap())
+            .collect::<Vec<_>>();
+
+        let (_, pk) = derive_with_offset(
+            &shares[0].public_key.to_curve(),
+            shares[0].root_chain_code.as_ref(),
+            &chain_path,
+        )
+        .unwrap();
+        assert_eq!(derived[0].derived_public_key(), pk.to_affine());
+
+        let parties = derived
+            .iter()
+            .map(|d| State::new_derived(&mut rng, d))
+            .collect::<Vec<_>>();
+        run_dsg(parties);
+
+        // a key refresh keeps the cache, another root chain code
+        // invalidates it
+        let mut d = derived[0].clone();
+        let mut refreshed = shares[0].clone();
+        refreshed.final_session_id = [7; 32];
+        d.update(refreshed.clone()).unwrap();
+        assert_eq!(d.derived_public_key(), pk.to_affine());
+        assert_eq!(d.keyshare().final_session_id, [7; 32]);
+
+        refreshed.root_chain_code = Some([9; 32]);
+        d.update(refreshed).unwrap();
+        assert_ne!(d.derived_public_key(), pk.to_affine());
+
+        // a key share of another key invalidates the cache
+        let mut d = derived[0].clone();
+        let other = dkg(3, 2);
+        d.update(other[0].clone()).unwrap();
+        assert_ne!(d.derived_public_key(), pk.to_affine());
+        assert_eq!(d.keyshare().public_key, other[0].public_key);
+    }
+
+    #[test]
+    fn sign_with_non_zero_ranks() {
+        let mut rng = rand::thread_rng();
+        let chain_path = DerivationPath::from_str("m").unwrap();
+
+        let shares = dkg_with_ranks(&[0, 1, 1], 2);
+        dsg(&shares[..2]);
+        dsg(&[shares[0].clone(), shares[2].clone()]);
+
+        let shares = dkg_with_ranks(&[0, 0, 1, 2], 3);
+        dsg(&shares[..3]);
+        dsg(&[shares[0].clone(), shares[2].clone(), shares[1].clone()]);
+
+        // two parties of rank 1 could not sign together
+        let shares = dkg_with_ranks(&[0, 1, 1], 2);
+        let mut parties = shares[1..]
+            .iter()
+            .map(|s| State::new(&mut rng, s.clone(), &chain_path).unwrap())
+            .collect::<Vec<_>>();
+
+        let msg1: Vec<SignMsg1> = parties
+            .iter_mut()
+            .map(|p| p.generate_msg1().unwrap())
+            .collect();
+        let msg2 = parties.iter_mut().fold(vec![], |mut msg2, party| {
+            let party_id = party.keyshare.party_id;
+            let batch = msg1.iter().filter(|msg| msg.from_id != party_id);
+            msg2.extend(party.handle_msg1(&mut rng, batch).unwrap());
+            msg2
+        });
+
+        let batch = msg2.iter().filter(|msg| msg.to_id == 1);
+        assert!(matches!(
+            parties[0].handle_msg2(&mut rng, batch),
+            Err(SignError::FailedCheck(_))
+        ));
+
+        // the party must be one of the signers
+        assert!(matches!(
+            interpolation_coeff(&shares[0], 2, [0, 1].into_iter()),
+            Err(SignError::InvalidSignerSet)
+        ));
+    }
+
+    #[test]
+    fn sign_after_reshare() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(3, 2);
+        let remaining = [0u8, 2];
+
+        let states = remaining
+            .iter()
+            .map(|p| {
+                crate::dkg::State::key_reshare(
+                    &shares[*p as usize],
+                    &remaining,
+                    2,
+                    &mut rng,
+                )
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+        let new_shares = dkg_inner(states);
+
+        dsg(&new_shares);
     }
 
-    sum_p_0 - sum_p_1
-}
+    #[test]
+    fn sign_with_dealer_imported_key() {
+        let mut rng = rand::thread_rng();
 
-// fn get_birkhoff_coefficients(
-//     keyshare: &Keyshare,
-//     sign_party_ids: &[(usize, u8)],
-// ) -> HashMap<usize, Scalar> {
-//     let params = sign_party_ids
-//         .iter()
-//         .map(|(_, pid)| {
-//             (
-//                 *keyshare.x_i_list[*pid as usize],
-//                 keyshare.rank_list[*pid as usize] as usize,
-//             )
-//         })
-//         .collect::<Vec<_>>();
+        let private_key = k256::NonZeroScalar::random(&mut rng);
+        let public_key =
+            (ProjectivePoint::GENERATOR * private_key.as_ref()).to_affine();
 
-//     let betta_vec = birkhoff_coeffs::<Secp256k1>(&params);
+        let shares =
+            crate::dkg::import_key(&private_key, vec![0; 3], 2, &mut rng)
+                .unwrap();
+        assert!(shares.iter().all(|s| s.public_key == public_key));
 
-//     sign_party_ids
-//         .iter()
-//         .zip(betta_vec.iter())
-//         .map(|((_, pid), w_i)| (*pid as usize, *w_i))
-//         .collect::<HashMap<_, _>>()
-// }
+        dsg(&shares[..2]);
+        dsg(&shares[1..]);
 
-fn get_lagrange_coeff(
-    keyshare: &Keyshare,
-    parties: impl Iterator<Item = u8>,
-) -> Scalar {
-    let mut coeff = Scalar::from(1u64);
-    let pid = keyshare.party_id;
-    let x_i = &keyshare.x_i_list[pid as usize] as &Scalar;
+        let shares =
+            crate::dkg::import_key(&private_key, vec![0, 1, 1], 2, &mut rng)
+                .unwrap();
+        dsg(&[shares[0].clone(), shares[2].clone()]);
 
-    for party_id in parties {
-        let x_j = &*keyshare.x_i_list[party_id as usize]; //  as &Scalar;
-        if x_i.ct_ne(x_j).into() {
-            let sub = x_j - x_i; // x_j != xi_i => sub != 0
-            coeff *= x_j * &sub.invert().unwrap(); //
+        assert!(matches!(
+            crate::dkg::import_key(&private_key, vec![0], 1, &mut rng),
+            Err(crate::dkg::KeygenError::InvalidParameters)
+        ));
+        assert!(matches!(
+            crate::dkg::import_key(&private_key, vec![0; 3], 4, &mut rng),
+            Err(crate::dkg::KeygenError::InvalidParameters)
+        ));
+    }
+
+    #[test]
+    fn sign_with_batch_keygen_shares() {
+        let shares = crate::batch_keygen::tests::batch_dkg(3, 2, 3);
+
+        for key in 0..3 {
+            let key_shares: Vec<Keyshare> =
+                shares.iter().map(|p| p[key].clone()).collect();
+            dsg(&key_shares[..2]);
+            dsg(&key_shares[1..]);
         }
     }
 
-    coeff
-}
+    #[test]
+    fn sign_with_ot_setup_seeds() {
+        use crate::ot_setup::tests::{dkg_with_seeds, ot_setup};
 
-/// Locally combine list of t partial signatures into a final signature
-fn combine_partial_signature(
-    partial_signatures: Vec<PS>,
-    t: usize,
-) -> Result<Signature, SignError> {
-    if partial_signatures.len() != t {
-        return Err(SignError::FailedCheck(
-            "Invalid number of partial signatures",
-        ));
+        let seeds = ot_setup(3);
+
+        for t in [2, 3] {
+            let shares = dkg_with_seeds(&seeds, t);
+            dsg(&shares[..t as usize]);
+            dsg(&shares[3 - t as usize..]);
+        }
     }
 
-    let final_session_id = partial_signatures[0].final_session_id;
-    let public_key = partial_signatures[0].public_key;
-    let message_hash = partial_signatures[0].message_hash;
-    let r = partial_signatures[0].r;
+    #[test]
+    fn sign_without_chain_code() {
+        let mut rng = rand::thread_rng();
 
-    let mut sum_s_0 = Scalar::ZERO;
-    let mut sum_s_1 = Scalar::ZERO;
-    for partial_sign in partial_signatures.into_iter() {
-        let cond = (partial_sign.final_session_id != final_session_id)
-            || (partial_sign.public_key != public_key)
-            || (partial_sign.r != r)
-            || (partial_sign.message_hash != message_hash);
-        if cond {
-            return Err(SignError::FailedCheck(
-                "Invalid list of partial signatures",
-            ));
-        }
-        sum_s_0 += partial_sign.s_0;
-        sum_s_1 += partial_sign.s_1;
+        let parties = (0..3)
+            .map(|id| {
+                crate::dkg::State::new(
+                    Party::new(3, 2, id).unwrap(),
+                    &mut rng,
+                )
+                .unwrap()
+                .without_chain_code()
+            })
+            .collect();
+        let shares = dkg_inner(parties);
+
+        assert!(shares.iter().all(|s| s.root_chain_code.is_none()));
+
+        dsg(&shares[..2]);
+
+        let chain_path = DerivationPath::from_str("m/1").unwrap();
+        assert!(matches!(
+            State::new(&mut rng, shares[0].clone(), &chain_path),
+            Err(SignError::MissingChainCode)
+        ));
     }
 
-    let r = r.to_affine().x();
-    let sum_s_1_inv = sum_s_1.invert().unwrap();
-    let s = sum_s_0 * sum_s_1_inv;
+    #[test]
+    fn sign_with_even_y_key() {
+        use k256::elliptic_curve::group::GroupEncoding;
 
-    let sign = Signature::from_scalars(r, s)?;
-    let sign = sign.normalize_s().unwrap_or(sign);
+        let mut rng = rand::thread_rng();
 
-    VerifyingKey::from_affine(public_key.to_affine())?
-        .verify_prehash(&message_hash, &sign)?;
+        // a random key has an odd Y coordinate with probability 1/2
+        for _ in 0..4 {
+            let parties = (0..3)
+                .map(|id| {
+                    crate::dkg::State::new(
+                        Party::new(3, 2, id).unwrap(),
+                        &mut rng,
+                    )
+                    .unwrap()
+                    .with_even_y()
+                })
+                .collect();
+            let shares = dkg_inner(parties);
 
-    Ok(sign)
-}
+            let public_key = shares[0].public_key;
+            assert!(!bool::from(public_key.y_is_odd()));
+            assert_eq!(
+                shares[0].x_only_public_key()[..],
+                public_key.to_bytes()[1..]
+            );
 
-/// Get the additive offset of a key share for a given derivation path
-pub fn derive_with_offset(
-    public_key: &ProjectivePoint,
-    root_chain_code: &[u8; 32],
-    chain_path: &DerivationPath,
-) -> Result<(Scalar, ProjectivePoint), BIP32Error> {
-    let mut pubkey = *public_key;
-    let mut chain_code = *root_chain_code;
-    let mut additive_offset = Scalar::ZERO;
-    for child_num in chain_path {
-        let (il_int, child_pubkey, child_chain_code) =
-            derive_child_pubkey(&pubkey, chain_code, child_num)?;
-        pubkey = child_pubkey;
-        chain_code = child_chain_code;
-        additive_offset += il_int;
+            dsg(&shares[..2]);
+        }
     }
 
-    // Perform the mod q operation to get the additive offset
-    Ok((additive_offset, pubkey))
-}
+    #[test]
+    fn sign_2_out_of_2() {
+        let shares = dkg(2, 2);
+        dsg(&shares[..2]);
+    }
 
-#[cfg(test)]
-mod tests {
-    use crate::dkg::{Party, RefreshShare};
-    use std::str::FromStr;
+    #[test]
+    fn sign_2_out_3() {
+        let shares = dkg(3, 2);
+        dsg(&shares[..2]);
+    }
 
-    use super::*;
+    #[test]
+    fn sign_3_out_3() {
+        let shares = dkg(3, 3);
+        dsg(&shares[..3]);
+    }
+
+    #[test]
+    fn sign_2_out_4() {
+        let shares = dkg(4, 2);
+        dsg(&shares[..2]);
+    }
 
-    use crate::dkg::tests::{check_serde, dkg, dkg_inner};
+    #[test]
+    fn sign_3_out_4() {
+        let shares = dkg(4, 3);
+        dsg(&shares[..3]);
+    }
 
-    fn dsg(shares: &[Keyshare]) {
+    #[test]
+    fn sign_with_incremental_intake() {
         let mut rng = rand::thread_rng();
 
+        let shares = dkg(3, 3);
         let chain_path = DerivationPath::from_str("m").unwrap();
         let mut parties = shares
             .iter()
             .map(|s| State::new(&mut rng, s.clone(), &chain_path).unwrap())
             .collect::<Vec<_>>();
 
-        let msg1: Vec<SignMsg1> =
-            parties.iter_mut().map(|p| p.generate_msg1()).collect();
+        // deliver messages one at a time, in reverse order
+        fn deliver<M: Clone + Into<SignRoundMsg>>(
+            rng: &mut ThreadRng,
+            party: &mut State,
+            msgs: &[M],
+        ) -> SignRoundOutput {
+            let mut complete = false;
+            for msg in msgs.iter().rev() {
+                assert!(!complete);
+                complete = party.push_msg(msg.clone()).unwrap();
+            }
+            assert!(complete);
 
-        check_serde(&msg1);
+            party.finish_round(rng).unwrap()
+        }
 
-        let msg2 = parties.iter_mut().fold(vec![], |mut msg2, party| {
-            let batch: Vec<SignMsg1> = msg1
+        let msg1: Vec<_> = parties
+            .iter_mut()
+            .map(|p| p.generate_msg1().unwrap())
+            .collect();
+
+        assert!(matches!(
+            parties[0].push_msg(msg1[0].clone()),
+            Err(SignError::UnexpectedParty(0))
+        ));
+        assert!(!parties[0].push_msg(msg1[1].clone()).unwrap());
+        assert!(matches!(
+            parties[0].push_msg(msg1[1].clone()),
+            Err(SignError::MissingMessage)
+        ));
+        assert!(parties[0].finish_round(&mut rng).is_err());
+        parties[0].pending.clear();
+
+        let mut msg2 = vec![];
+        for p in parties.iter_mut() {
+            let party_id = p.keyshare.party_id;
+            let batch = msg1
                 .iter()
-                .filter(|msg| msg.from_id != party.keyshare.party_id)
+                .filter(|m| m.from_id != party_id)
                 .cloned()
-                .collect();
-            msg2.extend(party.handle_msg1(&mut rng, batch).unwrap());
-            msg2
-        });
+                .collect::<Vec<_>>();
+            let SignRoundOutput::Msg2(out) = deliver(&mut rng, p, &batch)
+            else {
+                panic!("expected round 2 messages");
+            };
+            msg2.extend(out);
+        }
 
-        check_serde(&msg2);
+        assert!(matches!(
+            parties[0].push_msg(msg1[1].clone()),
+            Err(SignError::FailedCheck(_))
+        ));
 
-        let msg3 = parties.iter_mut().fold(vec![], |mut msg3, party| {
-            let batch: Vec<SignMsg2> = msg2
+        let mut msg3 = vec![];
+        for p in parties.iter_mut() {
+            let party_id = p.keyshare.party_id;
+            let batch = msg2
                 .iter()
-                .filter(|msg| msg.to_id == party.keyshare.party_id)
+                .filter(|m| m.to_id == party_id)
                 .cloned()
-                .collect();
-            msg3.extend(party.handle_msg2(&mut rng, batch).unwrap());
-            msg3
-        });
+                .collect::<Vec<_>>();
+            let SignRoundOutput::Msg3(out) = deliver(&mut rng, p, &batch)
+            else {
+                panic!("expected round 3 messages");
+            };
+            msg3.extend(out);
+        }
 
-        check_serde(&msg3);
+        let mut partials = vec![];
+        let mut msg4 = vec![];
+        for p in parties.iter_mut() {
+            let party_id = p.keyshare.party_id;
+            let batch = msg3
+                .iter()
+                .filter(|m| m.to_id == party_id)
+                .cloned()
+                .collect::<Vec<_>>();
+            let SignRoundOutput::PreSignature(pre) =
+                deliver(&mut rng, p, &batch)
+            else {
+                panic!("expected pre-signature");
+            };
+            let (partial, msg) =
+                create_partial_signature(pre, [4; 32]).unwrap();
+            partials.push(partial);
+            msg4.push(msg);
+        }
 
-        let pre_signs = parties
-            .iter_mut()
-            .map(|party| {
-                let batch: Vec<SignMsg3> = msg3
-                    .iter()
-                    .filter(|msg| msg.to_id == party.keyshare.party_id)
-                    .cloned()
-                    .collect();
+        for p in partials {
+            let party_id = p.party_id;
+            let batch = msg4.iter().filter(|m| m.from_id != party_id);
+            combine_signatures(p, batch).unwrap();
+        }
+    }
 
-                party.handle_msg3(batch).unwrap()
+    #[test]
+    fn sign_with_shared_keyshare() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(3, 2)
+            .into_iter()
+            .take(2)
+            .map(Arc::new)
+            .collect::<Vec<_>>();
+        let chain_path = DerivationPath::from_str("m/1").unwrap();
+
+        let sessions = (0..3)
+            .map(|_| {
+                shares
+                    .iter()
+                    .map(|s| {
+                        State::new(&mut rng, s.clone(), &chain_path).unwrap()
+                    })
+                    .collect::<Vec<_>>()
             })
             .collect::<Vec<_>>();
 
-        check_serde(&pre_signs);
+        // all concurrent sessions use the same copy of a key share
+        assert_eq!(Arc::strong_count(&shares[0]), 4);
+        assert!(Arc::ptr_eq(
+            &sessions[0][0].keyshare,
+            &sessions[2][0].keyshare
+        ));
 
-        let hash = [255; 32];
+        for parties in sessions {
+            run_dsg(parties);
+        }
+        assert_eq!(Arc::strong_count(&shares[0]), 1);
+    }
 
-        let (partials, msg4): (Vec<_>, Vec<_>) = pre_signs
-            .into_iter()
-            .map(|pre| create_partial_signature(pre, hash))
-            .unzip();
-        // at this point the partial signatures are created you can store them for later usage
-        // an example of a final signature is shown below.
-        let _sigs = partials
-            .into_iter()
-            .map(|p| {
-                let batch: Vec<SignMsg4> = msg4
-                    .iter()
-                    .filter(|msg| msg.from_id != p.party_id)
-                    .cloned()
-                    .collect();
+    #[test]
+    fn sign_with_precomputed_sessions() {
+        let mut rng = rand::thread_rng();
 
-                combine_signatures(p, batch)
+        let shares = dkg(3, 2);
+        let paths = ["m", "m/0/1"]
+            .map(|p| DerivationPath::from_str(p).unwrap())
+            .to_vec();
+
+        let mut precomputed = shares[..2]
+            .iter()
+            .map(|s| {
+                Precomputed::new(&mut rng, s.clone(), &paths, 2).unwrap()
             })
-            .collect::<Result<Vec<_>, _>>()
-            .unwrap();
-    }
+            .collect::<Vec<_>>();
 
-    #[test]
-    fn sign_2_out_of_2() {
-        let shares = dkg(2, 2);
-        dsg(&shares[..2]);
+        for path in &paths {
+            let parties = precomputed
+                .iter_mut()
+                .map(|p| p.take(&mut rng, path).unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(parties[0].chain_path().as_ref(), Some(path));
+            run_dsg(parties);
+        }
+
+        assert_eq!(precomputed[0].available(&paths[0]), 1);
+        precomputed[0].refill(&mut rng);
+        assert_eq!(precomputed[0].available(&paths[0]), 2);
     }
 
     #[test]
-    fn sign_2_out_3() {
+    fn presignature_store() {
+        let mut rng = rand::thread_rng();
+
         let shares = dkg(3, 2);
-        dsg(&shares[..2]);
-    }
+        let chain_path = DerivationPath::from_str("m").unwrap();
 
-    #[test]
-    fn sign_3_out_3() {
-        let shares = dkg(3, 3);
-        dsg(&shares[..3]);
+        let mut parties = shares[..2]
+            .iter()
+            .map(|s| State::new(&mut rng, s.clone(), &chain_path).unwrap())
+            .collect::<Vec<_>>();
+
+        let mut stores = run_presign(&mut parties)
+            .into_iter()
+            .map(|pre| {
+                let mut store = PresignatureStore::new();
+                store.insert(pre).unwrap();
+                store
+            })
+            .collect::<Vec<_>>();
+
+        let id = stores[0].ids().next().unwrap();
+        let backup = serde_json::to_string(&stores[0]).unwrap();
+
+        let (partials, msg4): (Vec<_>, Vec<_>) = stores
+            .iter_mut()
+            .map(|s| s.create_partial_signature(&id, [1; 32]).unwrap())
+            .unzip();
+        for p in partials {
+            let party_id = p.party_id;
+            let batch = msg4.iter().filter(|m| m.from_id != party_id);
+            combine_signatures(p, batch).unwrap();
+        }
+
+        assert!(stores[0].is_empty());
+        assert!(stores[0].is_used(&id));
+        assert!(matches!(
+            stores[0].create_partial_signature(&id, [2; 32]),
+            Err(SignError::PreSignatureReuse)
+        ));
+        assert!(matches!(
+            stores[0].take(&[0; 32]),
+            Err(SignError::UnknownPreSignature)
+        ));
+
+        // a copy restored from the old state of the store
+        let mut backup: PresignatureStore =
+            serde_json::from_str(&backup).unwrap();
+        let copy = backup.take(&id).unwrap();
+        assert!(matches!(
+            stores[0].insert(copy),
+            Err(SignError::PreSignatureReuse)
+        ));
     }
 
     #[test]
-    fn sign_2_out_4() {
-        let shares = dkg(4, 2);
-        dsg(&shares[..2]);
+    fn presignature_keyshare_binding() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(3, 2);
+        let other = dkg(3, 2);
+        let chain_path = DerivationPath::from_str("m").unwrap();
+
+        let mut parties = shares[..2]
+            .iter()
+            .map(|s| State::new(&mut rng, s.clone(), &chain_path).unwrap())
+            .collect::<Vec<_>>();
+
+        let mut pre = run_presign(&mut parties);
+
+        assert_eq!(pre[0].version, PRESIGNATURE_VERSION);
+        assert_eq!(pre[0].key_fingerprint, pre[1].key_fingerprint);
+        assert_eq!(pre[0].key_fingerprint, shares[2].key_fingerprint());
+        assert_eq!(pre[0].keyshare_epoch, shares[0].final_session_id);
+
+        pre[0].check_keyshare(&shares[0]).unwrap();
+        assert!(matches!(
+            pre[0].check_keyshare(&shares[1]),
+            Err(SignError::PreSignatureKeyMismatch)
+        ));
+        assert!(matches!(
+            pre[0].check_keyshare(&other[0]),
+            Err(SignError::PreSignatureKeyMismatch)
+        ));
+
+        // a pre-signature serialized before versioning
+        let mut legacy = serde_json::to_value(&pre[1]).unwrap();
+        let fields = legacy.as_object_mut().unwrap();
+        fields.remove("version");
+        fields.remove("key_fingerprint");
+        fields.remove("keyshare_epoch");
+        let legacy: PreSignature = serde_json::from_value(legacy).unwrap();
+        assert_eq!(legacy.version, 0);
+        legacy.check_keyshare(&other[1]).unwrap();
+        assert!(matches!(
+            legacy.check_keyshare(&shares[0]),
+            Err(SignError::PreSignatureKeyMismatch)
+        ));
+
+        let mut newer: PreSignature =
+            serde_json::from_value(serde_json::to_value(&pre[0]).unwrap())
+                .unwrap();
+        newer.version = PRESIGNATURE_VERSION + 1;
+        assert!(matches!(
+            newer.check_keyshare(&shares[0]),
+            Err(SignError::UnsupportedPreSignatureVersion(_))
+        ));
+        assert!(matches!(
+            create_partial_signature(newer, [1; 32]),
+            Err(SignError::UnsupportedPreSignatureVersion(_))
+        ));
+
+        // pre-signatures of both formats complete a signature
+        let (partials, msg4): (Vec<_>, Vec<_>) = [pre.remove(0), legacy]
+            .into_iter()
+            .map(|pre| create_partial_signature(pre, [1; 32]).unwrap())
+            .unzip();
+        for p in partials {
+            let party_id = p.party_id;
+            let batch = msg4.iter().filter(|m| m.from_id != party_id);
+            combine_signatures(p, batch).unwrap();
+        }
     }
 
     #[test]
-    fn sign_3_out_4() {
-        let shares = dkg(4, 3);
-        dsg(&shares[..3]);
+    fn session_bound_to_keyshare_epoch() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(2, 2);
+        let rotation_states = shares
+            .iter()
+            .map(|s| crate::dkg::State::key_rotation(s, &mut rng).unwrap())
+            .collect::<Vec<_>>();
+        let rotated = dkg_inner(rotation_states);
+        let chain_path = DerivationPath::from_str("m").unwrap();
+
+        let mut old =
+            State::new(&mut rng, shares[0].clone(), &chain_path).unwrap();
+        let mut new =
+            State::new(&mut rng, rotated[0].clone(), &chain_path).unwrap();
+        let mut other =
+            State::new(&mut rng, shares[1].clone(), &chain_path).unwrap();
+
+        // the same session IDs with a key share of another epoch
+        let sid = *old.sid_list.find_pair(0);
+        new.sid_list = Pairs::new_with_item(0, sid);
+
+        let msg1 = other.generate_msg1().unwrap();
+        old.handle_msg1(&mut rng, [&msg1]).unwrap();
+        new.handle_msg1(&mut rng, [&msg1]).unwrap();
+        assert_ne!(old.final_session_id, new.final_session_id);
+
+        // a replayed session ID of the receiver
+        let mut replay =
+            State::new(&mut rng, shares[0].clone(), &chain_path).unwrap();
+        let mut msg1 = msg1.clone();
+        msg1.session_id = *replay.sid_list.find_pair(0);
+        assert!(matches!(
+            replay.handle_msg1(&mut rng, [msg1]),
+            Err(SignError::DuplicateSessionId(1))
+        ));
     }
 
     #[test]
@@ -867,7 +4640,7 @@ mod tests {
             party_id: 0,
         };
 
-        let refresh_shares = vec![
+        let refresh_shares = [
             RefreshShare::from_lost_keyshare(
                 party_with_lost_keyshare,
                 public_key,
@@ -0,0 +1,109 @@
+// Copyright (c) Silence Laboratories Pte. Ltd. All Rights Reserved.
+// This software is licensed under the Silence Laboratories License Agreement.
+
+//! Mixing of external entropy, for example from an HSM or a TEE, into
+//! the randomness of keygen and signing.
+//!
+//! All protocol handlers take any `RngCore + CryptoRng`. Pass a
+//! [`MixedRng`] to use an external entropy source together with the
+//! local one.
+
+use rand::prelude::*;
+use rand_chacha::ChaCha20Rng;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroize;
+
+use crate::constants::ENTROPY_MIX_LABEL;
+
+/// External source of entropy.
+pub trait EntropySource {
+    /// Fill `dest` with random bytes.
+    fn fill_entropy(&mut self, dest: &mut [u8]);
+}
+
+impl<F: FnMut(&mut [u8])> EntropySource for F {
+    fn fill_entropy(&mut self, dest: &mut [u8]) {
+        self(dest)
+    }
+}
+
+/// CSPRNG seeded by both a local RNG and an external entropy source.
+/// The output is unpredictable as long as at least one of the sources
+/// is.
+pub struct MixedRng(ChaCha20Rng);
+
+impl MixedRng {
+    /// Seed the generator by 32 bytes of each source.
+    pub fn new<R: RngCore + CryptoRng, E: EntropySource + ?Sized>(
+        local: &mut R,
+        external: &mut E,
+    ) -> Self {
+        let mut local_seed = [0u8; 32];
+        let mut external_seed = [0u8; 32];
+        local.fill_bytes(&mut local_seed);
+        external.fill_entropy(&mut external_seed);
+
+        let mut hasher = Sha256::new();
+        hasher.update(ENTROPY_MIX_LABEL);
+        hasher.update(local_seed);
+        hasher.update(external_seed);
+        let seed: [u8; 32] = hasher.finalize().into();
+
+        local_seed.zeroize();
+        external_seed.zeroize();
+
+        Self(ChaCha20Rng::from_seed(seed))
+    }
+}
+
+impl RngCore for MixedRng {
+    fn next_u32(&mut self) -> u32 {
+        self.0.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.0.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.0.try_fill_bytes(dest)
+    }
+}
+
+impl CryptoRng for MixedRng {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::dkg::{tests::dkg_inner, Party, State};
+
+    #[test]
+    fn mixed_rng() {
+        let output = |local: u8, external: u8| {
+            let mut local = ChaCha20Rng::from_seed([local; 32]);
+            let mut rng =
+                MixedRng::new(&mut local, &mut |dest: &mut [u8]| {
+                    dest.fill(external)
+                });
+            rng.gen::<[u8; 32]>()
+        };
+
+        assert_eq!(output(1, 2), output(1, 2));
+        assert_ne!(output(1, 2), output(1, 3));
+        assert_ne!(output(1, 2), output(3, 2));
+
+        let mut hsm = |dest: &mut [u8]| dest.fill(7);
+        let mut rng = MixedRng::new(&mut rand::thread_rng(), &mut hsm);
+        let parties = (0..3)
+            .map(|id| {
+                State::new(Party::new(3, 2, id).unwrap(), &mut rng).unwrap()
+            })
+            .collect();
+        dkg_inner(parties);
+    }
+}
@@ -13,36 +13,38 @@ pub enum KeygenError {
     InvalidMessage,
 
     /// Invalid commitment hash
-    #[error("Invalid commitment hash")]
-    InvalidCommitmentHash,
+    #[error("Invalid commitment hash of party {0}")]
+    InvalidCommitmentHash(u8),
 
-    #[error("Invalid DLog proof")]
+    #[error("Invalid DLog proof of party {0}")]
     /// Invalid DLog proof
-    InvalidDLogProof,
+    InvalidDLogProof(u8),
 
-    #[error("Invalid Polynomial Point")]
+    #[error("Invalid Polynomial Point of party {0}")]
     /// Invalid Polynomial Point
-    InvalidPolynomialPoint,
+    InvalidPolynomialPoint(u8),
 
     /// Not unique x_i values
-    #[error("Not unique x_i values")]
-    NotUniqueXiValues,
+    #[error("Not unique x_i value of party {0}")]
+    NotUniqueXiValues(u8),
 
     /// Big F vec mismatch
-    #[error("Big F vec mismatch")]
-    BigFVecMismatch,
+    #[error("Big F vec mismatch with party {0}")]
+    BigFVecMismatch(u8),
 
     /// Failed felman verify
-    #[error("Failed felman verify")]
-    FailedFelmanVerify,
+    #[error("Failed felman verify of party {0}")]
+    FailedFelmanVerify(u8),
 
-    /// Public key mismatch between the message and the party
+    /// Public key mismatch between the message and the party. Shares
+    /// of all parties take part in the check, so the error is not
+    /// attributed to a single party.
     #[error("Public key mismatch between the message and the party")]
     PublicKeyMismatch,
 
     /// Big S value mismatch
-    #[error("Big S value mismatch")]
-    BigSMismatch,
+    #[error("Big S value mismatch of party {0}")]
+    BigSMismatch(u8),
 
     #[error("PPRF error {0}")]
     /// PPRF error
@@ -54,6 +56,229 @@ pub enum KeygenError {
     #[error("Invalid key refresh")]
     /// Invalid key refresh
     InvalidKeyRefresh,
+
+    /// Parties received different broadcast values
+    #[error("Broadcast digest mismatch with party {0}")]
+    BroadcastDigestMismatch(u8),
+
+    /// Ranks of parties do not allow to reconstruct the key
+    #[error("Invalid ranks")]
+    InvalidRanks,
+
+    /// Threshold, number of parties or party ID are out of range
+    #[error("Invalid keygen parameters")]
+    InvalidParameters,
+
+    /// Abort the protocol and ban the party
+    #[error("Abort the protocol and ban the party {0}")]
+    AbortProtocolAndBanParty(u8),
+
+    /// Message of a party has unsupported version
+    #[error("Unsupported message version {1} of party {0}")]
+    VersionMismatch(u8, u8),
+
+    /// Sender ID of a message is out of range or equal to own ID
+    #[error("Invalid sender id {0}")]
+    InvalidSenderId(u8),
+
+    /// Message of a party is addressed to another party
+    #[error("Message of party {0} is addressed to another party")]
+    InvalidRecipientId(u8),
+
+    /// More than one message of a party in a round
+    #[error("Duplicate message of party {0}")]
+    DuplicateMessage(u8),
+
+    /// Identity keys do not match the parties of the keygen
+    #[error("Invalid identity keys")]
+    InvalidIdentityKeys,
+
+    /// Missing or invalid signature of the final session ID by the
+    /// identity key of a party
+    #[error("Invalid identity signature of party {0}")]
+    InvalidIdentitySignature(u8),
+
+    /// Chain code commitments passed to the third round do not
+    /// match the parties of the keygen
+    #[error("Invalid chain code commitments")]
+    InvalidChainCodeCommitments,
+
+    /// OT seeds do not match the party or the number of parties
+    #[error("Invalid OT seeds")]
+    InvalidOtSeeds,
+
+    /// Party has another number of seed refreshes with this party
+    #[error("Seed refresh counter mismatch with party {0}")]
+    SeedRefreshCounterMismatch(u8),
+
+    /// Public data of a party does not match the key share
+    #[error("Key share mismatch with party {0}")]
+    KeyshareMismatch(u8),
+
+    /// Unknown version of a suspended state
+    #[error("Unsupported suspended state version {0}")]
+    UnsupportedStateVersion(u8),
+
+    /// Suspended state is corrupted or belongs to another round
+    #[error("Invalid suspended state")]
+    InvalidSuspendedState,
+
+    /// Round handler is called in another round
+    #[error("Unexpected round handler, the state is in {0:?}")]
+    UnexpectedRound(crate::dkg::KeygenRound),
+}
+
+impl KeygenError {
+    /// ID of the party which message triggered the error, if the
+    /// error could be attributed to a single party.
+    pub fn party_id(&self) -> Option<u8> {
+        match self {
+            Self::InvalidCommitmentHash(p)
+            | Self::InvalidDLogProof(p)
+            | Self::InvalidPolynomialPoint(p)
+            | Self::NotUniqueXiValues(p)
+            | Self::BigFVecMismatch(p)
+            | Self::FailedFelmanVerify(p)
+            | Self::BigSMismatch(p)
+            | Self::BroadcastDigestMismatch(p)
+            | Self::AbortProtocolAndBanParty(p)
+            | Self::DuplicateMessage(p)
+            | Self::SeedRefreshCounterMismatch(p)
+            | Self::KeyshareMismatch(p)
+            | Self::InvalidIdentitySignature(p)
+            | Self::InvalidSenderId(p)
+            | Self::InvalidRecipientId(p)
+            | Self::VersionMismatch(p, _) => Some(*p),
+
+            Self::InvalidMessage
+            | Self::PublicKeyMismatch
+            | Self::PPRFError(_)
+            | Self::MissingMessage
+            | Self::InvalidKeyRefresh
+            | Self::InvalidRanks
+            | Self::InvalidParameters
+            | Self::InvalidIdentityKeys
+            | Self::InvalidChainCodeCommitments
+            | Self::InvalidOtSeeds
+            | Self::UnsupportedStateVersion(_)
+            | Self::InvalidSuspendedState
+            | Self::UnexpectedRound(_) => None,
+        }
+    }
+}
+
+/// Errors of the compact key share encoding
+#[derive(Debug, Error)]
+pub enum KeyshareEncodingError {
+    /// Unknown version of the encoding
+    #[error("Unsupported encoding version {0}")]
+    UnsupportedVersion(u8),
+
+    /// Input is shorter or longer than expected
+    #[error("Invalid encoding length")]
+    InvalidLength,
+
+    /// Invalid parameters, point or scalar
+    #[error("Invalid key share data")]
+    InvalidData,
+}
+
+/// Errors of the P2P transport encryption
+#[cfg(feature = "transport")]
+#[derive(Debug, Error)]
+pub enum TransportError {
+    /// Invalid or missing public key of a party
+    #[error("Invalid transport public key of party {0}")]
+    InvalidPublicKey(u8),
+
+    /// No transport key agreed with a party
+    #[error("Unknown party {0}")]
+    UnknownParty(u8),
+
+    /// Message is addressed to another party or the sealed message
+    /// does not match its envelope
+    #[error("Invalid message routing")]
+    InvalidRouting,
+
+    /// Ciphertext of a party could not be decrypted
+    #[error("Decryption of a message of party {0} failed")]
+    DecryptionFailed(u8),
+
+    /// Error while serializing or deserializing a message
+    #[error("Error while serializing or deserializing message")]
+    InvalidMessage,
+}
+
+/// Errors of the round drivers
+#[cfg(feature = "runner")]
+#[derive(Debug, Error)]
+pub enum RunnerError<E> {
+    /// Transport failed to send or receive a message
+    #[error("Transport error: {0:?}")]
+    Transport(E),
+
+    /// Keygen round failed
+    #[error(transparent)]
+    Keygen(#[from] KeygenError),
+
+    /// Signing round failed
+    #[error(transparent)]
+    Sign(#[from] SignError),
+}
+
+/// Check of a signing message that failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AbortCheck {
+    /// Message of the OT extension is inconsistent
+    OtConsistency,
+
+    /// Nonce does not open the commitment of the first round
+    Commitment,
+
+    /// Digest of the session does not match
+    Digest,
+
+    /// Nonce commitment is the identity point
+    NonceCommitment,
+
+    /// Partial signature does not verify
+    PartialSignature,
+
+    /// Batch message carries a wrong number of session messages
+    BatchSize,
+}
+
+/// Context of an abort attributed to a party.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AbortContext {
+    /// Party to ban
+    pub party_id: u8,
+
+    /// Round of the protocol, starting from 1
+    pub round: u8,
+
+    /// Failed check
+    pub check: AbortCheck,
+
+    /// Final session ID, `None` if the abort happened before it was
+    /// agreed or for a batch message not assigned to a session yet
+    pub session_id: Option<[u8; 32]>,
+}
+
+impl AbortContext {
+    pub(crate) fn error(
+        party_id: u8,
+        round: u8,
+        check: AbortCheck,
+        session_id: Option<[u8; 32]>,
+    ) -> SignError {
+        SignError::AbortProtocolAndBanParty(Box::new(Self {
+            party_id,
+            round,
+            check,
+            session_id,
+        }))
+    }
 }
 
 /// Distributed key generation errors
@@ -82,7 +307,109 @@ pub enum SignError {
     #[error("Missing message")]
     MissingMessage,
 
+    /// Signer set is not valid for the key share
+    #[error("Invalid signer set")]
+    InvalidSignerSet,
+
+    /// Party ID occurs more than once in a signer set or among the
+    /// senders of a batch of messages
+    #[error("Duplicate party {0}")]
+    DuplicateParty(u8),
+
+    /// Session ID of a party is already used by another party
+    #[error("Duplicate session ID of party {0}")]
+    DuplicateSessionId(u8),
+
+    /// Message from a party outside of the signer set
+    #[error("Unexpected message from party {0}")]
+    UnexpectedParty(u8),
+
+    /// Message hash does not match the one bound in round 1
+    #[error("Message hash mismatch")]
+    MessageHashMismatch,
+
+    /// Pre-signature of a newer format
+    #[error("Unsupported pre-signature version {0}")]
+    UnsupportedPreSignatureVersion(u8),
+
+    /// Pre-signature was generated with another key share
+    #[error("Pre-signature does not match the key share")]
+    PreSignatureKeyMismatch,
+
+    /// Encoded pre-signature advertisement is malformed
+    #[error("Invalid pre-signature advertisement")]
+    InvalidPreSignatureAdvert,
+
+    /// One-shot signing of a session without a bound message hash
+    #[error("Message hash is not bound")]
+    UnboundMessageHash,
+
+    /// Message hash is bound twice or after the first message
+    #[error("Message hash can not be bound")]
+    InvalidMessageHashBinding,
+
+    /// Signing policy of the session refused the message hash
+    #[error("Message hash is rejected by the signing policy")]
+    PolicyRejected,
+
+    /// Session was wiped after an error of a previous round
+    #[error("Signing session is aborted")]
+    SessionAborted,
+
+    /// Abort message of a party carries a session ID other than the
+    /// one of its first message
+    #[error("Session ID mismatch with party {0}")]
+    SessionIdMismatch(u8),
+
+    /// Unknown version of a suspended state
+    #[error("Unsupported suspended state version {0}")]
+    UnsupportedStateVersion(u8),
+
+    /// Suspended state is corrupted or belongs to another round
+    #[error("Invalid suspended state")]
+    InvalidSuspendedState,
+
+    /// Derivation of a non-root path for a key without chain code
+    #[error("Key share has no chain code, only the root path is allowed")]
+    MissingChainCode,
+
+    /// Pre-signature was generated for a fixed derivation path
+    #[error("Pre-signature is bound to a derivation path")]
+    PathBoundPreSignature,
+
+    /// Pre-signature was already used to sign a message
+    #[error("Pre-signature is already used")]
+    PreSignatureReuse,
+
+    /// Pre-signature is not in the store
+    #[error("Unknown pre-signature")]
+    UnknownPreSignature,
+
+    /// BIP32 derivation error
+    #[error("BIP32 error: {0}")]
+    BIP32Error(#[from] sl_mpc_mate::bip32::BIP32Error),
+
     /// Abort the protocol and ban the party
-    #[error("Abort the protocol and ban the party {0}")]
-    AbortProtocolAndBanParty(u8),
+    #[error(
+        "Abort the protocol and ban the party {}: {:?} check of round {} failed",
+        .0.party_id, .0.check, .0.round
+    )]
+    AbortProtocolAndBanParty(Box<AbortContext>),
+
+    /// MtA consistency check failed, with an unverified claim against
+    /// the party
+    #[error("MtA consistency check failed for party {}", .0.accused)]
+    MtaCheckFailed(Box<crate::dsg::SignBlameClaim>),
+}
+
+impl SignError {
+    /// ID of the party which message triggered the error, if the
+    /// error could be attributed to a single party.
+    pub fn party_id(&self) -> Option<u8> {
+        match self {
+            Self::AbortProtocolAndBanParty(ctx) => Some(ctx.party_id),
+            Self::MtaCheckFailed(blame) => Some(blame.accused),
+            _ => None,
+        }
+    }
 }
@@ -0,0 +1,270 @@
+// Copyright (c) Silence Laboratories Pte. Ltd. All Rights Reserved.
+// This software is licensed under the Silence Laboratories License Agreement.
+
+//! Ethereum message digests and signatures.
+//!
+//! [`personal_message_hash`] and [`typed_data_hash`] compute the 32
+//! byte digests of EIP-191 personal messages and EIP-712 typed data.
+//! A digest converts to [`MessageToSign`](crate::dsg::MessageToSign)
+//! and is signed as is by
+//! [`create_partial_signature`](crate::dsg::create_partial_signature);
+//! [`combine_signatures`] returns the signature as `r`, `s`, `v`.
+
+use std::borrow::Borrow;
+
+use k256::ecdsa::{RecoveryId, Signature};
+
+use crate::{
+    dsg::{self, PartialSignature, SignMsg4},
+    error::SignError,
+    utils::keccak256,
+};
+
+/// EIP-191 digest of a personal message, as signed by `personal_sign`
+/// and `eth_sign`.
+pub fn personal_message_hash(message: &[u8]) -> [u8; 32] {
+    let prefix = format!("\x19Ethereum Signed Message:\n{}", message.len());
+
+    let mut data = Vec::with_capacity(prefix.len() + message.len());
+    data.extend_from_slice(prefix.as_bytes());
+    data.extend_from_slice(message);
+
+    keccak256(&data)
+}
+
+/// EIP-712 `hashStruct` of a struct: the hash of the type signature,
+/// e.g. `Person(string name,address wallet)` followed by the types it
+/// references, and of the encoded fields in order of the signature.
+///
+/// Every field is encoded to 32 bytes: atomic values as ABI words,
+/// `string` and `bytes` as their Keccak-256 hash, nested structs as
+/// their `hashStruct`.
+pub fn hash_struct(type_signature: &str, fields: &[[u8; 32]]) -> [u8; 32] {
+    let mut data = Vec::with_capacity(32 * (fields.len() + 1));
+    data.extend_from_slice(&keccak256(type_signature.as_bytes()));
+    for field in fields {
+        data.extend_from_slice(field);
+    }
+
+    keccak256(&data)
+}
+
+/// ABI word of an address.
+pub fn encode_address(address: &[u8; 20]) -> [u8; 32] {
+    let mut word = [0; 32];
+    word[12..].copy_from_slice(address);
+    word
+}
+
+/// ABI word of an unsigned integer.
+pub fn encode_uint(value: u64) -> [u8; 32] {
+    let mut word = [0; 32];
+    word[24..].copy_from_slice(&value.to_be_bytes());
+    word
+}
+
+/// EIP-712 domain. Only the fields that are set take part in the
+/// domain separator.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Eip712Domain {
+    pub name: Option<String>,
+    pub version: Option<String>,
+    pub chain_id: Option<u64>,
+    pub verifying_contract: Option<[u8; 20]>,
+    pub salt: Option<[u8; 32]>,
+}
+
+impl Eip712Domain {
+    /// Domain separator, the `hashStruct` of the domain.
+    pub fn separator(&self) -> [u8; 32] {
+        let mut types = vec![];
+        let mut fields = vec![];
+
+        if let Some(name) = &self.name {
+            types.push("string name");
+            fields.push(keccak256(name.as_bytes()));
+        }
+        if let Some(version) = &self.version {
+            types.push("string version");
+            fields.push(keccak256(version.as_bytes()));
+        }
+        if let Some(chain_id) = self.chain_id {
+            types.push("uint256 chainId");
+            fields.push(encode_uint(chain_id));
+        }
+        if let Some(contract) = &self.verifying_contract {
+            types.push("address verifyingContract");
+            fields.push(encode_address(contract));
+        }
+        if let Some(salt) = self.salt {
+            types.push("bytes32 salt");
+            fields.push(salt);
+        }
+
+        let type_signature = format!("EIP712Domain({})", types.join(","));
+        hash_struct(&type_signature, &fields)
+    }
+}
+
+/// EIP-712 digest of typed data, as signed by `eth_signTypedData_v4`.
+pub fn typed_data_hash(
+    domain: &Eip712Domain,
+    struct_hash: &[u8; 32],
+) -> [u8; 32] {
+    let mut data = [0; 66];
+    data[..2].copy_from_slice(&[0x19, 0x01]);
+    data[2..34].copy_from_slice(&domain.separator());
+    data[34..].copy_from_slice(struct_hash);
+
+    keccak256(&data)
+}
+
+/// Ethereum signature with a low `s`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EthSignature {
+    pub r: [u8; 32],
+    pub s: [u8; 32],
+    /// Recovery ID with the legacy offset of 27.
+    pub v: u8,
+}
+
+impl EthSignature {
+    /// 65 bytes `r || s || v`.
+    pub fn to_bytes(&self) -> [u8; 65] {
+        let mut bytes = [0; 65];
+        bytes[..32].copy_from_slice(&self.r);
+        bytes[32..64].copy_from_slice(&self.s);
+        bytes[64] = self.v;
+        bytes
+    }
+}
+
+impl From<(Signature, RecoveryId)> for EthSignature {
+    fn from((sign, recid): (Signature, RecoveryId)) -> Self {
+        let (r, s) = sign.split_bytes();
+
+        Self {
+            r: r.into(),
+            s: s.into(),
+            v: 27 + recid.to_byte(),
+        }
+    }
+}
+
+/// Combine the signature of a digest, see [`dsg::combine_signatures`].
+pub fn combine_signatures(
+    partial: PartialSignature,
+    msgs: impl IntoIterator<Item = impl Borrow<SignMsg4>>,
+) -> Result<EthSignature, SignError> {
+    dsg::combine_signatures(partial, msgs).map(EthSignature::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use derivation_path::DerivationPath;
+    use k256::ecdsa::VerifyingKey;
+
+    use super::*;
+
+    use crate::{dkg::tests::dkg, dsg::State};
+
+    fn hex32(hex: &str) -> [u8; 32] {
+        hex::decode(hex).unwrap().try_into().unwrap()
+    }
+
+    #[test]
+    fn personal_message() {
+        assert_eq!(
+            personal_message_hash(b"hello world"),
+            hex32(
+                "d9eba16ed0ecae432b71fe008c98cc872bb4cc214d3220a36f365326cf807d68"
+            )
+        );
+    }
+
+    // the example of EIP-712
+    #[test]
+    fn typed_data_mail() {
+        let domain = Eip712Domain {
+            name: Some("Ether Mail".into()),
+            version: Some("1".into()),
+            chain_id: Some(1),
+            verifying_contract: Some([0xcc; 20]),
+            salt: None,
+        };
+        assert_eq!(
+            domain.separator(),
+            hex32(
+                "f2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f"
+            )
+        );
+
+        let person = |name: &str, wallet: &str| {
+            let wallet = hex::decode(wallet).unwrap().try_into().unwrap();
+            hash_struct(
+                "Person(string name,address wallet)",
+                &[keccak256(name.as_bytes()), encode_address(&wallet)],
+            )
+        };
+        let mail = hash_struct(
+            "Mail(Person from,Person to,string contents)\
+             Person(string name,address wallet)",
+            &[
+                person("Cow", "cd2a3d9f938e13cd947ec05abc7fe734df8dd826"),
+                person("Bob", "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb"),
+                keccak256(b"Hello, Bob!"),
+            ],
+        );
+        assert_eq!(
+            mail,
+            hex32(
+                "c52c0ee5d84264471806290a3f2c4cecfc5490626bf912d01f240d7a274b371e"
+            )
+        );
+
+        assert_eq!(
+            typed_data_hash(&domain, &mail),
+            hex32(
+                "be609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2"
+            )
+        );
+    }
+
+    #[test]
+    fn sign_personal_message() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(3, 2);
+        let chain_path = DerivationPath::from_str("m/0").unwrap();
+        let mut parties = shares[..2]
+            .iter()
+            .map(|s| State::new(&mut rng, s.clone(), &chain_path).unwrap())
+            .collect::<Vec<_>>();
+        let public_key = parties[0].derived_public_key;
+
+        let hash = personal_message_hash(b"hello");
+        let (partials, msg4): (Vec<_>, Vec<_>) =
+            crate::dsg::tests::run_presign(&mut parties)
+                .into_iter()
+                .map(|pre| dsg::create_partial_signature(pre, hash).unwrap())
+                .unzip();
+
+        for p in partials {
+            let party_id = p.party_id;
+            let batch = msg4.iter().filter(|m| m.from_id != party_id);
+            let sign = combine_signatures(p, batch).unwrap();
+
+            assert!(sign.v == 27 || sign.v == 28);
+            let bytes = sign.to_bytes();
+            let key = VerifyingKey::recover_from_prehash(
+                &hash,
+                &Signature::from_slice(&bytes[..64]).unwrap(),
+                RecoveryId::from_byte(bytes[64] - 27).unwrap(),
+            )
+            .unwrap();
+            assert_eq!(key.as_affine(), &public_key);
+        }
+    }
+}
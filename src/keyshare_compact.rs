@@ -0,0 +1,262 @@
+// Copyright (c) Silence Laboratories Pte. Ltd. All Rights Reserved.
+// This software is licensed under the Silence Laboratories License Agreement.
+
+//! Compact fixed-layout binary encoding of a key share.
+//!
+//! Layout of version 3, all fields are concatenated without any tags
+//! or length prefixes:
+//!
+//! | field              | size                              |
+//! |--------------------|-----------------------------------|
+//! | version            | 1                                 |
+//! | total_parties (n)  | 1                                 |
+//! | threshold          | 1                                 |
+//! | party_id           | 1                                 |
+//! | rank_list          | n                                 |
+//! | public_key         | 33, SEC1 compressed               |
+//! | has_chain_code     | 1, 0 or 1                         |
+//! | root_chain_code    | 32, zeros without a chain code    |
+//! | final_session_id   | 32                                |
+//! | seed_ot_receivers  | (n - 1) * size of ReceiverOTSeed  |
+//! | seed_ot_senders    | (n - 1) * size of SenderOTSeed    |
+//! | sent_seed_list     | (n - 1 - party_id) * 32           |
+//! | rec_seed_list      | party_id * 32                     |
+//! | s_i                | 32, big-endian                    |
+//! | big_s_list         | n * 33                            |
+//! | x_i_list           | n * 32                            |
+//! | refresh counters   | n * 4, big-endian                 |
+//!
+//! Version 2 has no `has_chain_code` byte, all key shares have a chain
+//! code. Version 1 also has no seed refresh counters, they are decoded
+//! as zeros.
+
+use std::mem;
+
+use k256::{
+    elliptic_curve::{group::GroupEncoding, PrimeField},
+    AffinePoint, NonZeroScalar, Scalar,
+};
+use sl_oblivious::soft_spoken::{ReceiverOTSeed, SenderOTSeed};
+
+use bytemuck::{AnyBitPattern, NoUninit};
+use zeroize::Zeroize;
+
+use crate::{
+    dkg::{zeta_seeds_by_party, Keyshare, KEYSHARE_COMPACT_VERSION},
+    error::KeyshareEncodingError,
+    utils::{POINT_SIZE, SCALAR_SIZE, ZS},
+};
+
+const COUNTER_SIZE: usize = 4;
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(
+        &mut self,
+        len: usize,
+    ) -> Result<&'a [u8], KeyshareEncodingError> {
+        if self.bytes.len() < len {
+            return Err(KeyshareEncodingError::InvalidLength);
+        }
+        let (head, tail) = self.bytes.split_at(len);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, KeyshareEncodingError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn array<const N: usize>(
+        &mut self,
+    ) -> Result<[u8; N], KeyshareEncodingError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn point(&mut self) -> Result<AffinePoint, KeyshareEncodingError> {
+        let bytes = self.array::<POINT_SIZE>()?;
+        Option::from(AffinePoint::from_bytes(&bytes.into()))
+            .ok_or(KeyshareEncodingError::InvalidData)
+    }
+
+    fn scalar(&mut self) -> Result<Scalar, KeyshareEncodingError> {
+        let bytes = self.array::<SCALAR_SIZE>()?;
+        Option::from(Scalar::from_repr(bytes.into()))
+            .ok_or(KeyshareEncodingError::InvalidData)
+    }
+
+    fn zs<T: AnyBitPattern + NoUninit>(
+        &mut self,
+    ) -> Result<ZS<T>, KeyshareEncodingError> {
+        let mut v = ZS::<T>::default();
+        bytemuck::bytes_of_mut(&mut *v)
+            .copy_from_slice(self.take(mem::size_of::<T>())?);
+        Ok(v)
+    }
+}
+
+impl Keyshare {
+    /// Exact size of a key share of a party of `n` participants in the
+    /// compact encoding. The size does not depend on the threshold nor
+    /// on the party ID.
+    pub fn compact_size(n: u8) -> usize {
+        let n = n as usize;
+        let seeds =
+            mem::size_of::<ReceiverOTSeed>() + mem::size_of::<SenderOTSeed>();
+
+        4 + n
+            + POINT_SIZE
+            + 1
+            + 32
+            + 32
+            + (n - 1) * (seeds + 32)
+            + SCALAR_SIZE
+            + n * (POINT_SIZE + SCALAR_SIZE + COUNTER_SIZE)
+    }
+
+    /// Encode the key share using compact fixed-layout encoding.
+    /// The output contains secret material.
+    pub fn to_compact_bytes(&self) -> Vec<u8> {
+        let mut out =
+            Vec::with_capacity(Self::compact_size(self.total_parties));
+
+        out.extend([
+            KEYSHARE_COMPACT_VERSION,
+            self.total_parties,
+            self.threshold,
+            self.party_id,
+        ]);
+        out.extend(&self.rank_list);
+        out.extend(self.public_key.to_bytes());
+        out.push(self.root_chain_code.is_some() as u8);
+        out.extend(self.root_chain_code.unwrap_or_default());
+        out.extend(self.final_session_id);
+        for seed in &self.seed_ot_receivers {
+            out.extend(bytemuck::bytes_of(&**seed));
+        }
+        for seed in &self.seed_ot_senders {
+            out.extend(bytemuck::bytes_of(&**seed));
+        }
+        // seeds of parties with greater IDs first
+        let others =
+            (self.party_id + 1..self.total_parties).chain(0..self.party_id);
+        for p in others {
+            out.extend(self.zeta_seed(p).unwrap_or(&[0; 32]));
+        }
+        out.extend(self.s_i.to_repr());
+        for p in &self.big_s_list {
+            out.extend(p.to_bytes());
+        }
+        for x in &self.x_i_list {
+            out.extend(x.to_repr());
+        }
+        for p in 0..self.total_parties {
+            out.extend(self.seed_refresh_counter(p).to_be_bytes());
+        }
+
+        debug_assert_eq!(out.len(), Self::compact_size(self.total_parties));
+
+        out
+    }
+
+    /// Decode a key share encoded by [`Keyshare::to_compact_bytes`].
+    pub fn from_compact_bytes(
+        bytes: &[u8],
+    ) -> Result<Self, KeyshareEncodingError> {
+        let mut r = Reader { bytes };
+
+        let version = r.u8()?;
+        if !(1..=KEYSHARE_COMPACT_VERSION).contains(&version) {
+            return Err(KeyshareEncodingError::UnsupportedVersion(version));
+        }
+
+        let total_parties = r.u8()?;
+        let threshold = r.u8()?;
+        let party_id = r.u8()?;
+
+        if total_parties < 2
+            || threshold < 2
+            || threshold > total_parties
+            || party_id >= total_parties
+        {
+            return Err(KeyshareEncodingError::InvalidData);
+        }
+
+        let n = total_parties as usize;
+
+        let counters = if version == 1 { 0 } else { n };
+        let flag = if version < 3 { 0 } else { 1 };
+        let size = Self::compact_size(total_parties)
+            - (n - counters) * COUNTER_SIZE
+            - (1 - flag);
+        if bytes.len() != size {
+            return Err(KeyshareEncodingError::InvalidLength);
+        }
+
+        let others = n - 1;
+        let sent = others - party_id as usize;
+
+        let rank_list = r.take(n)?.to_vec();
+        let public_key = r.point()?;
+        let has_chain_code = if version < 3 { 1 } else { r.u8()? };
+        let root_chain_code = match has_chain_code {
+            0 => {
+                r.array::<32>()?;
+                None
+            }
+            1 => Some(r.array()?),
+            _ => return Err(KeyshareEncodingError::InvalidData),
+        };
+        let final_session_id = r.array()?;
+        let seed_ot_receivers = (0..others)
+            .map(|_| r.zs::<ReceiverOTSeed>())
+            .collect::<Result<_, _>>()?;
+        let seed_ot_senders = (0..others)
+            .map(|_| r.zs::<SenderOTSeed>())
+            .collect::<Result<_, _>>()?;
+        let mut sent_seed_list: Vec<_> =
+            (0..sent).map(|_| r.array()).collect::<Result<_, _>>()?;
+        let mut rec_seed_list: Vec<_> = (sent..others)
+            .map(|_| r.array())
+            .collect::<Result<_, _>>()?;
+        let zeta_seeds =
+            zeta_seeds_by_party(party_id, &sent_seed_list, &rec_seed_list);
+        sent_seed_list.zeroize();
+        rec_seed_list.zeroize();
+        let s_i = r.scalar()?;
+        let big_s_list =
+            (0..n).map(|_| r.point()).collect::<Result<_, _>>()?;
+        let x_i_list = (0..n)
+            .map(|_| {
+                Option::from(NonZeroScalar::new(r.scalar()?))
+                    .ok_or(KeyshareEncodingError::InvalidData)
+            })
+            .collect::<Result<_, _>>()?;
+        let mut seed_refresh_counters = (0..counters)
+            .map(|_| r.array().map(u32::from_be_bytes))
+            .collect::<Result<Vec<_>, _>>()?;
+        seed_refresh_counters.resize(n, 0);
+
+        Ok(Keyshare {
+            total_parties,
+            threshold,
+            rank_list,
+            party_id,
+            public_key,
+            root_chain_code,
+            final_session_id,
+            seed_ot_receivers,
+            seed_ot_senders,
+            sent_seed_list: vec![],
+            rec_seed_list: vec![],
+            s_i,
+            big_s_list,
+            x_i_list,
+            seed_refresh_counters,
+            zeta_seeds,
+        })
+    }
+}
@@ -1,12 +1,27 @@
 // Copyright (c) Silence Laboratories Pte. Ltd. All Rights Reserved.
 // This software is licensed under the Silence Laboratories License Agreement.
 
+pub mod batch_keygen;
+pub mod batch_sign;
+pub mod derivation;
 pub mod dkg;
 pub mod dsg;
+pub mod entropy;
+pub mod eth;
+pub mod ot_setup;
+#[cfg(feature = "runner")]
+pub mod runner;
+pub mod seed_refresh;
+#[cfg(feature = "transport")]
+pub mod transport;
+pub mod tsg;
 
+mod base_ot;
+mod batch;
 mod constants;
 mod error;
+mod keyshare_compact;
 mod pairs;
 mod utils;
 
-pub const VERSION: u16 = 1;
+pub const VERSION: u16 = 2;
@@ -0,0 +1,381 @@
+// Copyright (c) Silence Laboratories Pte. Ltd. All Rights Reserved.
+// This software is licensed under the Silence Laboratories License Agreement.
+
+//! Base OT setup as a standalone protocol.
+//!
+//! Endemic OT and PPRF messages are the bulkiest part of a keygen.
+//! This protocol runs them once for a set of parties and returns
+//! [`OtSeeds`], pairwise OT seeds and zeta seeds of a party. Every
+//! following keygen of the same parties, with the same party IDs,
+//! could reuse them by [`dkg::State::with_ot_seeds`] and skip base
+//! OT. Signing sessions derive fresh OT extensions from the final
+//! session ID of a key, so sharing seeds between keys is safe, see
+//! also [`crate::batch_keygen`].
+//!
+//! The protocol has three rounds, like [`crate::seed_refresh`].
+
+use std::borrow::Borrow;
+
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use sl_oblivious::{
+    endemic_ot::{EndemicOTMsg1, EndemicOTMsg2, EndemicOTReceiver},
+    soft_spoken::{PPRFOutput, ReceiverOTSeed, SenderOTSeed},
+};
+
+use crate::{base_ot, dkg, pairs::*, utils::*};
+
+pub use crate::error::KeygenError;
+
+/// Broadcast message of the first round.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct OtSetupMsg1 {
+    pub from_id: u8,
+    session_id: [u8; 32],
+}
+
+/// P2P message of the second round.
+#[derive(Clone, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+pub struct OtSetupMsg2 {
+    pub from_id: u8,
+    pub to_id: u8,
+    ot: ZS<EndemicOTMsg1>,
+}
+
+/// P2P, encrypted message of the third round.
+#[derive(Clone, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+pub struct OtSetupMsg3 {
+    pub from_id: u8,
+    pub to_id: u8,
+    base_ot_msg2: ZS<EndemicOTMsg2>,
+    pprf_output: ZS<PPRFOutput>,
+    seed_i_j: Option<[u8; 32]>,
+}
+
+/// Pairwise OT seeds and zeta seeds of a party, the result of the
+/// protocol. Must be kept as secret as a key share.
+#[derive(Clone, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+pub struct OtSeeds {
+    /// Party ID of the owner
+    pub party_id: u8,
+    /// Total number of parties
+    pub total_parties: u8,
+
+    pub(crate) seed_ot_receivers: Vec<ZS<ReceiverOTSeed>>,
+    pub(crate) seed_ot_senders: Vec<ZS<SenderOTSeed>>,
+    pub(crate) sent_seed_list: Vec<[u8; 32]>,
+    pub(crate) rec_seed_list: Vec<[u8; 32]>,
+}
+
+/// Base OT setup session of a party.
+#[derive(Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+pub struct State {
+    party_id: u8,
+    total_parties: u8,
+    final_session_id: [u8; 32],
+    sid_list: Pairs<[u8; 32]>,
+    base_ot_receivers: Pairs<EndemicOTReceiver>,
+    seed_ot_receivers: Pairs<ZS<ReceiverOTSeed>>,
+    seed_ot_senders: Pairs<ZS<SenderOTSeed>>,
+    seed_i_j_list: Pairs<[u8; 32]>,
+    rec_seed_list: Pairs<[u8; 32]>,
+}
+
+impl State {
+    /// Create a session of party `party_id` out of `total_parties`.
+    pub fn new<R: RngCore + CryptoRng>(
+        rng: &mut R,
+        total_parties: u8,
+        party_id: u8,
+    ) -> Result<Self, KeygenError> {
+        if total_parties < 2 || party_id >= total_parties {
+            return Err(KeygenError::InvalidParameters);
+        }
+
+        let session_id: [u8; 32] = rng.gen();
+
+        Ok(Self {
+            party_id,
+            total_parties,
+            final_session_id: [0; 32],
+            sid_list: Pairs::new_with_item(party_id, session_id),
+            base_ot_receivers: Pairs::new(),
+            seed_ot_receivers: Pairs::new(),
+            seed_ot_senders: Pairs::new(),
+            seed_i_j_list: Pairs::new(),
+            rec_seed_list: Pairs::new(),
+        })
+    }
+
+    fn others(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..self.total_parties).filter(|p| *p != self.party_id)
+    }
+
+    fn check_sender(&self, from_id: u8) -> Result<(), KeygenError> {
+        if from_id == self.party_id || from_id >= self.total_parties {
+            return Err(KeygenError::InvalidSenderId(from_id));
+        }
+
+        Ok(())
+    }
+
+    fn check_batch_size(&self, len: usize) -> Result<(), KeygenError> {
+        if len != self.total_parties as usize - 1 {
+            return Err(KeygenError::MissingMessage);
+        }
+
+        Ok(())
+    }
+
+    pub fn generate_msg1(&self) -> OtSetupMsg1 {
+        OtSetupMsg1 {
+            from_id: self.party_id,
+            session_id: *self.sid_list.find_pair(self.party_id),
+        }
+    }
+
+    /// Round 1.
+    pub fn handle_msg1<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+        msgs: impl IntoIterator<Item = impl Borrow<OtSetupMsg1>>,
+    ) -> Result<Vec<OtSetupMsg2>, KeygenError> {
+        borrow_msgs!(msgs: OtSetupMsg1);
+
+        self.check_batch_size(msgs.len())?;
+
+        for msg in msgs {
+            self.check_sender(msg.from_id)?;
+            if self.sid_list.iter().any(|(p, _)| *p == msg.from_id) {
+                return Err(KeygenError::DuplicateMessage(msg.from_id));
+            }
+            self.sid_list.push(msg.from_id, msg.session_id);
+        }
+
+        self.final_session_id = hash_ot_setup_final_session_id(
+            self.sid_list.iter().map(|(p, sid)| (*p, sid)),
+        );
+
+        let party_id = self.party_id;
+        let mut output = vec![];
+
+        self.base_ot_receivers = self
+            .others()
+            .map(|p| {
+                let (receiver, msg1) =
+                    base_ot::start(&self.final_session_id, party_id, p, rng);
+
+                output.push(OtSetupMsg2 {
+                    from_id: party_id,
+                    to_id: p,
+                    ot: msg1,
+                });
+
+                (p, receiver)
+            })
+            .collect::<Vec<_>>()
+            .into();
+
+        Ok(output)
+    }
+
+    /// Round 2.
+    pub fn handle_msg2<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+        msgs: impl IntoIterator<Item = impl Borrow<OtSetupMsg2>>,
+    ) -> Result<Vec<OtSetupMsg3>, KeygenError> {
+        borrow_msgs!(msgs: OtSetupMsg2);
+
+        self.check_batch_size(msgs.len())?;
+
+        let party_id = self.party_id;
+
+        msgs.into_iter()
+            .map(|msg| {
+                self.check_sender(msg.from_id)?;
+                if msg.to_id != party_id {
+                    return Err(KeygenError::InvalidRecipientId(msg.from_id));
+                }
+                if self.seed_ot_senders.iter().any(|(p, _)| *p == msg.from_id)
+                {
+                    return Err(KeygenError::DuplicateMessage(msg.from_id));
+                }
+
+                let (base_ot_msg2, pprf_output, sender_seed) =
+                    base_ot::reply(
+                        &self.final_session_id,
+                        party_id,
+                        msg.from_id,
+                        &msg.ot,
+                        rng,
+                    )?;
+
+                self.seed_ot_senders.push(msg.from_id, sender_seed);
+
+                let seed_i_j = if msg.from_id > party_id {
+                    let seed_i_j = rng.gen();
+                    self.seed_i_j_list.push(msg.from_id, seed_i_j);
+                    Some(seed_i_j)
+                } else {
+                    None
+                };
+
+                Ok(OtSetupMsg3 {
+                    from_id: party_id,
+                    to_id: msg.from_id,
+                    base_ot_msg2,
+                    pprf_output,
+                    seed_i_j,
+                })
+            })
+            .collect()
+    }
+
+    /// Round 3. Returns OT seeds of the party.
+    pub fn handle_msg3(
+        mut self,
+        msgs: impl IntoIterator<Item = impl Borrow<OtSetupMsg3>>,
+    ) -> Result<OtSeeds, KeygenError> {
+        borrow_msgs!(msgs: OtSetupMsg3);
+
+        self.check_batch_size(msgs.len())?;
+
+        let party_id = self.party_id;
+
+        for msg in msgs {
+            let ban = || KeygenError::AbortProtocolAndBanParty(msg.from_id);
+
+            self.check_sender(msg.from_id)?;
+            if msg.to_id != party_id {
+                return Err(KeygenError::InvalidRecipientId(msg.from_id));
+            }
+
+            // the seed is sent by the party with the lower id
+            if msg.seed_i_j.is_some() != (msg.from_id < party_id) {
+                return Err(ban());
+            }
+
+            let receiver = self.base_ot_receivers.pop_pair_or_err(
+                msg.from_id,
+                KeygenError::DuplicateMessage(msg.from_id),
+            )?;
+            let receiver_seed = base_ot::finish(
+                &self.final_session_id,
+                party_id,
+                msg.from_id,
+                receiver,
+                &msg.base_ot_msg2,
+                &msg.pprf_output,
+            )?;
+
+            self.seed_ot_receivers.push(msg.from_id, receiver_seed);
+            if let Some(seed_j_i) = msg.seed_i_j {
+                self.rec_seed_list.push(msg.from_id, seed_j_i);
+            }
+        }
+
+        Ok(OtSeeds {
+            party_id,
+            total_parties: self.total_parties,
+            seed_ot_receivers: self.seed_ot_receivers.remove_ids(),
+            seed_ot_senders: self.seed_ot_senders.remove_ids(),
+            sent_seed_list: self.seed_i_j_list.remove_ids(),
+            rec_seed_list: self.rec_seed_list.remove_ids(),
+        })
+    }
+}
+
+impl OtSeeds {
+    /// Copy the seeds into a key share generated without base OT.
+    pub(crate) fn apply(&self, share: &mut dkg::Keyshare) {
+        share.seed_ot_receivers = self.seed_ot_receivers.clone();
+        share.seed_ot_senders = self.seed_ot_senders.clone();
+        share.sent_seed_list.zeroize();
+        share.rec_seed_list.zeroize();
+        share.zeta_seeds = dkg::zeta_seeds_by_party(
+            self.party_id,
+            &self.sent_seed_list,
+            &self.rec_seed_list,
+        );
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    use crate::dkg::{
+        tests::{dkg_inner, init_states},
+        Keyshare,
+    };
+
+    /// Run the OT setup, returns seeds of each party.
+    pub fn ot_setup(n: u8) -> Vec<OtSeeds> {
+        let mut rng = rand::thread_rng();
+
+        let mut parties = (0..n)
+            .map(|id| State::new(&mut rng, n, id).unwrap())
+            .collect::<Vec<_>>();
+
+        let msg1: Vec<_> =
+            parties.iter().map(|p| p.generate_msg1()).collect();
+        let msg2 = parties.iter_mut().fold(vec![], |mut out, p| {
+            let party_id = p.party_id;
+            let batch = msg1.iter().filter(|m| m.from_id != party_id);
+            out.extend(p.handle_msg1(&mut rng, batch).unwrap());
+            out
+        });
+        let msg3 = parties.iter_mut().fold(vec![], |mut out, p| {
+            let party_id = p.party_id;
+            let batch = msg2.iter().filter(|m| m.to_id == party_id);
+            out.extend(p.handle_msg2(&mut rng, batch).unwrap());
+            out
+        });
+
+        parties
+            .into_iter()
+            .map(|p| {
+                let party_id = p.party_id;
+                let batch = msg3.iter().filter(|m| m.to_id == party_id);
+                p.handle_msg3(batch).unwrap()
+            })
+            .collect()
+    }
+
+    /// Run a keygen reusing OT seeds of a setup.
+    pub fn dkg_with_seeds(seeds: &[OtSeeds], t: u8) -> Vec<Keyshare> {
+        let parties = init_states(&vec![0; seeds.len()], t)
+            .into_iter()
+            .zip(seeds)
+            .map(|(p, s)| p.with_ot_seeds(s.clone()).unwrap())
+            .collect();
+
+        dkg_inner(parties)
+    }
+
+    #[test]
+    fn keygen_with_ot_seeds() {
+        let seeds = ot_setup(3);
+
+        let a = dkg_with_seeds(&seeds, 2);
+        let b = dkg_with_seeds(&seeds, 3);
+        assert_ne!(a[0].public_key, b[0].public_key);
+        assert_eq!(a[0].zeta_seed(2), b[0].zeta_seed(2));
+        assert_eq!(a[2].zeta_seed(1), seeds[2].rec_seed_list.get(1));
+
+        let mut parties = init_states(&[0; 3], 2);
+        assert!(matches!(
+            parties.remove(0).with_ot_seeds(seeds[1].clone()),
+            Err(KeygenError::InvalidOtSeeds)
+        ));
+
+        let mut rng = rand::thread_rng();
+        assert!(matches!(
+            State::new(&mut rng, 3, 3),
+            Err(KeygenError::InvalidParameters)
+        ));
+    }
+}
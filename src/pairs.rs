@@ -14,6 +14,7 @@ impl<T: Zeroize, I> Zeroize for Pairs<T, I> {
         for (_, v) in &mut self.0 {
             v.zeroize()
         }
+        self.0.clear();
     }
 }
 
@@ -48,6 +49,10 @@ impl<T, I: Ord> Pairs<T, I> {
         self.0.len()
     }
 
+    pub fn capacity(&self) -> usize {
+        self.0.capacity()
+    }
+
     pub fn new_with_item(id: I, value: T) -> Self {
         Self(vec![(id, value)])
     }
@@ -0,0 +1,353 @@
+// Copyright (c) Silence Laboratories Pte. Ltd. All Rights Reserved.
+// This software is licensed under the Silence Laboratories License Agreement.
+
+//! Async drivers running all rounds of keygen and signing over a
+//! user supplied [`Transport`].
+//!
+//! The drivers only sequence the rounds: they send own messages of a
+//! round, wait for the messages of all other parties and pass them to
+//! the round handler. Delivery, authentication and encryption of the
+//! messages are up to the transport.
+
+use std::future::Future;
+
+use k256::ecdsa::{RecoveryId, Signature};
+use rand::prelude::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    dkg::{self, KeygenMsg1, KeygenMsg2, KeygenMsg3, KeygenMsg4, Keyshare},
+    dsg::{self, SignMsg1, SignMsg2, SignMsg3, SignMsg4},
+};
+
+pub use crate::error::RunnerError;
+
+/// Message exchange of a party with other parties of a session.
+///
+/// Messages of a session are tagged by the round number, starting
+/// from 1. A transport must deliver every message sent by another
+/// party to this party exactly once, and keep messages of future
+/// rounds received from faster parties until they are requested.
+pub trait Transport {
+    type Error;
+
+    /// Send a message of `round` to party `to`, or to all other
+    /// parties if `to` is `None`.
+    fn send<M: Serialize>(
+        &mut self,
+        round: u8,
+        to: Option<u8>,
+        msg: &M,
+    ) -> impl Future<Output = Result<(), Self::Error>>;
+
+    /// Receive the next message of `round` sent to this party.
+    fn recv<M: DeserializeOwned>(
+        &mut self,
+        round: u8,
+    ) -> impl Future<Output = Result<M, Self::Error>>;
+}
+
+/// Commitment to the chain code of a party, broadcast after the first
+/// keygen round.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CommitmentMsg {
+    pub from_id: u8,
+    pub commitment: [u8; 32],
+}
+
+/// Recipient of a protocol message, `None` for a broadcast.
+trait Routing {
+    fn recipient(&self) -> Option<u8>;
+}
+
+macro_rules! routing {
+    (broadcast: $($b:ty),*; p2p: $($p:ty),*) => {
+        $(impl Routing for $b {
+            fn recipient(&self) -> Option<u8> { None }
+        })*
+        $(impl Routing for $p {
+            fn recipient(&self) -> Option<u8> { Some(self.to_id) }
+        })*
+    };
+}
+
+routing!(
+    broadcast: KeygenMsg1, CommitmentMsg, KeygenMsg4, SignMsg1, SignMsg4;
+    p2p: KeygenMsg2, KeygenMsg3, SignMsg2, SignMsg3
+);
+
+/// Send own messages of `round` and receive `expected` messages of the
+/// same round from other parties.
+async fn exchange<T, I, O>(
+    transport: &mut T,
+    round: u8,
+    out: &[I],
+    expected: usize,
+) -> Result<Vec<O>, T::Error>
+where
+    T: Transport,
+    I: Serialize + Routing,
+    O: DeserializeOwned,
+{
+    for msg in out {
+        transport.send(round, msg.recipient(), msg).await?;
+    }
+
+    let mut input = Vec::with_capacity(expected);
+    for _ in 0..expected {
+        input.push(transport.recv(round).await?);
+    }
+
+    Ok(input)
+}
+
+/// Run all rounds of a keygen and return the key share of the party.
+pub async fn run_keygen<R, T>(
+    rng: &mut R,
+    mut state: dkg::State,
+    transport: &mut T,
+) -> Result<Keyshare, RunnerError<T::Error>>
+where
+    R: RngCore + CryptoRng,
+    T: Transport,
+{
+    let n = state.expected_message_count();
+    let party_id = state.party_id();
+    let t = RunnerError::Transport;
+
+    let msg1: Vec<KeygenMsg1> =
+        exchange(transport, 1, &[state.generate_msg1()], n)
+            .await
+            .map_err(t)?;
+    let msg2 = state.handle_msg1(rng, msg1)?;
+
+    let own = CommitmentMsg {
+        from_id: party_id,
+        commitment: state.calculate_commitment_2(),
+    };
+    let mut commitments: Vec<CommitmentMsg> =
+        exchange(transport, 2, std::slice::from_ref(&own), n)
+            .await
+            .map_err(t)?;
+    commitments.push(own);
+    commitments.sort_by_key(|c| c.from_id);
+    let commitments: Vec<[u8; 32]> =
+        commitments.into_iter().map(|c| c.commitment).collect();
+
+    let msg2: Vec<KeygenMsg2> =
+        exchange(transport, 3, &msg2, n).await.map_err(t)?;
+    let msg3 = state.handle_msg2(rng, msg2)?;
+
+    let msg3: Vec<KeygenMsg3> =
+        exchange(transport, 4, &msg3, n).await.map_err(t)?;
+    let msg4 = state.handle_msg3(rng, msg3, &commitments)?;
+
+    let msg4: Vec<KeygenMsg4> =
+        exchange(transport, 5, &[msg4], n).await.map_err(t)?;
+
+    Ok(state.handle_msg4(msg4)?)
+}
+
+/// Run all rounds of a signing session and return the signature of
+/// the message hash together with its recovery ID.
+pub async fn run_sign<R, T>(
+    rng: &mut R,
+    mut state: dsg::State,
+    hash: [u8; 32],
+    transport: &mut T,
+) -> Result<(Signature, RecoveryId), RunnerError<T::Error>>
+where
+    R: RngCore + CryptoRng,
+    T: Transport,
+{
+    let n = state.keyshare.threshold as usize - 1;
+    let t = RunnerError::Transport;
+
+    let msg1: Vec<SignMsg1> =
+        exchange(transport, 1, &[state.generate_msg1()?], n)
+            .await
+            .map_err(t)?;
+    let msg2 = state.handle_msg1(rng, msg1)?;
+
+    let msg2: Vec<SignMsg2> =
+        exchange(transport, 2, &msg2, n).await.map_err(t)?;
+    let msg3 = state.handle_msg2(rng, msg2)?;
+
+    let msg3: Vec<SignMsg3> =
+        exchange(transport, 3, &msg3, n).await.map_err(t)?;
+    let pre = state.handle_msg3(msg3)?;
+
+    let (partial, msg4) = dsg::create_partial_signature(pre, hash)?;
+    let msg4: Vec<SignMsg4> =
+        exchange(transport, 4, &[msg4], n).await.map_err(t)?;
+
+    Ok(dsg::combine_signatures(partial, msg4)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        pin::pin,
+        str::FromStr,
+        sync::mpsc::{channel, Receiver, Sender},
+        task::{Context, Poll, Waker},
+        thread,
+    };
+
+    use derivation_path::DerivationPath;
+    use k256::ecdsa::{signature::hazmat::PrehashVerifier, VerifyingKey};
+
+    use super::*;
+
+    use crate::dkg::Party;
+
+    struct Frame {
+        round: u8,
+        payload: Vec<u8>,
+    }
+
+    /// In-memory transport of one party, messages are CBOR encoded.
+    struct Channel {
+        party_id: u8,
+        peers: Vec<(u8, Sender<Frame>)>,
+        inbox: Receiver<Frame>,
+        stash: Vec<Frame>,
+    }
+
+    #[derive(Debug)]
+    struct Disconnected;
+
+    impl Transport for Channel {
+        type Error = Disconnected;
+
+        async fn send<M: Serialize>(
+            &mut self,
+            round: u8,
+            to: Option<u8>,
+            msg: &M,
+        ) -> Result<(), Disconnected> {
+            let mut payload = vec![];
+            ciborium::into_writer(msg, &mut payload).unwrap();
+
+            for (id, peer) in &self.peers {
+                if *id != self.party_id && to.unwrap_or(*id) == *id {
+                    let payload = payload.clone();
+                    peer.send(Frame { round, payload })
+                        .map_err(|_| Disconnected)?;
+                }
+            }
+
+            Ok(())
+        }
+
+        async fn recv<M: DeserializeOwned>(
+            &mut self,
+            round: u8,
+        ) -> Result<M, Disconnected> {
+            let frame = match self.stash.iter().position(|f| f.round == round)
+            {
+                Some(idx) => self.stash.remove(idx),
+                None => loop {
+                    let frame =
+                        self.inbox.recv().map_err(|_| Disconnected)?;
+                    if frame.round == round {
+                        break frame;
+                    }
+                    self.stash.push(frame);
+                },
+            };
+
+            Ok(ciborium::from_reader(frame.payload.as_slice()).unwrap())
+        }
+    }
+
+    fn channels(ids: &[u8]) -> Vec<Channel> {
+        let (senders, inboxes): (Vec<_>, Vec<_>) =
+            ids.iter().map(|_| channel()).unzip();
+        let peers: Vec<_> = ids.iter().copied().zip(senders).collect();
+
+        ids.iter()
+            .zip(inboxes)
+            .map(|(&party_id, inbox)| Channel {
+                party_id,
+                peers: peers.clone(),
+                inbox,
+                stash: vec![],
+            })
+            .collect()
+    }
+
+    /// Transports of the tests are always ready, a future is polled
+    /// until it completes.
+    fn block_on<F: Future>(fut: F) -> F::Output {
+        let mut fut = pin!(fut);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(out) = fut.as_mut().poll(&mut cx) {
+                return out;
+            }
+        }
+    }
+
+    #[test]
+    fn run_keygen_and_sign() {
+        let ids = [0, 1, 2];
+        let handles = channels(&ids)
+            .into_iter()
+            .map(|mut channel| {
+                thread::spawn(move || {
+                    let mut rng = rand::thread_rng();
+                    let party =
+                        Party::new(3, 2, channel.party_id as usize).unwrap();
+                    let state = dkg::State::new(party, &mut rng).unwrap();
+                    block_on(run_keygen(&mut rng, state, &mut channel))
+                        .unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+        let shares = handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .collect::<Vec<_>>();
+
+        let hash = [7; 32];
+        let chain_path = DerivationPath::from_str("m/3").unwrap();
+        let handles = channels(&[0, 2])
+            .into_iter()
+            .map(|mut channel| {
+                let share = shares[channel.party_id as usize].clone();
+                let chain_path = chain_path.clone();
+                thread::spawn(move || {
+                    let mut rng = rand::thread_rng();
+                    let state = dsg::State::new(&mut rng, share, &chain_path)
+                        .unwrap();
+                    block_on(run_sign(&mut rng, state, hash, &mut channel))
+                        .unwrap()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let public_key = shares[1].derived_public_key(&chain_path).unwrap();
+        let vk = VerifyingKey::from_affine(public_key).unwrap();
+        for h in handles {
+            let (sign, _) = h.join().unwrap();
+            vk.verify_prehash(&hash, &sign).unwrap();
+        }
+    }
+
+    #[test]
+    fn transport_error() {
+        let mut channels = channels(&[0, 1]);
+        drop(channels.pop());
+
+        let mut rng = rand::thread_rng();
+        let state =
+            dkg::State::new(Party::new(2, 2, 0).unwrap(), &mut rng).unwrap();
+        let mut channel = channels.pop().unwrap();
+
+        assert!(matches!(
+            block_on(run_keygen(&mut rng, state, &mut channel)),
+            Err(RunnerError::Transport(Disconnected))
+        ));
+    }
+}
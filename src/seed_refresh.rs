@@ -0,0 +1,510 @@
+// Copyright (c) Silence Laboratories Pte. Ltd. All Rights Reserved.
+// This software is licensed under the Silence Laboratories License Agreement.
+
+//! Refresh of pairwise OT seeds and zeta seeds of key shares.
+//!
+//! Parties of a key share run base OT and exchange new randomization
+//! seeds again, for every pair of participants. The secret share
+//! `s_i`, the public key and the root chain code stay the same, so
+//! this protocol is much cheaper than a key rotation and could be
+//! run by a subset of parties, for example by two parties after a
+//! suspected compromise of OT seeds of one of them.
+//!
+//! Every key share counts refreshes with each other party. Parties
+//! exchange their counters in the first round and bind them into the
+//! final session ID, a pair of parties with different counters, for
+//! example one of them restored an old backup, fails with
+//! [`KeygenError::SeedRefreshCounterMismatch`]. Counters of refreshed
+//! pairs are incremented in the last round.
+//!
+//! The protocol has three rounds. A party should replace its key
+//! share only after all participants finished the protocol; if any
+//! of them fails, all participants must keep their old key shares.
+
+use std::borrow::Borrow;
+
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use sl_oblivious::{
+    endemic_ot::{EndemicOTMsg1, EndemicOTMsg2, EndemicOTReceiver},
+    soft_spoken::{PPRFOutput, SenderOTSeed},
+};
+
+use crate::{base_ot, dkg::Keyshare, pairs::*, utils::*};
+
+pub use crate::error::KeygenError;
+
+/// Broadcast message of the first round.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SeedRefreshMsg1 {
+    pub from_id: u8,
+    session_id: [u8; 32],
+    /// refresh counters of the sender with all participants
+    counters: Vec<u32>,
+}
+
+/// P2P message of the second round.
+#[derive(Clone, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+pub struct SeedRefreshMsg2 {
+    pub from_id: u8,
+    pub to_id: u8,
+    ot: ZS<EndemicOTMsg1>,
+}
+
+/// P2P, encrypted message of the third round.
+#[derive(Clone, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+pub struct SeedRefreshMsg3 {
+    pub from_id: u8,
+    pub to_id: u8,
+    base_ot_msg2: ZS<EndemicOTMsg2>,
+    pprf_output: ZS<PPRFOutput>,
+    seed_i_j: Option<[u8; 32]>,
+}
+
+/// Seed refresh session of a party.
+#[derive(Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+pub struct State {
+    keyshare: Keyshare,
+    participants: Vec<u8>,
+    final_session_id: [u8; 32],
+    sid_list: Pairs<[u8; 32]>,
+    counter_list: Pairs<Vec<u32>>,
+    base_ot_receivers: Pairs<EndemicOTReceiver>,
+    seed_ot_senders: Pairs<ZS<SenderOTSeed>>,
+    seed_i_j_list: Pairs<[u8; 32]>,
+}
+
+impl State {
+    /// Create a session refreshing seeds shared with all other
+    /// `participants`. All participants must pass the same list.
+    ///
+    /// Returns [`KeygenError::InvalidParameters`] unless the list has
+    /// at least two distinct valid party IDs including the own one.
+    pub fn new<R: RngCore + CryptoRng>(
+        rng: &mut R,
+        keyshare: Keyshare,
+        participants: &[u8],
+    ) -> Result<Self, KeygenError> {
+        let mut participants = participants.to_vec();
+        participants.sort_unstable();
+
+        let cond = participants.len() < 2
+            || participants.windows(2).any(|w| w[0] == w[1])
+            || participants.iter().any(|p| *p >= keyshare.total_parties)
+            || !participants.contains(&keyshare.party_id);
+        if cond {
+            return Err(KeygenError::InvalidParameters);
+        }
+
+        let party_id = keyshare.party_id;
+        let session_id: [u8; 32] = rng.gen();
+        let counters = participants
+            .iter()
+            .map(|p| keyshare.seed_refresh_counter(*p))
+            .collect();
+
+        Ok(Self {
+            keyshare,
+            participants,
+            final_session_id: [0; 32],
+            sid_list: Pairs::new_with_item(party_id, session_id),
+            counter_list: Pairs::new_with_item(party_id, counters),
+            base_ot_receivers: Pairs::new(),
+            seed_ot_senders: Pairs::new(),
+            seed_i_j_list: Pairs::new(),
+        })
+    }
+
+    fn others(&self) -> impl Iterator<Item = u8> + '_ {
+        let party_id = self.keyshare.party_id;
+        self.participants
+            .iter()
+            .copied()
+            .filter(move |p| *p != party_id)
+    }
+
+    fn check_sender(&self, from_id: u8) -> Result<(), KeygenError> {
+        if from_id == self.keyshare.party_id
+            || !self.participants.contains(&from_id)
+        {
+            return Err(KeygenError::InvalidSenderId(from_id));
+        }
+
+        Ok(())
+    }
+
+    pub fn generate_msg1(&self) -> SeedRefreshMsg1 {
+        SeedRefreshMsg1 {
+            from_id: self.keyshare.party_id,
+            session_id: *self.sid_list.find_pair(self.keyshare.party_id),
+            counters: self
+                .counter_list
+                .find_pair(self.keyshare.party_id)
+                .clone(),
+        }
+    }
+
+    /// Round 1.
+    pub fn handle_msg1<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+        msgs: impl IntoIterator<Item = impl Borrow<SeedRefreshMsg1>>,
+    ) -> Result<Vec<SeedRefreshMsg2>, KeygenError> {
+        borrow_msgs!(msgs: SeedRefreshMsg1);
+
+        if msgs.len() != self.participants.len() - 1 {
+            return Err(KeygenError::MissingMessage);
+        }
+
+        let party_id = self.keyshare.party_id;
+        let own_idx = self
+            .participants
+            .iter()
+            .position(|p| *p == party_id)
+            .unwrap();
+
+        for msg in msgs {
+            self.check_sender(msg.from_id)?;
+            if self.sid_list.iter().any(|(p, _)| *p == msg.from_id) {
+                return Err(KeygenError::DuplicateMessage(msg.from_id));
+            }
+            if msg.counters.len() != self.participants.len() {
+                return Err(KeygenError::AbortProtocolAndBanParty(
+                    msg.from_id,
+                ));
+            }
+            if msg.counters[own_idx]
+                != self.keyshare.seed_refresh_counter(msg.from_id)
+            {
+                return Err(KeygenError::SeedRefreshCounterMismatch(
+                    msg.from_id,
+                ));
+            }
+            self.sid_list.push(msg.from_id, msg.session_id);
+            self.counter_list.push(msg.from_id, msg.counters.clone());
+        }
+
+        self.final_session_id = hash_seed_refresh_final_session_id(
+            self.sid_list.iter().map(|(p, sid)| (*p, sid)),
+            self.counter_list.iter().map(|(p, c)| (*p, c.as_slice())),
+            &self.keyshare.final_session_id,
+        );
+
+        let mut output = vec![];
+
+        self.base_ot_receivers = self
+            .others()
+            .map(|p| {
+                let (receiver, msg1) =
+                    base_ot::start(&self.final_session_id, party_id, p, rng);
+
+                output.push(SeedRefreshMsg2 {
+                    from_id: party_id,
+                    to_id: p,
+                    ot: msg1,
+                });
+
+                (p, receiver)
+            })
+            .collect::<Vec<_>>()
+            .into();
+
+        Ok(output)
+    }
+
+    /// Round 2.
+    pub fn handle_msg2<R: RngCore + CryptoRng>(
+        &mut self,
+        rng: &mut R,
+        msgs: impl IntoIterator<Item = impl Borrow<SeedRefreshMsg2>>,
+    ) -> Result<Vec<SeedRefreshMsg3>, KeygenError> {
+        borrow_msgs!(msgs: SeedRefreshMsg2);
+
+        if msgs.len() != self.participants.len() - 1 {
+            return Err(KeygenError::MissingMessage);
+        }
+
+        let party_id = self.keyshare.party_id;
+
+        msgs.into_iter()
+            .map(|msg| {
+                self.check_sender(msg.from_id)?;
+                if msg.to_id != party_id {
+                    return Err(KeygenError::InvalidRecipientId(msg.from_id));
+                }
+                if self.seed_ot_senders.iter().any(|(p, _)| *p == msg.from_id)
+                {
+                    return Err(KeygenError::DuplicateMessage(msg.from_id));
+                }
+
+                let (base_ot_msg2, pprf_output, sender_seed) =
+                    base_ot::reply(
+                        &self.final_session_id,
+                        party_id,
+                        msg.from_id,
+                        &msg.ot,
+                        rng,
+                    )?;
+
+                self.seed_ot_senders.push(msg.from_id, sender_seed);
+
+                let seed_i_j = if msg.from_id > party_id {
+                    let seed_i_j = rng.gen();
+                    self.seed_i_j_list.push(msg.from_id, seed_i_j);
+                    Some(seed_i_j)
+                } else {
+                    None
+                };
+
+                Ok(SeedRefreshMsg3 {
+                    from_id: party_id,
+                    to_id: msg.from_id,
+                    base_ot_msg2,
+                    pprf_output,
+                    seed_i_j,
+                })
+            })
+            .collect()
+    }
+
+    /// Round 3. Returns the key share with refreshed seeds.
+    pub fn handle_msg3(
+        mut self,
+        msgs: impl IntoIterator<Item = impl Borrow<SeedRefreshMsg3>>,
+    ) -> Result<Keyshare, KeygenError> {
+        borrow_msgs!(msgs: SeedRefreshMsg3);
+
+        if msgs.len() != self.participants.len() - 1 {
+            return Err(KeygenError::MissingMessage);
+        }
+
+        let party_id = self.keyshare.party_id;
+        let mut keyshare = self.keyshare.clone();
+        keyshare
+            .seed_refresh_counters
+            .resize(keyshare.total_parties as usize, 0);
+        keyshare.migrate_zeta_seeds();
+
+        for msg in msgs {
+            self.check_sender(msg.from_id)?;
+            if msg.to_id != party_id {
+                return Err(KeygenError::InvalidRecipientId(msg.from_id));
+            }
+
+            // the seed is sent by the party with the lower id
+            if msg.seed_i_j.is_some() != (msg.from_id < party_id) {
+                return Err(KeygenError::AbortProtocolAndBanParty(
+                    msg.from_id,
+                ));
+            }
+
+            let receiver = self.base_ot_receivers.pop_pair_or_err(
+                msg.from_id,
+                KeygenError::DuplicateMessage(msg.from_id),
+            )?;
+            let idx = get_idx_from_id(party_id, msg.from_id) as usize;
+
+            keyshare.seed_ot_receivers[idx] = base_ot::finish(
+                &self.final_session_id,
+                party_id,
+                msg.from_id,
+                receiver,
+                &msg.base_ot_msg2,
+                &msg.pprf_output,
+            )?;
+            keyshare.seed_ot_senders[idx] =
+                self.seed_ot_senders.find_pair(msg.from_id).clone();
+            keyshare.seed_refresh_counters[msg.from_id as usize] += 1;
+
+            let seed = match msg.seed_i_j {
+                Some(seed_j_i) => seed_j_i,
+                None => *self.seed_i_j_list.find_pair(msg.from_id),
+            };
+            let _ = keyshare.zeta_seeds.pop_pair_or_err(msg.from_id, ());
+            keyshare.zeta_seeds.push(msg.from_id, seed);
+        }
+
+        Ok(keyshare)
+    }
+}
+
+impl Keyshare {
+    /// Number of seed refreshes of this party with party `party_id`.
+    pub fn seed_refresh_counter(&self, party_id: u8) -> u32 {
+        self.seed_refresh_counters
+            .get(party_id as usize)
+            .copied()
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use derivation_path::DerivationPath;
+
+    use super::*;
+
+    use crate::{dkg::tests::dkg, dsg};
+
+    fn sign(shares: &[Keyshare]) {
+        let mut rng = rand::thread_rng();
+        let chain_path = DerivationPath::from_str("m").unwrap();
+
+        let mut parties = shares
+            .iter()
+            .map(|s| {
+                dsg::State::new(&mut rng, s.clone(), &chain_path).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let msg1: Vec<_> = parties
+            .iter_mut()
+            .map(|p| p.generate_msg1().unwrap())
+            .collect();
+        let msg2 = parties.iter_mut().fold(vec![], |mut out, p| {
+            let party_id = p.keyshare.party_id;
+            let batch = msg1.iter().filter(|m| m.from_id != party_id);
+            out.extend(p.handle_msg1(&mut rng, batch).unwrap());
+            out
+        });
+        let msg3 = parties.iter_mut().fold(vec![], |mut out, p| {
+            let party_id = p.keyshare.party_id;
+            let batch = msg2.iter().filter(|m| m.to_id == party_id);
+            out.extend(p.handle_msg2(&mut rng, batch).unwrap());
+            out
+        });
+        let (partials, msg4): (Vec<_>, Vec<_>) = parties
+            .iter_mut()
+            .map(|p| {
+                let party_id = p.keyshare.party_id;
+                let batch = msg3.iter().filter(|m| m.to_id == party_id);
+                let pre = p.handle_msg3(batch).unwrap();
+                dsg::create_partial_signature(pre, [1; 32]).unwrap()
+            })
+            .unzip();
+
+        for p in partials {
+            let batch: Vec<_> = msg4
+                .iter()
+                .filter(|m| m.from_id != p.party_id)
+                .cloned()
+                .collect();
+            dsg::combine_signatures(p, batch).unwrap();
+        }
+    }
+
+    fn refresh(shares: &[Keyshare], participants: &[u8]) -> Vec<Keyshare> {
+        let mut rng = rand::thread_rng();
+
+        let mut parties = participants
+            .iter()
+            .map(|p| {
+                State::new(
+                    &mut rng,
+                    shares[*p as usize].clone(),
+                    participants,
+                )
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let msg1: Vec<_> =
+            parties.iter().map(|p| p.generate_msg1()).collect();
+        let msg2 = parties.iter_mut().fold(vec![], |mut out, p| {
+            let party_id = p.keyshare.party_id;
+            let batch = msg1.iter().filter(|m| m.from_id != party_id);
+            out.extend(p.handle_msg1(&mut rng, batch).unwrap());
+            out
+        });
+        let msg3 = parties.iter_mut().fold(vec![], |mut out, p| {
+            let party_id = p.keyshare.party_id;
+            let batch = msg2.iter().filter(|m| m.to_id == party_id);
+            out.extend(p.handle_msg2(&mut rng, batch).unwrap());
+            out
+        });
+
+        let mut new_shares = shares.to_vec();
+        for p in parties {
+            let party_id = p.keyshare.party_id;
+            let batch = msg3.iter().filter(|m| m.to_id == party_id);
+            new_shares[party_id as usize] = p.handle_msg3(batch).unwrap();
+        }
+
+        new_shares
+    }
+
+    #[test]
+    fn refresh_pair_and_all() {
+        let shares = dkg(3, 2);
+
+        let pair = refresh(&shares, &[0, 2]);
+        assert_eq!(pair[1].zeta_seed(2), shares[1].zeta_seed(2));
+        assert_ne!(pair[0].zeta_seed(2), shares[0].zeta_seed(2));
+        assert_eq!(pair[0].s_i, shares[0].s_i);
+        sign(&[pair[0].clone(), pair[2].clone()]);
+        sign(&[pair[0].clone(), pair[1].clone()]);
+
+        let all = refresh(&pair, &[0, 1, 2]);
+        assert_eq!(all[1].public_key, shares[1].public_key);
+        sign(&all[..2]);
+        sign(&all[1..]);
+
+        assert_eq!(all[0].seed_refresh_counter(2), 2);
+        assert_eq!(all[0].seed_refresh_counter(1), 1);
+        assert_eq!(all[2].seed_refresh_counter(0), 2);
+    }
+
+    #[test]
+    fn reject_invalid_participants_and_senders() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(3, 2);
+
+        for participants in [&[0][..], &[0, 0, 1], &[0, 3], &[1, 2]] {
+            assert!(matches!(
+                State::new(&mut rng, shares[0].clone(), participants),
+                Err(KeygenError::InvalidParameters)
+            ));
+        }
+
+        let mut party =
+            State::new(&mut rng, shares[0].clone(), &[0, 1]).unwrap();
+        let other = State::new(&mut rng, shares[2].clone(), &[0, 2]).unwrap();
+        assert!(matches!(
+            party.handle_msg1(&mut rng, [other.generate_msg1()]),
+            Err(KeygenError::InvalidSenderId(2))
+        ));
+
+        let mut party =
+            State::new(&mut rng, shares[0].clone(), &[0, 1, 2]).unwrap();
+        let other = State::new(&mut rng, shares[1].clone(), &[0, 1, 2])
+            .unwrap()
+            .generate_msg1();
+        assert!(matches!(
+            party.handle_msg1(&mut rng, [&other, &other]),
+            Err(KeygenError::DuplicateMessage(1))
+        ));
+    }
+
+    #[test]
+    fn refresh_with_stale_share() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(3, 2);
+        let refreshed = refresh(&shares, &[0, 2]);
+
+        // party 2 restored a key share from before the refresh
+        let mut party =
+            State::new(&mut rng, refreshed[0].clone(), &[0, 2]).unwrap();
+        let stale = State::new(&mut rng, shares[2].clone(), &[0, 2]).unwrap();
+
+        assert!(matches!(
+            party.handle_msg1(&mut rng, [stale.generate_msg1()]),
+            Err(KeygenError::SeedRefreshCounterMismatch(2))
+        ));
+    }
+}
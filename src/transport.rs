@@ -0,0 +1,355 @@
+// Copyright (c) Silence Laboratories Pte. Ltd. All Rights Reserved.
+// This software is licensed under the Silence Laboratories License Agreement.
+
+//! Encryption of P2P protocol messages.
+//!
+//! P2P messages of keygen and signing contain secret material and
+//! must be encrypted. This module implements a simple encryption
+//! layer for them:
+//!
+//! - Each party creates a [`Transport`] with an ephemeral key and
+//!   broadcasts [`TransportKeyMsg`] together with its first protocol
+//!   message.
+//! - After [`Transport::handle_key_messages`], a party seals its
+//!   outgoing P2P messages with [`Transport::seal`] and opens incoming
+//!   ones with [`Transport::open`].
+//!
+//! Keys are agreed by ECDH over secp256k1. Every direction of every
+//! pair of parties uses a separate ChaCha20-Poly1305 key, separate
+//! for keygen and signing messages. Like any
+//! other broadcast message, `TransportKeyMsg` must be delivered over
+//! an authenticated channel, otherwise a man in the middle could
+//! substitute its own key. A `Transport` must not be reused across
+//! sessions.
+
+use std::borrow::Borrow;
+
+use chacha20poly1305::{
+    aead::{Aead, Payload},
+    ChaCha20Poly1305, Key, KeyInit, Nonce,
+};
+use k256::{
+    elliptic_curve::{
+        group::prime::PrimeCurveAffine, point::AffineCoordinates,
+    },
+    AffinePoint, NonZeroScalar, ProjectivePoint,
+};
+use merlin::Transcript;
+use rand::prelude::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sl_oblivious::label::Label;
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use crate::{
+    constants::{DKG_LABEL, TRANSPORT_KEY_LABEL, TRANSPORT_SIGN_LABEL},
+    dkg::{KeygenMsg2, KeygenMsg2P2P, KeygenMsg3},
+    dsg::{SignMsg2, SignMsg3},
+    pairs::Pairs,
+};
+
+pub use crate::error::TransportError;
+
+/// P2P protocol message which could be sealed by a [`Transport`].
+pub trait P2PMessage: Serialize + DeserializeOwned {
+    /// Label of the protocol of the message, messages of each
+    /// protocol are encrypted with separate keys.
+    const LABEL: &'static Label;
+
+    /// ID of the sender.
+    fn sender(&self) -> u8;
+
+    /// ID of the recipient.
+    fn recipient(&self) -> u8;
+}
+
+macro_rules! p2p_message {
+    ($label:expr => $($t:ty),*) => {$(
+        impl P2PMessage for $t {
+            const LABEL: &'static Label = &$label;
+
+            fn sender(&self) -> u8 {
+                self.from_id
+            }
+
+            fn recipient(&self) -> u8 {
+                self.to_id
+            }
+        }
+    )*};
+}
+
+p2p_message!(DKG_LABEL => KeygenMsg2, KeygenMsg2P2P, KeygenMsg3);
+p2p_message!(TRANSPORT_SIGN_LABEL => SignMsg2, SignMsg3);
+
+/// Broadcast message with the ephemeral public key of a party.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TransportKeyMsg {
+    pub from_id: u8,
+    public_key: AffinePoint,
+}
+
+/// Encrypted P2P message.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SealedMsg {
+    pub from_id: u8,
+    pub to_id: u8,
+    nonce: [u8; 12],
+    ciphertext: Vec<u8>,
+}
+
+/// ECDH shared secret and the ephemeral public key of a party.
+#[derive(Zeroize)]
+struct Channel {
+    shared: AffinePoint,
+    public_key: AffinePoint,
+}
+
+/// Transport encryption keys of a party for one session.
+#[derive(Zeroize, ZeroizeOnDrop)]
+pub struct Transport {
+    party_id: u8,
+    secret: NonZeroScalar,
+    #[zeroize(skip)]
+    public_key: AffinePoint,
+    channels: Pairs<Channel>,
+}
+
+impl Transport {
+    /// Create an ephemeral transport key of a party.
+    pub fn new<R: RngCore + CryptoRng>(party_id: u8, rng: &mut R) -> Self {
+        let secret = NonZeroScalar::random(rng);
+        let public_key = (ProjectivePoint::GENERATOR * *secret).to_affine();
+
+        Self {
+            party_id,
+            secret,
+            public_key,
+            channels: Pairs::new(),
+        }
+    }
+
+    /// Message to broadcast to all other parties.
+    pub fn key_message(&self) -> TransportKeyMsg {
+        TransportKeyMsg {
+            from_id: self.party_id,
+            public_key: self.public_key,
+        }
+    }
+
+    /// Agree on encryption keys with all parties of `msgs`.
+    pub fn handle_key_messages(
+        &mut self,
+        msgs: impl IntoIterator<Item = impl Borrow<TransportKeyMsg>>,
+    ) -> Result<(), TransportError> {
+        for msg in msgs {
+            let msg = msg.borrow();
+
+            if msg.from_id == self.party_id
+                || msg.public_key.is_identity().into()
+                || self.channels.iter().any(|(id, _)| *id == msg.from_id)
+            {
+                return Err(TransportError::InvalidPublicKey(msg.from_id));
+            }
+
+            let shared = (ProjectivePoint::from(msg.public_key)
+                * *self.secret)
+                .to_affine();
+
+            self.channels.push(
+                msg.from_id,
+                Channel {
+                    shared,
+                    public_key: msg.public_key,
+                },
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Encrypt a P2P message of this party.
+    pub fn seal<M: P2PMessage, R: RngCore + CryptoRng>(
+        &self,
+        rng: &mut R,
+        msg: &M,
+    ) -> Result<SealedMsg, TransportError> {
+        let (from_id, to_id) = (msg.sender(), msg.recipient());
+        if from_id != self.party_id {
+            return Err(TransportError::InvalidRouting);
+        }
+
+        let channel = self
+            .channels
+            .find_pair_or_err(to_id, TransportError::UnknownParty(to_id))?;
+        let cipher = self.cipher(
+            M::LABEL,
+            &channel.shared,
+            (from_id, &self.public_key),
+            (to_id, &channel.public_key),
+        );
+
+        let mut plaintext =
+            bincode::serde::encode_to_vec(msg, bincode::config::standard())
+                .map_err(|_| TransportError::InvalidMessage)?;
+
+        let nonce: [u8; 12] = rng.gen();
+        let ciphertext = cipher
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &plaintext,
+                    aad: &[from_id, to_id],
+                },
+            )
+            .map_err(|_| TransportError::InvalidMessage)?;
+
+        plaintext.zeroize();
+
+        Ok(SealedMsg {
+            from_id,
+            to_id,
+            nonce,
+            ciphertext,
+        })
+    }
+
+    /// Decrypt a P2P message addressed to this party.
+    pub fn open<M: P2PMessage>(
+        &self,
+        msg: &SealedMsg,
+    ) -> Result<M, TransportError> {
+        if msg.to_id != self.party_id {
+            return Err(TransportError::InvalidRouting);
+        }
+
+        let channel = self.channels.find_pair_or_err(
+            msg.from_id,
+            TransportError::UnknownParty(msg.from_id),
+        )?;
+        let cipher = self.cipher(
+            M::LABEL,
+            &channel.shared,
+            (msg.from_id, &channel.public_key),
+            (msg.to_id, &self.public_key),
+        );
+
+        let mut plaintext = cipher
+            .decrypt(
+                Nonce::from_slice(&msg.nonce),
+                Payload {
+                    msg: &msg.ciphertext,
+                    aad: &[msg.from_id, msg.to_id],
+                },
+            )
+            .map_err(|_| TransportError::DecryptionFailed(msg.from_id))?;
+
+        let decoded = bincode::serde::decode_from_slice::<M, _>(
+            &plaintext,
+            bincode::config::standard(),
+        );
+        plaintext.zeroize();
+
+        let (inner, _) =
+            decoded.map_err(|_| TransportError::InvalidMessage)?;
+
+        if inner.sender() != msg.from_id || inner.recipient() != msg.to_id {
+            return Err(TransportError::InvalidRouting);
+        }
+
+        Ok(inner)
+    }
+
+    /// Cipher of direction `from` -> `to` for messages of the protocol
+    /// `label`.
+    fn cipher(
+        &self,
+        label: &'static Label,
+        shared: &AffinePoint,
+        from: (u8, &AffinePoint),
+        to: (u8, &AffinePoint),
+    ) -> ChaCha20Poly1305 {
+        let mut transcript = Transcript::new(label);
+        transcript.append_message(b"shared_secret", &shared.x());
+        transcript.append_u64(b"from_id", from.0 as u64);
+        transcript.append_message(b"from_key", &from.1.x());
+        transcript.append_u64(b"to_id", to.0 as u64);
+        transcript.append_message(b"to_key", &to.1.x());
+
+        let mut key = [0u8; 32];
+        transcript.challenge_bytes(&TRANSPORT_KEY_LABEL, &mut key);
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(&key));
+        key.zeroize();
+
+        cipher
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::dkg::{tests::init_states, KeygenMsg1};
+
+    fn transports(n: u8) -> Vec<Transport> {
+        let mut rng = rand::thread_rng();
+
+        let mut transports: Vec<_> =
+            (0..n).map(|id| Transport::new(id, &mut rng)).collect();
+        let msgs: Vec<_> =
+            transports.iter().map(|t| t.key_message()).collect();
+
+        for t in &mut transports {
+            let party_id = t.party_id;
+            let batch = msgs.iter().filter(|m| m.from_id != party_id);
+            t.handle_key_messages(batch).unwrap();
+        }
+
+        transports
+    }
+
+    #[test]
+    fn seal_and_open() {
+        let mut rng = rand::thread_rng();
+        let transports = transports(3);
+        let mut parties = init_states(&[0; 3], 2);
+
+        let msg1: Vec<KeygenMsg1> =
+            parties.iter().map(|p| p.generate_msg1()).collect();
+        let batch = msg1.iter().filter(|msg| msg.from_id != 0);
+        let msg2 = parties[0].handle_msg1(&mut rng, batch).unwrap();
+
+        for msg in &msg2 {
+            let sealed = transports[0].seal(&mut rng, msg).unwrap();
+            let to = &transports[msg.to_id as usize];
+            let opened: KeygenMsg2 = to.open(&sealed).unwrap();
+            assert_eq!(opened.from_id, 0);
+            assert_eq!(opened.to_id, msg.to_id);
+
+            // addressed to another party
+            let other = &transports[3 - msg.to_id as usize];
+            assert!(matches!(
+                other.open::<KeygenMsg2>(&sealed),
+                Err(TransportError::InvalidRouting)
+            ));
+
+            // keygen and signing messages use separate keys
+            assert!(matches!(
+                to.open::<SignMsg2>(&sealed),
+                Err(TransportError::DecryptionFailed(0))
+            ));
+
+            let mut tampered = sealed.clone();
+            tampered.ciphertext[0] ^= 1;
+            assert!(matches!(
+                to.open::<KeygenMsg2>(&tampered),
+                Err(TransportError::DecryptionFailed(0))
+            ));
+        }
+
+        // a party could seal only own messages
+        assert!(matches!(
+            transports[1].seal(&mut rng, &msg2[0]),
+            Err(TransportError::InvalidRouting)
+        ));
+    }
+}
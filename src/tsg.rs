@@ -0,0 +1,688 @@
+// Copyright (c) Silence Laboratories Pte. Ltd. All Rights Reserved.
+// This software is licensed under the Silence Laboratories License Agreement.
+
+//! Threshold BIP340 Schnorr signing with the key shares of the ECDSA
+//! keygen.
+//!
+//! The same distributed key could sign both ECDSA and Taproot
+//! spends. Signing takes two broadcast rounds, with nonce commitments
+//! bound to the message in the style of FROST:
+//!
+//! - Each party broadcasts [`TsgMsg1`] with commitments to two
+//!   nonces.
+//! - After [`State::handle_msg1`] every party broadcasts its partial
+//!   signature [`TsgMsg2`].
+//! - [`State::combine`] checks all partial signatures and returns the
+//!   64 bytes signature.
+//!
+//! The key is the x-only public key of the derivation path, optionally
+//! tweaked for a BIP341 key path spend, see [`Tweak`]. Like the ECDSA
+//! pre-signature, a session must sign only one message.
+
+use std::borrow::Borrow;
+
+use derivation_path::DerivationPath;
+use k256::{
+    elliptic_curve::{
+        group::prime::PrimeCurveAffine, ops::Reduce,
+        point::AffineCoordinates, Field, Group, PrimeField,
+    },
+    AffinePoint, ProjectivePoint, Scalar, U256,
+};
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+use crate::{
+    dkg::Keyshare,
+    dsg::{derive_with_offset, interpolation_coeff},
+    pairs::Pairs,
+    utils::{
+        borrow_msgs, hash_tsg_binding_factors, hash_tsg_final_session_id,
+    },
+};
+
+pub use crate::error::{AbortCheck, AbortContext, SignError};
+
+/// Tweak of the key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Tweak {
+    /// Sign with the x-only public key of the derivation path.
+    None,
+
+    /// BIP341 key path spend of an output with the given Merkle root
+    /// of the script tree, or of an output without scripts (BIP86).
+    Taproot(Option<[u8; 32]>),
+}
+
+/// Broadcast message of the first round.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TsgMsg1 {
+    pub from_id: u8,
+    pub session_id: [u8; 32],
+    pub big_d: AffinePoint,
+    pub big_e: AffinePoint,
+}
+
+/// Broadcast message of the second round, a partial signature.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TsgMsg2 {
+    pub from_id: u8,
+    pub final_session_id: [u8; 32],
+    pub message: [u8; 32],
+    pub z_i: Scalar,
+}
+
+/// Even-y output key of a derivation path and tweak, and how to get
+/// its additive shares from the ones of the untweaked key.
+#[derive(Clone, Serialize, Deserialize, Zeroize)]
+struct OutputKey {
+    /// Per party share of the additive offset of the path
+    additive_offset: Scalar,
+    /// The public key of the path has odd y
+    negate_internal: bool,
+    /// Per party share of the tweak
+    tweak: Scalar,
+    /// The tweaked key has odd y
+    negate_output: bool,
+    output_key: AffinePoint,
+}
+
+impl OutputKey {
+    fn new(
+        keyshare: &Keyshare,
+        chain_path: &DerivationPath,
+        tweak: &Tweak,
+    ) -> Result<Self, SignError> {
+        let (additive_offset, public_key) = derive_with_offset(
+            &keyshare.public_key.to_curve(),
+            keyshare.root_chain_code.as_ref(),
+            chain_path,
+        )?;
+
+        let negate_internal: bool = public_key.to_affine().y_is_odd().into();
+        let internal_key = if negate_internal {
+            -public_key
+        } else {
+            public_key
+        };
+
+        let tweak = match tweak {
+            Tweak::None => Scalar::ZERO,
+            Tweak::Taproot(merkle_root) => {
+                let internal_x = internal_key.to_affine().x();
+                let mut data = internal_x.to_vec();
+                data.extend_from_slice(
+                    merkle_root.as_ref().map_or(&[], |r| r),
+                );
+
+                let t = tagged_hash(b"TapTweak", &data);
+                Option::from(Scalar::from_repr(t.into()))
+                    .ok_or(SignError::FailedCheck("Invalid Taproot tweak"))?
+            }
+        };
+
+        let output_key = internal_key + ProjectivePoint::GENERATOR * tweak;
+        if output_key.is_identity().into() {
+            return Err(SignError::FailedCheck("Invalid Taproot tweak"));
+        }
+
+        let negate_output: bool = output_key.to_affine().y_is_odd().into();
+        let output_key = if negate_output {
+            -output_key
+        } else {
+            output_key
+        };
+
+        // can not fail because T != 0
+        let threshold_inv =
+            Scalar::from(keyshare.threshold as u32).invert().unwrap();
+
+        Ok(Self {
+            additive_offset: additive_offset * threshold_inv,
+            negate_internal,
+            tweak: tweak * threshold_inv,
+            negate_output,
+            output_key: output_key.to_affine(),
+        })
+    }
+
+    /// X-only encoding of the output key.
+    fn x_only(&self) -> [u8; 32] {
+        self.output_key.x().into()
+    }
+
+    /// Additive share of the secret key of the output key, from the
+    /// interpolated share of the root key.
+    fn secret_share(&self, share: Scalar) -> Scalar {
+        let share = share + self.additive_offset;
+        let share = if self.negate_internal { -share } else { share };
+        let share = share + self.tweak;
+
+        if self.negate_output {
+            -share
+        } else {
+            share
+        }
+    }
+
+    /// Public counterpart of [`OutputKey::secret_share`].
+    fn public_share(&self, share: ProjectivePoint) -> ProjectivePoint {
+        let share = share + ProjectivePoint::GENERATOR * self.additive_offset;
+        let share = if self.negate_internal { -share } else { share };
+        let share = share + ProjectivePoint::GENERATOR * self.tweak;
+
+        if self.negate_output {
+            -share
+        } else {
+            share
+        }
+    }
+}
+
+/// X-only public key that signatures of the given path and tweak are
+/// verified against, for example the Taproot output key.
+pub fn x_only_public_key(
+    keyshare: &Keyshare,
+    chain_path: &DerivationPath,
+    tweak: &Tweak,
+) -> Result<[u8; 32], SignError> {
+    Ok(OutputKey::new(keyshare, chain_path, tweak)?.x_only())
+}
+
+/// Threshold Schnorr signing session of a party.
+#[derive(Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
+pub struct State {
+    keyshare: Keyshare,
+    key: OutputKey,
+    nonces: Option<[Scalar; 2]>,
+    commitments: Pairs<([u8; 32], AffinePoint, AffinePoint)>,
+    final_session_id: [u8; 32],
+    message: [u8; 32],
+    big_r: AffinePoint,
+    negate_nonce: bool,
+    challenge: Scalar,
+    binding_factors: Vec<(u8, Scalar)>,
+    z_i: Scalar,
+}
+
+impl State {
+    /// Create a signing session for the given derivation path and
+    /// tweak.
+    pub fn new<R: RngCore + CryptoRng>(
+        rng: &mut R,
+        keyshare: Keyshare,
+        chain_path: &DerivationPath,
+        tweak: &Tweak,
+    ) -> Result<Self, SignError> {
+        let key = OutputKey::new(&keyshare, chain_path, tweak)?;
+
+        let session_id: [u8; 32] = rng.gen();
+        let d_i = Scalar::random(&mut *rng);
+        let e_i = Scalar::random(&mut *rng);
+
+        let big_d = (ProjectivePoint::GENERATOR * d_i).to_affine();
+        let big_e = (ProjectivePoint::GENERATOR * e_i).to_affine();
+
+        let t = keyshare.threshold as usize;
+        let commitments = Pairs::with_capacity(t)
+            .add(keyshare.party_id, (session_id, big_d, big_e));
+
+        Ok(Self {
+            keyshare,
+            key,
+            nonces: Some([d_i, e_i]),
+            commitments,
+            final_session_id: [0; 32],
+            message: [0; 32],
+            big_r: AffinePoint::IDENTITY,
+            negate_nonce: false,
+            challenge: Scalar::ZERO,
+            binding_factors: vec![],
+            z_i: Scalar::ZERO,
+        })
+    }
+
+    /// X-only public key the signature is verified against.
+    pub fn public_key(&self) -> [u8; 32] {
+        self.key.x_only()
+    }
+
+    /// Round 1.
+    pub fn generate_msg1(&self) -> TsgMsg1 {
+        let party_id = self.keyshare.party_id;
+        let (session_id, big_d, big_e) = self.commitments.find_pair(party_id);
+
+        TsgMsg1 {
+            from_id: party_id,
+            session_id: *session_id,
+            big_d: *big_d,
+            big_e: *big_e,
+        }
+    }
+
+    /// Round 2. Sign the 32 bytes message, all parties must pass the
+    /// same message. Returns the partial signature to broadcast.
+    pub fn handle_msg1(
+        &mut self,
+        msgs: impl IntoIterator<Item = impl Borrow<TsgMsg1>>,
+        message: [u8; 32],
+    ) -> Result<TsgMsg2, SignError> {
+        borrow_msgs!(msgs: TsgMsg1);
+
+        if msgs.len() != self.keyshare.threshold as usize - 1 {
+            return Err(SignError::MissingMessage);
+        }
+
+        if self.nonces.is_none() {
+            return Err(SignError::FailedCheck("Nonces are already used"));
+        }
+
+        // validate the whole batch before the state is changed
+        for (idx, msg) in msgs.iter().enumerate() {
+            if msg.from_id as usize >= self.keyshare.rank_list.len() {
+                return Err(SignError::UnexpectedParty(msg.from_id));
+            }
+
+            let seen = self
+                .commitments
+                .iter()
+                .map(|(p, (sid, _, _))| (*p, sid))
+                .chain(
+                    msgs[..idx].iter().map(|m| (m.from_id, &m.session_id)),
+                );
+            for (p, sid) in seen {
+                if p == msg.from_id {
+                    return Err(SignError::DuplicateParty(msg.from_id));
+                }
+                if *sid == msg.session_id {
+                    return Err(SignError::DuplicateSessionId(msg.from_id));
+                }
+            }
+
+            if msg.big_d.is_identity().into()
+                || msg.big_e.is_identity().into()
+            {
+                return Err(AbortContext::error(
+                    msg.from_id,
+                    1,
+                    AbortCheck::NonceCommitment,
+                    None,
+                ));
+            }
+        }
+
+        for msg in msgs {
+            self.commitments
+                .push(msg.from_id, (msg.session_id, msg.big_d, msg.big_e));
+        }
+
+        // can not fail, checked above
+        let mut nonces = self.nonces.take().unwrap();
+
+        self.final_session_id = hash_tsg_final_session_id(
+            self.commitments.iter().map(|(p, (sid, _, _))| (*p, sid)),
+            &self.keyshare.final_session_id,
+        );
+        self.message = message;
+
+        self.binding_factors = hash_tsg_binding_factors(
+            &self.final_session_id,
+            self.commitments.iter().map(|(p, (_, d, e))| (*p, d, e)),
+            &self.key.x_only(),
+            &message,
+        );
+
+        let big_r = self
+            .binding_factors
+            .iter()
+            .fold(ProjectivePoint::IDENTITY, |sum, (p, rho)| {
+                sum + self.nonce_commitment(*p, rho)
+            });
+        if big_r.is_identity().into() {
+            return Err(SignError::FailedCheck("Invalid group nonce"));
+        }
+
+        self.negate_nonce = big_r.to_affine().y_is_odd().into();
+        self.big_r =
+            if self.negate_nonce { -big_r } else { big_r }.to_affine();
+        self.challenge = bip340_challenge(
+            &self.big_r.x().into(),
+            &self.key.x_only(),
+            &message,
+        );
+
+        let party_id = self.keyshare.party_id;
+        let coeff = interpolation_coeff(
+            &self.keyshare,
+            party_id,
+            self.commitments.iter().map(|(p, _)| *p),
+        )?;
+
+        let rho_i = self.binding_factor(party_id);
+        let k_i = nonces[0] + rho_i * nonces[1];
+        let k_i = if self.negate_nonce { -k_i } else { k_i };
+        let x_i = self.key.secret_share(coeff * self.keyshare.s_i);
+
+        self.z_i = k_i + self.challenge * x_i;
+
+        nonces.zeroize();
+
+        Ok(TsgMsg2 {
+            from_id: party_id,
+            final_session_id: self.final_session_id,
+            message,
+            z_i: self.z_i,
+        })
+    }
+
+    /// Check partial signatures of all other parties and combine them
+    /// into the 64 bytes BIP340 signature.
+    pub fn combine(
+        &self,
+        msgs: impl IntoIterator<Item = impl Borrow<TsgMsg2>>,
+    ) -> Result<[u8; 64], SignError> {
+        borrow_msgs!(msgs: TsgMsg2);
+
+        if self.nonces.is_some()
+            || msgs.len() != self.keyshare.threshold as usize - 1
+        {
+            return Err(SignError::MissingMessage);
+        }
+
+        let party_id = self.keyshare.party_id;
+        let mut seen = vec![party_id];
+        let mut s = self.z_i;
+
+        for msg in msgs {
+            if !self.commitments.iter().any(|(p, _)| *p == msg.from_id) {
+                return Err(SignError::UnexpectedParty(msg.from_id));
+            }
+
+            if seen.contains(&msg.from_id) {
+                return Err(SignError::DuplicateParty(msg.from_id));
+            }
+            seen.push(msg.from_id);
+
+            if msg.final_session_id != self.final_session_id {
+                return Err(SignError::InvalidFinalSessionID);
+            }
+
+            if msg.message != self.message {
+                return Err(SignError::MessageHashMismatch);
+            }
+
+            self.verify_partial(msg)?;
+
+            s += msg.z_i;
+        }
+
+        let big_r = ProjectivePoint::from(self.big_r);
+        let output_key = ProjectivePoint::from(self.key.output_key);
+        if ProjectivePoint::GENERATOR * s
+            != big_r + output_key * self.challenge
+        {
+            return Err(SignError::FailedCheck("Invalid signature"));
+        }
+
+        let mut sign = [0u8; 64];
+        sign[..32].copy_from_slice(&self.big_r.x());
+        sign[32..].copy_from_slice(&s.to_bytes());
+
+        Ok(sign)
+    }
+
+    fn verify_partial(&self, msg: &TsgMsg2) -> Result<(), SignError> {
+        let party_id = msg.from_id;
+
+        let coeff = interpolation_coeff(
+            &self.keyshare,
+            party_id,
+            self.commitments.iter().map(|(p, _)| *p),
+        )?;
+        let big_s = self.keyshare.big_s_list[party_id as usize].to_curve();
+        let x_j = self.key.public_share(big_s * coeff);
+
+        let k_j =
+            self.nonce_commitment(party_id, &self.binding_factor(party_id));
+        let k_j = if self.negate_nonce { -k_j } else { k_j };
+
+        if ProjectivePoint::GENERATOR * msg.z_i != k_j + x_j * self.challenge
+        {
+            return Err(AbortContext::error(
+                party_id,
+                2,
+                AbortCheck::PartialSignature,
+                Some(self.final_session_id),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn binding_factor(&self, party_id: u8) -> Scalar {
+        self.binding_factors
+            .iter()
+            .find(|(p, _)| *p == party_id)
+            .map(|(_, rho)| *rho)
+            .unwrap_or(Scalar::ZERO)
+    }
+
+    /// D_j + rho_j * E_j
+    fn nonce_commitment(
+        &self,
+        party_id: u8,
+        rho: &Scalar,
+    ) -> ProjectivePoint {
+        let (_, big_d, big_e) = self.commitments.find_pair(party_id);
+
+        big_d.to_curve() + big_e.to_curve() * rho
+    }
+}
+
+/// BIP340 tagged hash.
+fn tagged_hash(tag: &[u8], data: &[u8]) -> [u8; 32] {
+    let tag = Sha256::digest(tag);
+
+    Sha256::new()
+        .chain_update(tag)
+        .chain_update(tag)
+        .chain_update(data)
+        .finalize()
+        .into()
+}
+
+fn bip340_challenge(
+    r_x: &[u8; 32],
+    public_key: &[u8; 32],
+    message: &[u8; 32],
+) -> Scalar {
+    let mut data = [0u8; 96];
+    data[..32].copy_from_slice(r_x);
+    data[32..64].copy_from_slice(public_key);
+    data[64..].copy_from_slice(message);
+
+    let e = tagged_hash(b"BIP0340/challenge", &data);
+    Scalar::reduce(U256::from_be_slice(&e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use k256::schnorr::{
+        signature::hazmat::PrehashVerifier, Signature, VerifyingKey,
+    };
+
+    use super::*;
+
+    use crate::dkg::tests::{check_serde, dkg, dkg_with_ranks};
+
+    fn run_tsg(
+        shares: &[Keyshare],
+        chain_path: &DerivationPath,
+        tweak: &Tweak,
+        message: [u8; 32],
+    ) -> Vec<[u8; 64]> {
+        let mut rng = rand::thread_rng();
+
+        let mut parties = shares
+            .iter()
+            .map(|s| {
+                State::new(&mut rng, s.clone(), chain_path, tweak).unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let msg1: Vec<_> =
+            parties.iter().map(|p| p.generate_msg1()).collect();
+        check_serde(&msg1);
+
+        let msg2: Vec<_> = parties
+            .iter_mut()
+            .map(|p| {
+                let party_id = p.keyshare.party_id;
+                let batch = msg1.iter().filter(|m| m.from_id != party_id);
+                p.handle_msg1(batch, message).unwrap()
+            })
+            .collect();
+        check_serde(&msg2);
+
+        parties
+            .iter()
+            .map(|p| {
+                let party_id = p.keyshare.party_id;
+                let batch = msg2.iter().filter(|m| m.from_id != party_id);
+                p.combine(batch).unwrap()
+            })
+            .collect()
+    }
+
+    fn verify(public_key: &[u8; 32], message: &[u8; 32], sign: &[u8; 64]) {
+        VerifyingKey::from_bytes(public_key)
+            .unwrap()
+            .verify_prehash(message, &Signature::try_from(&sign[..]).unwrap())
+            .unwrap();
+    }
+
+    #[test]
+    fn sign_taproot() {
+        let shares = dkg(3, 2);
+        let message = [5; 32];
+
+        for path in ["m", "m/0/1"] {
+            let chain_path = DerivationPath::from_str(path).unwrap();
+
+            for tweak in [
+                Tweak::None,
+                Tweak::Taproot(None),
+                Tweak::Taproot(Some([9; 32])),
+            ] {
+                let public_key =
+                    x_only_public_key(&shares[0], &chain_path, &tweak)
+                        .unwrap();
+
+                for sign in
+                    run_tsg(&shares[1..], &chain_path, &tweak, message)
+                {
+                    verify(&public_key, &message, &sign);
+                }
+            }
+        }
+
+        let root = DerivationPath::from_str("m").unwrap();
+        assert_ne!(
+            x_only_public_key(&shares[0], &root, &Tweak::None).unwrap(),
+            x_only_public_key(&shares[0], &root, &Tweak::Taproot(None))
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn sign_taproot_with_ranks() {
+        let shares = dkg_with_ranks(&[0, 1, 1], 2);
+        let chain_path = DerivationPath::from_str("m").unwrap();
+        let tweak = Tweak::Taproot(None);
+        let public_key =
+            x_only_public_key(&shares[0], &chain_path, &tweak).unwrap();
+
+        let sign = run_tsg(&shares[..2], &chain_path, &tweak, [1; 32]);
+        verify(&public_key, &[1; 32], &sign[0]);
+    }
+
+    #[test]
+    fn reject_duplicates_without_state_change() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(3, 3);
+        let chain_path = DerivationPath::from_str("m").unwrap();
+
+        let mut parties = shares
+            .iter()
+            .map(|s| {
+                State::new(&mut rng, s.clone(), &chain_path, &Tweak::None)
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let msg1_1 = parties[1].generate_msg1();
+        let mut msg1_2 = parties[2].generate_msg1();
+
+        assert!(matches!(
+            parties[0].handle_msg1([&msg1_1, &msg1_1], [1; 32]),
+            Err(SignError::DuplicateParty(1))
+        ));
+
+        let session_id = msg1_2.session_id;
+        msg1_2.session_id = msg1_1.session_id;
+        assert!(matches!(
+            parties[0].handle_msg1([&msg1_1, &msg1_2], [1; 32]),
+            Err(SignError::DuplicateSessionId(2))
+        ));
+
+        // rejected batches leave no trace in the session
+        msg1_2.session_id = session_id;
+        parties[0].handle_msg1([&msg1_1, &msg1_2], [1; 32]).unwrap();
+    }
+
+    #[test]
+    fn invalid_partial_signature() {
+        let mut rng = rand::thread_rng();
+
+        let shares = dkg(3, 2);
+        let chain_path = DerivationPath::from_str("m").unwrap();
+
+        let mut parties = shares[..2]
+            .iter()
+            .map(|s| {
+                State::new(&mut rng, s.clone(), &chain_path, &Tweak::None)
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        let msg1_0 = parties[0].generate_msg1();
+        let msg1_1 = parties[1].generate_msg1();
+
+        let msg2_0 = parties[0].handle_msg1([&msg1_1], [1; 32]).unwrap();
+        assert!(parties[0].handle_msg1([&msg1_1], [1; 32]).is_err());
+
+        // party 1 signs another message
+        let mut msg2_1 = parties[1].handle_msg1([&msg1_0], [2; 32]).unwrap();
+        assert!(matches!(
+            parties[0].combine([&msg2_1]),
+            Err(SignError::MessageHashMismatch)
+        ));
+        assert!(matches!(
+            parties[1].combine([&msg2_0]),
+            Err(SignError::MessageHashMismatch)
+        ));
+
+        msg2_1.message = [1; 32];
+        assert!(matches!(
+            parties[0].combine([&msg2_1]),
+            Err(SignError::AbortProtocolAndBanParty(ctx))
+                if ctx.party_id == 1
+                    && ctx.check == AbortCheck::PartialSignature
+        ));
+    }
+}
@@ -11,25 +11,63 @@ use bytemuck::{AnyBitPattern, NoUninit};
 use k256::{
     elliptic_curve::{
         group::GroupEncoding,
+        ops::Reduce,
         subtle::{Choice, ConstantTimeEq},
     },
-    NonZeroScalar, ProjectivePoint, Secp256k1,
+    AffinePoint, NonZeroScalar, ProjectivePoint, Scalar, Secp256k1, U256,
 };
 use merlin::Transcript;
 use sha2::{Digest, Sha256};
+use sha3::Keccak256;
 
-use sl_mpc_mate::{math::birkhoff_coeffs, math::GroupPolynomial};
+use sl_mpc_mate::math::{birkhoff_coeffs, GroupPolynomial, Polynomial};
 use sl_oblivious::{utils::TranscriptProtocol, zkproofs::DLogProof};
 use zeroize::Zeroize;
 
 use crate::{constants::*, error::KeygenError};
 
+/// Collect the messages passed to a round handler as
+/// `impl IntoIterator<Item = impl Borrow<T>>` into a `Vec<&T>` of the
+/// same name.
+macro_rules! borrow_msgs {
+    ($msgs:ident: $t:ty) => {
+        let $msgs: Vec<_> = $msgs.into_iter().collect();
+        let $msgs: Vec<&$t> =
+            $msgs.iter().map(::std::borrow::Borrow::borrow).collect();
+    };
+}
+
+pub(crate) use borrow_msgs;
+
+/// Size of a SEC1 compressed point.
+pub(crate) const POINT_SIZE: usize = 33;
+
+/// Size of an encoded scalar.
+pub(crate) const SCALAR_SIZE: usize = 32;
+
+/// Upper bound of the encoded size of a message with fields of the
+/// given sizes in bytes. CBOR takes up to two bytes per byte of an
+/// array, a length header and the name of each field, compact
+/// encodings like postcard or bincode take less.
+pub(crate) fn max_encoded_size(
+    fields: impl IntoIterator<Item = usize>,
+) -> usize {
+    9 + fields
+        .into_iter()
+        .map(|bytes| 2 * bytes + 9 + 32)
+        .sum::<usize>()
+}
+
+/// Zeroized storage of a secret OT payload. The bytes are kept on
+/// the heap, moving a `ZS` moves only the pointer, so the secret is
+/// never copied around the stack and is wiped in one place.
 #[derive(Zeroize)]
 pub struct ZS<T: AnyBitPattern + NoUninit> {
     buffer: Vec<u8>,
     marker: PhantomData<T>,
 }
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn hash_commitment(
     session_id: &[u8; 32],
     party_id: usize,
@@ -37,6 +75,8 @@ pub(crate) fn hash_commitment(
     x_i: &NonZeroScalar,
     big_f_i_vec: &GroupPolynomial<Secp256k1>,
     r_i: &[u8; 32],
+    identity_key: Option<&[u8]>,
+    mode: u8,
 ) -> [u8; 32] {
     let mut hasher = Sha256::new();
     hasher.update(DKG_LABEL);
@@ -48,6 +88,11 @@ pub(crate) fn hash_commitment(
         hasher.update(point.to_bytes());
     }
     hasher.update(r_i);
+    if let Some(identity_key) = identity_key {
+        hasher.update((identity_key.len() as u64).to_be_bytes());
+        hasher.update(identity_key);
+    }
+    hasher.update([mode]);
     hasher.update(COMMITMENT_1_LABEL);
     hasher.finalize().into()
 }
@@ -66,6 +111,223 @@ pub(crate) fn hash_commitment_2(
     hasher.finalize().into()
 }
 
+pub(crate) fn hash_broadcast_digest<'a>(
+    final_session_id: &[u8; 32],
+    commitments: impl Iterator<Item = (u8, &'a [u8; 32])>,
+    x_i_list: impl Iterator<Item = &'a NonZeroScalar>,
+    r_i_list: impl Iterator<Item = (u8, &'a [u8; 32])>,
+    big_f_i_vecs: impl Iterator<Item = (u8, &'a GroupPolynomial<Secp256k1>)>,
+    big_f_vec: &GroupPolynomial<Secp256k1>,
+    commitment_2_list: &[[u8; 32]],
+) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(DKG_LABEL);
+    hasher.update(final_session_id);
+    for (party_id, commitment) in commitments {
+        hasher.update([party_id]);
+        hasher.update(commitment);
+    }
+    for x_i in x_i_list {
+        hasher.update(x_i.to_bytes());
+    }
+    for (party_id, r_i) in r_i_list {
+        hasher.update([party_id]);
+        hasher.update(r_i);
+    }
+    for (party_id, big_f_i_vec) in big_f_i_vecs {
+        hasher.update([party_id]);
+        for point in big_f_i_vec.points() {
+            hasher.update(point.to_bytes());
+        }
+    }
+    for point in big_f_vec.points() {
+        hasher.update(point.to_bytes());
+    }
+    for commitment_2 in commitment_2_list {
+        hasher.update(commitment_2);
+    }
+    hasher.update(BROADCAST_DIGEST_LABEL);
+    hasher.finalize().into()
+}
+
+/// Bind per-party 32 byte values into a transcript, ordered by party ID.
+fn append_party_values<'a>(
+    transcript: &mut Transcript,
+    label: &'static [u8],
+    values: impl Iterator<Item = (u8, &'a [u8; 32])>,
+) {
+    for (party_id, value) in values {
+        transcript.append_u64(b"party_id", party_id as u64);
+        transcript.append_message(label, value);
+    }
+}
+
+fn challenge(transcript: &mut Transcript, label: &'static [u8]) -> [u8; 32] {
+    let mut buf = [0u8; 32];
+    transcript.challenge_bytes(label, &mut buf);
+    buf
+}
+
+/// Final session ID of a keygen from the session IDs of all parties.
+pub(crate) fn hash_dkg_final_session_id<'a>(
+    sid_list: impl Iterator<Item = (u8, &'a [u8; 32])>,
+    session_nonce: Option<&[u8; 32]>,
+    identity_keys: Option<&[Vec<u8>]>,
+    mode: u8,
+) -> [u8; 32] {
+    let mut transcript = Transcript::new(&DKG_LABEL);
+    append_party_values(&mut transcript, b"session_id", sid_list);
+    transcript.append_u64(b"mode", mode as u64);
+    if let Some(session_nonce) = session_nonce {
+        transcript.append_message(b"session_nonce", session_nonce);
+    }
+    for (party_id, identity_key) in
+        identity_keys.into_iter().flatten().enumerate()
+    {
+        transcript.append_u64(b"party_id", party_id as u64);
+        transcript.append_message(b"identity_key", identity_key);
+    }
+    challenge(&mut transcript, &DKG_FINAL_SESSION_ID_LABEL)
+}
+
+/// Message signed by the identity key of a party to bind it to the
+/// keygen with the final session ID.
+pub(crate) fn hash_identity_message(
+    final_session_id: &[u8; 32],
+    party_id: u8,
+) -> [u8; 32] {
+    let mut transcript = Transcript::new(&DKG_LABEL);
+    transcript.append_message(b"final_session_id", final_session_id);
+    transcript.append_u64(b"party_id", party_id as u64);
+    challenge(&mut transcript, &IDENTITY_SIGNATURE_LABEL)
+}
+
+/// Seed of a party's randomness for one round of a deterministic
+/// keygen.
+pub(crate) fn hash_round_seed(
+    seed: &[u8; 32],
+    party_id: u8,
+    round: u8,
+) -> [u8; 32] {
+    let mut transcript = Transcript::new(&DKG_LABEL);
+    transcript.append_message(b"seed", seed);
+    transcript.append_u64(b"party_id", party_id as u64);
+    transcript.append_u64(b"round", round as u64);
+    challenge(&mut transcript, &DETERMINISTIC_RNG_LABEL)
+}
+
+/// Common root chain code from chain code contributions of all parties.
+pub(crate) fn hash_root_chain_code<'a>(
+    chain_code_sids: impl Iterator<Item = (u8, &'a [u8; 32])>,
+) -> [u8; 32] {
+    let mut transcript = Transcript::new(&DKG_LABEL);
+    append_party_values(&mut transcript, b"chain_code_sid", chain_code_sids);
+    challenge(&mut transcript, &ROOT_CHAIN_CODE_LABEL)
+}
+
+/// Final session ID of a signing session, bound to the key share and
+/// an optional message hash.
+pub(crate) fn hash_dsg_final_session_id<'a>(
+    sid_list: impl Iterator<Item = (u8, &'a [u8; 32])>,
+    keyshare_final_session_id: &[u8; 32],
+    message_hash: Option<&[u8; 32]>,
+) -> [u8; 32] {
+    let mut transcript = Transcript::new(&DSG_LABEL);
+    append_party_values(&mut transcript, b"session_id", sid_list);
+    transcript
+        .append_message(b"keyshare_session_id", keyshare_final_session_id);
+    if let Some(message_hash) = message_hash {
+        transcript.append_message(b"message_hash", message_hash);
+    }
+    challenge(&mut transcript, &DSG_FINAL_SESSION_ID_LABEL)
+}
+
+/// Final session ID of a seed refresh, bound to the key share.
+pub(crate) fn hash_seed_refresh_final_session_id<'a>(
+    sid_list: impl Iterator<Item = (u8, &'a [u8; 32])>,
+    counter_list: impl Iterator<Item = (u8, &'a [u32])>,
+    keyshare_final_session_id: &[u8; 32],
+) -> [u8; 32] {
+    let mut transcript = Transcript::new(&SEED_REFRESH_LABEL);
+    append_party_values(&mut transcript, b"session_id", sid_list);
+    for (party_id, counters) in counter_list {
+        transcript.append_u64(b"party_id", party_id as u64);
+        for counter in counters {
+            transcript.append_u64(b"refresh_counter", *counter as u64);
+        }
+    }
+    transcript
+        .append_message(b"keyshare_session_id", keyshare_final_session_id);
+    challenge(&mut transcript, &SEED_REFRESH_FINAL_SESSION_ID_LABEL)
+}
+
+/// Final session ID of a standalone base OT setup.
+pub(crate) fn hash_ot_setup_final_session_id<'a>(
+    sid_list: impl Iterator<Item = (u8, &'a [u8; 32])>,
+) -> [u8; 32] {
+    let mut transcript = Transcript::new(&OT_SETUP_LABEL);
+    append_party_values(&mut transcript, b"session_id", sid_list);
+    challenge(&mut transcript, &OT_SETUP_FINAL_SESSION_ID_LABEL)
+}
+
+/// Final session ID of a threshold Schnorr signing, bound to the key
+/// share.
+pub(crate) fn hash_tsg_final_session_id<'a>(
+    sid_list: impl Iterator<Item = (u8, &'a [u8; 32])>,
+    keyshare_final_session_id: &[u8; 32],
+) -> [u8; 32] {
+    let mut transcript = Transcript::new(&TSG_LABEL);
+    append_party_values(&mut transcript, b"session_id", sid_list);
+    transcript
+        .append_message(b"keyshare_session_id", keyshare_final_session_id);
+    challenge(&mut transcript, &TSG_FINAL_SESSION_ID_LABEL)
+}
+
+/// Nonce binding factors of all signing parties: every factor depends
+/// on the nonce commitments of all parties, the key and the message.
+pub(crate) fn hash_tsg_binding_factors<'a>(
+    final_session_id: &[u8; 32],
+    commitments: impl Iterator<Item = (u8, &'a AffinePoint, &'a AffinePoint)>,
+    public_key: &[u8; 32],
+    message: &[u8; 32],
+) -> Vec<(u8, Scalar)> {
+    let mut transcript = Transcript::new(&TSG_LABEL);
+    transcript.append_message(b"final_session_id", final_session_id);
+    transcript.append_message(b"public_key", public_key);
+    transcript.append_message(b"message", message);
+
+    let mut parties = vec![];
+    for (party_id, big_d, big_e) in commitments {
+        transcript.append_u64(b"party_id", party_id as u64);
+        transcript.append_message(b"big_d", &big_d.to_bytes());
+        transcript.append_message(b"big_e", &big_e.to_bytes());
+        parties.push(party_id);
+    }
+
+    parties
+        .into_iter()
+        .map(|party_id| {
+            let mut transcript = transcript.clone();
+            transcript.append_u64(b"binding_party_id", party_id as u64);
+            let rho = challenge(&mut transcript, &TSG_BINDING_FACTOR_LABEL);
+            (party_id, Scalar::reduce(U256::from_be_slice(&rho)))
+        })
+        .collect()
+}
+
+/// Digest of session IDs and commitments of all signing parties.
+pub(crate) fn hash_digest_i<'a>(
+    items: impl Iterator<Item = (u8, &'a [u8; 32], &'a [u8; 32])>,
+) -> [u8; 32] {
+    let mut transcript = Transcript::new(&DSG_LABEL);
+    for (party_id, sid, commitment) in items {
+        transcript.append_u64(b"party_id", party_id as u64);
+        transcript.append_message(b"session_id", sid);
+        transcript.append_message(b"commitment", commitment);
+    }
+    challenge(&mut transcript, &DIGEST_I_LABEL)
+}
+
 pub(crate) fn get_base_ot_session_id(
     sender_id: usize,
     receiver_id: usize,
@@ -124,7 +386,7 @@ pub(crate) fn verify_dlog_proofs<'a>(
     }
 
     if ok.unwrap_u8() == 0 {
-        return Err(KeygenError::InvalidDLogProof);
+        return Err(KeygenError::InvalidDLogProof(party_id as u8));
     }
 
     Ok(())
@@ -217,6 +479,30 @@ pub(crate) fn get_idx_from_id(current_party_id: u8, for_party_id: u8) -> u8 {
     }
 }
 
+/// Map `items` in order, on the rayon thread pool with the `rayon`
+/// feature and sequentially otherwise.
+pub(crate) fn par_map<T: Send, U: Send>(
+    items: Vec<T>,
+    f: impl Fn(T) -> U + Sync + Send,
+) -> Vec<U> {
+    #[cfg(feature = "rayon")]
+    {
+        use rayon::prelude::*;
+        items.into_par_iter().map(f).collect()
+    }
+
+    #[cfg(not(feature = "rayon"))]
+    {
+        items.into_iter().map(f).collect()
+    }
+}
+
+/// Keccak-256 as used by Ethereum, i.e. with the original Keccak
+/// padding rather than the one of SHA3-256.
+pub(crate) fn keccak256(data: &[u8]) -> [u8; 32] {
+    Keccak256::digest(data).into()
+}
+
 impl<T> From<Box<T>> for ZS<T>
 where
     T: AnyBitPattern + NoUninit,
@@ -247,6 +533,43 @@ where
     }
 }
 
+/// Secret polynomial, coefficients are wiped on drop.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+#[serde(transparent)]
+pub struct SecretPolynomial(Polynomial<Secp256k1>);
+
+impl From<Polynomial<Secp256k1>> for SecretPolynomial {
+    fn from(p: Polynomial<Secp256k1>) -> Self {
+        Self(p)
+    }
+}
+
+impl Deref for SecretPolynomial {
+    type Target = Polynomial<Secp256k1>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for SecretPolynomial {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl Zeroize for SecretPolynomial {
+    fn zeroize(&mut self) {
+        self.0.coeffs.zeroize();
+    }
+}
+
+impl Drop for SecretPolynomial {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
 impl<T> Deref for ZS<T>
 where
     T: AnyBitPattern + NoUninit,
@@ -317,3 +640,165 @@ where
         })
     }
 }
+
+/// Serde helpers encoding points and scalars as lowercase hex strings.
+pub(crate) mod hex_serde {
+    use k256::{
+        elliptic_curve::{group::GroupEncoding, PrimeField},
+        AffinePoint, NonZeroScalar, Scalar,
+    };
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    fn decode<'de, D: Deserializer<'de>, const N: usize>(
+        deserializer: D,
+    ) -> Result<[u8; N], D::Error> {
+        let s = String::deserialize(deserializer)?;
+        let mut bytes = [0u8; N];
+        hex::decode_to_slice(s, &mut bytes).map_err(D::Error::custom)?;
+        Ok(bytes)
+    }
+
+    fn to_point<E: Error>(bytes: [u8; 33]) -> Result<AffinePoint, E> {
+        Option::from(AffinePoint::from_bytes(&bytes.into()))
+            .ok_or_else(|| E::custom("invalid point"))
+    }
+
+    fn to_scalar<E: Error>(bytes: [u8; 32]) -> Result<NonZeroScalar, E> {
+        Option::<Scalar>::from(Scalar::from_repr(bytes.into()))
+            .and_then(|s| NonZeroScalar::new(s).into())
+            .ok_or_else(|| E::custom("invalid scalar"))
+    }
+
+    pub mod opt_bytes32 {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(
+            v: &Option<[u8; 32]>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match v {
+                Some(v) => serializer.serialize_str(&hex::encode(v)),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<[u8; 32]>, D::Error> {
+            Option::<String>::deserialize(deserializer)?
+                .map(|s| {
+                    let mut bytes = [0u8; 32];
+                    hex::decode_to_slice(s, &mut bytes)
+                        .map_err(D::Error::custom)?;
+                    Ok(bytes)
+                })
+                .transpose()
+        }
+    }
+
+    pub mod point {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(
+            v: &AffinePoint,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            serializer.serialize_str(&hex::encode(v.to_bytes()))
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<AffinePoint, D::Error> {
+            to_point(decode(deserializer)?)
+        }
+    }
+
+    pub mod points {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(
+            v: &[AffinePoint],
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            serializer
+                .collect_seq(v.iter().map(|p| hex::encode(p.to_bytes())))
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Vec<AffinePoint>, D::Error> {
+            Vec::<String>::deserialize(deserializer)?
+                .into_iter()
+                .map(|s| {
+                    let mut bytes = [0u8; 33];
+                    hex::decode_to_slice(s, &mut bytes)
+                        .map_err(D::Error::custom)?;
+                    to_point(bytes)
+                })
+                .collect()
+        }
+    }
+
+    pub mod scalars {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(
+            v: &[NonZeroScalar],
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            serializer
+                .collect_seq(v.iter().map(|s| hex::encode(s.to_bytes())))
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Vec<NonZeroScalar>, D::Error> {
+            Vec::<String>::deserialize(deserializer)?
+                .into_iter()
+                .map(|s| {
+                    let mut bytes = [0u8; 32];
+                    hex::decode_to_slice(s, &mut bytes)
+                        .map_err(D::Error::custom)?;
+                    to_scalar(bytes)
+                })
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keccak256_vectors() {
+        assert_eq!(
+            hex::encode(keccak256(b"")),
+            "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+        );
+        assert_eq!(
+            hex::encode(keccak256(b"abc")),
+            "4e03657aea45a94fc7d47ba826c8d667c0d1e6e33a64a036ec44f58fa12d6c45"
+        );
+        // a message longer than one block
+        assert_eq!(
+            hex::encode(keccak256(&[7; 200])),
+            "4143ef737e81b990c8b604140712d1b0667457ad90b65918a9fa319d00c5b361"
+        );
+    }
+
+    #[test]
+    fn secret_polynomial() {
+        let mut rng = rand::thread_rng();
+        let mut p = SecretPolynomial::from(Polynomial::random(&mut rng, 2));
+
+        let mut bytes = vec![];
+        ciborium::into_writer(&p, &mut bytes).unwrap();
+        let q: SecretPolynomial =
+            ciborium::from_reader(bytes.as_slice()).unwrap();
+        assert_eq!(p.coeffs, q.coeffs);
+
+        p.zeroize();
+        assert!(p.coeffs.is_empty());
+    }
+}
@@ -0,0 +1,39 @@
+// Copyright (c) Silence Laboratories Pte. Ltd. All Rights Reserved.
+// This software is licensed under the Silence Laboratories License Agreement.
+
+//! Encoding of messages, sessions and key shares.
+//!
+//! CBOR is used by default. The `postcard` feature switches to a
+//! compact non-self-describing encoding, which gives a smaller bundle
+//! and faster encode/decode. Both sides of a ceremony must be built
+//! with the same encoding.
+
+use serde::{de::DeserializeOwned, Serialize};
+use wasm_bindgen::prelude::*;
+
+#[cfg(not(any(feature = "cbor", feature = "postcard")))]
+compile_error!("enable either `cbor` or `postcard` feature");
+
+#[cfg(feature = "postcard")]
+pub fn encode<T: Serialize + ?Sized>(value: &T) -> Vec<u8> {
+    postcard::to_allocvec(value).expect_throw("encode error")
+}
+
+#[cfg(feature = "postcard")]
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> T {
+    // trailing bytes (padding) are ignored
+    postcard::from_bytes(bytes).expect_throw("decode error")
+}
+
+#[cfg(all(feature = "cbor", not(feature = "postcard")))]
+pub fn encode<T: Serialize + ?Sized>(value: &T) -> Vec<u8> {
+    let mut buffer = vec![];
+    ciborium::into_writer(value, &mut buffer)
+        .expect_throw("CBOR encode error");
+    buffer
+}
+
+#[cfg(all(feature = "cbor", not(feature = "postcard")))]
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> T {
+    ciborium::from_reader(bytes).expect_throw("CBOR decode error")
+}
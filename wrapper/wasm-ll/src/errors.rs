@@ -1,30 +1,57 @@
-use js_sys::{Error, Reflect};
+use js_sys::{Error, Reflect, Uint8Array};
 use wasm_bindgen::{prelude::*, throw_str};
 
 use dkls23_ll::{dkg::KeygenError, dsg::SignError};
 
-fn set_party_id(js_err: &js_sys::Error, prop: &str, party_id: u8) {
-    let ok = Reflect::set(
-        js_err,
-        &JsValue::from_str(prop),
-        &JsValue::from_f64(party_id as _),
-    );
+use crate::codec;
+
+fn set_property(js_err: &js_sys::Error, prop: &str, value: &JsValue) {
+    let ok = Reflect::set(js_err, &JsValue::from_str(prop), value);
 
     if ok != Ok(true) {
         throw_str("expect to set property on an error object");
     }
 }
 
+fn set_party_id(js_err: &js_sys::Error, prop: &str, party_id: u8) {
+    set_property(js_err, prop, &JsValue::from_f64(party_id as _));
+}
+
 pub fn keygen_error(err: KeygenError) -> js_sys::Error {
-    Error::new(&err.to_string())
+    let js_err = Error::new(&err.to_string());
+
+    if let Some(p) = err.party_id() {
+        set_party_id(&js_err, "banParty", p);
+    }
+
+    js_err
 }
 
 pub fn sign_error(err: SignError) -> js_sys::Error {
     let js_err = Error::new(&err.to_string());
 
-    if let SignError::AbortProtocolAndBanParty(p) = err {
+    if let Some(p) = err.party_id() {
         set_party_id(&js_err, "banParty", p);
     }
 
+    if let SignError::AbortProtocolAndBanParty(ctx) = &err {
+        let round = JsValue::from_f64(ctx.round as _);
+        let check = JsValue::from_str(&format!("{:?}", ctx.check));
+        set_property(&js_err, "abortRound", &round);
+        set_property(&js_err, "abortCheck", &check);
+
+        if let Some(session_id) = &ctx.session_id {
+            let bytes = Uint8Array::from(session_id.as_slice());
+            set_property(&js_err, "sessionId", &bytes);
+        }
+    }
+
+    // unverified claim, not evidence; could be forwarded to other
+    // parties to vote on the ban
+    if let SignError::MtaCheckFailed(claim) = &err {
+        let bytes = Uint8Array::from(codec::encode(claim).as_slice());
+        set_property(&js_err, "blameClaim", &bytes);
+    }
+
     js_err
 }
@@ -5,15 +5,20 @@ use js_sys::{Array, Error, Uint8Array};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 
-use k256::{elliptic_curve::group::GroupEncoding, AffinePoint};
+use k256::{
+    elliptic_curve::group::GroupEncoding, AffinePoint, NonZeroScalar,
+};
 
 use dkls23_ll::dkg::{self, KeygenError};
 
 use crate::{
+    codec,
     errors::keygen_error,
     keyshare::Keyshare,
-    maybe_seeded_rng,
-    message::{Message, MessageRouting},
+    maybe_seed, maybe_seeded_rng,
+    message::{Message, MessageRouting, PaddedSize},
+    progress_hook,
+    trace::{Call, Trace},
 };
 
 #[derive(Serialize, Deserialize)]
@@ -34,6 +39,10 @@ pub struct KeygenSession {
     state: dkg::State,
     n: usize,
     round: Round,
+    #[serde(default)]
+    padding: bool,
+    #[serde(default)]
+    trace: Option<Trace>,
 }
 
 #[wasm_bindgen]
@@ -44,7 +53,7 @@ impl KeygenSession {
         threshold: u8,
         party_id: u8,
         seed: Option<Vec<u8>>,
-    ) -> Self {
+    ) -> Result<KeygenSession, Error> {
         let mut rng = maybe_seeded_rng(seed);
 
         let party = dkg::Party {
@@ -53,25 +62,23 @@ impl KeygenSession {
             party_id,
         };
 
-        KeygenSession {
+        Ok(KeygenSession {
             n: party.ranks.len(),
-            state: dkg::State::new(party, &mut rng),
+            state: dkg::State::new(party, &mut rng).map_err(keygen_error)?,
             round: Round::Init,
-        }
+            padding: false,
+            trace: None,
+        })
     }
 
     #[wasm_bindgen(js_name = toBytes)]
     pub fn to_bytes(&self) -> Vec<u8> {
-        let mut buffer = vec![];
-        ciborium::into_writer(self, &mut buffer)
-            .expect_throw("CBOR encode error");
-
-        buffer
+        codec::encode(self)
     }
 
     #[wasm_bindgen(js_name = fromBytes)]
     pub fn from_bytes(bytes: &[u8]) -> KeygenSession {
-        ciborium::from_reader(bytes).expect_throw("CBOR decode")
+        codec::decode(bytes)
     }
 
     #[wasm_bindgen(js_name = initKeyRotation)]
@@ -87,6 +94,38 @@ impl KeygenSession {
             state: dkg::State::key_rotation(oldshare, &mut rng)
                 .map_err(keygen_error)?,
             round: Round::Init,
+            padding: false,
+            trace: None,
+        })
+    }
+
+    /// Reshare an existing key to a smaller set of parties, for
+    /// example to remove a party. `remaining` lists old party IDs of
+    /// all parties keeping the key. Each of them gets a new party ID,
+    /// its index in the sorted list, and a new key share for
+    /// `threshold` out of `remaining.length` parties with the same
+    /// public key.
+    #[wasm_bindgen(js_name = initKeyReshare)]
+    pub fn init_key_reshare(
+        oldshare: &Keyshare,
+        remaining: Vec<u8>,
+        threshold: u8,
+        seed: Option<Vec<u8>>,
+    ) -> Result<KeygenSession, Error> {
+        let oldshare = oldshare.as_ref();
+        let mut rng = maybe_seeded_rng(seed);
+
+        let state = dkg::State::key_reshare(
+            oldshare, &remaining, threshold, &mut rng,
+        )
+        .map_err(keygen_error)?;
+
+        Ok(KeygenSession {
+            n: state.expected_message_count() + 1,
+            state,
+            round: Round::Init,
+            padding: false,
+            trace: None,
         })
     }
 
@@ -111,6 +150,8 @@ impl KeygenSession {
             )
             .map_err(keygen_error)?,
             round: Round::Init,
+            padding: false,
+            trace: None,
         })
     }
 
@@ -149,6 +190,135 @@ impl KeygenSession {
             )
             .map_err(keygen_error)?,
             round: Round::Init,
+            padding: false,
+            trace: None,
+        })
+    }
+
+    /// Import an existing private key as a trusted dealer. The dealer
+    /// takes part in the ceremony as party `party_id`, all other
+    /// parties join with initKeyImportJoin().
+    #[wasm_bindgen(js_name = initKeyImport)]
+    pub fn init_key_import(
+        secret_key: Vec<u8>,
+        participants: u8,
+        threshold: u8,
+        party_id: u8,
+        seed: Option<Vec<u8>>,
+    ) -> Result<KeygenSession, Error> {
+        let mut rng = maybe_seeded_rng(seed);
+
+        let party = dkg::Party {
+            ranks: vec![0; participants as usize],
+            t: threshold,
+            party_id,
+        };
+
+        let secret_key: [u8; 32] = secret_key
+            .try_into()
+            .map_err(|_| Error::new("invalid secret key size"))?;
+        let secret_key: Option<NonZeroScalar> =
+            NonZeroScalar::from_repr(secret_key.into()).into();
+        let secret_key =
+            secret_key.ok_or_else(|| Error::new("invalid secret key"))?;
+
+        Ok(KeygenSession {
+            n: participants as _,
+            state: dkg::State::key_import(party, &secret_key, None, &mut rng)
+                .map_err(keygen_error)?,
+            round: Round::Init,
+            padding: false,
+            trace: None,
+        })
+    }
+
+    /// Join import of a private key distributed by the dealer
+    /// `dealer_id`. `pk` is the public key of the imported key.
+    #[wasm_bindgen(js_name = initKeyImportJoin)]
+    pub fn init_key_import_join(
+        participants: u8,
+        threshold: u8,
+        party_id: u8,
+        dealer_id: u8,
+        pk: Vec<u8>,
+        seed: Option<Vec<u8>>,
+    ) -> Result<KeygenSession, Error> {
+        let mut rng = maybe_seeded_rng(seed);
+
+        let party = dkg::Party {
+            ranks: vec![0; participants as usize],
+            t: threshold,
+            party_id,
+        };
+
+        let pk: [u8; 33] =
+            pk.try_into().map_err(|_| Error::new("invalid PK size"))?;
+        let pk: Option<AffinePoint> =
+            AffinePoint::from_bytes(&pk.into()).into();
+        let pk = pk.ok_or_else(|| Error::new("invalid PK"))?;
+
+        Ok(KeygenSession {
+            n: participants as _,
+            state: dkg::State::key_import_join(
+                party, dealer_id, pk, &mut rng,
+            )
+            .map_err(keygen_error)?,
+            round: Round::Init,
+            padding: false,
+            trace: None,
+        })
+    }
+
+    /// Pad all outgoing messages with zero bytes to a fixed size of
+    /// their round, which depends only on the threshold. All messages
+    /// of a round are then of the same size in every session with the
+    /// same threshold.
+    #[wasm_bindgen(js_name = setPadding)]
+    pub fn set_padding(&mut self, enabled: bool) {
+        self.padding = enabled;
+    }
+
+    fn padded_size<T: PaddedSize>(&self) -> Option<usize> {
+        self.padding.then(|| T::padded_size(self.state.threshold()))
+    }
+
+    /// Start recording a trace of the session: a snapshot of the
+    /// current state, RNG seeds and all inbound and outbound messages
+    /// of the following calls. The trace contains secret key material.
+    #[wasm_bindgen(js_name = startTrace)]
+    pub fn start_trace(&mut self) {
+        self.trace = None;
+        self.trace = Some(Trace::new(codec::encode(self)));
+    }
+
+    /// Return the encoded trace recorded so far, if any.
+    #[wasm_bindgen(js_name = trace)]
+    pub fn trace(&self) -> Option<Vec<u8>> {
+        self.trace.as_ref().map(codec::encode)
+    }
+
+    /// Restore a session from a trace and replay all recorded calls.
+    /// Fails if the session produces an outcome different from the
+    /// recorded one, otherwise returns the session in its final state.
+    #[wasm_bindgen(js_name = replayTrace)]
+    pub fn replay_trace(trace: &[u8]) -> Result<KeygenSession, Error> {
+        Trace::replay(trace, |session: &mut KeygenSession, call| match call {
+            Call::FirstMessage => {
+                session.create_first_message().map(|msg| vec![msg])
+            }
+
+            Call::HandleMessages {
+                seed,
+                input,
+                commitments,
+            } => session.handle_round(
+                Call::input(input),
+                Call::commitments(commitments),
+                *seed,
+                None,
+            ),
+
+            Call::LastMessage { .. } => Err(Error::new("invalid trace")),
         })
     }
 
@@ -175,10 +345,25 @@ impl KeygenSession {
 
     #[wasm_bindgen(js_name = createFirstMessage)]
     pub fn create_first_message(&mut self) -> Result<Message, Error> {
+        let res = self.first_message();
+        if let Some(trace) = &mut self.trace {
+            trace.record(
+                Call::FirstMessage,
+                res.as_ref().map(std::slice::from_ref),
+            );
+        }
+        res
+    }
+
+    fn first_message(&mut self) -> Result<Message, Error> {
         match self.round {
             Round::Init => {
+                let msg = Message::with_padding(
+                    self.state.generate_msg1(),
+                    self.padded_size::<dkg::KeygenMsg1>(),
+                )?;
                 self.round = Round::WaitMsg1;
-                Ok(Message::new(self.state.generate_msg1()))
+                Ok(msg)
             }
 
             _ => Err(Error::new("invalid state")),
@@ -198,55 +383,108 @@ impl KeygenSession {
     ) -> Result<Vec<Message>, Error>
     where
         T: DeserializeOwned,
-        U: Serialize + MessageRouting,
+        U: Serialize + MessageRouting + PaddedSize,
         H: FnMut(&mut dkg::State, Vec<T>) -> Result<Vec<U>, dkg::KeygenError>,
     {
         let msgs: Vec<T> = Message::decode_vector(&msgs);
 
         match h(&mut self.state, msgs) {
             Ok(msgs) => {
-                let out = Message::encode_vector(msgs);
-                self.round = next;
-                Ok(out)
-            }
+                match Message::encode_vector(msgs, self.padded_size::<U>()) {
+                    Ok(out) => {
+                        self.round = next;
+                        Ok(out)
+                    }
 
-            Err(err) => {
-                self.round = Round::Failed;
-                Err(keygen_error(err))
+                    // the state is already in the next round
+                    Err(err) => {
+                        self.round = Round::Failed;
+                        Err(err)
+                    }
+                }
             }
+
+            Err(err) => Err(self.fail(err)),
+        }
+    }
+
+    /// Handle an error of the current round. A batch rejected before
+    /// the round started leaves the session in the current round, so
+    /// the round could be retried with a corrected batch. An error of
+    /// the round itself aborts the keygen and fails the session.
+    fn fail(&mut self, err: KeygenError) -> Error {
+        if self.state.round() == dkg::KeygenRound::Aborted {
+            self.round = Round::Failed;
         }
+
+        keygen_error(err)
     }
 
     // , typescript_type = "handleMessages(msgs: (Message)[], commitments?: Array<Uint8Array>): (Message)[]"
+    /// Handle a batch of messages of the current round.
+    ///
+    /// If the batch is incomplete, of another round or contains a
+    /// message from an unexpected or duplicate sender the call throws
+    /// and the session stays in the current round, so the caller could
+    /// pass a corrected batch. Any other error fails the session.
+    ///
+    /// Optional `progress(partyId, done, total)` is called for each
+    /// counterparty once its base OT message is processed in round 2.
     #[wasm_bindgen(js_name = handleMessages)]
     pub fn handle_messages(
         &mut self,
         msgs: Vec<Message>,
         commitments: Option<Array>,
         seed: Option<Vec<u8>>,
+        progress: Option<js_sys::Function>,
     ) -> Result<Vec<Message>, Error> {
-        let mut rng = maybe_seeded_rng(seed);
+        let seed = maybe_seed(seed);
+        let call = self.trace.as_ref().map(|_| {
+            Call::handle_messages(seed, &msgs, commitments.as_ref())
+        });
+
+        let res =
+            self.handle_round(msgs, commitments, seed, progress.as_ref());
+        if let (Some(trace), Some(call)) = (&mut self.trace, call) {
+            trace.record(call, res.as_ref());
+        }
+
+        res
+    }
+
+    fn handle_round(
+        &mut self,
+        msgs: Vec<Message>,
+        commitments: Option<Array>,
+        seed: [u8; 32],
+        progress: Option<&js_sys::Function>,
+    ) -> Result<Vec<Message>, Error> {
+        let mut rng = maybe_seeded_rng(Some(seed));
 
         match &self.round {
             Round::WaitMsg1 => self.handle(
                 msgs,
-                |state, msgs| state.handle_msg1(&mut rng, msgs),
+                |state, msgs: Vec<dkg::KeygenMsg1>| {
+                    state.handle_msg1(&mut rng, msgs)
+                },
                 Round::WaitMsg2,
             ),
 
             Round::WaitMsg2 => self.handle(
                 msgs,
-                |state, msgs| state.handle_msg2(&mut rng, msgs),
+                |state, msgs: Vec<dkg::KeygenMsg2>| {
+                    let hook = progress_hook(progress, msgs.len());
+                    state.handle_msg2_with_progress(&mut rng, msgs, hook)
+                },
                 Round::WaitMsg3,
             ),
 
             Round::WaitMsg3 => {
-                let commitments = commitments.ok_or_else(|| {
-                    keygen_error(KeygenError::InvalidMessage)
-                })?;
-                let len = self.n as u32;
-                if commitments.length() != len {
-                    return Err(keygen_error(KeygenError::InvalidMessage));
+                let invalid =
+                    || keygen_error(KeygenError::InvalidChainCodeCommitments);
+                let commitments = commitments.ok_or_else(invalid)?;
+                if commitments.length() != self.n as u32 {
+                    return Err(invalid());
                 }
 
                 let commitments: Vec<_> = commitments
@@ -257,15 +495,13 @@ impl KeygenSession {
                             bytes.copy_to(&mut b);
                             Ok(b)
                         }
-                        _ => Err(keygen_error(
-                            KeygenError::InvalidCommitmentHash,
-                        )),
+                        _ => Err(invalid()),
                     })
                     .collect::<Result<Vec<_>, js_sys::Error>>()?;
 
                 self.handle(
                     msgs,
-                    |state, msgs| {
+                    |state, msgs: Vec<dkg::KeygenMsg3>| {
                         state
                             .handle_msg3(&mut rng, msgs, &commitments)
                             .map(|m| vec![m])
@@ -275,13 +511,11 @@ impl KeygenSession {
             }
 
             Round::WaitMsg4 => {
-                let msgs = Message::decode_vector(&msgs);
+                let msgs: Vec<dkg::KeygenMsg4> =
+                    Message::decode_vector(&msgs);
                 match self.state.handle_msg4(msgs) {
                     Ok(keyshare) => self.round = Round::Share(keyshare),
-                    Err(err) => {
-                        self.round = Round::Failed;
-                        return Err(keygen_error(err));
-                    }
+                    Err(err) => return Err(self.fail(err)),
                 };
 
                 Ok(vec![])
@@ -333,3 +567,27 @@ impl MessageRouting for dkg::KeygenMsg4 {
         None
     }
 }
+
+impl PaddedSize for dkg::KeygenMsg1 {
+    fn padded_size(_t: u8) -> usize {
+        Self::max_encoded_size()
+    }
+}
+
+impl PaddedSize for dkg::KeygenMsg2 {
+    fn padded_size(t: u8) -> usize {
+        Self::max_encoded_size(t)
+    }
+}
+
+impl PaddedSize for dkg::KeygenMsg3 {
+    fn padded_size(t: u8) -> usize {
+        Self::max_encoded_size(t)
+    }
+}
+
+impl PaddedSize for dkg::KeygenMsg4 {
+    fn padded_size(_t: u8) -> usize {
+        Self::max_encoded_size()
+    }
+}
@@ -1,6 +1,9 @@
 // Copyright (c) Silence Laboratories Pte. Ltd. All Rights Reserved.
 // This software is licensed under the Silence Laboratories License Agreement.
 
+use std::str::FromStr;
+
+use derivation_path::DerivationPath;
 use js_sys::Uint8Array;
 use wasm_bindgen::prelude::*;
 
@@ -8,6 +11,8 @@ use k256::elliptic_curve::group::GroupEncoding;
 
 use dkls23_ll::dkg;
 
+use crate::codec;
+
 // use bincode::serde::{decode_from_slice, encode_to_vec};
 
 #[wasm_bindgen]
@@ -36,7 +41,7 @@ impl Keyshare {
     /// Create an instance of keyshare from passed array of bytes.
     #[wasm_bindgen(js_name = fromBytes)]
     pub fn from_bytes(bytes: &[u8]) -> Result<Keyshare, JsError> {
-        let inner = ciborium::from_reader(bytes).expect_throw("CBOR decode");
+        let inner = codec::decode(bytes);
 
         Ok(Keyshare { inner })
     }
@@ -44,10 +49,22 @@ impl Keyshare {
     /// Serialize keyshare into array of bytes.
     #[wasm_bindgen(js_name = toBytes)]
     pub fn to_bytes(&self) -> Vec<u8> {
-        let mut buffer = vec![];
-        ciborium::into_writer(&self.inner, &mut buffer)
-            .expect_throw("CBOR encode error");
-        buffer
+        codec::encode(&self.inner)
+    }
+
+    /// Create an instance of keyshare from the compact fixed-layout
+    /// encoding produced by toCompactBytes().
+    #[wasm_bindgen(js_name = fromCompactBytes)]
+    pub fn from_compact_bytes(bytes: &[u8]) -> Result<Keyshare, JsError> {
+        let inner = dkg::Keyshare::from_compact_bytes(bytes)?;
+
+        Ok(Keyshare { inner })
+    }
+
+    /// Serialize keyshare using the compact fixed-layout encoding.
+    #[wasm_bindgen(js_name = toCompactBytes)]
+    pub fn to_compact_bytes(&self) -> Vec<u8> {
+        self.inner.to_compact_bytes()
     }
 
     #[wasm_bindgen(js_name = publicKey, getter)]
@@ -72,6 +89,26 @@ impl Keyshare {
         self.inner.party_id
     }
 
+    /// Public key of the derivation path, for example "m/0/1".
+    #[wasm_bindgen(js_name = derivedPublicKey)]
+    pub fn derived_public_key(
+        &self,
+        chain_path: &str,
+    ) -> Result<Uint8Array, JsError> {
+        let chain_path = DerivationPath::from_str(chain_path)?;
+        let public_key = self.inner.derived_public_key(&chain_path)?;
+
+        Ok(Uint8Array::from(public_key.to_bytes().as_ref()))
+    }
+
+    /// Base58Check encoded extended public key of the derivation path.
+    #[wasm_bindgen(js_name = xpub)]
+    pub fn xpub(&self, chain_path: &str) -> Result<String, JsError> {
+        let chain_path = DerivationPath::from_str(chain_path)?;
+
+        Ok(self.inner.xpub(&chain_path)?.to_string())
+    }
+
     /// Depricated method, the method does nothing.
     /// It exists for backward compatibility only
     #[wasm_bindgen(js_name = finishKeyRotation)]
@@ -1,25 +1,89 @@
 // Copyright (c) Silence Laboratories Pte. Ltd. All Rights Reserved.
 // This software is licensed under the Silence Laboratories License Agreement.
 
+use std::cell::RefCell;
+
 use rand::prelude::*;
 use rand_chacha::ChaCha20Rng;
 
 use wasm_bindgen::prelude::*;
 
+use dkls23_ll::entropy::MixedRng;
+
+mod codec;
 mod errors;
 mod keygen;
 mod keyshare;
 mod message;
 mod sign;
+mod trace;
 mod utils;
 
-pub fn maybe_seeded_rng<T: AsRef<[u8]>>(seed: Option<T>) -> ChaCha20Rng {
-    let seed = match seed.as_ref() {
+thread_local! {
+    static ENTROPY_SOURCE: RefCell<Option<js_sys::Function>> =
+        const { RefCell::new(None) };
+}
+
+/// Set an external entropy source, for example an HSM or a TEE, or
+/// remove it by passing `undefined`. The function is called with a
+/// number of bytes and must return an `Uint8Array` of this size. Its
+/// output is mixed with local randomness whenever a seed is not
+/// passed explicitly.
+#[wasm_bindgen(js_name = setEntropySource)]
+pub fn set_entropy_source(source: Option<js_sys::Function>) {
+    ENTROPY_SOURCE.with(|s| *s.borrow_mut() = source);
+}
+
+fn random_seed() -> [u8; 32] {
+    ENTROPY_SOURCE.with(|source| match source.borrow().as_ref() {
         None => rand::thread_rng().gen(),
+        Some(f) => {
+            let mut external = |dest: &mut [u8]| {
+                let bytes = f
+                    .call1(&JsValue::NULL, &(dest.len() as u32).into())
+                    .map(|v| js_sys::Uint8Array::new(&v))
+                    .expect_throw("entropy source failed");
+                if bytes.length() as usize != dest.len() {
+                    wasm_bindgen::throw_str("invalid entropy size");
+                }
+                bytes.copy_to(dest);
+            };
+            MixedRng::new(&mut rand::thread_rng(), &mut external).gen()
+        }
+    })
+}
+
+/// Return passed seed or generate a random one.
+pub fn maybe_seed<T: AsRef<[u8]>>(seed: Option<T>) -> [u8; 32] {
+    match seed.as_ref() {
+        None => random_seed(),
         Some(seed) => {
             seed.as_ref().try_into().expect_throw("invalid seed size")
         }
-    };
+    }
+}
 
-    ChaCha20Rng::from_seed(seed)
+/// Turn an optional JS callback `progress(partyId, done, total)` into a
+/// progress hook for a round processing `total` counterparties.
+pub fn progress_hook(
+    progress: Option<&js_sys::Function>,
+    total: usize,
+) -> impl FnMut(u8) + '_ {
+    let mut done = 0u32;
+    move |party_id| {
+        done += 1;
+        if let Some(f) = progress {
+            // an exception thrown by the callback must not abort the round
+            let _ = f.call3(
+                &JsValue::NULL,
+                &party_id.into(),
+                &done.into(),
+                &(total as u32).into(),
+            );
+        }
+    }
+}
+
+pub fn maybe_seeded_rng<T: AsRef<[u8]>>(seed: Option<T>) -> ChaCha20Rng {
+    ChaCha20Rng::from_seed(maybe_seed(seed))
 }
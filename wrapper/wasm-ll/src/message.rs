@@ -3,14 +3,26 @@
 
 use serde::{de::DeserializeOwned, Serialize};
 
-use js_sys::Uint8Array;
+use js_sys::{Error, Uint8Array};
 use wasm_bindgen::prelude::*;
 
+use crate::codec;
+
+/// Size of the routing header of an encoded message.
+const HEADER_SIZE: usize = 3;
+
 pub trait MessageRouting {
     fn src_party_id(&self) -> u8;
     fn dst_party_id(&self) -> Option<u8>;
 }
 
+/// Fixed size of padded messages of a round.
+pub trait PaddedSize {
+    /// Size of a padded message of a session with threshold `t`, an
+    /// upper bound of the encoded size of any message of the round.
+    fn padded_size(t: u8) -> usize;
+}
+
 #[wasm_bindgen]
 pub struct Message {
     /// Source party ID
@@ -39,6 +51,44 @@ impl Message {
         }
     }
 
+    /// Serialize the complete message: routing header and payload.
+    ///
+    /// Layout: `from_id`, 1 if `to_id` is present or 0 for
+    /// broadcast messages, `to_id` or 0, followed by the payload.
+    #[wasm_bindgen(js_name = toBytes)]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes =
+            Vec::with_capacity(HEADER_SIZE + self.payload.length() as usize);
+        bytes.extend([
+            self.from_id,
+            self.to_id.is_some() as u8,
+            self.to_id.unwrap_or(0),
+        ]);
+        bytes.extend(self.payload.to_vec());
+        bytes
+    }
+
+    /// Deserialize a message produced by toBytes().
+    #[wasm_bindgen(js_name = fromBytes)]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Message, Error> {
+        let (from_id, to_id, payload) = match bytes {
+            [from_id, 0, 0, payload @ ..] => (*from_id, None, payload),
+            [from_id, 1, to_id, payload @ ..] => {
+                (*from_id, Some(*to_id), payload)
+            }
+            [_, _, _, ..] => {
+                return Err(Error::new("invalid message header"))
+            }
+            _ => return Err(Error::new("invalid message size")),
+        };
+
+        Ok(Message {
+            from_id,
+            to_id,
+            payload: Uint8Array::from(payload),
+        })
+    }
+
     #[wasm_bindgen]
     pub fn clone(&self) -> Message {
         let len = self.payload.length();
@@ -52,23 +102,42 @@ impl Message {
 
 impl Message {
     pub fn new<T: Serialize + MessageRouting>(payload: T) -> Self {
-        let mut buffer = vec![];
-        ciborium::into_writer(&payload, &mut buffer)
-            .expect_throw("CBOR encode error");
+        let buffer = codec::encode(&payload);
+        Self::from_buffer(&payload, &buffer)
+    }
+
+    /// Encode a message and append zero bytes up to `size` bytes.
+    /// Encoded values are self-delimiting, so decode() ignores the
+    /// padding and no explicit unpadding is required.
+    ///
+    /// Returns an error if the encoded message exceeds `size`.
+    pub fn with_padding<T: Serialize + MessageRouting>(
+        payload: T,
+        size: Option<usize>,
+    ) -> Result<Self, Error> {
+        let mut buffer = codec::encode(&payload);
+
+        if let Some(size) = size {
+            if buffer.len() > size {
+                return Err(Error::new("message exceeds padded size"));
+            }
+            buffer.resize(size, 0);
+        }
 
-        let from_id = payload.src_party_id();
-        let to_id = payload.dst_party_id();
+        Ok(Self::from_buffer(&payload, &buffer))
+    }
+
+    fn from_buffer<T: MessageRouting>(payload: &T, buffer: &[u8]) -> Self {
         Self {
-            from_id,
-            to_id,
-            payload: Uint8Array::from(buffer.as_ref()),
+            from_id: payload.src_party_id(),
+            to_id: payload.dst_party_id(),
+            payload: Uint8Array::from(buffer),
         }
     }
 
     pub fn decode<T: DeserializeOwned>(&self) -> T {
         let buffer = self.payload.to_vec();
-        // TODO implement Read for Uint8Array ?
-        ciborium::from_reader(&buffer as &[u8]).expect_throw("CBOR decode")
+        codec::decode(&buffer)
     }
 
     pub fn decode_vector<T: DeserializeOwned>(input: &[Self]) -> Vec<T> {
@@ -77,7 +146,10 @@ impl Message {
 
     pub fn encode_vector<T: Serialize + MessageRouting>(
         msgs: Vec<T>,
-    ) -> Vec<Self> {
-        msgs.into_iter().map(|msg| Self::new(msg)).collect()
+        size: Option<usize>,
+    ) -> Result<Vec<Self>, Error> {
+        msgs.into_iter()
+            .map(|msg| Self::with_padding(msg, size))
+            .collect()
     }
 }
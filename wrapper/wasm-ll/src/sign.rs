@@ -8,13 +8,18 @@ use js_sys::{Array, Error, Uint8Array};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 
+use k256::elliptic_curve::group::GroupEncoding;
+
 use dkls23_ll::dsg;
 
 use crate::{
+    codec,
     errors::sign_error,
     keyshare::Keyshare,
-    maybe_seeded_rng,
-    message::{Message, MessageRouting},
+    maybe_seed, maybe_seeded_rng,
+    message::{Message, MessageRouting, PaddedSize},
+    progress_hook,
+    trace::{Call, Trace},
 };
 
 #[derive(Serialize, Deserialize)]
@@ -34,6 +39,14 @@ enum Round {
 pub struct SignSession {
     state: dsg::State,
     round: Round,
+    #[serde(default)]
+    padding: bool,
+    #[serde(default)]
+    trace: Option<Trace>,
+    #[serde(default)]
+    chain_path: Option<String>,
+    #[serde(skip)]
+    policy: Option<js_sys::Function>,
 }
 
 #[wasm_bindgen]
@@ -57,23 +70,125 @@ impl SignSession {
         SignSession {
             state,
             round: Round::Init,
+            padding: false,
+            trace: None,
+            chain_path: Some(chain_path.to_string()),
+            policy: None,
         }
     }
 
+    /// Final session ID, available once the first round is handled.
+    #[wasm_bindgen(js_name = finalSessionId, getter)]
+    pub fn final_session_id(&self) -> Option<Uint8Array> {
+        let fsid = self.state.final_session_id;
+
+        (fsid != [0; 32]).then(|| Uint8Array::from(fsid.as_ref()))
+    }
+
+    /// Public key derived by the derivation path of the session,
+    /// 33 bytes SEC1 compressed point.
+    #[wasm_bindgen(js_name = derivedPublicKey, getter)]
+    pub fn derived_public_key(&self) -> Uint8Array {
+        let bytes = self.state.derived_public_key.to_bytes();
+
+        Uint8Array::from(bytes.as_ref())
+    }
+
+    /// Advertisement of the pre-signature: final session ID, public
+    /// key and nonce R, 98 bytes. Available once the session contains
+    /// a pre-signature and before lastMessage().
+    #[wasm_bindgen(js_name = presignatureAdvert, getter)]
+    pub fn presignature_advert(&self) -> Option<Uint8Array> {
+        match &self.round {
+            Round::Pre(pre) => {
+                Some(Uint8Array::from(pre.advert().to_bytes().as_ref()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Derivation path of the session. Undefined for sessions
+    /// serialized by an older version.
+    #[wasm_bindgen(js_name = chainPath, getter)]
+    pub fn chain_path(&self) -> Option<String> {
+        self.chain_path.clone()
+    }
+
+    /// Set a signing policy, called by lastMessage() before the
+    /// pre-signature is used as
+    /// `policy(messageHash, chainPath, derivedPublicKey)`. The session
+    /// fails unless the policy returns true. The policy is not
+    /// serialized with the session.
+    #[wasm_bindgen(js_name = setPolicy)]
+    pub fn set_policy(&mut self, policy: Option<js_sys::Function>) {
+        self.policy = policy;
+    }
+
+    /// Number of parties expected to take part in the session.
+    #[wasm_bindgen(js_name = signers, getter)]
+    pub fn signers(&self) -> u8 {
+        self.state.keyshare.threshold
+    }
+
     /// Serialize session into array of bytes.
     #[wasm_bindgen(js_name = toBytes)]
     pub fn to_bytes(&self) -> Vec<u8> {
-        let mut buffer = vec![];
-        ciborium::into_writer(self, &mut buffer)
-            .expect_throw("CBOR encode error");
-
-        buffer
+        codec::encode(self)
     }
 
     /// Deserialize session from array of bytes.
     #[wasm_bindgen(js_name = fromBytes)]
     pub fn from_bytes(bytes: &[u8]) -> SignSession {
-        ciborium::from_reader(bytes).expect_throw("CBOR decode error")
+        codec::decode(bytes)
+    }
+
+    /// Pad all outgoing messages but abort messages with zero bytes
+    /// to a fixed size of their round. All messages of a round are
+    /// then of the same size in every session.
+    #[wasm_bindgen(js_name = setPadding)]
+    pub fn set_padding(&mut self, enabled: bool) {
+        self.padding = enabled;
+    }
+
+    fn padded_size<T: PaddedSize>(&self) -> Option<usize> {
+        self.padding
+            .then(|| T::padded_size(self.state.keyshare.threshold))
+    }
+
+    /// Start recording a trace of the session: a snapshot of the
+    /// current state, RNG seeds and all inbound and outbound messages
+    /// of the following calls up to lastMessage(). The trace contains
+    /// secret key material.
+    #[wasm_bindgen(js_name = startTrace)]
+    pub fn start_trace(&mut self) {
+        self.trace = None;
+        self.trace = Some(Trace::new(codec::encode(self)));
+    }
+
+    /// Return the encoded trace recorded so far, if any.
+    #[wasm_bindgen(js_name = trace)]
+    pub fn trace(&self) -> Option<Vec<u8>> {
+        self.trace.as_ref().map(codec::encode)
+    }
+
+    /// Restore a session from a trace and replay all recorded calls.
+    /// Fails if the session produces an outcome different from the
+    /// recorded one, otherwise returns the session in its final state.
+    #[wasm_bindgen(js_name = replayTrace)]
+    pub fn replay_trace(trace: &[u8]) -> Result<SignSession, Error> {
+        Trace::replay(trace, |session: &mut SignSession, call| match call {
+            Call::FirstMessage => {
+                session.create_first_message().map(|msg| vec![msg])
+            }
+
+            Call::HandleMessages { seed, input, .. } => {
+                session.handle_round(Call::input(input), *seed, None)
+            }
+
+            Call::LastMessage { message_hash } => session
+                .last_message(message_hash, None)
+                .map(|msg| vec![msg]),
+        })
     }
 
     /// Return an error message, if any.
@@ -85,13 +200,49 @@ impl SignSession {
         }
     }
 
+    /// Bind the session to a message hash. The hash is committed in
+    /// the first message and lastMessage() will refuse to sign any
+    /// other hash. Must be called before createFirstMessage().
+    #[wasm_bindgen(js_name = bindMessageHash)]
+    pub fn bind_message_hash(
+        &mut self,
+        message_hash: &[u8],
+    ) -> Result<(), Error> {
+        let hash = message_hash
+            .try_into()
+            .map_err(|_| Error::new("invalid message hash"))?;
+
+        match self.round {
+            Round::Init => {
+                self.state.bind_message_hash(hash).map_err(sign_error)
+            }
+
+            _ => Err(Error::new("invalid state")),
+        }
+    }
+
     /// Create a fist message and change session state from Init to WaitMg1.
     #[wasm_bindgen(js_name = createFirstMessage)]
     pub fn create_first_message(&mut self) -> Result<Message, Error> {
+        let res = self.first_message();
+        if let Some(trace) = &mut self.trace {
+            trace.record(
+                Call::FirstMessage,
+                res.as_ref().map(std::slice::from_ref),
+            );
+        }
+        res
+    }
+
+    fn first_message(&mut self) -> Result<Message, Error> {
         match self.round {
             Round::Init => {
+                let msg = Message::with_padding(
+                    self.state.generate_msg1().map_err(sign_error)?,
+                    self.padded_size::<dsg::SignMsg1>(),
+                )?;
                 self.round = Round::WaitMsg1;
-                Ok(Message::new(self.state.generate_msg1()))
+                Ok(msg)
             }
 
             _ => Err(Error::new("invalid state")),
@@ -106,50 +257,107 @@ impl SignSession {
     ) -> Result<Vec<Message>, Error>
     where
         T: DeserializeOwned,
-        U: Serialize + MessageRouting,
+        U: Serialize + MessageRouting + PaddedSize,
         H: FnMut(&mut dsg::State, Vec<T>) -> Result<Vec<U>, dsg::SignError>,
     {
         let msgs: Vec<T> = Message::decode_vector(&msgs);
         match h(&mut self.state, msgs) {
             Ok(msgs) => {
-                let out = Message::encode_vector(msgs);
-                self.round = next;
-                Ok(out)
+                match Message::encode_vector(msgs, self.padded_size::<U>()) {
+                    Ok(out) => {
+                        self.round = next;
+                        Ok(out)
+                    }
+
+                    // the state is already in the next round
+                    Err(err) => {
+                        self.round = Round::Failed;
+                        Err(err)
+                    }
+                }
             }
 
-            Err(err) => {
-                self.round = Round::Failed;
-                Err(sign_error(err))
-            }
+            Err(err) => Err(self.fail(err)),
         }
     }
 
+    /// Handle an error of the current round. A batch rejected before
+    /// the round started leaves the session in the current round, so
+    /// the round could be retried with a corrected batch. An error of
+    /// the round itself aborts the session, its secrets are already
+    /// wiped by the round handler.
+    fn fail(&mut self, err: dsg::SignError) -> Error {
+        if self.state.round() == dsg::SignRound::Aborted {
+            self.round = Round::Failed;
+        }
+
+        sign_error(err)
+    }
+
     /// Handle a batch of messages.
     /// Decode, process and return an array messages to send to other parties.
+    ///
+    /// If the batch is incomplete, contains a message from an
+    /// unexpected or duplicate sender or of another session the call
+    /// throws and the session stays in the current round, so the
+    /// caller could pass a corrected batch. Any other error fails the
+    /// session.
+    ///
+    /// Optional `progress(partyId, done, total)` is called for each
+    /// counterparty once its MtA message is processed in round 2.
     #[wasm_bindgen(js_name = handleMessages)]
     pub fn handle_messages(
         &mut self,
         msgs: Vec<Message>,
         seed: Option<Vec<u8>>,
+        progress: Option<js_sys::Function>,
     ) -> Result<Vec<Message>, Error> {
-        let mut rng = maybe_seeded_rng(seed);
+        let seed = maybe_seed(seed);
+        let call = self
+            .trace
+            .as_ref()
+            .map(|_| Call::handle_messages(seed, &msgs, None));
+
+        let res = self.handle_round(msgs, seed, progress.as_ref());
+        if let (Some(trace), Some(call)) = (&mut self.trace, call) {
+            trace.record(call, res.as_ref());
+        }
+
+        res
+    }
+
+    fn handle_round(
+        &mut self,
+        msgs: Vec<Message>,
+        seed: [u8; 32],
+        progress: Option<&js_sys::Function>,
+    ) -> Result<Vec<Message>, Error> {
+        let mut rng = maybe_seeded_rng(Some(seed));
 
         match &self.round {
             Round::WaitMsg1 => self.handle(
                 msgs,
-                |state, msgs| state.handle_msg1(&mut rng, msgs),
+                |state, msgs: Vec<dsg::SignMsg1>| {
+                    state.handle_msg1(&mut rng, msgs)
+                },
                 Round::WaitMsg2,
             ),
 
             Round::WaitMsg2 => self.handle(
                 msgs,
-                |state, msgs| state.handle_msg2(&mut rng, msgs),
+                |state, msgs: Vec<dsg::SignMsg2>| {
+                    let hook = progress_hook(progress, msgs.len());
+                    state.handle_msg2_with_progress(&mut rng, msgs, hook)
+                },
                 Round::WaitMsg3,
             ),
 
             Round::WaitMsg3 => {
-                let msgs = Message::decode_vector(&msgs);
-                let pre = self.state.handle_msg3(msgs).map_err(sign_error)?;
+                let msgs: Vec<dsg::SignMsg3> = Message::decode_vector(&msgs);
+                let pre = match self.state.handle_msg3(msgs) {
+                    Ok(pre) => pre,
+                    Err(err) => return Err(self.fail(err)),
+                };
 
                 self.round = Round::Pre(pre);
 
@@ -164,24 +372,87 @@ impl SignSession {
 
     /// The session contains a "pre-signature".
     /// Returns a last message.
+    ///
+    /// Without `hashFn` the message must be a 32 byte hash and is
+    /// signed as is. Otherwise the message is hashed by `hashFn`, one
+    /// of "sha256", "keccak256" or "double-sha256".
     #[wasm_bindgen(js_name = lastMessage)]
     pub fn last_message(
         &mut self,
-        message_hash: &[u8],
+        message: &[u8],
+        hash_fn: Option<String>,
     ) -> Result<Message, Error> {
-        if message_hash.len() != 32 {
-            return Err(Error::new("invalid message hash"));
+        let to_sign = match hash_fn {
+            None => message_hash(message),
+            Some(hash_fn) => hash_algorithm(&hash_fn).map(|hash_fn| {
+                dsg::MessageToSign::Raw(message.to_vec(), hash_fn)
+            }),
+        };
+
+        // the trace records the signed hash, replayed without hashFn
+        let (res, message_hash) = match to_sign {
+            Ok(to_sign) => {
+                let hash = to_sign.hash();
+                (self.create_last_message(to_sign), hash.to_vec())
+            }
+            Err(err) => (Err(err), message.to_vec()),
+        };
+
+        if let Some(trace) = &mut self.trace {
+            let call = Call::LastMessage { message_hash };
+            trace.record(call, res.as_ref().map(std::slice::from_ref));
+        }
+        res
+    }
+
+    fn check_policy(&self, hash: &[u8; 32]) -> Result<(), dsg::SignError> {
+        let Some(policy) = &self.policy else {
+            return Ok(());
+        };
+
+        // an exception thrown by the policy refuses the hash
+        let allowed = policy.call3(
+            &JsValue::NULL,
+            &Uint8Array::from(hash.as_slice()),
+            &self.chain_path.clone().into(),
+            &self.derived_public_key(),
+        );
+
+        match allowed {
+            Ok(v) if v.is_truthy() => Ok(()),
+            _ => Err(dsg::SignError::PolicyRejected),
         }
+    }
 
+    fn create_last_message(
+        &mut self,
+        message: dsg::MessageToSign,
+    ) -> Result<Message, Error> {
         match core::mem::replace(&mut self.round, Round::Finished) {
             Round::Pre(pre) => {
-                let hash = message_hash.try_into().unwrap();
-                let (partial, msg4) =
-                    dsg::create_partial_signature(pre, hash);
+                if let Err(err) = self.check_policy(&message.hash()) {
+                    self.round = Round::Failed;
+                    return Err(sign_error(err));
+                }
 
-                self.round = Round::WaitMsg4(partial);
-
-                Ok(Message::new(msg4))
+                let (partial, msg4) =
+                    match dsg::create_partial_signature(pre, message) {
+                        Ok(res) => res,
+                        Err(err) => {
+                            self.round = Round::Failed;
+                            return Err(sign_error(err));
+                        }
+                    };
+
+                let size = self.padded_size::<dsg::SignMsg4>();
+                let msg4 = Message::with_padding(msg4, size);
+
+                self.round = match msg4 {
+                    Ok(_) => Round::WaitMsg4(partial),
+                    Err(_) => Round::Failed,
+                };
+
+                msg4
             }
 
             prev => {
@@ -191,8 +462,46 @@ impl SignSession {
         }
     }
 
-    /// Combine last messages and return signature as [R, S].
-    /// R, S are 32 byte UintArray.
+    /// Stop the session and return a broadcast message that notifies
+    /// the other parties, so that they do not wait for the next
+    /// message of this party. `reason` is "policy" or "cancel".
+    #[wasm_bindgen(js_name = abort)]
+    pub fn abort(&mut self, reason: &str) -> Result<Message, Error> {
+        let reason = match reason {
+            "policy" => dsg::SignAbortReason::PolicyRejected,
+            "cancel" => dsg::SignAbortReason::Cancelled,
+            _ => return Err(Error::new("invalid abort reason")),
+        };
+
+        self.round = Round::Failed;
+
+        Ok(Message::new(self.state.abort(reason)))
+    }
+
+    /// Handle an abort message of another party and fail the session.
+    /// Returns the reason, "policy" or "cancel".
+    ///
+    /// Throws and keeps the session intact if the message does not
+    /// belong to the session.
+    #[wasm_bindgen(js_name = handleAbort)]
+    pub fn handle_abort(&mut self, msg: &Message) -> Result<String, Error> {
+        let msg: dsg::SignAbort = msg.decode();
+        self.state.handle_abort(&msg).map_err(sign_error)?;
+
+        self.round = Round::Failed;
+
+        Ok(match msg.reason {
+            dsg::SignAbortReason::PolicyRejected => "policy".into(),
+            dsg::SignAbortReason::Cancelled => "cancel".into(),
+        })
+    }
+
+    /// Combine last messages and return signature as [R, S, V].
+    /// R, S are 32 byte UintArray, V is the recovery ID (0..3), the
+    /// Ethereum `v` without the offset of 27.
+    ///
+    /// S is normalized to the lower half of the scalar field unless
+    /// `raw_s` is true. V always matches the returned S.
     ///
     /// This method consumes the session and deallocates all
     /// internal data.
@@ -201,19 +510,31 @@ impl SignSession {
     pub fn combine_partial_signature(
         self,
         msgs: Vec<Message>,
+        raw_s: Option<bool>,
     ) -> Result<Array, Error> {
+        let normalization = if raw_s.unwrap_or(false) {
+            dsg::SNormalization::Raw
+        } else {
+            dsg::SNormalization::LowS
+        };
+
         match self.round {
             Round::WaitMsg4(partial) => {
-                let msgs = Message::decode_vector(&msgs);
-                let sign = dsg::combine_signatures(partial, msgs)
-                    .map_err(sign_error)?;
+                let msgs = msgs.iter().map(Message::decode::<dsg::SignMsg4>);
+                let (sign, recid) = dsg::combine_signatures_with(
+                    partial,
+                    msgs,
+                    normalization,
+                )
+                .map_err(sign_error)?;
 
                 let (r, s) = sign.split_bytes();
 
-                let a = js_sys::Array::new_with_length(2);
+                let a = js_sys::Array::new_with_length(3);
 
                 a.set(0, Uint8Array::from(&r as &[u8]).into());
                 a.set(1, Uint8Array::from(&s as &[u8]).into());
+                a.set(2, recid.to_byte().into());
 
                 Ok(a)
             }
@@ -253,6 +574,16 @@ impl MessageRouting for dsg::SignMsg3 {
     }
 }
 
+impl MessageRouting for dsg::SignAbort {
+    fn src_party_id(&self) -> u8 {
+        self.from_id
+    }
+
+    fn dst_party_id(&self) -> Option<u8> {
+        None
+    }
+}
+
 impl MessageRouting for dsg::SignMsg4 {
     fn src_party_id(&self) -> u8 {
         self.from_id
@@ -272,3 +603,44 @@ impl MessageRouting for dsg::PreSignature {
         None
     }
 }
+
+fn message_hash(hash: &[u8]) -> Result<dsg::MessageToSign, Error> {
+    let hash: [u8; 32] = hash
+        .try_into()
+        .map_err(|_| Error::new("invalid message hash"))?;
+
+    Ok(dsg::MessageToSign::Prehashed(hash))
+}
+
+fn hash_algorithm(hash_fn: &str) -> Result<dsg::HashAlgorithm, Error> {
+    match hash_fn {
+        "sha256" => Ok(dsg::HashAlgorithm::Sha256),
+        "keccak256" => Ok(dsg::HashAlgorithm::Keccak256),
+        "double-sha256" => Ok(dsg::HashAlgorithm::DoubleSha256),
+        _ => Err(Error::new("unknown hash function")),
+    }
+}
+
+impl PaddedSize for dsg::SignMsg1 {
+    fn padded_size(_t: u8) -> usize {
+        Self::max_encoded_size()
+    }
+}
+
+impl PaddedSize for dsg::SignMsg2 {
+    fn padded_size(_t: u8) -> usize {
+        Self::max_encoded_size()
+    }
+}
+
+impl PaddedSize for dsg::SignMsg3 {
+    fn padded_size(_t: u8) -> usize {
+        Self::max_encoded_size()
+    }
+}
+
+impl PaddedSize for dsg::SignMsg4 {
+    fn padded_size(_t: u8) -> usize {
+        Self::max_encoded_size()
+    }
+}
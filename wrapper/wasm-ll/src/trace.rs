@@ -0,0 +1,157 @@
+// Copyright (c) Silence Laboratories Pte. Ltd. All Rights Reserved.
+// This software is licensed under the Silence Laboratories License Agreement.
+
+//! Recording and replay of session traces.
+//!
+//! A trace holds a snapshot of a session taken when recording started
+//! and every following call that advanced the session: RNG seed,
+//! inbound and outbound messages or an error. Replaying a trace feeds
+//! the recorded inputs through a session restored from the snapshot
+//! and checks that it produces the same outputs. This reproduces a
+//! failed ceremony locally.
+//!
+//! A trace contains secret key material of the party and must be
+//! protected the same way as a key share.
+
+use js_sys::{Array, Error, Uint8Array};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{codec, message::Message};
+
+#[derive(Serialize, Deserialize, PartialEq)]
+pub struct TraceMessage {
+    from_id: u8,
+    to_id: Option<u8>,
+    payload: Vec<u8>,
+}
+
+impl From<&Message> for TraceMessage {
+    fn from(msg: &Message) -> Self {
+        Self {
+            from_id: msg.from_id,
+            to_id: msg.to_id,
+            payload: msg.payload().to_vec(),
+        }
+    }
+}
+
+impl From<&TraceMessage> for Message {
+    fn from(msg: &TraceMessage) -> Self {
+        Message::create(
+            Uint8Array::from(msg.payload.as_slice()),
+            msg.from_id,
+            msg.to_id,
+        )
+    }
+}
+
+/// A recorded call of a session method.
+#[derive(Serialize, Deserialize)]
+pub enum Call {
+    FirstMessage,
+    HandleMessages {
+        seed: [u8; 32],
+        input: Vec<TraceMessage>,
+        commitments: Option<Vec<Vec<u8>>>,
+    },
+    LastMessage {
+        message_hash: Vec<u8>,
+    },
+}
+
+impl Call {
+    pub fn handle_messages(
+        seed: [u8; 32],
+        input: &[Message],
+        commitments: Option<&Array>,
+    ) -> Self {
+        Call::HandleMessages {
+            seed,
+            input: input.iter().map(TraceMessage::from).collect(),
+            commitments: commitments.map(|a| {
+                a.iter().map(|c| Uint8Array::new(&c).to_vec()).collect()
+            }),
+        }
+    }
+
+    /// Recorded input messages.
+    pub fn input(input: &[TraceMessage]) -> Vec<Message> {
+        input.iter().map(Message::from).collect()
+    }
+
+    /// Recorded commitments as passed to handleMessages().
+    pub fn commitments(commitments: &Option<Vec<Vec<u8>>>) -> Option<Array> {
+        commitments.as_ref().map(|c| {
+            c.iter()
+                .map(|c| Uint8Array::from(c.as_slice()))
+                .collect::<Array>()
+        })
+    }
+}
+
+type Outcome = Result<Vec<TraceMessage>, String>;
+
+fn outcome<'a>(
+    res: Result<impl IntoIterator<Item = &'a Message>, &Error>,
+) -> Outcome {
+    match res {
+        Ok(msgs) => Ok(msgs.into_iter().map(TraceMessage::from).collect()),
+        Err(err) => Err(err.message().into()),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Step {
+    call: Call,
+    outcome: Outcome,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Trace {
+    session: Vec<u8>,
+    steps: Vec<Step>,
+}
+
+impl Trace {
+    /// Start a new trace from an encoded session.
+    pub fn new(session: Vec<u8>) -> Self {
+        Self {
+            session,
+            steps: vec![],
+        }
+    }
+
+    /// Record a call that returned a batch of messages or an error.
+    pub fn record<'a>(
+        &mut self,
+        call: Call,
+        res: Result<impl IntoIterator<Item = &'a Message>, &Error>,
+    ) {
+        self.steps.push(Step {
+            call,
+            outcome: outcome(res),
+        });
+    }
+
+    /// Restore a session from the snapshot of an encoded trace and
+    /// apply all recorded calls. Returns an error describing the
+    /// first step which outcome differs from the recorded one.
+    pub fn replay<S: DeserializeOwned>(
+        bytes: &[u8],
+        mut apply: impl FnMut(&mut S, &Call) -> Result<Vec<Message>, Error>,
+    ) -> Result<S, Error> {
+        let trace: Trace = codec::decode(bytes);
+        let mut session: S = codec::decode(&trace.session);
+
+        for (idx, step) in trace.steps.iter().enumerate() {
+            let res = apply(&mut session, &step.call);
+            if outcome(res.as_ref()) != step.outcome {
+                return Err(Error::new(&format!(
+                    "trace diverged at step {idx}"
+                )));
+            }
+        }
+
+        Ok(session)
+    }
+}
@@ -4,11 +4,12 @@
 // to run tests we need web build
 // wasm-pack build -t web ..
 
-import { assertEquals, assertThrows } from "https://deno.land/std@0.224.0/assert/mod.ts";
+import { assert, assertEquals, assertThrows } from "https://deno.land/std@0.224.0/assert/mod.ts";
 
 import initDkls from '../pkg/dkls_wasm_ll.js';
 import {KeygenSession, Keyshare} from '../pkg/dkls_wasm_ll.js';
 import {SignSession, Message} from '../pkg/dkls_wasm_ll.js';
+import {setEntropySource} from '../pkg/dkls_wasm_ll.js';
 
 
 export const test = (name: string, f: any) => {
@@ -157,6 +158,21 @@ test('Key rotation', async() => {
     let new_signs = dsg(new_shares, 2, messageHash);
 });
 
+test('Key reshare to a smaller party set', () => {
+    let shares = dkg(3, 2);
+    let pk = shares[0].publicKey;
+
+    // party 1 is removed, parties 0 and 2 get new IDs 0 and 1
+    let parties = [shares[0], shares[2]].map(s => KeygenSession.initKeyReshare(s, new Uint8Array([0, 2]), 2));
+    let new_shares = dkg_inner(parties);
+
+    new_shares.forEach(s => assertEquals(s.publicKey, pk));
+
+    dsg(new_shares, 2, new Uint8Array(32).fill(7));
+
+    assertThrows(() => KeygenSession.initKeyReshare(shares[1], new Uint8Array([0, 2]), 2));
+});
+
 test('DKG session should fail', () => {
 
     let s = new KeygenSession(3, 2, 1);
@@ -165,6 +181,9 @@ test('DKG session should fail', () => {
     assertThrows(() => s.createFirstMessage())
 
     assertThrows(() => s.handleMessages([m]));
+
+    assertThrows(() => new KeygenSession(3, 4, 0));
+    assertThrows(() => new KeygenSession(3, 2, 3));
 });
 
 test('DSG session should fail', () => {
@@ -205,3 +224,300 @@ test('key share recovery', () => {
     let messageHash = Uint8Array.from({length: 32}, () => Math.floor(Math.random() * 255));
     let new_signs = dsg(new_shares, 2, messageHash);
 });
+
+test('DSG with padded messages', () => {
+    let shares = dkg(3, 2);
+
+    let parties = shares.slice(0, 2).map(s => {
+        let p = new SignSession(s, "m");
+        p.setPadding(true);
+        return p;
+    });
+
+    let msg1: Message[] = parties.map(p => p.createFirstMessage());
+    let msg2: Message[] = parties.flatMap((p, pid) => p.handleMessages(filterMessages(msg1, pid)));
+    let msg3: Message[] = parties.flatMap((p, pid) => p.handleMessages(selectMessages(msg2, pid)));
+
+    parties.forEach((p, pid) => p.handleMessages(selectMessages(msg3, pid)));
+
+    let msg4: Message[] = parties.map(p => p.lastMessage(new Uint8Array(32)));
+
+    // all messages of a round have the same size
+    [msg1, msg2, msg3, msg4].forEach(msgs => {
+        msgs.forEach(m => assertEquals(m.payload.length, msgs[0].payload.length));
+    });
+
+    parties.forEach((p, pid) => p.combine(filterMessages(msg4, pid)));
+});
+
+test('DSG trace replay', () => {
+    let shares = dkg(3, 2);
+    let messageHash = new Uint8Array(32);
+
+    let parties = shares.slice(0, 2).map(s => {
+        let p = new SignSession(s, "m");
+        p.startTrace();
+        return p;
+    });
+
+    let msg1: Message[] = parties.map(p => p.createFirstMessage());
+    let msg2: Message[] = parties.flatMap((p, pid) => p.handleMessages(filterMessages(msg1, pid)));
+    let msg3: Message[] = parties.flatMap((p, pid) => p.handleMessages(selectMessages(msg2, pid)));
+
+    parties.forEach((p, pid) => p.handleMessages(selectMessages(msg3, pid)));
+
+    let msg4: Message[] = parties.map(p => p.lastMessage(messageHash));
+
+    let replayed = SignSession.replayTrace(parties[0].trace()!);
+    let sign = parties[0].combine(filterMessages(msg4, 0));
+    let replayedSign = replayed.combine(filterMessages(msg4, 0));
+
+    assertEquals(sign, replayedSign);
+});
+
+test('DKG progress callback', () => {
+    let n = 3;
+    let parties = [0, 1, 2].map(i => new KeygenSession(n, 2, i));
+
+    let msg1: Message[] = parties.map(p => p.createFirstMessage());
+    let msg2: Message[] = parties.flatMap((p, pid) => p.handleMessages(filterMessages(msg1, pid)));
+
+    parties.forEach((p, pid) => {
+        let calls: number[][] = [];
+        p.handleMessages(selectMessages(msg2, pid), undefined, undefined,
+                         (id: number, done: number, total: number) => calls.push([id, done, total]));
+
+        assertEquals(calls.length, n - 1);
+        assertEquals(calls.map(c => c[1]), [1, 2]);
+        calls.forEach(c => assertEquals(c[2], n - 1));
+    });
+});
+
+test('Keyshare compact encoding', () => {
+    let shares = dkg(3, 2);
+
+    shares.forEach(s => {
+        let bytes = s.toCompactBytes();
+        let copy = Keyshare.fromCompactBytes(bytes);
+
+        assertEquals(copy.toBytes(), s.toBytes());
+        assertEquals(bytes.length < s.toBytes().length, true);
+    });
+});
+
+test('Message toBytes/fromBytes', () => {
+    let payload = Uint8Array.from([1, 2, 3]);
+
+    [new Message(payload, 1, 2), new Message(payload, 1)].forEach(m => {
+        let copy = Message.fromBytes(m.toBytes());
+
+        assertEquals(copy.from_id, m.from_id);
+        assertEquals(copy.to_id, m.to_id);
+        assertEquals(copy.payload, m.payload);
+    });
+
+    assertThrows(() => Message.fromBytes(Uint8Array.from([0, 1])));
+    assertThrows(() => Message.fromBytes(Uint8Array.from([0, 2, 0])));
+});
+
+test('Key import', () => {
+    // secret key 1, its public key is the generator point
+    let secretKey = new Uint8Array(32);
+    secretKey[31] = 1;
+    let pk = Uint8Array.from(
+        "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798".match(/../g)!,
+        h => parseInt(h, 16)
+    );
+
+    let shares = dkg_inner([
+        KeygenSession.initKeyImportJoin(3, 2, 0, 1, pk),
+        KeygenSession.initKeyImport(secretKey, 3, 2, 1),
+        KeygenSession.initKeyImportJoin(3, 2, 2, 1, pk),
+    ]);
+
+    shares.forEach(s => assertEquals(s.publicKey, pk));
+
+    dsg(shares, 2, new Uint8Array(32));
+});
+
+test('DSG retry round after incomplete batch', () => {
+    let shares = dkg(3, 3);
+
+    let parties = shares.map(s => new SignSession(s, "m"));
+
+    let msg1: Message[] = parties.map(p => p.createFirstMessage());
+
+    // one message is missing, the session stays in the current round
+    assertThrows(() => parties[0].handleMessages(filterMessages(msg1, 0).slice(1)));
+    assertEquals(parties[0].error(), undefined);
+
+    let msg2: Message[] = parties.flatMap((p, pid) => p.handleMessages(filterMessages(msg1, pid)));
+    let msg3: Message[] = parties.flatMap((p, pid) => p.handleMessages(selectMessages(msg2, pid)));
+
+    parties.forEach((p, pid) => p.handleMessages(selectMessages(msg3, pid)));
+
+    let msg4: Message[] = parties.map(p => p.lastMessage(new Uint8Array(32)));
+
+    parties.forEach((p, pid) => p.combine(filterMessages(msg4, pid)));
+});
+
+test('DSG session context getters', () => {
+    let shares = dkg(3, 2);
+    let pk = shares[0].publicKey;
+
+    let parties = shares.slice(0, 2).map(s => new SignSession(s, "m"));
+
+    parties.forEach(p => {
+        assertEquals(p.chainPath, "m");
+        assertEquals(p.derivedPublicKey, pk);
+        assertEquals(p.signers, 2);
+        assertEquals(p.finalSessionId, undefined);
+    });
+
+    let msg1: Message[] = parties.map(p => p.createFirstMessage());
+    let msg2: Message[] = parties.flatMap((p, pid) => p.handleMessages(filterMessages(msg1, pid)));
+
+    assertEquals(parties[0].finalSessionId, parties[1].finalSessionId);
+    assertEquals(parties[0].finalSessionId!.length, 32);
+    assertEquals(parties[0].presignatureAdvert, undefined);
+
+    let msg3: Message[] = parties.flatMap((p, pid) => p.handleMessages(selectMessages(msg2, pid)));
+    parties.forEach((p, pid) => p.handleMessages(selectMessages(msg3, pid)));
+
+    let advert = parties[0].presignatureAdvert!;
+    assertEquals(advert.length, 98);
+    assertEquals(advert, parties[1].presignatureAdvert);
+    assertEquals(advert.slice(0, 32), parties[0].finalSessionId);
+    assertEquals(advert.slice(32, 65), pk);
+});
+
+test('DKG with external entropy source', () => {
+    let calls = 0;
+    setEntropySource((size: number) => {
+        calls += 1;
+        return new Uint8Array(size).fill(7);
+    });
+
+    try {
+        dsg(dkg(3, 2), 2, new Uint8Array(32));
+        assert(calls > 0);
+    } finally {
+        setEntropySource(undefined);
+    }
+});
+
+test('DSG recovery id', () => {
+    let signs = dsg(dkg(3, 2), 2, new Uint8Array(32).fill(1));
+
+    for (let [r, s, v] of signs) {
+        assertEquals(r.length, 32);
+        assertEquals(s.length, 32);
+        assert(v >= 0 && v < 4);
+        assertEquals(v, signs[0][2]);
+    }
+});
+
+test('DSG of a full message', () => {
+    let shares = dkg(3, 2);
+    let message = new TextEncoder().encode("hello world");
+
+    for (let hashFn of ["sha256", "keccak256", "double-sha256"]) {
+        let parties = shares.slice(0, 2).map(s => new SignSession(copyKeyshare(s), "m"));
+
+        let msg1: Message[] = parties.map(p => p.createFirstMessage());
+        let msg2: Message[] = parties.flatMap((p, pid) => p.handleMessages(filterMessages(msg1, pid)));
+        let msg3: Message[] = parties.flatMap((p, pid) => p.handleMessages(selectMessages(msg2, pid)));
+
+        parties.forEach((p, pid) => p.handleMessages(selectMessages(msg3, pid)));
+
+        let msg4: Message[] = parties.map(p => p.lastMessage(message, hashFn));
+
+        parties.forEach((p, pid) => p.combine(filterMessages(msg4, pid)));
+    }
+
+    let p = new SignSession(shares[0], "m");
+    assertThrows(() => p.lastMessage(message, "md5"));
+    // an unhashed message is never signed as a hash
+    assertThrows(() => p.lastMessage(message));
+});
+
+test('DSG signing policy', () => {
+    let shares = dkg(3, 2);
+    let allowed = new Uint8Array(32).fill(3);
+
+    let parties = shares.slice(0, 2).map(s => new SignSession(copyKeyshare(s), "m/0/2"));
+    let calls: string[] = [];
+    parties.forEach(p => p.setPolicy((hash: Uint8Array, path: string, pk: Uint8Array) => {
+        calls.push(path);
+        assertEquals(pk, p.derivedPublicKey);
+        return hash.every((b, i) => b === allowed[i]);
+    }));
+
+    let msg1: Message[] = parties.map(p => p.createFirstMessage());
+    let msg2: Message[] = parties.flatMap((p, pid) => p.handleMessages(filterMessages(msg1, pid)));
+    let msg3: Message[] = parties.flatMap((p, pid) => p.handleMessages(selectMessages(msg2, pid)));
+
+    parties.forEach((p, pid) => p.handleMessages(selectMessages(msg3, pid)));
+
+    parties[0].lastMessage(allowed);
+    assertThrows(() => parties[1].lastMessage(new Uint8Array(32).fill(4)));
+    assertEquals(calls, ["m/0/2", "m/0/2"]);
+});
+
+test('DSG abort', () => {
+    let shares = dkg(3, 3);
+    let parties = shares.map(s => new SignSession(copyKeyshare(s), "m"));
+
+    let msg1: Message[] = parties.map(p => p.createFirstMessage());
+    parties.slice(0, 2).forEach((p, pid) => p.handleMessages(filterMessages(msg1, pid)));
+
+    let abort = parties[2].abort("policy");
+    assertThrows(() => parties[2].abort("unknown"));
+
+    // message of another session of the same party
+    let own = new SignSession(copyKeyshare(shares[0]), "m").abort("cancel");
+    assertThrows(() => parties[0].handleAbort(own));
+    assertEquals(parties[0].error(), undefined);
+
+    parties.slice(0, 2).forEach(p => {
+        assertEquals(p.handleAbort(abort.clone()), "policy");
+        assert(p.error() !== undefined);
+    });
+});
+
+test('Keyshare derived public keys', () => {
+    let shares = dkg(3, 2);
+
+    let p = new SignSession(copyKeyshare(shares[0]), "m/0/7");
+    assertEquals(shares[1].derivedPublicKey("m/0/7"), p.derivedPublicKey);
+
+    let xpub = shares[2].xpub("m/0");
+    assertEquals(xpub, shares[0].xpub("m/0"));
+    assert(xpub.startsWith("xpub"));
+
+    assertThrows(() => shares[0].derivedPublicKey("m/0'"));
+    assertThrows(() => shares[0].xpub("not a path"));
+});
+
+test('DSG combine with raw S', () => {
+    let shares = dkg(2, 2);
+    let parties = shares.map(s => new SignSession(copyKeyshare(s), "m"));
+
+    let msg1: Message[] = parties.map(p => p.createFirstMessage());
+    let msg2: Message[] = parties.flatMap((p, pid) => p.handleMessages(filterMessages(msg1, pid)));
+    let msg3: Message[] = parties.flatMap((p, pid) => p.handleMessages(selectMessages(msg2, pid)));
+
+    parties.forEach((p, pid) => p.handleMessages(selectMessages(msg3, pid)));
+
+    let msg4: Message[] = parties.map(p => p.lastMessage(new Uint8Array(32).fill(5)));
+
+    let [r0, s0, v0] = parties[0].combine(filterMessages(msg4, 0), true);
+    let [r1, s1, v1] = parties[1].combine(filterMessages(msg4, 1));
+
+    assertEquals(r0, r1);
+    if (s0.toString() == s1.toString()) {
+        assertEquals(v0, v1);
+    } else {
+        assertEquals(v0 ^ 1, v1);
+    }
+});